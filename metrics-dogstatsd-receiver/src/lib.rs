@@ -0,0 +1,64 @@
+//! A local DogStatsD receiver, for testing and debugging DogStatsD clients.
+//!
+//! This crate provides two things: a parser for the DogStatsD protocol, which turns payloads into typed metrics,
+//! service checks, and events, and an in-process [`Receiver`] which listens for payloads and parses them as they arrive.
+//!
+//! It is primarily intended for testing clients such as `metrics-exporter-dogstatsd`, where asserting against parsed
+//! messages is far less brittle than comparing raw payloads, but can also be used to quickly inspect what a client is
+//! sending without running the Datadog Agent.
+//!
+//! # Parsing
+//!
+//! [`parse`] parses a payload of newline-delimited messages, while [`parse_line`] parses a single message. Both support
+//! the full set of DogStatsD metric types, including multi-value payloads (`name:1:2:3|h`), along with sample rates,
+//! timestamps, tags, and container IDs:
+//!
+//! ```rust
+//! use metrics_dogstatsd_receiver::{parse_line, Message, MetricValues};
+//!
+//! let Message::Metric(metric) = parse_line("request.latency:1.5:2.5|d|#service:web,canary").unwrap() else {
+//!     panic!("expected a metric");
+//! };
+//!
+//! assert_eq!(metric.name, "request.latency");
+//! assert_eq!(metric.values, MetricValues::Distribution(vec![1.5, 2.5]));
+//! assert_eq!(metric.tag("service"), Some("web"));
+//! assert_eq!(metric.tag("canary"), Some(""));
+//! ```
+//!
+//! # Receiving
+//!
+//! A [`Receiver`] binds to a UDP socket, a Unix datagram socket, or a Unix stream socket, and processes payloads on a
+//! background thread until it is dropped. Its [`address`](Receiver::address) is in the same form as the remote
+//! addresses accepted by `metrics-exporter-dogstatsd`, so it can be passed directly to a client under test:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use metrics_dogstatsd_receiver::Receiver;
+//!
+//! let receiver = Receiver::bind_udp().expect("failed to bind receiver");
+//! println!("send metrics to {}", receiver.address());
+//!
+//! // ...configure a client to send to the receiver, and emit some metrics...
+//!
+//! let messages = receiver.wait_for_messages(1, Duration::from_secs(5));
+//! assert_eq!(messages.len(), 1);
+//! ```
+//!
+//! Unix stream sockets use the same framing as the Datadog Agent, where each payload is prefixed with its length as a
+//! 32-bit little-endian integer.
+#![deny(missing_docs)]
+#![deny(clippy::all)]
+#![deny(clippy::pedantic)]
+#![allow(clippy::must_use_candidate)]
+#![allow(clippy::module_name_repetitions)]
+#![cfg_attr(docsrs, feature(doc_cfg), deny(rustdoc::broken_intra_doc_links))]
+
+mod parse;
+pub use self::parse::{
+    parse, parse_line, Event, Message, Metric, MetricValues, ParseError, ServiceCheck,
+};
+
+mod receiver;
+pub use self::receiver::Receiver;
@@ -0,0 +1,496 @@
+use thiserror::Error;
+
+/// Errors that could occur while parsing a DogStatsD payload.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ParseError {
+    /// The payload was not valid UTF-8.
+    #[error("payload is not valid UTF-8")]
+    InvalidUtf8,
+
+    /// The message did not have the expected structure.
+    #[error("malformed message '{0}'")]
+    Malformed(String),
+
+    /// The metric type was not recognized.
+    #[error("unknown metric type '{0}'")]
+    UnknownMetricType(String),
+
+    /// A value could not be parsed.
+    #[error("invalid value '{0}'")]
+    InvalidValue(String),
+
+    /// An optional field was not recognized, or its value could not be parsed.
+    #[error("invalid field '{0}'")]
+    InvalidField(String),
+}
+
+/// A parsed DogStatsD message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// A metric.
+    Metric(Metric),
+
+    /// A service check.
+    ServiceCheck(ServiceCheck),
+
+    /// An event.
+    Event(Event),
+}
+
+/// Values of a metric, by metric type.
+///
+/// A single message can carry multiple values for the same metric, such as `name:1:2:3|h`, so values are always held as
+/// a list, in the order they appeared in the message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricValues {
+    /// Counter values (`c`).
+    Counter(Vec<f64>),
+
+    /// Gauge values (`g`).
+    Gauge(Vec<f64>),
+
+    /// Histogram values (`h`).
+    Histogram(Vec<f64>),
+
+    /// Distribution values (`d`).
+    Distribution(Vec<f64>),
+
+    /// Timer values (`ms`).
+    Timer(Vec<f64>),
+
+    /// Set values (`s`).
+    Set(Vec<String>),
+}
+
+impl MetricValues {
+    /// Returns the numeric values, or `None` if this is a set.
+    pub fn as_numbers(&self) -> Option<&[f64]> {
+        match self {
+            MetricValues::Counter(values)
+            | MetricValues::Gauge(values)
+            | MetricValues::Histogram(values)
+            | MetricValues::Distribution(values)
+            | MetricValues::Timer(values) => Some(values),
+            MetricValues::Set(_) => None,
+        }
+    }
+}
+
+/// A DogStatsD metric.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// Name of the metric.
+    pub name: String,
+
+    /// Values of the metric.
+    pub values: MetricValues,
+
+    /// Sample rate of the metric, if one was given.
+    pub sample_rate: Option<f64>,
+
+    /// Timestamp of the metric, in seconds since the Unix epoch, if one was given.
+    pub timestamp: Option<u64>,
+
+    /// Tags of the metric, as written: either `key:value` or a bare `key`.
+    pub tags: Vec<String>,
+
+    /// Container ID of the client, if one was given.
+    pub container_id: Option<String>,
+}
+
+impl Metric {
+    /// Returns the value of the given tag, if present.
+    ///
+    /// Bare tags, which have no value, return an empty string.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(&self.tags, key)
+    }
+}
+
+/// A DogStatsD service check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceCheck {
+    /// Name of the service check.
+    pub name: String,
+
+    /// Status of the service check: `0` for OK, `1` for warning, `2` for critical, and `3` for unknown.
+    pub status: u8,
+
+    /// Timestamp of the service check, in seconds since the Unix epoch, if one was given.
+    pub timestamp: Option<u64>,
+
+    /// Hostname of the service check, if one was given.
+    pub hostname: Option<String>,
+
+    /// Tags of the service check, as written: either `key:value` or a bare `key`.
+    pub tags: Vec<String>,
+
+    /// Container ID of the client, if one was given.
+    pub container_id: Option<String>,
+
+    /// Message of the service check, if one was given.
+    pub message: Option<String>,
+}
+
+impl ServiceCheck {
+    /// Returns the value of the given tag, if present.
+    ///
+    /// Bare tags, which have no value, return an empty string.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(&self.tags, key)
+    }
+}
+
+/// A DogStatsD event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// Title of the event.
+    pub title: String,
+
+    /// Text of the event.
+    pub text: String,
+
+    /// Timestamp of the event, in seconds since the Unix epoch, if one was given.
+    pub timestamp: Option<u64>,
+
+    /// Hostname of the event, if one was given.
+    pub hostname: Option<String>,
+
+    /// Aggregation key of the event, if one was given.
+    pub aggregation_key: Option<String>,
+
+    /// Priority of the event, such as `normal` or `low`, if one was given.
+    pub priority: Option<String>,
+
+    /// Source type name of the event, if one was given.
+    pub source_type_name: Option<String>,
+
+    /// Alert type of the event, such as `error` or `info`, if one was given.
+    pub alert_type: Option<String>,
+
+    /// Tags of the event, as written: either `key:value` or a bare `key`.
+    pub tags: Vec<String>,
+
+    /// Container ID of the client, if one was given.
+    pub container_id: Option<String>,
+}
+
+impl Event {
+    /// Returns the value of the given tag, if present.
+    ///
+    /// Bare tags, which have no value, return an empty string.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        find_tag(&self.tags, key)
+    }
+}
+
+/// Parses a payload of newline-delimited DogStatsD messages.
+///
+/// Empty lines are skipped.
+///
+/// # Errors
+///
+/// If the payload is not valid UTF-8, or any message in the payload cannot be parsed, an error is returned.
+pub fn parse(payload: &[u8]) -> Result<Vec<Message>, ParseError> {
+    let payload = std::str::from_utf8(payload).map_err(|_| ParseError::InvalidUtf8)?;
+    payload.lines().filter(|line| !line.is_empty()).map(parse_line).collect()
+}
+
+/// Parses a single DogStatsD message.
+///
+/// # Errors
+///
+/// If the message cannot be parsed, an error is returned.
+pub fn parse_line(line: &str) -> Result<Message, ParseError> {
+    if let Some(rest) = line.strip_prefix("_sc|") {
+        parse_service_check(line, rest).map(Message::ServiceCheck)
+    } else if let Some(rest) = line.strip_prefix("_e{") {
+        parse_event(line, rest).map(Message::Event)
+    } else {
+        parse_metric(line).map(Message::Metric)
+    }
+}
+
+fn parse_metric(line: &str) -> Result<Metric, ParseError> {
+    let malformed = || ParseError::Malformed(line.to_string());
+
+    let mut fields = line.split('|');
+    let (name, raw_values) = fields.next().and_then(|s| s.split_once(':')).ok_or_else(malformed)?;
+    let metric_type = fields.next().ok_or_else(malformed)?;
+    if name.is_empty() || raw_values.is_empty() {
+        return Err(malformed());
+    }
+
+    let raw_values = raw_values.split(':');
+    let values = match metric_type {
+        "c" => MetricValues::Counter(parse_numbers(raw_values)?),
+        "g" => MetricValues::Gauge(parse_numbers(raw_values)?),
+        "h" => MetricValues::Histogram(parse_numbers(raw_values)?),
+        "d" => MetricValues::Distribution(parse_numbers(raw_values)?),
+        "ms" => MetricValues::Timer(parse_numbers(raw_values)?),
+        "s" => MetricValues::Set(raw_values.map(ToString::to_string).collect()),
+        other => return Err(ParseError::UnknownMetricType(other.to_string())),
+    };
+
+    let mut metric = Metric {
+        name: name.to_string(),
+        values,
+        sample_rate: None,
+        timestamp: None,
+        tags: Vec::new(),
+        container_id: None,
+    };
+
+    for field in fields {
+        if let Some(rate) = field.strip_prefix('@') {
+            metric.sample_rate =
+                Some(rate.parse().map_err(|_| ParseError::InvalidField(field.to_string()))?);
+        } else if let Some(tags) = field.strip_prefix('#') {
+            metric.tags.extend(parse_tags(tags));
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            metric.container_id = Some(container_id.to_string());
+        } else if let Some(timestamp) = field.strip_prefix('T') {
+            metric.timestamp = Some(parse_timestamp(field, timestamp)?);
+        } else {
+            return Err(ParseError::InvalidField(field.to_string()));
+        }
+    }
+
+    Ok(metric)
+}
+
+fn parse_service_check(line: &str, rest: &str) -> Result<ServiceCheck, ParseError> {
+    let malformed = || ParseError::Malformed(line.to_string());
+
+    // The message always comes last, and any occurrence of `m:` within it is escaped, so the first `|m:` is the start
+    // of the message, even if the message itself contains pipes.
+    let (rest, message) = match rest.split_once("|m:") {
+        Some((rest, message)) => (rest, Some(unescape_text(message).replace("m\\:", "m:"))),
+        None => (rest, None),
+    };
+
+    let mut fields = rest.split('|');
+    let name = fields.next().filter(|name| !name.is_empty()).ok_or_else(malformed)?;
+    let status = fields.next().ok_or_else(malformed)?;
+    let status = status
+        .parse()
+        .ok()
+        .filter(|status| *status <= 3)
+        .ok_or_else(|| ParseError::InvalidValue(status.to_string()))?;
+
+    let mut check = ServiceCheck {
+        name: name.to_string(),
+        status,
+        timestamp: None,
+        hostname: None,
+        tags: Vec::new(),
+        container_id: None,
+        message,
+    };
+
+    for field in fields {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            check.timestamp = Some(parse_timestamp(field, timestamp)?);
+        } else if let Some(hostname) = field.strip_prefix("h:") {
+            check.hostname = Some(hostname.to_string());
+        } else if let Some(tags) = field.strip_prefix('#') {
+            check.tags.extend(parse_tags(tags));
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            check.container_id = Some(container_id.to_string());
+        } else {
+            return Err(ParseError::InvalidField(field.to_string()));
+        }
+    }
+
+    Ok(check)
+}
+
+fn parse_event(line: &str, rest: &str) -> Result<Event, ParseError> {
+    let malformed = || ParseError::Malformed(line.to_string());
+
+    // Events are in the form of `_e{<title length>,<text length>}:<title>|<text>|<fields>`, where the lengths are in
+    // bytes, which lets the title and text contain pipes.
+    let (lengths, rest) = rest.split_once("}:").ok_or_else(malformed)?;
+    let (title_len, text_len) = lengths.split_once(',').ok_or_else(malformed)?;
+    let title_len: usize = title_len.parse().map_err(|_| malformed())?;
+    let text_len: usize = text_len.parse().map_err(|_| malformed())?;
+
+    let title = rest.get(..title_len).ok_or_else(malformed)?;
+    let rest = rest.get(title_len..).and_then(|s| s.strip_prefix('|')).ok_or_else(malformed)?;
+    let text = rest.get(..text_len).ok_or_else(malformed)?;
+    let rest = rest.get(text_len..).ok_or_else(malformed)?;
+
+    let mut event = Event {
+        title: unescape_text(title),
+        text: unescape_text(text),
+        timestamp: None,
+        hostname: None,
+        aggregation_key: None,
+        priority: None,
+        source_type_name: None,
+        alert_type: None,
+        tags: Vec::new(),
+        container_id: None,
+    };
+
+    if rest.is_empty() {
+        return Ok(event);
+    }
+
+    let rest = rest.strip_prefix('|').ok_or_else(malformed)?;
+    for field in rest.split('|') {
+        if let Some(timestamp) = field.strip_prefix("d:") {
+            event.timestamp = Some(parse_timestamp(field, timestamp)?);
+        } else if let Some(hostname) = field.strip_prefix("h:") {
+            event.hostname = Some(hostname.to_string());
+        } else if let Some(aggregation_key) = field.strip_prefix("k:") {
+            event.aggregation_key = Some(aggregation_key.to_string());
+        } else if let Some(priority) = field.strip_prefix("p:") {
+            event.priority = Some(priority.to_string());
+        } else if let Some(source_type_name) = field.strip_prefix("s:") {
+            event.source_type_name = Some(source_type_name.to_string());
+        } else if let Some(alert_type) = field.strip_prefix("t:") {
+            event.alert_type = Some(alert_type.to_string());
+        } else if let Some(tags) = field.strip_prefix('#') {
+            event.tags.extend(parse_tags(tags));
+        } else if let Some(container_id) = field.strip_prefix("c:") {
+            event.container_id = Some(container_id.to_string());
+        } else {
+            return Err(ParseError::InvalidField(field.to_string()));
+        }
+    }
+
+    Ok(event)
+}
+
+fn parse_numbers<'a>(raw_values: impl Iterator<Item = &'a str>) -> Result<Vec<f64>, ParseError> {
+    raw_values
+        .map(|value| value.parse().map_err(|_| ParseError::InvalidValue(value.to_string())))
+        .collect()
+}
+
+fn parse_timestamp(field: &str, timestamp: &str) -> Result<u64, ParseError> {
+    timestamp.parse().map_err(|_| ParseError::InvalidField(field.to_string()))
+}
+
+fn parse_tags(tags: &str) -> impl Iterator<Item = String> + '_ {
+    tags.split(',').filter(|tag| !tag.is_empty()).map(ToString::to_string)
+}
+
+fn find_tag<'a>(tags: &'a [String], key: &str) -> Option<&'a str> {
+    tags.iter().find_map(|tag| match tag.split_once(':') {
+        Some((tag_key, value)) if tag_key == key => Some(value),
+        None if tag == key => Some(""),
+        _ => None,
+    })
+}
+
+/// Reverses the escaping of newlines in free-form text.
+fn unescape_text(text: &str) -> String {
+    text.replace("\\n", "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_line, Message, Metric, MetricValues, ParseError};
+
+    fn metric(line: &str) -> Metric {
+        match parse_line(line).unwrap() {
+            Message::Metric(metric) => metric,
+            other => panic!("expected a metric, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn metric_types() {
+        let cases = [
+            ("a:1|c", MetricValues::Counter(vec![1.0])),
+            ("a:-2.5|g", MetricValues::Gauge(vec![-2.5])),
+            ("a:3|h", MetricValues::Histogram(vec![3.0])),
+            ("a:4|d", MetricValues::Distribution(vec![4.0])),
+            ("a:5|ms", MetricValues::Timer(vec![5.0])),
+            ("a:user1|s", MetricValues::Set(vec!["user1".to_string()])),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(metric(line).values, expected, "line: {line}");
+        }
+    }
+
+    #[test]
+    fn metric_with_all_fields() {
+        let metric =
+            metric("request.latency:1.5:2:3e2|h|@0.25|#service:web,canary|c:abc123|T1700000000");
+        assert_eq!(metric.name, "request.latency");
+        assert_eq!(metric.values, MetricValues::Histogram(vec![1.5, 2.0, 300.0]));
+        assert_eq!(metric.sample_rate, Some(0.25));
+        assert_eq!(metric.timestamp, Some(1_700_000_000));
+        assert_eq!(metric.tags, ["service:web", "canary"]);
+        assert_eq!(metric.tag("service"), Some("web"));
+        assert_eq!(metric.tag("canary"), Some(""));
+        assert_eq!(metric.tag("missing"), None);
+        assert_eq!(metric.container_id.as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn multiple_lines() {
+        let messages = parse(b"a:1|c\n\nb:2|g\n").unwrap();
+        assert_eq!(messages.len(), 2);
+
+        assert_eq!(parse(b"a:1|c\n\xff"), Err(ParseError::InvalidUtf8));
+        assert_eq!(parse(b"a:1|c\nb:x|g\n"), Err(ParseError::InvalidValue("x".to_string())));
+    }
+
+    #[test]
+    fn invalid_metrics() {
+        assert_eq!(parse_line("a:1"), Err(ParseError::Malformed("a:1".to_string())));
+        assert_eq!(parse_line("a|c"), Err(ParseError::Malformed("a|c".to_string())));
+        assert_eq!(parse_line("a:1|x"), Err(ParseError::UnknownMetricType("x".to_string())));
+        assert_eq!(parse_line("a:1|c|@x"), Err(ParseError::InvalidField("@x".to_string())));
+        assert_eq!(parse_line("a:1|c|z:1"), Err(ParseError::InvalidField("z:1".to_string())));
+    }
+
+    #[test]
+    fn service_check() {
+        let line =
+            "_sc|db.up|1|d:1700000000|h:db-1|#env:prod|c:abc123|m:lost a|m\\: replica\\nretrying";
+        let Message::ServiceCheck(check) = parse_line(line).unwrap() else {
+            panic!("expected a service check");
+        };
+
+        assert_eq!(check.name, "db.up");
+        assert_eq!(check.status, 1);
+        assert_eq!(check.timestamp, Some(1_700_000_000));
+        assert_eq!(check.hostname.as_deref(), Some("db-1"));
+        assert_eq!(check.tag("env"), Some("prod"));
+        assert_eq!(check.container_id.as_deref(), Some("abc123"));
+        assert_eq!(check.message.as_deref(), Some("lost a|m: replica\nretrying"));
+
+        assert_eq!(parse_line("_sc|db.up|4"), Err(ParseError::InvalidValue("4".to_string())));
+    }
+
+    #[test]
+    fn event() {
+        let line = "_e{9,10}:Deploy|v2|line one\\n|d:1700000000|h:web-1|k:deploys|p:low|s:ci|t:success|#team:core";
+        let Message::Event(event) = parse_line(line).unwrap() else {
+            panic!("expected an event");
+        };
+
+        assert_eq!(event.title, "Deploy|v2");
+        assert_eq!(event.text, "line one\n");
+        assert_eq!(event.timestamp, Some(1_700_000_000));
+        assert_eq!(event.hostname.as_deref(), Some("web-1"));
+        assert_eq!(event.aggregation_key.as_deref(), Some("deploys"));
+        assert_eq!(event.priority.as_deref(), Some("low"));
+        assert_eq!(event.source_type_name.as_deref(), Some("ci"));
+        assert_eq!(event.alert_type.as_deref(), Some("success"));
+        assert_eq!(event.tag("team"), Some("core"));
+
+        let line = "_e{5,4}:title|text";
+        assert!(matches!(parse_line(line), Ok(Message::Event(_))));
+
+        // Lengths which don't match the title and text are rejected.
+        let line = "_e{6,4}:title|text";
+        assert_eq!(parse_line(line), Err(ParseError::Malformed(line.to_string())));
+    }
+}
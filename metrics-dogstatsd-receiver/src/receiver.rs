@@ -0,0 +1,359 @@
+use std::{
+    io::{self, Read as _},
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixDatagram, UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use crate::parse::{parse_line, Message, ParseError};
+
+/// How long background threads block on a socket before checking if the receiver has been dropped.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Largest payload accepted over datagram sockets.
+const MAX_DATAGRAM_LEN: usize = 65_536;
+
+#[derive(Default)]
+struct Inbox {
+    messages: Vec<Message>,
+    errors: Vec<ParseError>,
+}
+
+#[derive(Default)]
+struct Shared {
+    stopped: AtomicBool,
+    inbox: Mutex<Inbox>,
+    received: Condvar,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Shared {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    fn spawn<F>(self: &Arc<Self>, f: F)
+    where
+        F: FnOnce(Arc<Self>) + Send + 'static,
+    {
+        let shared = Arc::clone(self);
+        let handle = thread::spawn(move || f(shared));
+        self.threads.lock().unwrap_or_else(PoisonError::into_inner).push(handle);
+    }
+
+    /// Parses every message in the payload, recording messages and errors alike.
+    fn process_payload(&self, payload: &[u8]) {
+        let mut inbox = self.inbox.lock().unwrap_or_else(PoisonError::into_inner);
+        match std::str::from_utf8(payload) {
+            Ok(payload) => {
+                for line in payload.lines().filter(|line| !line.is_empty()) {
+                    match parse_line(line) {
+                        Ok(message) => inbox.messages.push(message),
+                        Err(e) => inbox.errors.push(e),
+                    }
+                }
+            }
+            Err(_) => inbox.errors.push(ParseError::InvalidUtf8),
+        }
+        drop(inbox);
+
+        self.received.notify_all();
+    }
+}
+
+/// A local DogStatsD receiver.
+///
+/// Payloads are received and parsed on background threads, and the resulting messages are buffered until they are
+/// taken. Messages which could not be parsed are recorded as errors, rather than stopping the receiver.
+///
+/// Background threads are stopped, and any socket file is removed, when the receiver is dropped.
+pub struct Receiver {
+    address: String,
+    shared: Arc<Shared>,
+    #[cfg(unix)]
+    socket_path: Option<PathBuf>,
+}
+
+impl Receiver {
+    /// Binds a receiver to a UDP socket on an ephemeral port on the loopback interface.
+    ///
+    /// # Errors
+    ///
+    /// If the socket cannot be bound, an error is returned.
+    pub fn bind_udp() -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let address = format!("udp://{}", socket.local_addr()?);
+
+        let shared = Arc::new(Shared::default());
+        shared.spawn(move |shared| {
+            let mut buf = vec![0; MAX_DATAGRAM_LEN];
+            while !shared.is_stopped() {
+                match socket.recv(&mut buf) {
+                    Ok(len) => shared.process_payload(&buf[..len]),
+                    Err(e) if is_timeout(&e) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            address,
+            shared,
+            #[cfg(unix)]
+            socket_path: None,
+        })
+    }
+
+    /// Binds a receiver to a Unix datagram socket at the given path.
+    ///
+    /// # Errors
+    ///
+    /// If the socket cannot be bound, an error is returned.
+    #[cfg(unix)]
+    pub fn bind_unixgram<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let shared = Arc::new(Shared::default());
+        shared.spawn(move |shared| {
+            let mut buf = vec![0; MAX_DATAGRAM_LEN];
+            while !shared.is_stopped() {
+                match socket.recv(&mut buf) {
+                    Ok(len) => shared.process_payload(&buf[..len]),
+                    Err(e) if is_timeout(&e) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            address: format!("unixgram://{}", path.display()),
+            shared,
+            socket_path: Some(path),
+        })
+    }
+
+    /// Binds a receiver to a Unix stream socket at the given path.
+    ///
+    /// Each payload must be prefixed with its length, as a 32-bit little-endian integer. Any number of clients can
+    /// connect, and reconnect, over the lifetime of the receiver.
+    ///
+    /// # Errors
+    ///
+    /// If the socket cannot be bound, an error is returned.
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let shared = Arc::new(Shared::default());
+        shared.spawn(move |shared| {
+            while !shared.is_stopped() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        shared.spawn(move |shared| read_length_prefixed(&shared, stream));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self { address: format!("unix://{}", path.display()), shared, socket_path: Some(path) })
+    }
+
+    /// Returns the address of the receiver.
+    ///
+    /// The address includes the transport scheme, such as `udp://127.0.0.1:9125` or `unix:///tmp/dsd.sock`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns a copy of all messages received so far, without removing them.
+    pub fn messages(&self) -> Vec<Message> {
+        self.shared.inbox.lock().unwrap_or_else(PoisonError::into_inner).messages.clone()
+    }
+
+    /// Removes and returns all messages received so far.
+    pub fn take_messages(&self) -> Vec<Message> {
+        std::mem::take(
+            &mut self.shared.inbox.lock().unwrap_or_else(PoisonError::into_inner).messages,
+        )
+    }
+
+    /// Waits until at least `count` messages have been received, and then removes and returns all of them.
+    ///
+    /// If `timeout` elapses first, whatever messages have been received so far are returned.
+    pub fn wait_for_messages(&self, count: usize, timeout: Duration) -> Vec<Message> {
+        let deadline = Instant::now() + timeout;
+        let mut inbox = self.shared.inbox.lock().unwrap_or_else(PoisonError::into_inner);
+        while inbox.messages.len() < count {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+
+            inbox = self
+                .shared
+                .received
+                .wait_timeout(inbox, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        std::mem::take(&mut inbox.messages)
+    }
+
+    /// Returns a copy of all errors encountered while parsing received payloads.
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.shared.inbox.lock().unwrap_or_else(PoisonError::into_inner).errors.clone()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+
+        // Connection threads can be spawned while we're joining, so keep going until there are none left.
+        loop {
+            let threads = std::mem::take(
+                &mut *self.shared.threads.lock().unwrap_or_else(PoisonError::into_inner),
+            );
+            if threads.is_empty() {
+                break;
+            }
+
+            for thread in threads {
+                let _ = thread.join();
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads length-prefixed payloads from a stream until it is closed or the receiver is dropped.
+#[cfg(unix)]
+fn read_length_prefixed(shared: &Shared, mut stream: UnixStream) {
+    if stream.set_nonblocking(false).is_err()
+        || stream.set_read_timeout(Some(POLL_INTERVAL)).is_err()
+    {
+        return;
+    }
+
+    // We buffer everything we read and only process complete frames, since a read timeout can land in the middle of
+    // a frame.
+    let mut buf = Vec::new();
+    let mut chunk = vec![0; MAX_DATAGRAM_LEN];
+    while !shared.is_stopped() {
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+            Err(e) if is_timeout(&e) => continue,
+            Err(_) => break,
+        }
+
+        let mut offset = 0;
+        while let Some(prefix) = buf.get(offset..offset + 4) {
+            let frame_len = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+            let Some(frame) = buf.get(offset + 4..offset + 4 + frame_len) else {
+                break;
+            };
+
+            shared.process_payload(frame);
+            offset += 4 + frame_len;
+        }
+        buf.drain(..offset);
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::Receiver;
+    use crate::{Message, MetricValues, ParseError};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn receives_over_udp() {
+        let receiver = Receiver::bind_udp().unwrap();
+        let address = receiver.address().strip_prefix("udp://").unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"a:1|c\nb:2:3|h|#env:prod\nbogus\n", address).unwrap();
+
+        let messages = receiver.wait_for_messages(2, TIMEOUT);
+        assert_eq!(messages.len(), 2);
+        let Message::Metric(metric) = &messages[1] else { panic!("expected a metric") };
+        assert_eq!(metric.name, "b");
+        assert_eq!(metric.values, MetricValues::Histogram(vec![2.0, 3.0]));
+        assert_eq!(metric.tag("env"), Some("prod"));
+
+        assert_eq!(receiver.errors(), [ParseError::Malformed("bogus".to_string())]);
+        assert!(receiver.take_messages().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn receives_over_unix_sockets() {
+        use std::{
+            io::Write as _,
+            os::unix::net::{UnixDatagram, UnixStream},
+        };
+
+        let dir =
+            std::env::temp_dir().join(format!("metrics-dogstatsd-receiver-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let dgram_path = dir.join("dsd-dgram.sock");
+        let receiver = Receiver::bind_unixgram(&dgram_path).unwrap();
+        assert_eq!(receiver.address(), format!("unixgram://{}", dgram_path.display()));
+        UnixDatagram::unbound().unwrap().send_to(b"a:1|g\n", &dgram_path).unwrap();
+        assert_eq!(receiver.wait_for_messages(1, TIMEOUT).len(), 1);
+        drop(receiver);
+        assert!(!dgram_path.exists());
+
+        let stream_path = dir.join("dsd-stream.sock");
+        let receiver = Receiver::bind_unix(&stream_path).unwrap();
+        let mut stream = UnixStream::connect(&stream_path).unwrap();
+
+        // Frames split across writes are reassembled before being parsed.
+        let payload = b"a:1|c\nb:x|s\n";
+        let mut framed = u32::try_from(payload.len()).unwrap().to_le_bytes().to_vec();
+        framed.extend_from_slice(payload);
+        stream.write_all(&framed[..6]).unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        stream.write_all(&framed[6..]).unwrap();
+
+        let messages = receiver.wait_for_messages(2, TIMEOUT);
+        assert_eq!(messages.len(), 2);
+        let Message::Metric(metric) = &messages[1] else { panic!("expected a metric") };
+        assert_eq!(metric.values, MetricValues::Set(vec!["x".to_string()]));
+
+        drop(receiver);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,13 +1,18 @@
-use std::{fmt, net::SocketAddr, sync::Arc, time::Duration};
+use std::{env, fmt, net::SocketAddr, sync::Arc, time::Duration};
 
 use metrics::Label;
 use thiserror::Error;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::{
-    forwarder::{self, ForwarderConfiguration, RemoteAddr},
-    recorder::DogStatsDRecorder,
+    forwarder::{self, Control, ForwarderConfiguration, RemoteAddr},
+    matcher::PrefixMatcher,
+    origin,
+    recorder::{DogStatsDHandle, DogStatsDRecorder},
+    sampling::SampleRates,
+    sanitize::Sanitizer,
     state::{State, StateConfiguration},
+    storage::HistogramOverrides,
 };
 
 // Maximum data length for a UDP datagram.
@@ -20,6 +25,15 @@ const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
 const DEFAULT_FLUSH_INTERVAL_CONSERVATIVE: Duration = Duration::from_secs(3);
 const DEFAULT_FLUSH_INTERVAL_AGGRESSIVE: Duration = Duration::from_secs(10);
 const DEFAULT_HISTOGRAM_RESERVOIR_SIZE: usize = 1024;
+const DEFAULT_DOGSTATSD_PORT: &str = "8125";
+
+// Environment variables used by the official DogStatsD clients, which we honor in `DogStatsDBuilder::from_env`.
+const AGENT_HOST_ENV_VAR: &str = "DD_AGENT_HOST";
+const DOGSTATSD_PORT_ENV_VAR: &str = "DD_DOGSTATSD_PORT";
+const DOGSTATSD_URL_ENV_VAR: &str = "DD_DOGSTATSD_URL";
+const TAGS_ENV_VAR: &str = "DD_TAGS";
+const UNIFIED_SERVICE_TAGGING_ENV_VARS: [(&str, &str); 3] =
+    [("DD_ENV", "env"), ("DD_SERVICE", "service"), ("DD_VERSION", "version")];
 
 /// Errors that could occur while building or installing a DogStatsD recorder/exporter.
 #[derive(Debug, Error, Eq, PartialEq)]
@@ -85,12 +99,122 @@ impl fmt::Display for AggregationMode {
     }
 }
 
+/// Gauge aggregation.
+///
+/// Controls how the values a gauge takes between flushes are reduced to the values that are sent. By default, only the
+/// last value is sent. See [`DogStatsDBuilder::with_gauge_aggregation`] for more information.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GaugeAggregation {
+    /// The last value.
+    Last,
+
+    /// The minimum value.
+    Min,
+
+    /// The maximum value.
+    Max,
+
+    /// The average of all values.
+    Avg,
+
+    /// The sum of all values.
+    Sum,
+}
+
+impl GaugeAggregation {
+    /// Returns the suffix used for the metric name when sending multiple aggregations of the same gauge.
+    pub(crate) const fn suffix(self) -> &'static str {
+        match self {
+            GaugeAggregation::Last => "last",
+            GaugeAggregation::Min => "min",
+            GaugeAggregation::Max => "max",
+            GaugeAggregation::Avg => "avg",
+            GaugeAggregation::Sum => "sum",
+        }
+    }
+}
+
+/// Handling of reserved characters in metric names and tags.
+///
+/// Characters which delimit the fields of a metric -- `:` and `|` in metric names and tag keys, and `|`, `,`, `#`, and
+/// newlines in tags -- would otherwise corrupt the payload, so they are either replaced or removed before being sent.
+///
+/// Dialects which carry tags in the metric name reserve the characters that delimit those tags as well, such as `;` and
+/// `=` for [`WireDialect::Graphite`], or `.` in tags for [`WireDialect::Etsy`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReservedCharacters {
+    /// Reserved characters are replaced with an underscore.
+    #[default]
+    Replace,
+
+    /// Reserved characters are removed.
+    Strip,
+}
+
+/// Wire dialect.
+///
+/// Controls how metrics are serialized, which allows sending metrics to servers that understand the StatsD protocol but
+/// not the DogStatsD extensions to it, such as Telegraf or `statsd_exporter`. The transport, aggregation, and flushing
+/// behavior are the same for all dialects.
+///
+/// DogStatsD extensions -- multiple values per metric, timestamps, container IDs, service checks, and events -- are only
+/// sent when using [`WireDialect::DogStatsD`]. For all other dialects, values are sent one per metric, and service
+/// checks and events are dropped.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WireDialect {
+    /// DogStatsD, with tags in the form of `name:1|c|#tag:value`.
+    #[default]
+    DogStatsD,
+
+    /// Plain Etsy StatsD, without tags.
+    ///
+    /// Tags are folded into the metric name instead, in the form of `name.tag.value:1|c`.
+    Etsy,
+
+    /// Graphite-style tags, in the form of `name;tag=value:1|c`.
+    Graphite,
+
+    /// InfluxDB-style tags, in the form of `name,tag=value:1|c`.
+    Influx,
+
+    /// SignalFx-style tags, in the form of `name[tag=value]:1|c`.
+    SignalFx,
+}
+
+impl WireDialect {
+    /// Returns the characters which are reserved in metric names in addition to those reserved by every dialect, as
+    /// they would otherwise be mistaken for the start of the tags.
+    pub(crate) const fn reserved_in_names(self) -> &'static [char] {
+        match self {
+            WireDialect::DogStatsD | WireDialect::Etsy => &[],
+            WireDialect::Graphite => &[';'],
+            WireDialect::Influx => &[','],
+            WireDialect::SignalFx => &['['],
+        }
+    }
+
+    /// Returns the characters which are reserved in tags in addition to those reserved by every dialect.
+    ///
+    /// Dialects other than DogStatsD carry tags in the metric name, so the name/value separator, the tag delimiters,
+    /// and the `:` that ends the name are all reserved.
+    pub(crate) const fn reserved_in_tags(self) -> &'static [char] {
+        match self {
+            WireDialect::DogStatsD => &[],
+            WireDialect::Etsy => &[':', '.'],
+            WireDialect::Graphite => &[':', ';', '='],
+            WireDialect::Influx => &[':', ',', '='],
+            WireDialect::SignalFx => &[':', '[', ']', ',', '='],
+        }
+    }
+}
+
 /// Builder for a DogStatsD exporter.
 #[derive(Debug)]
 pub struct DogStatsDBuilder {
     remote_addr: RemoteAddr,
     write_timeout: Duration,
     max_payload_len: Option<usize>,
+    retry_buffer_len: usize,
     flush_interval: Option<Duration>,
     synchronous: bool,
     agg_mode: AggregationMode,
@@ -98,11 +222,109 @@ pub struct DogStatsDBuilder {
     histogram_sampling: bool,
     histogram_reservoir_size: usize,
     histograms_as_distributions: bool,
+    histogram_overrides: HistogramOverrides,
+    sample_rates: SampleRates,
+    gauge_aggregations: PrefixMatcher<Vec<GaugeAggregation>>,
     global_labels: Vec<Label>,
+    env_labels: Vec<Label>,
     global_prefix: Option<String>,
+    origin_detection: bool,
+    dialect: WireDialect,
+    sanitizer: Sanitizer,
 }
 
 impl DogStatsDBuilder {
+    /// Creates a builder configured from the standard DogStatsD environment variables.
+    ///
+    /// The following environment variables are supported, matching the official DogStatsD clients:
+    ///
+    /// - `DD_DOGSTATSD_URL`: the remote address, in any format supported by
+    ///   [`with_remote_address`][Self::with_remote_address], such as `udp://localhost:8125` or
+    ///   `unix:///var/run/datadog/dsd.socket`.
+    /// - `DD_AGENT_HOST` and `DD_DOGSTATSD_PORT`: the host and port of the remote address, when `DD_DOGSTATSD_URL` is
+    ///   not set. The port defaults to 8125.
+    /// - `DD_ENV`, `DD_SERVICE`, and `DD_VERSION`: [unified service tags][ust], added as the `env`, `service`, and
+    ///   `version` global labels.
+    /// - `DD_TAGS`: additional global labels, as a comma or space-separated list of `key:value` pairs.
+    ///
+    /// Any setting not provided by the environment uses its default value. Explicit calls on the returned builder take
+    /// precedence over the environment, including global labels from [`with_global_labels`][Self::with_global_labels],
+    /// which replace any labels from the environment with the same key.
+    ///
+    /// # Errors
+    ///
+    /// If the remote address derived from the environment is not valid, an error will be returned.
+    ///
+    /// [ust]: https://docs.datadoghq.com/getting_started/tagging/unified_service_tagging/
+    pub fn from_env() -> Result<Self, BuildError> {
+        Self::default().with_env(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn with_env<F>(mut self, get_var: F) -> Result<Self, BuildError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(url) = get_var(DOGSTATSD_URL_ENV_VAR) {
+            self = self.with_remote_address(url)?;
+        } else if let Some(host) = get_var(AGENT_HOST_ENV_VAR) {
+            let port = get_var(DOGSTATSD_PORT_ENV_VAR);
+            let port = port.as_deref().unwrap_or(DEFAULT_DOGSTATSD_PORT);
+            if port.parse::<u16>().is_err() {
+                return Err(BuildError::InvalidConfiguration {
+                    reason: format!("invalid port in {DOGSTATSD_PORT_ENV_VAR}: '{port}'"),
+                });
+            }
+
+            // Bare IPv6 addresses need to be bracketed before a port can be added.
+            let addr = if host.contains(':') && !host.starts_with('[') {
+                format!("[{host}]:{port}")
+            } else {
+                format!("{host}:{port}")
+            };
+            self = self.with_remote_address(addr)?;
+        }
+
+        for (var, key) in UNIFIED_SERVICE_TAGGING_ENV_VARS {
+            if let Some(value) = get_var(var) {
+                self.env_labels.push(Label::new(key, value));
+            }
+        }
+
+        if let Some(tags) = get_var(TAGS_ENV_VAR) {
+            let tags =
+                tags.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty());
+            for tag in tags {
+                let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                self.env_labels.push(Label::new(key.to_string(), value.to_string()));
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Returns the global labels, with any labels from the environment not overridden by an explicit global label.
+    ///
+    /// Labels which would be dropped as invalid when written are removed here, so that they're only reported once
+    /// rather than on every flush.
+    fn get_global_labels(&self) -> Vec<Label> {
+        let mut labels = self.global_labels.clone();
+        for label in &self.env_labels {
+            if !self.global_labels.iter().any(|global| global.key() == label.key()) {
+                labels.push(label.clone());
+            }
+        }
+
+        let reserved = self.dialect.reserved_in_tags();
+        labels.retain(|label| {
+            let is_valid = self.sanitizer.is_valid_tag(label, reserved);
+            if !is_valid {
+                warn!(key = label.key(), "Dropping invalid global label.");
+            }
+            is_valid
+        });
+        labels
+    }
+
     fn get_max_payload_len(&self) -> usize {
         self.max_payload_len.unwrap_or_else(|| self.remote_addr.default_max_payload_len())
     }
@@ -193,6 +415,22 @@ impl DogStatsDBuilder {
         Ok(self)
     }
 
+    /// Sets the maximum length of the retry buffer, in bytes.
+    ///
+    /// When enabled, payloads which fail to send are kept in memory and retried, in order, before any newer payloads
+    /// are sent, while reconnection attempts are backed off exponentially. This allows metrics to survive a slow or
+    /// briefly unavailable remote server, such as when the Datadog Agent is restarting, which is particularly useful
+    /// with Unix domain stream sockets (`unix://`). When the buffer is full, the oldest payloads are dropped first.
+    ///
+    /// Buffered and dropped payloads are reported through the [telemetry][Self::with_telemetry] metrics.
+    ///
+    /// Defaults to 0, which disables the retry buffer, so that payloads which fail to send are dropped immediately.
+    #[must_use]
+    pub fn with_retry_buffer_length(mut self, max_len: usize) -> Self {
+        self.retry_buffer_len = max_len;
+        self
+    }
+
     /// Use a synchronous backend for forwarding metrics.
     ///
     /// A background OS thread will be spawned to handle forwarding metrics to the remote server.
@@ -204,6 +442,21 @@ impl DogStatsDBuilder {
         self
     }
 
+    /// Use an asynchronous backend for forwarding metrics.
+    ///
+    /// A background task will be spawned on the current Tokio runtime to handle forwarding metrics to the remote
+    /// server, instead of a dedicated OS thread. The exporter must be built from within the context of a Tokio runtime
+    /// with both the I/O and time drivers enabled.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "async-runtime")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-runtime")))]
+    #[must_use]
+    pub fn with_asynchronous_backend(mut self) -> Self {
+        self.synchronous = false;
+        self
+    }
+
     /// Set the aggregation mode for the exporter.
     ///
     /// Counters and gauges are always aggregated locally before forwarding to the Datadog Agent, but the aggregation
@@ -292,6 +545,20 @@ impl DogStatsDBuilder {
         self
     }
 
+    /// Sets whether or not to enable histogram sampling for histograms whose name starts with the given prefix.
+    ///
+    /// This overrides [`with_histogram_sampling`][Self::with_histogram_sampling] for matching histograms. When multiple
+    /// prefixes match a metric name, the longest prefix wins. Overrides are applied when a histogram is first
+    /// registered, so they must be configured before building the exporter.
+    #[must_use]
+    pub fn with_histogram_sampling_for<P>(mut self, prefix: P, histogram_sampling: bool) -> Self
+    where
+        P: Into<String>,
+    {
+        self.histogram_overrides.sampling.insert(prefix.into(), histogram_sampling);
+        self
+    }
+
     /// Sets the reservoir size for histogram sampling.
     ///
     /// Defaults to 1,024.
@@ -301,6 +568,21 @@ impl DogStatsDBuilder {
         self
     }
 
+    /// Sets the reservoir size for histogram sampling for histograms whose name starts with the given prefix.
+    ///
+    /// This overrides [`with_histogram_reservoir_size`][Self::with_histogram_reservoir_size] for matching histograms,
+    /// and only has an effect when histogram sampling is enabled for them. When multiple prefixes match a metric name,
+    /// the longest prefix wins. Overrides are applied when a histogram is first registered, so they must be configured
+    /// before building the exporter.
+    #[must_use]
+    pub fn with_histogram_reservoir_size_for<P>(mut self, prefix: P, reservoir_size: usize) -> Self
+    where
+        P: Into<String>,
+    {
+        self.histogram_overrides.reservoir_size.insert(prefix.into(), reservoir_size);
+        self
+    }
+
     /// Sets whether or not to send histograms as distributions.
     ///
     /// When enabled, histograms will be sent as distributions to the remote server. This changes the default behavior
@@ -319,6 +601,191 @@ impl DogStatsDBuilder {
         self
     }
 
+    /// Sets whether or not to send histograms whose name starts with the given prefix as distributions.
+    ///
+    /// This overrides [`send_histograms_as_distributions`][Self::send_histograms_as_distributions] for matching
+    /// histograms, which allows sending only the histograms that need global percentiles as distributions, while
+    /// cheaper histograms are aggregated by the Datadog Agent. When multiple prefixes match a metric name, the longest
+    /// prefix wins. Overrides are applied when a histogram is first registered, so they must be configured before
+    /// building the exporter.
+    #[must_use]
+    pub fn send_histograms_as_distributions_for<P>(
+        mut self,
+        prefix: P,
+        histograms_as_distributions: bool,
+    ) -> Self
+    where
+        P: Into<String>,
+    {
+        self.histogram_overrides
+            .as_distributions
+            .insert(prefix.into(), histograms_as_distributions);
+        self
+    }
+
+    /// Sets the client-side sample rate for counters and histograms whose name starts with the given prefix.
+    ///
+    /// Sampled metrics randomly drop updates on the client side, keeping only the given fraction of them, and send the
+    /// sample rate along with the metric (`|@<rate>`) so that the Datadog Agent can scale the values back up. This can
+    /// significantly reduce overhead for metrics updated on very hot paths, at the cost of precision. Counters updated
+    /// with absolute values are never sampled.
+    ///
+    /// When multiple prefixes match a metric name, the longest prefix wins. Setting a sample rate of 1.0 disables
+    /// sampling for matching metrics. Sample rates are applied when a metric is first registered, so they must be
+    /// configured before building the exporter.
+    ///
+    /// This is independent of [histogram sampling][Self::with_histogram_sampling], which bounds the memory used by
+    /// each histogram: when both are in use, the sample rates are combined.
+    ///
+    /// # Errors
+    ///
+    /// If the sample rate is not greater than 0.0 and less than or equal to 1.0, an error will be returned.
+    pub fn with_sample_rate<P>(mut self, prefix: P, sample_rate: f64) -> Result<Self, BuildError>
+    where
+        P: Into<String>,
+    {
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(BuildError::InvalidConfiguration {
+                reason: format!("sample rate must be in the range (0.0, 1.0], got {sample_rate}"),
+            });
+        }
+
+        self.sample_rates.insert(prefix.into(), sample_rate);
+        Ok(self)
+    }
+
+    /// Sets how gauges whose name starts with the given prefix are aggregated between flushes.
+    ///
+    /// By default, gauges only keep their last value between flushes. This is a poor fit for gauges that are updated
+    /// far more often than they are flushed, such as sampled resource usage, where short spikes would go unnoticed.
+    /// Instead, the minimum, maximum, average, and sum of all the values a gauge has taken since the last flush can be
+    /// sent.
+    ///
+    /// When a single aggregation is given, it's sent under the gauge's name. When multiple aggregations are given, each
+    /// is sent as a separate gauge, with the aggregation appended to the gauge's name: for example, `.max` and `.avg`
+    /// for [`GaugeAggregation::Max`] and [`GaugeAggregation::Avg`]. If a gauge isn't updated between flushes, all
+    /// aggregations use its current value.
+    ///
+    /// When multiple prefixes match a metric name, the longest prefix wins. Aggregations are applied when a gauge is
+    /// first registered, so they must be configured before building the exporter.
+    ///
+    /// # Errors
+    ///
+    /// If no aggregations are given, an error will be returned.
+    pub fn with_gauge_aggregation<P>(
+        mut self,
+        prefix: P,
+        aggregations: &[GaugeAggregation],
+    ) -> Result<Self, BuildError>
+    where
+        P: Into<String>,
+    {
+        if aggregations.is_empty() {
+            return Err(BuildError::InvalidConfiguration {
+                reason: "at least one gauge aggregation must be given".to_string(),
+            });
+        }
+
+        let mut deduplicated = Vec::with_capacity(aggregations.len());
+        for aggregation in aggregations {
+            if !deduplicated.contains(aggregation) {
+                deduplicated.push(*aggregation);
+            }
+        }
+
+        self.gauge_aggregations.insert(prefix.into(), deduplicated);
+        Ok(self)
+    }
+
+    /// Sets whether or not to enable origin detection.
+    ///
+    /// When enabled, the ID of the container the application is running in is discovered from `/proc/self/cgroup` and
+    /// `/proc/self/mountinfo`, and sent with every payload, which allows the Datadog Agent to tag metrics with
+    /// information about the container, pod, and so on.
+    ///
+    /// Origin detection is also disabled if the `DD_ORIGIN_DETECTION_ENABLED` environment variable is set to `false`, or
+    /// if the `DD_ENTITY_ID` environment variable is set. In the latter case, the entity ID is always sent as the
+    /// `dd.internal.entity_id` label instead, as it identifies the origin more precisely.
+    ///
+    /// Defaults to `true`.
+    #[must_use]
+    pub fn with_origin_detection(mut self, origin_detection: bool) -> Self {
+        self.origin_detection = origin_detection;
+        self
+    }
+
+    /// Sets the wire dialect used to serialize metrics.
+    ///
+    /// See [`WireDialect`] for more details.
+    ///
+    /// Defaults to [`WireDialect::DogStatsD`].
+    #[must_use]
+    pub fn with_wire_dialect(mut self, dialect: WireDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets how reserved characters in metric names and tags are handled.
+    ///
+    /// See [`ReservedCharacters`] for more details. Tags whose key is empty once reserved characters are removed are
+    /// dropped, and counted as serializer failures in the exporter's telemetry.
+    ///
+    /// Defaults to [`ReservedCharacters::Replace`].
+    #[must_use]
+    pub fn with_reserved_characters(mut self, reserved_chars: ReservedCharacters) -> Self {
+        self.sanitizer.reserved_chars = reserved_chars;
+        self
+    }
+
+    /// Sets whether or not to lowercase tag keys.
+    ///
+    /// Datadog treats tags as case-insensitive, so lowercasing tag keys on the client side avoids the same tag showing up
+    /// under multiple spellings.
+    ///
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn with_lowercase_tags(mut self, lowercase_tags: bool) -> Self {
+        self.sanitizer.lowercase_tags = lowercase_tags;
+        self
+    }
+
+    /// Sets the maximum length of a tag, in characters.
+    ///
+    /// Tags longer than this are truncated, which includes both the key and the value. If the key alone is as long as
+    /// the maximum length, it is truncated and the value is dropped.
+    ///
+    /// Defaults to 200 characters, which is the maximum length of a tag in Datadog.
+    ///
+    /// # Errors
+    ///
+    /// If the maximum length is zero, an error will be returned.
+    pub fn with_maximum_tag_length(mut self, max_len: usize) -> Result<Self, BuildError> {
+        if max_len == 0 {
+            return Err(BuildError::InvalidConfiguration {
+                reason: "maximum tag length must be greater than zero".to_string(),
+            });
+        }
+
+        self.sanitizer.max_tag_len = max_len;
+        Ok(self)
+    }
+
+    /// Sets whether or not to validate metric names.
+    ///
+    /// When enabled, metrics whose names -- including any global prefix -- don't start with a letter, contain
+    /// characters other than ASCII alphanumerics, underscores, and periods, or are longer than 200 characters, are
+    /// dropped instead of being sent, and counted as serializer failures in the exporter's telemetry. Datadog would
+    /// otherwise rename or reject these metrics.
+    ///
+    /// Metric names are always checked for reserved characters, regardless of this setting.
+    ///
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn with_metric_name_validation(mut self, validate_metric_names: bool) -> Self {
+        self.sanitizer.validate_metric_names = validate_metric_names;
+        self
+    }
+
     /// Builds the recorder.
     ///
     /// The configured backend will be spawned to forward metrics to the remote server, but the recorder must be
@@ -333,6 +800,7 @@ impl DogStatsDBuilder {
 
         let max_payload_len = self.get_max_payload_len();
         let flush_interval = self.get_flush_interval();
+        let mut global_labels = self.get_global_labels();
 
         debug!(
             agg_mode = %self.agg_mode,
@@ -347,13 +815,14 @@ impl DogStatsDBuilder {
             histogram_sampling: self.histogram_sampling,
             histogram_reservoir_size: self.histogram_reservoir_size,
             histograms_as_distributions: self.histograms_as_distributions,
+            histogram_overrides: self.histogram_overrides,
             global_prefix: self.global_prefix,
+            sample_rates: self.sample_rates,
+            gauge_aggregations: self.gauge_aggregations,
         };
 
         let state = Arc::new(State::new(state_config));
 
-        let recorder = DogStatsDRecorder::new(Arc::clone(&state));
-
         debug!(
             remote_addr = %self.remote_addr,
             max_payload_len,
@@ -361,44 +830,83 @@ impl DogStatsDBuilder {
             write_timeout = ?self.write_timeout,
             "Building DogStatsD forwarder."
         );
+        let entity_id = origin::entity_id();
+        let container_id =
+            if self.origin_detection && entity_id.is_none() && origin::enabled_by_env() {
+                origin::container_id()
+            } else {
+                None
+            };
+        debug!(?entity_id, ?container_id, "Resolved origin detection information.");
+
+        if let Some(entity_id) = entity_id {
+            global_labels.push(Label::new(origin::ENTITY_ID_LABEL, entity_id));
+        }
+
         let forwarder_config = ForwarderConfiguration {
             remote_addr: self.remote_addr,
             max_payload_len,
+            retry_buffer_len: self.retry_buffer_len,
             flush_interval,
             write_timeout: self.write_timeout,
-            global_labels: self.global_labels,
+            global_labels,
+            container_id,
+            dialect: self.dialect,
+            sanitizer: self.sanitizer,
         };
+        let control = Arc::new(Control::new(forwarder_config, state));
 
         if self.synchronous {
             debug!("Spawning synchronous forwarder backend.");
 
-            let forwarder = forwarder::sync::Forwarder::new(forwarder_config, state);
+            let forwarder = forwarder::sync::Forwarder::new(Arc::clone(&control));
 
-            std::thread::Builder::new()
+            let thread = std::thread::Builder::new()
                 .name("metrics-exporter-dogstatsd-forwarder".to_string())
                 .spawn(move || forwarder.run())
                 .map_err(|_| BuildError::Backend)?;
+            control.set_thread(thread);
         } else {
-            unreachable!("Asynchronous backend should not be configurable yet.");
+            #[cfg(feature = "async-runtime")]
+            {
+                debug!("Spawning asynchronous forwarder backend.");
+
+                let handle = tokio::runtime::Handle::try_current().map_err(|_| {
+                    BuildError::InvalidConfiguration {
+                        reason: "asynchronous backend must be built within a Tokio runtime"
+                            .to_string(),
+                    }
+                })?;
+
+                let forwarder = forwarder::tokio::Forwarder::new(Arc::clone(&control));
+                handle.spawn(forwarder.run());
+            }
+
+            #[cfg(not(feature = "async-runtime"))]
+            unreachable!("asynchronous backend requires the `async-runtime` feature");
         }
 
-        Ok(recorder)
+        Ok(DogStatsDRecorder::new(control))
     }
 
     /// Builds and installs the recorder.
     ///
     /// The configured backend will be spawned to forward metrics to the remote server, and the recorder will be
-    /// installed as the global recorder.
+    /// installed as the global recorder. A handle to the exporter is returned, which can be used to flush the exporter
+    /// on demand and to shut it down.
     ///
     /// # Errors
     ///
     /// If the exporter is configured to use an asynchronous backend but is not built in the context of an asynchronous
     /// runtime, or if the maximum payload length is not valid for the underlying transport, or if a global recorder is
     /// already installed, an error will be returned.
-    pub fn install(self) -> Result<(), BuildError> {
+    pub fn install(self) -> Result<DogStatsDHandle, BuildError> {
         let recorder = self.build()?;
+        let handle = recorder.handle();
+
+        metrics::set_global_recorder(recorder).map_err(|_| BuildError::FailedToInstall)?;
 
-        metrics::set_global_recorder(recorder).map_err(|_| BuildError::FailedToInstall)
+        Ok(handle)
     }
 }
 
@@ -408,6 +916,7 @@ impl Default for DogStatsDBuilder {
             remote_addr: RemoteAddr::Udp(vec![SocketAddr::from(([127, 0, 0, 1], 8125))]),
             write_timeout: DEFAULT_WRITE_TIMEOUT,
             max_payload_len: None,
+            retry_buffer_len: 0,
             flush_interval: None,
             synchronous: true,
             agg_mode: AggregationMode::Conservative,
@@ -415,8 +924,15 @@ impl Default for DogStatsDBuilder {
             histogram_sampling: false,
             histogram_reservoir_size: DEFAULT_HISTOGRAM_RESERVOIR_SIZE,
             histograms_as_distributions: true,
+            histogram_overrides: HistogramOverrides::default(),
+            sample_rates: SampleRates::default(),
+            gauge_aggregations: PrefixMatcher::default(),
             global_labels: Vec::default(),
+            env_labels: Vec::default(),
             global_prefix: Option::default(),
+            origin_detection: true,
+            dialect: WireDialect::default(),
+            sanitizer: Sanitizer::default(),
         }
     }
 }
@@ -466,6 +982,135 @@ mod tests {
         );
     }
 
+    fn env_lookup<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |name| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| (*value).to_string())
+    }
+
+    #[test]
+    fn sample_rate_range() {
+        for sample_rate in [0.0, -0.5, 1.5, f64::NAN] {
+            let result = DogStatsDBuilder::default().with_sample_rate("http.", sample_rate);
+            assert!(
+                matches!(result, Err(BuildError::InvalidConfiguration { .. })),
+                "sample rate {sample_rate} should be rejected"
+            );
+        }
+
+        let builder = DogStatsDBuilder::default().with_sample_rate("http.", 0.1).unwrap();
+        assert_eq!(builder.sample_rates.get("http.requests"), Some(0.1));
+    }
+
+    #[test]
+    fn gauge_aggregation() {
+        let result = DogStatsDBuilder::default().with_gauge_aggregation("system.", &[]);
+        assert!(matches!(result, Err(BuildError::InvalidConfiguration { .. })));
+
+        let builder = DogStatsDBuilder::default()
+            .with_gauge_aggregation(
+                "system.",
+                &[GaugeAggregation::Max, GaugeAggregation::Avg, GaugeAggregation::Max],
+            )
+            .unwrap();
+        assert_eq!(
+            builder.gauge_aggregations.get("system.cpu"),
+            Some(&vec![GaugeAggregation::Max, GaugeAggregation::Avg])
+        );
+        assert_eq!(builder.gauge_aggregations.get("requests"), None);
+    }
+
+    #[test]
+    fn from_env_remote_address() {
+        let builder = DogStatsDBuilder::default().with_env(env_lookup(&[])).unwrap();
+        assert_eq!(builder.remote_addr, DogStatsDBuilder::default().remote_addr);
+
+        let vars = [("DD_AGENT_HOST", "127.0.0.2")];
+        let builder = DogStatsDBuilder::default().with_env(env_lookup(&vars)).unwrap();
+        assert_eq!(builder.remote_addr.to_string(), "udp://127.0.0.2:8125");
+
+        let vars = [("DD_AGENT_HOST", "::1"), ("DD_DOGSTATSD_PORT", "9125")];
+        let builder = DogStatsDBuilder::default().with_env(env_lookup(&vars)).unwrap();
+        assert_eq!(builder.remote_addr.to_string(), "udp://[::1]:9125");
+
+        let vars = [("DD_AGENT_HOST", "127.0.0.2"), ("DD_DOGSTATSD_URL", "udp://127.0.0.3:8126")];
+        let builder = DogStatsDBuilder::default().with_env(env_lookup(&vars)).unwrap();
+        assert_eq!(builder.remote_addr.to_string(), "udp://127.0.0.3:8126");
+
+        let vars = [("DD_AGENT_HOST", "127.0.0.2"), ("DD_DOGSTATSD_PORT", "http")];
+        let result = DogStatsDBuilder::default().with_env(env_lookup(&vars));
+        assert_eq!(
+            result.unwrap_err(),
+            BuildError::InvalidConfiguration {
+                reason: "invalid port in DD_DOGSTATSD_PORT: 'http'".to_string()
+            }
+        );
+
+        let vars = [("DD_DOGSTATSD_URL", "tcp://127.0.0.3:8126")];
+        let result = DogStatsDBuilder::default().with_env(env_lookup(&vars));
+        assert!(matches!(result, Err(BuildError::InvalidRemoteAddress { .. })));
+    }
+
+    #[test]
+    fn from_env_global_labels() {
+        let vars = [
+            ("DD_ENV", "prod"),
+            ("DD_SERVICE", "checkout"),
+            ("DD_VERSION", "1.2.3"),
+            ("DD_TAGS", "team:payments, region:us-east-1 canary"),
+        ];
+        let builder = DogStatsDBuilder::default().with_env(env_lookup(&vars)).unwrap();
+        assert_eq!(
+            builder.get_global_labels(),
+            vec![
+                Label::new("env", "prod"),
+                Label::new("service", "checkout"),
+                Label::new("version", "1.2.3"),
+                Label::new("team", "payments"),
+                Label::new("region", "us-east-1"),
+                Label::new("canary", ""),
+            ]
+        );
+
+        // Explicit global labels take precedence over those from the environment.
+        let builder = builder
+            .with_global_labels(vec![Label::new("env", "staging"), Label::new("owner", "me")]);
+        assert_eq!(
+            builder.get_global_labels(),
+            vec![
+                Label::new("env", "staging"),
+                Label::new("owner", "me"),
+                Label::new("service", "checkout"),
+                Label::new("version", "1.2.3"),
+                Label::new("team", "payments"),
+                Label::new("region", "us-east-1"),
+                Label::new("canary", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_global_labels() {
+        let builder = DogStatsDBuilder::default()
+            .with_reserved_characters(ReservedCharacters::Strip)
+            .with_global_labels(vec![Label::new("|#", "x"), Label::new("env", "prod|")]);
+        assert_eq!(builder.get_global_labels(), vec![Label::new("env", "prod|")]);
+    }
+
+    #[cfg(feature = "async-runtime")]
+    #[test]
+    fn asynchronous_backend_requires_runtime() {
+        let result = DogStatsDBuilder::default().with_asynchronous_backend().build();
+        assert_eq!(
+            result.err(),
+            Some(BuildError::InvalidConfiguration {
+                reason: "asynchronous backend must be built within a Tokio runtime".to_string()
+            })
+        );
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let _guard = runtime.enter();
+        assert!(DogStatsDBuilder::default().with_asynchronous_backend().build().is_ok());
+    }
+
     #[cfg(unix)]
     mod linux {
         use super::*;
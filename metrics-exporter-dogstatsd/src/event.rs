@@ -0,0 +1,229 @@
+use metrics::Label;
+
+/// Status of a service check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServiceCheckStatus {
+    /// The service is healthy.
+    Ok,
+    /// The service is degraded.
+    Warning,
+    /// The service is unhealthy.
+    Critical,
+    /// The status of the service is unknown.
+    Unknown,
+}
+
+impl ServiceCheckStatus {
+    pub(crate) const fn as_bytes(self) -> &'static [u8] {
+        match self {
+            ServiceCheckStatus::Ok => b"0",
+            ServiceCheckStatus::Warning => b"1",
+            ServiceCheckStatus::Critical => b"2",
+            ServiceCheckStatus::Unknown => b"3",
+        }
+    }
+}
+
+/// A DogStatsD service check.
+///
+/// Service checks report the status of a service, and are used to drive monitors in Datadog.
+///
+/// Reserved characters in the name and hostname -- `|` and newlines -- are replaced or stripped when sent, as configured
+/// by [`DogStatsDBuilder::with_reserved_characters`][crate::DogStatsDBuilder::with_reserved_characters]. Service checks
+/// whose name is empty after this are dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceCheck {
+    pub(crate) name: String,
+    pub(crate) status: ServiceCheckStatus,
+    pub(crate) timestamp: Option<u64>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) labels: Vec<Label>,
+    pub(crate) message: Option<String>,
+}
+
+impl ServiceCheck {
+    /// Creates a new `ServiceCheck` with the given name and status.
+    pub fn new<N: Into<String>>(name: N, status: ServiceCheckStatus) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            timestamp: None,
+            hostname: None,
+            labels: Vec::new(),
+            message: None,
+        }
+    }
+
+    /// Sets the timestamp of the service check, in seconds since the Unix epoch.
+    ///
+    /// Defaults to the time the service check is received by the Datadog Agent.
+    #[must_use]
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the hostname of the service check.
+    ///
+    /// Defaults to the hostname of the Datadog Agent.
+    #[must_use]
+    pub fn with_hostname<H: Into<String>>(mut self, hostname: H) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Sets the labels of the service check.
+    ///
+    /// Global labels are applied in addition to these labels.
+    #[must_use]
+    pub fn with_labels(mut self, labels: Vec<Label>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Sets a message describing the status of the service check.
+    #[must_use]
+    pub fn with_message<M: Into<String>>(mut self, message: M) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+}
+
+/// Priority of an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventPriority {
+    /// Normal priority.
+    Normal,
+    /// Low priority.
+    Low,
+}
+
+impl EventPriority {
+    pub(crate) const fn as_bytes(self) -> &'static [u8] {
+        match self {
+            EventPriority::Normal => b"normal",
+            EventPriority::Low => b"low",
+        }
+    }
+}
+
+/// Alert type of an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventAlertType {
+    /// An error.
+    Error,
+    /// A warning.
+    Warning,
+    /// Informational.
+    Info,
+    /// A success.
+    Success,
+}
+
+impl EventAlertType {
+    pub(crate) const fn as_bytes(self) -> &'static [u8] {
+        match self {
+            EventAlertType::Error => b"error",
+            EventAlertType::Warning => b"warning",
+            EventAlertType::Info => b"info",
+            EventAlertType::Success => b"success",
+        }
+    }
+}
+
+/// A DogStatsD event.
+///
+/// Events are posted to the Datadog event stream, and are typically used to record notable occurrences such as
+/// deployments or failovers.
+///
+/// Reserved characters in the hostname, aggregation key, and source type name -- `|` and newlines -- are replaced or
+/// stripped when sent, as configured by
+/// [`DogStatsDBuilder::with_reserved_characters`][crate::DogStatsDBuilder::with_reserved_characters].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub(crate) title: String,
+    pub(crate) text: String,
+    pub(crate) timestamp: Option<u64>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) aggregation_key: Option<String>,
+    pub(crate) priority: Option<EventPriority>,
+    pub(crate) source_type_name: Option<String>,
+    pub(crate) alert_type: Option<EventAlertType>,
+    pub(crate) labels: Vec<Label>,
+}
+
+impl Event {
+    /// Creates a new `Event` with the given title and text.
+    pub fn new<T: Into<String>, X: Into<String>>(title: T, text: X) -> Self {
+        Self {
+            title: title.into(),
+            text: text.into(),
+            timestamp: None,
+            hostname: None,
+            aggregation_key: None,
+            priority: None,
+            source_type_name: None,
+            alert_type: None,
+            labels: Vec::new(),
+        }
+    }
+
+    /// Sets the timestamp of the event, in seconds since the Unix epoch.
+    ///
+    /// Defaults to the time the event is received by the Datadog Agent.
+    #[must_use]
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the hostname of the event.
+    ///
+    /// Defaults to the hostname of the Datadog Agent.
+    #[must_use]
+    pub fn with_hostname<H: Into<String>>(mut self, hostname: H) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Sets the aggregation key of the event, which is used to group related events together.
+    #[must_use]
+    pub fn with_aggregation_key<K: Into<String>>(mut self, aggregation_key: K) -> Self {
+        self.aggregation_key = Some(aggregation_key.into());
+        self
+    }
+
+    /// Sets the priority of the event.
+    ///
+    /// Defaults to [`EventPriority::Normal`].
+    #[must_use]
+    pub fn with_priority(mut self, priority: EventPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Sets the source type name of the event.
+    #[must_use]
+    pub fn with_source_type_name<S: Into<String>>(mut self, source_type_name: S) -> Self {
+        self.source_type_name = Some(source_type_name.into());
+        self
+    }
+
+    /// Sets the alert type of the event.
+    ///
+    /// Defaults to [`EventAlertType::Info`].
+    #[must_use]
+    pub fn with_alert_type(mut self, alert_type: EventAlertType) -> Self {
+        self.alert_type = Some(alert_type);
+        self
+    }
+
+    /// Sets the labels of the event.
+    ///
+    /// Global labels are applied in addition to these labels.
+    #[must_use]
+    pub fn with_labels(mut self, labels: Vec<Label>) -> Self {
+        self.labels = labels;
+        self
+    }
+}
@@ -3,12 +3,25 @@ use std::path::PathBuf;
 use std::{
     fmt,
     net::{SocketAddr, ToSocketAddrs as _},
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::JoinHandle,
     time::Duration,
 };
 
 use metrics::Label;
 
+use crate::{
+    builder::WireDialect,
+    sanitize::Sanitizer,
+    state::State,
+    telemetry::{Telemetry, TelemetryUpdate},
+    writer::PayloadWriter,
+};
+
+mod retry;
 pub mod sync;
+#[cfg(feature = "async-runtime")]
+pub mod tokio;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) enum RemoteAddr {
@@ -113,6 +126,11 @@ pub(crate) struct ForwarderConfiguration {
     /// Payloads may contain multiple metrics.
     pub max_payload_len: usize,
 
+    /// Maximum size, in bytes, of the buffer of payloads waiting to be retried.
+    ///
+    /// If zero, payloads which fail to send are dropped immediately.
+    pub retry_buffer_len: usize,
+
     /// Duration to wait between flushing metrics.
     pub flush_interval: Duration,
 
@@ -121,6 +139,15 @@ pub(crate) struct ForwarderConfiguration {
 
     /// Global labels to attach to all metrics.
     pub global_labels: Vec<Label>,
+
+    /// Container ID to attach to all metrics, for origin detection.
+    pub container_id: Option<String>,
+
+    /// Wire dialect used to serialize metrics.
+    pub dialect: WireDialect,
+
+    /// Sanitization applied to metric names and tags.
+    pub sanitizer: Sanitizer,
 }
 
 impl ForwarderConfiguration {
@@ -134,6 +161,167 @@ impl ForwarderConfiguration {
             RemoteAddr::Unixgram(_) => false,
         }
     }
+
+    /// Creates a new payload writer based on this configuration.
+    pub fn payload_writer(&self) -> PayloadWriter {
+        PayloadWriter::new(self.max_payload_len, self.is_length_prefixed())
+            .with_sanitizer(self.sanitizer.clone())
+            .with_global_labels(&self.global_labels)
+            .with_container_id(self.container_id.as_deref())
+            .with_dialect(self.dialect)
+    }
+}
+
+/// Outcome of sending a payload.
+pub(crate) enum SendOutcome {
+    /// The payload was sent.
+    Sent,
+
+    /// The payload could not be sent, and was buffered to be retried later.
+    Buffered,
+
+    /// The payload could not be sent, and was dropped.
+    Dropped,
+}
+
+/// Applies a telemetry update, initializing the telemetry metrics if necessary.
+///
+/// The update is always added to the running totals exposed through `DogStatsDHandle::telemetry`, whether or not
+/// telemetry metrics are enabled.
+fn update_telemetry(
+    telemetry: &mut Option<Telemetry>,
+    state: &State,
+    config: &ForwarderConfiguration,
+    update: &TelemetryUpdate,
+) {
+    state.record_telemetry(update);
+
+    // If we processed any metrics, update our telemetry.
+    //
+    // We do it in this lazily-initialized fashion because we need to register our internal telemetry metrics with
+    // the global recorder _after_ we've been installed, so that the metrics all flow through the same recorder
+    // stack and are affected by any relevant recorder layers, and so on.
+    //
+    // When we have updates, we know that can only have happened if the recorder was installed and metrics were
+    // being processed, so we can safely initialize our telemetry at this point.
+    if state.telemetry_enabled() && update.had_updates() {
+        let telemetry =
+            telemetry.get_or_insert_with(|| Telemetry::new(config.remote_addr.transport_id()));
+        telemetry.apply_update(update);
+    }
+}
+
+/// Control over a running forwarder.
+///
+/// This is shared between the forwarder and any handles to the exporter, and allows flushing on demand as well as
+/// shutting down the forwarder.
+pub(crate) struct Control {
+    config: ForwarderConfiguration,
+    state: Arc<State>,
+    shutdown: Mutex<bool>,
+    shutdown_cond: Condvar,
+    #[cfg(feature = "async-runtime")]
+    shutdown_notify: ::tokio::sync::Notify,
+    finished: Mutex<bool>,
+    finished_cond: Condvar,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    flusher: Mutex<Option<sync::ImmediateFlusher>>,
+}
+
+impl Control {
+    /// Creates a new `Control` for a forwarder with the given configuration and state.
+    pub fn new(config: ForwarderConfiguration, state: Arc<State>) -> Self {
+        Control {
+            config,
+            state,
+            shutdown: Mutex::new(false),
+            shutdown_cond: Condvar::new(),
+            #[cfg(feature = "async-runtime")]
+            shutdown_notify: ::tokio::sync::Notify::new(),
+            finished: Mutex::new(false),
+            finished_cond: Condvar::new(),
+            thread: Mutex::new(None),
+            flusher: Mutex::new(None),
+        }
+    }
+
+    /// Returns a reference to the forwarder configuration.
+    pub fn config(&self) -> &ForwarderConfiguration {
+        &self.config
+    }
+
+    /// Returns a reference to the exporter state.
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    /// Sets the thread the forwarder is running on, so that it can be joined during shutdown.
+    pub fn set_thread(&self, thread: JoinHandle<()>) {
+        *self.thread.lock().unwrap_or_else(PoisonError::into_inner) = Some(thread);
+    }
+
+    /// Waits up to `timeout` for a shutdown to be requested.
+    ///
+    /// Returns `true` if a shutdown has been requested.
+    pub fn wait_for_shutdown(&self, timeout: Duration) -> bool {
+        let shutdown = self.shutdown.lock().unwrap_or_else(PoisonError::into_inner);
+        let (shutdown, _) = self
+            .shutdown_cond
+            .wait_timeout_while(shutdown, timeout, |shutdown| !*shutdown)
+            .unwrap_or_else(PoisonError::into_inner);
+        *shutdown
+    }
+
+    /// Completes once a shutdown has been requested.
+    #[cfg(feature = "async-runtime")]
+    pub async fn shutdown_requested(&self) {
+        // Only the forwarder waits to be notified, and `notify_one` stores a permit if the forwarder isn't currently
+        // waiting, so checking the flag first cannot miss a shutdown request.
+        if !*self.shutdown.lock().unwrap_or_else(PoisonError::into_inner) {
+            self.shutdown_notify.notified().await;
+        }
+    }
+
+    /// Marks the forwarder as finished, waking up anyone waiting for it to shut down.
+    pub fn mark_finished(&self) {
+        *self.finished.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.finished_cond.notify_all();
+    }
+
+    /// Flushes the exporter state, sending all payloads immediately on the calling thread.
+    pub fn flush(&self) {
+        let mut flusher = self.flusher.lock().unwrap_or_else(PoisonError::into_inner);
+        flusher
+            .get_or_insert_with(|| sync::ImmediateFlusher::new(&self.config))
+            .flush(&self.config, &self.state);
+    }
+
+    /// Shuts down the forwarder, waiting up to `timeout` for it to finish.
+    ///
+    /// The forwarder sends any payloads it has yet to send, performs a final flush, and then stops. Returns `true` if
+    /// the forwarder finished within the timeout.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        *self.shutdown.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.shutdown_cond.notify_all();
+        #[cfg(feature = "async-runtime")]
+        self.shutdown_notify.notify_one();
+
+        let finished = self.finished.lock().unwrap_or_else(PoisonError::into_inner);
+        let (finished, _) = self
+            .finished_cond
+            .wait_timeout_while(finished, timeout, |finished| !*finished)
+            .unwrap_or_else(PoisonError::into_inner);
+        if !*finished {
+            return false;
+        }
+
+        // The forwarder has finished, so the thread is about to exit, if it hasn't already.
+        if let Some(thread) = self.thread.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            let _ = thread.join();
+        }
+
+        true
+    }
 }
 
 #[cfg(test)]
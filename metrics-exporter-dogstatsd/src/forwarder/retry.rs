@@ -0,0 +1,153 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::telemetry::TelemetryUpdate;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// A bounded buffer of payloads waiting to be retried.
+///
+/// Payloads which fail to send are buffered, in order, so that they can be sent once the remote server is reachable
+/// again. When the buffer is full, the oldest payloads are evicted to make room for new ones.
+pub(crate) struct RetryBuffer {
+    payloads: VecDeque<Vec<u8>>,
+    len: usize,
+    max_len: usize,
+}
+
+impl RetryBuffer {
+    /// Creates a new `RetryBuffer` holding up to `max_len` bytes of payloads.
+    ///
+    /// If `max_len` is zero, the buffer is disabled and never holds any payloads.
+    pub fn new(max_len: usize) -> Self {
+        Self { payloads: VecDeque::new(), len: 0, max_len }
+    }
+
+    /// Returns `true` if the buffer is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.max_len > 0
+    }
+
+    /// Returns the number of payloads in the buffer.
+    pub fn payloads_len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    /// Buffers a payload, evicting the oldest payloads if necessary to stay within the maximum buffer size.
+    ///
+    /// If the payload is larger than the maximum buffer size, it is dropped.
+    pub fn push(&mut self, payload: &[u8], telemetry: &mut TelemetryUpdate) {
+        if payload.len() > self.max_len {
+            telemetry.track_packet_evicted(payload.len());
+            return;
+        }
+
+        while self.len + payload.len() > self.max_len {
+            let evicted =
+                self.payloads.pop_front().expect("buffer cannot be empty if over capacity");
+            self.len -= evicted.len();
+            telemetry.track_packet_evicted(evicted.len());
+        }
+
+        self.len += payload.len();
+        self.payloads.push_back(payload.to_vec());
+        telemetry.track_packet_buffered(payload.len());
+    }
+
+    /// Returns the oldest payload in the buffer.
+    pub fn front(&self) -> Option<&[u8]> {
+        self.payloads.front().map(Vec::as_slice)
+    }
+
+    /// Removes the oldest payload from the buffer.
+    pub fn pop_front(&mut self) {
+        if let Some(payload) = self.payloads.pop_front() {
+            self.len -= payload.len();
+        }
+    }
+
+    /// Removes all payloads from the buffer, tracking them as dropped.
+    pub fn clear(&mut self, telemetry: &mut TelemetryUpdate) {
+        for payload in self.payloads.drain(..) {
+            telemetry.track_packet_evicted(payload.len());
+        }
+        self.len = 0;
+    }
+}
+
+/// Exponential backoff between attempts to reach the remote server.
+#[derive(Default)]
+pub(crate) struct Backoff {
+    current: Option<Duration>,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    /// Returns `true` if enough time has passed since the last failure to make another attempt.
+    pub fn is_ready(&self) -> bool {
+        self.next_attempt.map_or(true, |next_attempt| Instant::now() >= next_attempt)
+    }
+
+    /// Records a failed attempt, doubling the time until the next attempt up to a maximum.
+    pub fn failed(&mut self) {
+        let current =
+            self.current.map_or(INITIAL_BACKOFF, |current| (current * 2).min(MAX_BACKOFF));
+        self.current = Some(current);
+        self.next_attempt = Some(Instant::now() + current);
+    }
+
+    /// Records a successful attempt, resetting the backoff.
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.next_attempt = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, RetryBuffer, INITIAL_BACKOFF};
+    use crate::telemetry::TelemetryUpdate;
+
+    #[test]
+    fn retry_buffer_evicts_oldest() {
+        let mut telemetry = TelemetryUpdate::default();
+        let mut buffer = RetryBuffer::new(10);
+        assert!(buffer.is_enabled());
+
+        buffer.push(b"aaaa", &mut telemetry);
+        buffer.push(b"bbbb", &mut telemetry);
+        buffer.push(b"cccc", &mut telemetry);
+        assert_eq!(buffer.payloads_len(), 2);
+        assert_eq!(buffer.front(), Some(&b"bbbb"[..]));
+
+        // Payloads larger than the buffer are dropped outright.
+        buffer.push(b"dddddddddddd", &mut telemetry);
+        assert_eq!(buffer.payloads_len(), 2);
+
+        buffer.pop_front();
+        assert_eq!(buffer.front(), Some(&b"cccc"[..]));
+        buffer.pop_front();
+        assert_eq!(buffer.payloads_len(), 0);
+
+        assert!(!RetryBuffer::new(0).is_enabled());
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::default();
+        assert!(backoff.is_ready());
+
+        backoff.failed();
+        assert!(!backoff.is_ready());
+        assert_eq!(backoff.current, Some(INITIAL_BACKOFF));
+
+        backoff.failed();
+        assert_eq!(backoff.current, Some(INITIAL_BACKOFF * 2));
+
+        backoff.reset();
+        assert!(backoff.is_ready());
+    }
+}
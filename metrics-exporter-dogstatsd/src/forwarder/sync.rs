@@ -4,14 +4,16 @@ use std::{
     io::{self, Write as _},
     net::{Ipv4Addr, UdpSocket},
     sync::Arc,
-    thread::sleep,
     time::Instant,
 };
 use tracing::{debug, error, trace};
 
-use super::{ForwarderConfiguration, RemoteAddr};
+use super::{
+    retry::{Backoff, RetryBuffer},
+    update_telemetry, Control, ForwarderConfiguration, RemoteAddr, SendOutcome,
+};
 use crate::{
-    state::{FlushState, State},
+    state::State,
     telemetry::{Telemetry, TelemetryUpdate},
     writer::PayloadWriter,
 };
@@ -107,108 +109,406 @@ impl ClientState {
     }
 }
 
+/// Sends a payload, unless we're waiting to reconnect after a previous failure.
+fn try_send_with_backoff(
+    client_state: &mut ClientState,
+    backoff: &mut Backoff,
+    payload: &[u8],
+) -> io::Result<usize> {
+    if !backoff.is_ready() {
+        return Err(io::Error::new(io::ErrorKind::WouldBlock, "waiting to reconnect"));
+    }
+
+    let result = client_state.try_send(payload);
+    if result.is_ok() {
+        backoff.reset();
+    } else {
+        backoff.failed();
+    }
+
+    result
+}
+
+/// Flushes the exporter state and sends the resulting payloads immediately, on the calling thread.
+///
+/// This is used to flush on demand, regardless of which backend the forwarder itself is running on.
+pub(crate) struct ImmediateFlusher {
+    client_state: ClientState,
+    writer: PayloadWriter,
+    telemetry_update: TelemetryUpdate,
+    telemetry: Option<Telemetry>,
+}
+
+impl ImmediateFlusher {
+    /// Create a new `ImmediateFlusher`.
+    pub fn new(config: &ForwarderConfiguration) -> Self {
+        ImmediateFlusher {
+            client_state: ClientState::Disconnected(config.clone()),
+            writer: config.payload_writer(),
+            telemetry_update: TelemetryUpdate::default(),
+            telemetry: None,
+        }
+    }
+
+    /// Flushes the given state, sending out all payloads without any splaying.
+    pub fn flush(&mut self, config: &ForwarderConfiguration, state: &State) {
+        self.telemetry_update.clear();
+        state.flush(&mut self.writer, &mut self.telemetry_update);
+
+        let mut payloads = self.writer.payloads();
+        debug!(num_payloads = payloads.len(), "Sending payloads for on-demand flush.");
+
+        while let Some(payload) = payloads.next_payload() {
+            if let Err(e) = self.client_state.try_send(payload) {
+                error!(error = %e, "Failed to send payload.");
+                self.telemetry_update.track_packet_send_failed(payload.len());
+            } else {
+                self.telemetry_update.track_packet_send_succeeded(payload.len());
+            }
+        }
+
+        update_telemetry(&mut self.telemetry, state, config, &self.telemetry_update);
+    }
+}
+
 pub(crate) struct Forwarder {
     client_state: ClientState,
-    config: ForwarderConfiguration,
-    state: Arc<State>,
+    control: Arc<Control>,
     telemetry: Option<Telemetry>,
+    retry_buffer: RetryBuffer,
+    backoff: Backoff,
 }
 
 impl Forwarder {
     /// Create a new synchronous `Forwarder`.
-    pub fn new(config: ForwarderConfiguration, state: Arc<State>) -> Self {
+    pub fn new(control: Arc<Control>) -> Self {
         Forwarder {
-            client_state: ClientState::Disconnected(config.clone()),
-            config,
-            state,
+            client_state: ClientState::Disconnected(control.config().clone()),
+            retry_buffer: RetryBuffer::new(control.config().retry_buffer_len),
+            control,
             telemetry: None,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sends a payload, after first retrying any buffered payloads.
+    ///
+    /// If the payload can't be sent and the retry buffer is enabled, it's buffered to be retried later.
+    fn send(&mut self, payload: &[u8], telemetry_update: &mut TelemetryUpdate) -> SendOutcome {
+        // Payloads must be sent in order, so any buffered payloads have to be sent first.
+        let result = if self.retry_buffered(telemetry_update) {
+            self.try_send(payload)
+        } else {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "buffered payloads pending"))
+        };
+
+        match result {
+            Ok(_) => {
+                telemetry_update.track_packet_send_succeeded(payload.len());
+                SendOutcome::Sent
+            }
+            Err(e) if self.retry_buffer.is_enabled() => {
+                debug!(error = %e, "Failed to send payload. Buffering for retry.");
+                self.retry_buffer.push(payload, telemetry_update);
+                SendOutcome::Buffered
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to send payload.");
+                telemetry_update.track_packet_send_failed(payload.len());
+                SendOutcome::Dropped
+            }
+        }
+    }
+
+    /// Sends any buffered payloads, oldest first.
+    ///
+    /// Returns `true` if the retry buffer is empty.
+    fn retry_buffered(&mut self, telemetry_update: &mut TelemetryUpdate) -> bool {
+        while let Some(payload) = self.retry_buffer.front() {
+            let payload_len = payload.len();
+            let result = try_send_with_backoff(&mut self.client_state, &mut self.backoff, payload);
+            if result.is_err() {
+                return false;
+            }
+
+            self.retry_buffer.pop_front();
+            telemetry_update.track_packet_send_succeeded(payload_len);
         }
+
+        true
     }
 
-    fn update_telemetry(&mut self, update: &TelemetryUpdate) {
-        // If we processed any metrics, update our telemetry.
-        //
-        // We do it in this lazily-initialized fashion because we need to register our internal telemetry metrics with
-        // the global recorder _after_ we've been installed, so that the metrics all flow through the same recorder
-        // stack and are affected by any relevant recorder layers, and so on.
-        //
-        // When we have updates, we know that can only have happened if the recorder was installed and metrics were
-        // being processed, so we can safely initialize our telemetry at this point.
-        if self.state.telemetry_enabled() && update.had_updates() {
-            let telemetry = self
-                .telemetry
-                .get_or_insert_with(|| Telemetry::new(self.config.remote_addr.transport_id()));
-            telemetry.apply_update(update);
+    fn try_send(&mut self, payload: &[u8]) -> io::Result<usize> {
+        if self.retry_buffer.is_enabled() {
+            try_send_with_backoff(&mut self.client_state, &mut self.backoff, payload)
+        } else {
+            self.client_state.try_send(payload)
         }
     }
 
     /// Run the forwarder, sending out payloads to the configured remote address at the configured interval.
+    ///
+    /// When a shutdown is requested, any remaining payloads are sent immediately, and a final flush is performed
+    /// before returning.
     pub fn run(mut self) {
-        let mut flush_state = FlushState::default();
-        let mut writer =
-            PayloadWriter::new(self.config.max_payload_len, self.config.is_length_prefixed())
-                .with_global_labels(&self.config.global_labels);
+        let control = Arc::clone(&self.control);
+        let config = control.config();
+        let state = control.state();
+
+        let mut writer = config.payload_writer();
         let mut telemetry_update = TelemetryUpdate::default();
 
-        let mut next_flush = Instant::now() + self.config.flush_interval;
+        let mut next_flush = Instant::now() + config.flush_interval;
         loop {
-            // Sleep until our target flush deadline.
+            // Sleep until our target flush deadline, or until we're asked to shut down.
             //
             // If the previous flush iteration took longer than the flush interval, we won't sleep at all.
-            if let Some(sleep_duration) = next_flush.checked_duration_since(Instant::now()) {
-                sleep(sleep_duration);
-            }
+            let shutting_down =
+                control.wait_for_shutdown(next_flush.saturating_duration_since(Instant::now()));
 
             // Process our flush, building up all of our payloads.
             //
             // We'll also calculate our next flush time here, so that we can splay out the payloads over the remaining
             // time we have before we should be flushing again.
-            next_flush = Instant::now() + self.config.flush_interval;
+            next_flush = Instant::now() + config.flush_interval;
 
             telemetry_update.clear();
-            self.state.flush(&mut flush_state, &mut writer, &mut telemetry_update);
+            state.flush(&mut writer, &mut telemetry_update);
 
             // Send out all of the payloads that we've written, but splay them out over the remaining time until our
             // next flush, in order to smooth out the network traffic / processing demands on the Datadog Agent.
+            //
+            // Once a shutdown has been requested, we stop splaying and send the remaining payloads immediately.
             let mut payloads = writer.payloads();
             if u32::try_from(payloads.len()).is_err() {
                 error!(num_payloads = payloads.len(), "Too many payloads to send.");
-                continue;
+            } else {
+                let splay_duration = next_flush.saturating_duration_since(Instant::now());
+                debug!(
+                    ?splay_duration,
+                    num_payloads = payloads.len(),
+                    "Splaying payloads over remaining time until next flush."
+                );
+
+                let mut payloads_sent = 0;
+                let mut payloads_buffered = 0;
+                let mut payloads_dropped = 0;
+
+                while let Some(payload) = payloads.next_payload() {
+                    match self.send(payload, &mut telemetry_update) {
+                        SendOutcome::Sent => payloads_sent += 1,
+                        SendOutcome::Buffered => payloads_buffered += 1,
+                        SendOutcome::Dropped => payloads_dropped += 1,
+                    }
+
+                    // Figure out how long we should sleep based on the remaining time until the next flush and the
+                    // number of remaining payloads.
+                    let next_flush_delta = next_flush.saturating_duration_since(Instant::now());
+                    let remaining_payloads = u32::try_from(payloads.len()).unwrap();
+                    let inter_payload_sleep =
+                        next_flush_delta / remaining_payloads.saturating_add(1);
+
+                    trace!(
+                        remaining_payloads,
+                        "Sleeping {:?} between payloads.",
+                        inter_payload_sleep
+                    );
+                    control.wait_for_shutdown(inter_payload_sleep);
+                }
+
+                debug!(
+                    payloads_sent,
+                    payloads_buffered, payloads_dropped, "Finished sending payloads."
+                );
             }
 
-            let splay_duration = next_flush.saturating_duration_since(Instant::now());
-            debug!(
-                ?splay_duration,
-                num_payloads = payloads.len(),
-                "Splaying payloads over remaining time until next flush."
-            );
-
-            let mut payloads_sent = 0;
-            let mut payloads_dropped = 0;
-
-            while let Some(payload) = payloads.next_payload() {
-                if let Err(e) = self.client_state.try_send(payload) {
-                    error!(error = %e, "Failed to send payload.");
-                    telemetry_update.track_packet_send_failed(payload.len());
-                    payloads_dropped += 1;
-                } else {
-                    telemetry_update.track_packet_send_succeeded(payload.len());
-                    payloads_sent += 1;
+            if shutting_down {
+                // Make one last attempt to send any buffered payloads, regardless of any backoff, before dropping them.
+                self.backoff.reset();
+                if !self.retry_buffered(&mut telemetry_update) {
+                    error!(
+                        num_payloads = self.retry_buffer.payloads_len(),
+                        "Dropping buffered payloads on shutdown."
+                    );
+                    self.retry_buffer.clear(&mut telemetry_update);
                 }
+            }
 
-                // Figure out how long we should sleep based on the remaining time until the next flush and the number
-                // of remaining payloads.
-                let next_flush_delta = next_flush.saturating_duration_since(Instant::now());
-                let remaining_payloads = u32::try_from(payloads.len()).unwrap();
-                let inter_payload_sleep = next_flush_delta / remaining_payloads.saturating_add(1);
+            update_telemetry(&mut self.telemetry, state, config, &telemetry_update);
 
-                trace!(remaining_payloads, "Sleeping {:?} between payloads.", inter_payload_sleep);
-                sleep(inter_payload_sleep);
+            if shutting_down {
+                break;
             }
+        }
+
+        debug!("Forwarder shut down.");
+        control.mark_finished();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
 
-            debug!(payloads_sent, payloads_dropped, "Finished sending payloads.");
+    use metrics::{Key, Recorder as _};
+    use metrics_dogstatsd_receiver::{Message, Metric, MetricValues, Receiver};
 
-            self.update_telemetry(&telemetry_update);
+    use super::Forwarder;
+    use crate::{
+        builder::{AggregationMode, WireDialect},
+        forwarder::{Control, ForwarderConfiguration, RemoteAddr},
+        matcher::PrefixMatcher,
+        recorder::DogStatsDRecorder,
+        sampling::SampleRates,
+        sanitize::Sanitizer,
+        state::{State, StateConfiguration},
+        storage::HistogramOverrides,
+        telemetry::TelemetrySnapshot,
+    };
+
+    fn build_control(remote_addr: RemoteAddr, retry_buffer_len: usize) -> Arc<Control> {
+        let state = Arc::new(State::new(StateConfiguration {
+            agg_mode: AggregationMode::Conservative,
+            telemetry: false,
+            histogram_sampling: false,
+            histogram_reservoir_size: 1024,
+            histograms_as_distributions: true,
+            histogram_overrides: HistogramOverrides::default(),
+            global_prefix: None,
+            sample_rates: SampleRates::default(),
+            gauge_aggregations: PrefixMatcher::default(),
+        }));
+        let config = ForwarderConfiguration {
+            max_payload_len: remote_addr.default_max_payload_len(),
+            retry_buffer_len,
+            remote_addr,
+            // Long enough that a scheduled flush never happens during a test.
+            flush_interval: Duration::from_secs(3600),
+            write_timeout: Duration::from_secs(1),
+            global_labels: Vec::new(),
+            container_id: None,
+            dialect: WireDialect::DogStatsD,
+            sanitizer: Sanitizer::default(),
+        };
+        Arc::new(Control::new(config, state))
+    }
+
+    fn build_recorder(receiver: &Receiver) -> (DogStatsDRecorder, Arc<Control>) {
+        let remote_addr = RemoteAddr::try_from(receiver.address()).unwrap();
+        let control = build_control(remote_addr, 0);
+        (DogStatsDRecorder::new(Arc::clone(&control)), control)
+    }
+
+    fn receive_metric(receiver: &Receiver) -> Metric {
+        let mut messages = receiver.wait_for_messages(1, Duration::from_secs(5));
+        assert_eq!(messages.len(), 1, "expected a single message, got {messages:?}");
+        match messages.remove(0) {
+            Message::Metric(metric) => metric,
+            other => panic!("expected a metric, got {other:?}"),
         }
     }
+
+    #[test]
+    fn flush_on_demand() {
+        let receiver = Receiver::bind_udp().unwrap();
+        let (recorder, _) = build_recorder(&receiver);
+        let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+        recorder.register_counter(&Key::from_name("requests"), &metadata).increment(3);
+
+        recorder.handle().flush();
+
+        let metric = receive_metric(&receiver);
+        assert_eq!(metric.name, "requests");
+        assert_eq!(metric.values, MetricValues::Counter(vec![3.0]));
+    }
+
+    #[test]
+    fn telemetry_snapshot() {
+        let receiver = Receiver::bind_udp().unwrap();
+        let (recorder, _) = build_recorder(&receiver);
+        let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+        recorder.register_counter(&Key::from_name("requests"), &metadata).increment(3);
+        recorder.register_gauge(&Key::from_name("temperature"), &metadata).set(42.0);
+
+        let handle = recorder.handle();
+        assert_eq!(handle.telemetry(), TelemetrySnapshot::default());
+
+        handle.flush();
+        assert_eq!(receiver.wait_for_messages(2, Duration::from_secs(5)).len(), 2);
+
+        let telemetry = handle.telemetry();
+        assert_eq!(telemetry.metric_points(), 2);
+        assert_eq!(telemetry.counter_points(), 1);
+        assert_eq!(telemetry.gauge_points(), 1);
+        assert_eq!(telemetry.packets_sent(), 1);
+        assert_eq!(telemetry.bytes_sent(), b"requests:3|c\ntemperature:42.0|g\n".len() as u64);
+        assert_eq!(telemetry.packets_dropped(), 0);
+
+        // Totals accumulate across flushes. Gauges are sent on every flush, but only count as points when updated.
+        recorder.register_counter(&Key::from_name("requests"), &metadata).increment(1);
+        handle.flush();
+        assert_eq!(receiver.wait_for_messages(2, Duration::from_secs(5)).len(), 2);
+        assert_eq!(handle.telemetry().counter_points(), 2);
+        assert_eq!(handle.telemetry().gauge_points(), 1);
+        assert_eq!(handle.telemetry().packets_sent(), 2);
+    }
+
+    #[test]
+    fn shutdown_flushes_and_joins() {
+        let receiver = Receiver::bind_udp().unwrap();
+        let (recorder, control) = build_recorder(&receiver);
+        let forwarder = Forwarder::new(Arc::clone(&control));
+        control.set_thread(std::thread::spawn(move || forwarder.run()));
+
+        let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+        recorder.register_counter(&Key::from_name("requests"), &metadata).increment(5);
+
+        let handle = recorder.handle();
+        assert!(handle.shutdown(Duration::from_secs(5)));
+
+        let metric = receive_metric(&receiver);
+        assert_eq!(metric.name, "requests");
+        assert_eq!(metric.values, MetricValues::Counter(vec![5.0]));
+
+        // Shutting down again is a no-op.
+        assert!(handle.shutdown(Duration::ZERO));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retries_buffered_payloads_after_reconnecting() {
+        use std::{io::Read as _, os::unix::net::UnixListener};
+
+        use super::SendOutcome;
+        use crate::telemetry::TelemetryUpdate;
+
+        let dir = std::env::temp_dir()
+            .join(format!("metrics-exporter-dogstatsd-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dsd.sock");
+        let _ = std::fs::remove_file(&path);
+
+        let control = build_control(RemoteAddr::Unix(path.clone()), 1024);
+        let mut forwarder = Forwarder::new(control);
+        let mut telemetry_update = TelemetryUpdate::default();
+
+        // Nothing is listening yet, as if the agent was restarting, so the payload is buffered.
+        assert!(matches!(forwarder.send(b"first", &mut telemetry_update), SendOutcome::Buffered));
+
+        // While waiting to reconnect, newer payloads are buffered behind it without trying to send.
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(matches!(forwarder.send(b"second", &mut telemetry_update), SendOutcome::Buffered));
+
+        // Once the backoff has elapsed, the buffered payloads are sent before the new one, in order.
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(matches!(forwarder.send(b"third", &mut telemetry_update), SendOutcome::Sent));
+        assert_eq!(forwarder.retry_buffer.payloads_len(), 0);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = [0; 16];
+        stream.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"firstsecondthird");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -0,0 +1,439 @@
+use std::{future::Future, io, net::Ipv4Addr, sync::Arc, time::Duration};
+
+#[cfg(unix)]
+use tokio::net::{UnixDatagram, UnixStream};
+use tokio::{
+    io::AsyncWriteExt as _,
+    net::UdpSocket,
+    time::{timeout, timeout_at, Instant},
+};
+use tracing::{debug, error, trace};
+
+use super::{
+    retry::{Backoff, RetryBuffer},
+    update_telemetry, Control, ForwarderConfiguration, RemoteAddr, SendOutcome,
+};
+use crate::telemetry::{Telemetry, TelemetryUpdate};
+
+enum Client {
+    Udp(UdpSocket),
+
+    #[cfg(unix)]
+    Unixgram(UnixDatagram),
+
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Client {
+    async fn from_forwarder_config(config: &ForwarderConfiguration) -> io::Result<Self> {
+        match &config.remote_addr {
+            RemoteAddr::Udp(addrs) => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+                socket.connect(&addrs[..]).await?;
+                Ok(Client::Udp(socket))
+            }
+
+            #[cfg(unix)]
+            RemoteAddr::Unixgram(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Client::Unixgram(socket))
+            }
+
+            #[cfg(unix)]
+            RemoteAddr::Unix(path) => with_timeout(config.write_timeout, UnixStream::connect(path))
+                .await
+                .map(Client::Unix),
+        }
+    }
+
+    async fn send(&mut self, buf: &[u8], write_timeout: Duration) -> io::Result<usize> {
+        match self {
+            Client::Udp(socket) => with_timeout(write_timeout, socket.send(buf)).await,
+
+            #[cfg(unix)]
+            Client::Unixgram(socket) => with_timeout(write_timeout, socket.send(buf)).await,
+
+            #[cfg(unix)]
+            Client::Unix(socket) => {
+                with_timeout(write_timeout, socket.write_all(buf)).await.map(|()| buf.len())
+            }
+        }
+    }
+}
+
+/// Runs the given I/O operation, failing with `TimedOut` if it does not complete within `duration`.
+async fn with_timeout<F, T>(duration: Duration, fut: F) -> io::Result<T>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    match timeout(duration, fut).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out")),
+    }
+}
+
+enum ClientState {
+    // Forwarder is currently disconnected.
+    Disconnected,
+
+    // Forwarder is connected and ready to send metrics.
+    Ready(Client),
+}
+
+impl ClientState {
+    async fn try_send(
+        &mut self,
+        config: &ForwarderConfiguration,
+        payload: &[u8],
+    ) -> io::Result<usize> {
+        if let ClientState::Disconnected = self {
+            *self = ClientState::Ready(Client::from_forwarder_config(config).await?);
+        }
+
+        let ClientState::Ready(client) = self else {
+            unreachable!("client must be connected at this point");
+        };
+
+        let result = client.send(payload, config.write_timeout).await;
+        if result.is_err() {
+            // A partially-written payload would corrupt the stream, so we always reconnect after a failed send.
+            *self = ClientState::Disconnected;
+        }
+
+        result
+    }
+}
+
+/// Sends a payload, unless we're waiting to reconnect after a previous failure.
+async fn try_send_with_backoff(
+    client_state: &mut ClientState,
+    backoff: &mut Backoff,
+    config: &ForwarderConfiguration,
+    payload: &[u8],
+) -> io::Result<usize> {
+    if !backoff.is_ready() {
+        return Err(io::Error::new(io::ErrorKind::WouldBlock, "waiting to reconnect"));
+    }
+
+    let result = client_state.try_send(config, payload).await;
+    if result.is_ok() {
+        backoff.reset();
+    } else {
+        backoff.failed();
+    }
+
+    result
+}
+
+pub(crate) struct Forwarder {
+    client_state: ClientState,
+    control: Arc<Control>,
+    telemetry: Option<Telemetry>,
+    retry_buffer: RetryBuffer,
+    backoff: Backoff,
+}
+
+impl Forwarder {
+    /// Create a new asynchronous `Forwarder`.
+    pub fn new(control: Arc<Control>) -> Self {
+        Forwarder {
+            client_state: ClientState::Disconnected,
+            retry_buffer: RetryBuffer::new(control.config().retry_buffer_len),
+            control,
+            telemetry: None,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sends a payload, after first retrying any buffered payloads.
+    ///
+    /// If the payload can't be sent and the retry buffer is enabled, it's buffered to be retried later.
+    async fn send(
+        &mut self,
+        payload: &[u8],
+        telemetry_update: &mut TelemetryUpdate,
+    ) -> SendOutcome {
+        // Payloads must be sent in order, so any buffered payloads have to be sent first.
+        let result = if self.retry_buffered(telemetry_update).await {
+            self.try_send(payload).await
+        } else {
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "buffered payloads pending"))
+        };
+
+        match result {
+            Ok(_) => {
+                telemetry_update.track_packet_send_succeeded(payload.len());
+                SendOutcome::Sent
+            }
+            Err(e) if self.retry_buffer.is_enabled() => {
+                debug!(error = %e, "Failed to send payload. Buffering for retry.");
+                self.retry_buffer.push(payload, telemetry_update);
+                SendOutcome::Buffered
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to send payload.");
+                telemetry_update.track_packet_send_failed(payload.len());
+                SendOutcome::Dropped
+            }
+        }
+    }
+
+    /// Sends any buffered payloads, oldest first.
+    ///
+    /// Returns `true` if the retry buffer is empty.
+    async fn retry_buffered(&mut self, telemetry_update: &mut TelemetryUpdate) -> bool {
+        let config = self.control.config();
+        while let Some(payload) = self.retry_buffer.front() {
+            let payload_len = payload.len();
+            let result =
+                try_send_with_backoff(&mut self.client_state, &mut self.backoff, config, payload)
+                    .await;
+            if result.is_err() {
+                return false;
+            }
+
+            self.retry_buffer.pop_front();
+            telemetry_update.track_packet_send_succeeded(payload_len);
+        }
+
+        true
+    }
+
+    async fn try_send(&mut self, payload: &[u8]) -> io::Result<usize> {
+        let config = self.control.config();
+        if self.retry_buffer.is_enabled() {
+            try_send_with_backoff(&mut self.client_state, &mut self.backoff, config, payload).await
+        } else {
+            self.client_state.try_send(config, payload).await
+        }
+    }
+
+    /// Run the forwarder, sending out payloads to the configured remote address at the configured interval.
+    ///
+    /// When a shutdown is requested, any remaining payloads are sent immediately, and a final flush is performed
+    /// before returning.
+    pub async fn run(mut self) {
+        let control = Arc::clone(&self.control);
+        let config = control.config();
+        let state = control.state();
+
+        let mut writer = config.payload_writer();
+        let mut telemetry_update = TelemetryUpdate::default();
+
+        let mut next_flush = Instant::now() + config.flush_interval;
+        loop {
+            // Sleep until our target flush deadline, or until we're asked to shut down.
+            //
+            // If the previous flush iteration took longer than the flush interval, this completes immediately.
+            let shutting_down = timeout_at(next_flush, control.shutdown_requested()).await.is_ok();
+
+            // Process our flush, building up all of our payloads.
+            //
+            // We'll also calculate our next flush time here, so that we can splay out the payloads over the remaining
+            // time we have before we should be flushing again.
+            next_flush = Instant::now() + config.flush_interval;
+
+            telemetry_update.clear();
+            state.flush(&mut writer, &mut telemetry_update);
+
+            // Send out all of the payloads that we've written, but splay them out over the remaining time until our
+            // next flush, in order to smooth out the network traffic / processing demands on the Datadog Agent.
+            //
+            // Once a shutdown has been requested, we stop splaying and send the remaining payloads immediately.
+            let mut payloads = writer.payloads();
+            if u32::try_from(payloads.len()).is_err() {
+                error!(num_payloads = payloads.len(), "Too many payloads to send.");
+            } else {
+                let splay_duration = next_flush.saturating_duration_since(Instant::now());
+                debug!(
+                    ?splay_duration,
+                    num_payloads = payloads.len(),
+                    "Splaying payloads over remaining time until next flush."
+                );
+
+                let mut payloads_sent = 0;
+                let mut payloads_buffered = 0;
+                let mut payloads_dropped = 0;
+
+                while let Some(payload) = payloads.next_payload() {
+                    match self.send(payload, &mut telemetry_update).await {
+                        SendOutcome::Sent => payloads_sent += 1,
+                        SendOutcome::Buffered => payloads_buffered += 1,
+                        SendOutcome::Dropped => payloads_dropped += 1,
+                    }
+
+                    // Figure out how long we should sleep based on the remaining time until the next flush and the
+                    // number of remaining payloads.
+                    let next_flush_delta = next_flush.saturating_duration_since(Instant::now());
+                    let remaining_payloads = u32::try_from(payloads.len()).unwrap();
+                    let inter_payload_sleep =
+                        next_flush_delta / remaining_payloads.saturating_add(1);
+
+                    trace!(
+                        remaining_payloads,
+                        "Sleeping {:?} between payloads.",
+                        inter_payload_sleep
+                    );
+                    let _ = timeout(inter_payload_sleep, control.shutdown_requested()).await;
+                }
+
+                debug!(
+                    payloads_sent,
+                    payloads_buffered, payloads_dropped, "Finished sending payloads."
+                );
+            }
+
+            if shutting_down {
+                // Make one last attempt to send any buffered payloads, regardless of any backoff, before dropping them.
+                self.backoff.reset();
+                if !self.retry_buffered(&mut telemetry_update).await {
+                    error!(
+                        num_payloads = self.retry_buffer.payloads_len(),
+                        "Dropping buffered payloads on shutdown."
+                    );
+                    self.retry_buffer.clear(&mut telemetry_update);
+                }
+            }
+
+            update_telemetry(&mut self.telemetry, state, config, &telemetry_update);
+
+            if shutting_down {
+                break;
+            }
+        }
+
+        debug!("Forwarder shut down.");
+        control.mark_finished();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use metrics::{Key, Recorder as _};
+    use tokio::{net::UdpSocket, runtime::Builder, time::timeout};
+
+    use super::Forwarder;
+    use crate::{
+        builder::{AggregationMode, WireDialect},
+        forwarder::{Control, ForwarderConfiguration, RemoteAddr},
+        matcher::PrefixMatcher,
+        recorder::DogStatsDRecorder,
+        sampling::SampleRates,
+        sanitize::Sanitizer,
+        state::{State, StateConfiguration},
+        storage::HistogramOverrides,
+    };
+
+    fn build_control(remote_addr: RemoteAddr) -> Arc<Control> {
+        let state = Arc::new(State::new(StateConfiguration {
+            agg_mode: AggregationMode::Conservative,
+            telemetry: false,
+            histogram_sampling: false,
+            histogram_reservoir_size: 1024,
+            histograms_as_distributions: true,
+            histogram_overrides: HistogramOverrides::default(),
+            global_prefix: None,
+            sample_rates: SampleRates::default(),
+            gauge_aggregations: PrefixMatcher::default(),
+        }));
+        let config = ForwarderConfiguration {
+            max_payload_len: remote_addr.default_max_payload_len(),
+            retry_buffer_len: 0,
+            remote_addr,
+            flush_interval: Duration::from_millis(10),
+            write_timeout: Duration::from_secs(1),
+            global_labels: Vec::new(),
+            container_id: None,
+            dialect: WireDialect::DogStatsD,
+            sanitizer: Sanitizer::default(),
+        };
+        Arc::new(Control::new(config, state))
+    }
+
+    #[test]
+    fn forwards_over_udp() {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let remote_addr = RemoteAddr::Udp(vec![server.local_addr().unwrap()]);
+
+            let control = build_control(remote_addr);
+            let recorder = DogStatsDRecorder::new(Arc::clone(&control));
+            let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+            recorder.register_counter(&Key::from_name("requests"), &metadata).increment(3);
+
+            tokio::spawn(Forwarder::new(control).run());
+
+            let mut buf = [0; 1024];
+            let len =
+                timeout(Duration::from_secs(5), server.recv(&mut buf)).await.unwrap().unwrap();
+            assert_eq!(&buf[..len], b"requests:3|c\n");
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn forwards_over_uds_stream_with_length_prefix() {
+        use tokio::{io::AsyncReadExt as _, net::UnixListener};
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let dir = std::env::temp_dir()
+                .join(format!("metrics-exporter-dogstatsd-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("dsd.sock");
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+
+            let control = build_control(RemoteAddr::Unix(path.clone()));
+            let recorder = DogStatsDRecorder::new(Arc::clone(&control));
+            let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+            recorder.register_gauge(&Key::from_name("temperature"), &metadata).set(42.0);
+
+            tokio::spawn(Forwarder::new(control).run());
+
+            let (mut stream, _) =
+                timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+            let mut prefix = [0; 4];
+            stream.read_exact(&mut prefix).await.unwrap();
+            let mut payload = vec![0; u32::from_le_bytes(prefix) as usize];
+            stream.read_exact(&mut payload).await.unwrap();
+            assert_eq!(payload, b"temperature:42.0|g\n");
+
+            let _ = std::fs::remove_dir_all(&dir);
+        });
+    }
+
+    #[test]
+    fn shutdown_flushes_and_finishes() {
+        let runtime = Builder::new_multi_thread().worker_threads(1).enable_all().build().unwrap();
+        runtime.block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let remote_addr = RemoteAddr::Udp(vec![server.local_addr().unwrap()]);
+
+            let control = build_control(remote_addr);
+            let recorder = DogStatsDRecorder::new(Arc::clone(&control));
+            let metadata = metrics::Metadata::new("test", metrics::Level::INFO, None);
+
+            tokio::spawn(Forwarder::new(control).run());
+
+            recorder.register_counter(&Key::from_name("requests"), &metadata).increment(7);
+
+            let handle = recorder.handle();
+            let finished =
+                tokio::task::spawn_blocking(move || handle.shutdown(Duration::from_secs(5)))
+                    .await
+                    .unwrap();
+            assert!(finished);
+
+            let mut buf = [0; 1024];
+            let len =
+                timeout(Duration::from_secs(5), server.recv(&mut buf)).await.unwrap().unwrap();
+            assert_eq!(&buf[..len], b"requests:7|c\n");
+        });
+    }
+}
@@ -45,6 +45,50 @@
 //! population size: we can hold 1,000 to 2,000 samples and still get a good representation when the number of input
 //! values is in the millions.
 //!
+//! Sampling, the reservoir size, and whether histograms are sent as distributions can all be configured per metric,
+//! by name prefix, such as with [`DogStatsDBuilder::send_histograms_as_distributions_for`].
+//!
+//! ## Sets
+//!
+//! DogStatsD sets, which count the number of unique values seen during each flush interval, are supported through
+//! [`Set`] handles registered directly on the exporter, as `metrics` has no equivalent metric type:
+//!
+//! ```no_run
+//! # use metrics::Key;
+//! # use metrics_exporter_dogstatsd::DogStatsDBuilder;
+//! let handle = DogStatsDBuilder::default().install().expect("failed to install recorder");
+//! let unique_users = handle.register_set(&Key::from_name("unique_users"));
+//!
+//! unique_users.insert("alice");
+//! ```
+//!
+//! ## Service checks and events
+//!
+//! [Service checks][ServiceCheck] and [events][Event] can be sent through a [`DogStatsDHandle`], acquired from the
+//! recorder before installing it, and are forwarded to the DogStatsD server alongside metrics:
+//!
+//! ```no_run
+//! # use metrics_exporter_dogstatsd::{DogStatsDBuilder, Event, ServiceCheck, ServiceCheckStatus};
+//! let recorder = DogStatsDBuilder::default().build().expect("failed to build recorder");
+//! let handle = recorder.handle();
+//! metrics::set_global_recorder(recorder).expect("failed to install recorder");
+//!
+//! handle.service_check(ServiceCheck::new("app.can_connect", ServiceCheckStatus::Ok));
+//! handle.event(Event::new("Deployment", "Deployed version 1.2.3."));
+//! ```
+//!
+//! ## Client-side sample rates
+//!
+//! Counters and histograms on very hot paths can be sampled on the client side with
+//! [`DogStatsDBuilder::with_sample_rate`], which drops a fraction of updates locally and sends the sample rate along with
+//! the metric so that the downstream DogStatsD server can scale the values back up.
+//!
+//! ## Gauge aggregation
+//!
+//! Gauges normally only send their last value at each flush. With [`DogStatsDBuilder::with_gauge_aggregation`], gauges
+//! can instead send the minimum, maximum, average, or sum of all the values they took since the last flush, optionally
+//! as multiple series (such as `<name>.max` and `<name>.avg`), so that short spikes are not lost.
+//!
 //! ## Smart reporting
 //!
 //! The exporter will "splay" the reporting of metrics over time, to smooth out the rate of payloads received by the
@@ -62,6 +106,41 @@
 //! `SOCK_STREAM` mode is roughly equivalent to TCP, but only available on the same host, and provides better
 //! guarantees around message delivery in high-throughput scenarios.
 //!
+//! To ride out a slow or restarting Datadog Agent, payloads that fail to send can be held in a bounded retry buffer,
+//! configured with [`DogStatsDBuilder::with_retry_buffer_length`], and retried once the exporter has reconnected.
+//!
+//! ## Asynchronous backend
+//!
+//! By default, metrics are forwarded from a dedicated background OS thread. When the `async-runtime` feature is
+//! enabled, [`DogStatsDBuilder::with_asynchronous_backend`] can be used to instead forward metrics from a task spawned
+//! on the current Tokio runtime, which avoids spawning any additional threads.
+//!
+//! ## Flushing and shutdown
+//!
+//! [`DogStatsDBuilder::install`] returns a [`DogStatsDHandle`], which can be used to flush the exporter on demand with
+//! [`DogStatsDHandle::flush`], and to shut it down with [`DogStatsDHandle::shutdown`], which sends any remaining
+//! metrics before stopping the forwarder. This is useful for short-lived processes, such as batch jobs, which would
+//! otherwise exit before their metrics were sent.
+//!
+//! ## Origin detection
+//!
+//! The exporter supports origin detection (DSD v1.2), which helps the downstream DogStatsD server enrich metrics with
+//! additional metadata relevant to the container/application emitting them. The container ID is discovered
+//! automatically, and an entity ID can be provided with the `DD_ENTITY_ID` environment variable. See
+//! [`DogStatsDBuilder::with_origin_detection`] for more details.
+//!
+//! ## StatsD dialects
+//!
+//! Metrics can also be sent to servers that understand the StatsD protocol but not the DogStatsD extensions to it, with
+//! tags either folded into the metric name or written in the Graphite, InfluxDB, or SignalFx styles. See
+//! [`WireDialect`] for more details.
+//!
+//! ## Tag sanitization
+//!
+//! Reserved characters in metric names and tags, which would otherwise corrupt payloads, are replaced or stripped, and
+//! tags are truncated to the 200 character limit enforced by Datadog. Tag keys can optionally be lowercased, and metric
+//! names validated against Datadog's naming rules. See [`DogStatsDBuilder::with_reserved_characters`] for more details.
+//!
 //! ## Telemetry
 //!
 //! The exporter captures its own internal telemetry around the number of active metrics, points flushed or dropped,
@@ -71,16 +150,9 @@
 //! All internal telemetry is under the `datadog.dogstatsd.client` namespace, to align with the internal telemetry
 //! emitted by official DogStatsD clients.
 //!
-//! # Missing
-//!
-//! ## Container ID detection
-//!
-//! We do not yet support container ID detection (DSD v1.2) which is used to help aid the downstream DogStatsD server in
-//! enriching the metrics with additional metadata relevant to the host/application emitting the metrics.
-//!
-//! ## Asynchronous backend
-//!
-//! We do not yet support an asynchronous forwarder backend for flushing metrics.
+//! The same telemetry can also be read from within the application, whether or not it is being emitted, through
+//! [`DogStatsDHandle::telemetry`], which returns a [`TelemetrySnapshot`] of cumulative totals. This can be used to,
+//! for example, fail a readiness check if points are being dropped.
 
 #![deny(clippy::all)]
 #![deny(clippy::pedantic)]
@@ -94,13 +166,29 @@
 #![cfg_attr(docsrs, feature(doc_cfg), deny(rustdoc::broken_intra_doc_links))]
 
 mod builder;
-pub use self::builder::{AggregationMode, BuildError, DogStatsDBuilder};
+pub use self::builder::{
+    AggregationMode, BuildError, DogStatsDBuilder, GaugeAggregation, ReservedCharacters,
+    WireDialect,
+};
+
+mod event;
+pub use self::event::{Event, EventAlertType, EventPriority, ServiceCheck, ServiceCheckStatus};
 
 mod forwarder;
+mod matcher;
+mod origin;
 mod recorder;
-pub use self::recorder::DogStatsDRecorder;
+pub use self::recorder::{DogStatsDHandle, DogStatsDRecorder};
+
+mod sampling;
 
+mod set;
+pub use self::set::Set;
+
+mod sanitize;
 mod state;
 mod storage;
 mod telemetry;
+pub use self::telemetry::TelemetrySnapshot;
+
 mod writer;
@@ -0,0 +1,50 @@
+/// Values matched against metric names by prefix.
+///
+/// When multiple prefixes match a metric name, the longest one wins.
+#[derive(Clone, Debug)]
+pub(crate) struct PrefixMatcher<T> {
+    entries: Vec<(String, T)>,
+}
+
+impl<T> PrefixMatcher<T> {
+    /// Sets the value for all metrics whose name starts with `prefix`, replacing any existing value for the prefix.
+    pub fn insert(&mut self, prefix: String, value: T) {
+        self.entries.retain(|(existing, _)| *existing != prefix);
+        self.entries.push((prefix, value));
+    }
+
+    /// Gets the value for the given metric name, if any prefix matches it.
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.entries
+            .iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, value)| value)
+    }
+}
+
+impl<T> Default for PrefixMatcher<T> {
+    fn default() -> Self {
+        PrefixMatcher { entries: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefixMatcher;
+
+    #[test]
+    fn longest_prefix_wins() {
+        let mut matcher = PrefixMatcher::default();
+        matcher.insert(String::new(), "default");
+        matcher.insert("system.".to_string(), "system");
+        matcher.insert("system.cpu".to_string(), "cpu");
+
+        assert_eq!(matcher.get("system.cpu.usage"), Some(&"cpu"));
+        assert_eq!(matcher.get("system.memory.used"), Some(&"system"));
+        assert_eq!(matcher.get("requests"), Some(&"default"));
+
+        matcher.insert("system.".to_string(), "replaced");
+        assert_eq!(matcher.get("system.memory.used"), Some(&"replaced"));
+    }
+}
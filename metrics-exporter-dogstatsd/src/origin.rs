@@ -0,0 +1,180 @@
+//! Origin detection.
+//!
+//! The Datadog Agent can enrich metrics with tags describing where they came from, such as the container or pod, which
+//! is known as "origin detection". For this to work, the client must tell the Agent which entity it is running as: either
+//! through an explicit entity ID, typically injected via the Kubernetes downward API as `DD_ENTITY_ID`, or through the
+//! ID of the container the client is running in, which is discovered from the cgroup and mount information of the
+//! current process.
+
+use std::env;
+
+/// Label used to send the entity ID to the Datadog Agent.
+pub(crate) const ENTITY_ID_LABEL: &str = "dd.internal.entity_id";
+
+const ENTITY_ID_ENV_VAR: &str = "DD_ENTITY_ID";
+const ORIGIN_DETECTION_ENV_VAR: &str = "DD_ORIGIN_DETECTION_ENABLED";
+
+#[cfg(target_os = "linux")]
+const CGROUP_PATH: &str = "/proc/self/cgroup";
+#[cfg(target_os = "linux")]
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Returns the entity ID set in the environment, if any.
+pub(crate) fn entity_id() -> Option<String> {
+    env::var(ENTITY_ID_ENV_VAR).ok().filter(|id| !id.is_empty())
+}
+
+/// Returns `false` if origin detection has been explicitly disabled in the environment.
+pub(crate) fn enabled_by_env() -> bool {
+    env::var(ORIGIN_DETECTION_ENV_VAR).map_or(true, |value| !value.eq_ignore_ascii_case("false"))
+}
+
+/// Discovers the ID of the container the current process is running in.
+///
+/// The cgroup of the process is checked first, which covers cgroup v1 and most cgroup v2 setups. When running in a
+/// private cgroup namespace, the cgroup path is not visible, and we fall back to the mount information, which
+/// typically contains the container's `hostname`, `hosts`, and `resolv.conf` mounts under a path including the ID.
+#[cfg(target_os = "linux")]
+pub(crate) fn container_id() -> Option<String> {
+    let from_cgroup = std::fs::read_to_string(CGROUP_PATH)
+        .ok()
+        .and_then(|contents| container_id_from_cgroup(&contents));
+
+    from_cgroup.or_else(|| {
+        std::fs::read_to_string(MOUNTINFO_PATH)
+            .ok()
+            .and_then(|contents| container_id_from_mountinfo(&contents))
+    })
+}
+
+/// Discovers the ID of the container the current process is running in.
+///
+/// Container IDs can only be discovered on Linux.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn container_id() -> Option<String> {
+    None
+}
+
+/// Finds a container ID in the contents of `/proc/self/cgroup`.
+///
+/// Each line is in the form of `<hierarchy ID>:<controllers>:<path>`, where the last segment of the path may contain
+/// the container ID, optionally wrapped with a runtime-specific prefix and suffix, such as `docker-<ID>.scope`.
+fn container_id_from_cgroup(contents: &str) -> Option<String> {
+    contents.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        let segment = path.rsplit('/').next()?;
+        let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+
+        // Try the segment as-is, and then with each possible prefix stripped.
+        std::iter::once(segment)
+            .chain(segment.match_indices('-').map(|(idx, _)| &segment[idx + 1..]))
+            .find(|candidate| is_container_id(candidate))
+            .map(ToString::to_string)
+    })
+}
+
+/// Finds a container ID in the contents of `/proc/self/mountinfo`.
+///
+/// We look for mounts sourced from a path such as `/var/lib/docker/containers/<ID>/hostname`, as container runtimes
+/// bind mount these files into every container.
+fn container_id_from_mountinfo(contents: &str) -> Option<String> {
+    contents.lines().flat_map(str::split_whitespace).find_map(|field| {
+        let segments = field.split('/').collect::<Vec<_>>();
+        segments.windows(3).find_map(|window| {
+            let is_container_dir = window[0] == "containers" || window[0] == "sandboxes";
+            let is_container_file = matches!(window[2], "hostname" | "hosts" | "resolv.conf");
+            (is_container_dir && is_container_file && is_container_id(window[1]))
+                .then(|| window[1].to_string())
+        })
+    })
+}
+
+/// Returns `true` if the value looks like a container ID.
+///
+/// This covers the ID formats used by Docker and containerd (64 hexadecimal characters), ECS Fargate (32 hexadecimal
+/// characters, a dash, and a task-specific number), and UUIDs as used by some other runtimes.
+fn is_container_id(value: &str) -> bool {
+    fn is_hex(value: &str, len: usize) -> bool {
+        value.len() == len
+            && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    }
+
+    if is_hex(value, 64) {
+        return true;
+    }
+
+    if let Some((id, task)) = value.split_once('-') {
+        if is_hex(id, 32) && !task.is_empty() && task.bytes().all(|b| b.is_ascii_digit()) {
+            return true;
+        }
+    }
+
+    let parts = value.split('-').collect::<Vec<_>>();
+    parts.len() == 5 && parts.iter().zip([8, 4, 4, 4, 12]).all(|(part, len)| is_hex(part, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{container_id_from_cgroup, container_id_from_mountinfo, is_container_id};
+
+    const DOCKER_ID: &str = "3726184226f5d3147c25fdeab5b60097e378e8a720503a5e19ecfdf29f869860";
+
+    #[test]
+    fn container_id_formats() {
+        assert!(is_container_id(DOCKER_ID));
+        assert!(is_container_id("34dc0b5e626f2c5c4c5170e34b10e765-1234567890"));
+        assert!(is_container_id("0f2fbdea-7f5a-4b8d-97c7-b62d3bd4b2a4"));
+
+        assert!(!is_container_id(""));
+        assert!(!is_container_id("system.slice"));
+        assert!(!is_container_id(&DOCKER_ID[1..]));
+        assert!(!is_container_id(&DOCKER_ID.to_uppercase()));
+    }
+
+    #[test]
+    fn cgroup_v1() {
+        let contents = format!(
+            "12:pids:/docker/{DOCKER_ID}\n11:hugetlb:/docker/{DOCKER_ID}\n1:name=systemd:/docker/{DOCKER_ID}\n"
+        );
+        assert_eq!(container_id_from_cgroup(&contents).as_deref(), Some(DOCKER_ID));
+    }
+
+    #[test]
+    fn cgroup_v2_systemd_scope() {
+        let contents = format!(
+            "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice/cri-containerd-{DOCKER_ID}.scope\n"
+        );
+        assert_eq!(container_id_from_cgroup(&contents).as_deref(), Some(DOCKER_ID));
+    }
+
+    #[test]
+    fn cgroup_ecs_fargate() {
+        let contents = "9:perf_event:/ecs/55091c13-b8cf-4801-b527-f4601742204d/34dc0b5e626f2c5c4c5170e34b10e765-1234567890\n";
+        assert_eq!(
+            container_id_from_cgroup(contents).as_deref(),
+            Some("34dc0b5e626f2c5c4c5170e34b10e765-1234567890")
+        );
+    }
+
+    #[test]
+    fn cgroup_not_in_container() {
+        assert_eq!(
+            container_id_from_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            None
+        );
+        assert_eq!(container_id_from_cgroup("0::/\n"), None);
+    }
+
+    #[test]
+    fn mountinfo() {
+        let contents = format!(
+            "2046 2038 0:86 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw\n\
+             2064 2037 254:1 /docker/containers/{DOCKER_ID}/resolv.conf /etc/resolv.conf rw,relatime - ext4 /dev/vda1 rw\n\
+             2065 2037 254:1 /docker/containers/{DOCKER_ID}/hostname /etc/hostname rw,relatime - ext4 /dev/vda1 rw\n"
+        );
+        assert_eq!(container_id_from_mountinfo(&contents).as_deref(), Some(DOCKER_ID));
+
+        let contents = "2046 2038 0:86 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw\n";
+        assert_eq!(container_id_from_mountinfo(contents), None);
+    }
+}
@@ -1,17 +1,115 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
 
-use crate::state::State;
+use crate::{
+    event::{Event, ServiceCheck},
+    forwarder::Control,
+    set::Set,
+    state::State,
+    telemetry::TelemetrySnapshot,
+};
 
 /// A recorder that forwards metrics to a DogStatsD server.
 pub struct DogStatsDRecorder {
     state: Arc<State>,
+    control: Arc<Control>,
 }
 
 impl DogStatsDRecorder {
-    pub(crate) fn new(state: Arc<State>) -> Self {
-        DogStatsDRecorder { state }
+    pub(crate) fn new(control: Arc<Control>) -> Self {
+        DogStatsDRecorder { state: Arc::clone(control.state()), control }
+    }
+
+    /// Registers a set, returning a handle for recording unique values to it.
+    ///
+    /// As sets have no equivalent in `metrics`, they cannot be registered through the global recorder. Sets can be
+    /// registered here before installing the recorder, or afterwards with [`DogStatsDHandle::register_set`].
+    pub fn register_set(&self, key: &Key) -> Set {
+        Set::from_arc(self.state.register_set(key))
+    }
+
+    /// Returns a handle to the exporter.
+    ///
+    /// The handle can be used to interact with the exporter after the recorder has been installed globally.
+    pub fn handle(&self) -> DogStatsDHandle {
+        DogStatsDHandle { state: Arc::clone(&self.state), control: Arc::clone(&self.control) }
+    }
+}
+
+/// Handle to a DogStatsD exporter.
+///
+/// Handles are cheap to clone, and can be used to register sets and to send service checks and events through the same
+/// forwarder as metrics, as well as to flush the exporter on demand and to shut it down.
+#[derive(Clone)]
+pub struct DogStatsDHandle {
+    state: Arc<State>,
+    control: Arc<Control>,
+}
+
+impl DogStatsDHandle {
+    /// Registers a set, returning a handle for recording unique values to it.
+    ///
+    /// This allows sets to be registered after the recorder has been installed with
+    /// [`DogStatsDBuilder::install`][crate::DogStatsDBuilder::install]. Registering the same key more than once returns
+    /// handles to the same set.
+    pub fn register_set(&self, key: &Key) -> Set {
+        Set::from_arc(self.state.register_set(key))
+    }
+
+    /// Sends a service check.
+    ///
+    /// Service checks are queued and sent during the next flush, along with any metrics.
+    pub fn service_check(&self, check: ServiceCheck) {
+        self.state.push_service_check(check);
+    }
+
+    /// Sends an event.
+    ///
+    /// Events are queued and sent during the next flush, along with any metrics.
+    pub fn event(&self, event: Event) {
+        self.state.push_event(event);
+    }
+
+    /// Flushes the exporter.
+    ///
+    /// All aggregated metrics, as well as any queued service checks and events, are flushed and sent to the remote
+    /// server on the calling thread, without waiting for the next flush interval. This blocks until all payloads have
+    /// been sent, or have failed to send.
+    ///
+    /// This is useful for short-lived processes, such as batch jobs or serverless functions, which may exit before
+    /// the next scheduled flush.
+    pub fn flush(&self) {
+        self.control.flush();
+    }
+
+    /// Returns a snapshot of the exporter's telemetry.
+    ///
+    /// The snapshot holds cumulative totals, such as the number of points flushed and the number of packets sent or
+    /// dropped, since the exporter was built. Totals are updated after each flush, and are tracked regardless of
+    /// whether telemetry is enabled via [`DogStatsDBuilder::with_telemetry`][crate::DogStatsDBuilder::with_telemetry].
+    ///
+    /// This can be used to check the health of the exporter from within the application, such as failing a readiness
+    /// check if points are being dropped.
+    pub fn telemetry(&self) -> TelemetrySnapshot {
+        self.state.telemetry_snapshot()
+    }
+
+    /// Shuts down the exporter.
+    ///
+    /// The forwarder is signalled to stop: any payloads that it has yet to send are sent immediately, a final flush is
+    /// performed, and then the forwarder stops, joining the background thread when using the synchronous backend.
+    /// Metrics recorded after shutting down are only sent if [`flush`][Self::flush] is called.
+    ///
+    /// Blocks for up to `timeout` waiting for the forwarder to finish, and returns `true` if it finished in time.
+    ///
+    /// When using the asynchronous backend, the forwarder runs on the Tokio runtime, so this must not be called from
+    /// a thread that the runtime depends on to make progress, such as from within a current-thread runtime. Use
+    /// [`tokio::task::spawn_blocking`] in that case.
+    ///
+    /// [`tokio::task::spawn_blocking`]: https://docs.rs/tokio/latest/tokio/task/fn.spawn_blocking.html
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        self.control.shutdown(timeout)
     }
 }
 
@@ -0,0 +1,70 @@
+use std::cell::UnsafeCell;
+
+use rand::{rngs::OsRng, Rng as _, SeedableRng as _};
+use rand_xoshiro::Xoshiro256StarStar;
+
+use crate::matcher::PrefixMatcher;
+
+thread_local! {
+    static FAST_RNG: UnsafeCell<Xoshiro256StarStar> = {
+        UnsafeCell::new(Xoshiro256StarStar::try_from_rng(&mut OsRng).unwrap())
+    };
+}
+
+/// Returns `true` if a value should be kept when sampling at the given rate.
+pub(crate) fn should_sample(sample_rate: f64) -> bool {
+    FAST_RNG.with(|rng| {
+        // SAFETY: We know it's safe to take a mutable reference since we're getting a pointer to a thread-local value,
+        // and the reference never outlives the closure executing on this thread.
+        let rng = unsafe { &mut *rng.get() };
+        rng.random::<f64>() < sample_rate
+    })
+}
+
+/// Client-side sample rates, matched by metric name prefix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SampleRates {
+    rates: PrefixMatcher<f64>,
+}
+
+impl SampleRates {
+    /// Sets the sample rate for all metrics whose name starts with `prefix`.
+    pub fn insert(&mut self, prefix: String, sample_rate: f64) {
+        self.rates.insert(prefix, sample_rate);
+    }
+
+    /// Gets the sample rate for the given metric name.
+    ///
+    /// When multiple prefixes match, the longest one wins. If no prefix matches, or the sample rate is 1.0, `None` is
+    /// returned, as the metric is not sampled.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.rates.get(name).copied().filter(|sample_rate| *sample_rate < 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{should_sample, SampleRates};
+
+    #[test]
+    fn sample_rates_longest_prefix() {
+        let mut rates = SampleRates::default();
+        rates.insert("http.".to_string(), 0.5);
+        rates.insert("http.requests".to_string(), 0.1);
+        rates.insert("http.errors".to_string(), 1.0);
+
+        assert_eq!(rates.get("http.requests.total"), Some(0.1));
+        assert_eq!(rates.get("http.latency"), Some(0.5));
+        assert_eq!(rates.get("http.errors"), None);
+        assert_eq!(rates.get("db.queries"), None);
+
+        rates.insert("http.".to_string(), 0.25);
+        assert_eq!(rates.get("http.latency"), Some(0.25));
+    }
+
+    #[test]
+    fn sampling_keeps_roughly_rate() {
+        let kept = (0..100_000).filter(|_| should_sample(0.1)).count();
+        assert!((8_000..12_000).contains(&kept), "kept {kept} of 100,000 values");
+    }
+}
@@ -0,0 +1,281 @@
+//! Sanitization of metric names and tags.
+//!
+//! Metric names and tags are written as part of a plain-text protocol, where characters such as `|`, `,`, `#`, and
+//! newlines delimit the fields of a metric. If these characters appear in a name or tag, the payload ends up corrupted,
+//! so they are either replaced or stripped before being written.
+
+use metrics::Label;
+
+use crate::builder::ReservedCharacters;
+
+/// Default maximum length of a tag, in characters, matching the limit enforced by Datadog.
+pub(crate) const DEFAULT_MAX_TAG_LEN: usize = 200;
+
+/// Maximum length of a metric name, in characters, as enforced by Datadog.
+const MAX_METRIC_NAME_LEN: usize = 200;
+
+/// Character used in place of reserved characters.
+const REPLACEMENT_CHAR: char = '_';
+
+/// Sanitizes metric names and tags as they are written.
+#[derive(Clone, Debug)]
+pub(crate) struct Sanitizer {
+    pub reserved_chars: ReservedCharacters,
+    pub lowercase_tags: bool,
+    pub max_tag_len: usize,
+    pub validate_metric_names: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self {
+            reserved_chars: ReservedCharacters::Replace,
+            lowercase_tags: false,
+            max_tag_len: DEFAULT_MAX_TAG_LEN,
+            validate_metric_names: false,
+        }
+    }
+}
+
+impl Sanitizer {
+    /// Writes a metric name, along with an optional prefix.
+    ///
+    /// Characters in `reserved` are treated as reserved, in addition to those which are always reserved in names.
+    ///
+    /// Returns `false` if the name is empty after sanitization, or if metric name validation is enabled and the name is
+    /// not valid, in which case the buffer is left as it was.
+    pub fn write_metric_name(
+        &self,
+        buf: &mut Vec<u8>,
+        prefix: Option<&str>,
+        name: &str,
+        reserved: &[char],
+    ) -> bool {
+        let start = buf.len();
+        let is_reserved = |c| is_reserved_in_name(c) || reserved.contains(&c);
+        if let Some(prefix) = prefix {
+            self.write_component(buf, prefix, is_reserved, false, usize::MAX);
+            buf.push(b'.');
+        }
+        let name_len = self.write_component(buf, name, is_reserved, false, usize::MAX);
+
+        let is_valid = if self.validate_metric_names {
+            is_valid_metric_name(&buf[start..])
+        } else {
+            name_len > 0
+        };
+
+        if !is_valid {
+            buf.truncate(start);
+        }
+        is_valid
+    }
+
+    /// Writes a tag, in the form of `key<separator>value`, or only `key` if the value is empty.
+    ///
+    /// The tag is truncated to the maximum tag length, dropping the value entirely if the key alone reaches it. Characters
+    /// in `reserved` are treated as reserved in both the key and value, in addition to those which are always reserved.
+    ///
+    /// Returns `false` if the key is empty after sanitization, in which case the buffer is left as it was.
+    pub fn write_tag(
+        &self,
+        buf: &mut Vec<u8>,
+        label: &Label,
+        separator: u8,
+        reserved: &[char],
+    ) -> bool {
+        let start = buf.len();
+        let key_len = self.write_component(
+            buf,
+            label.key(),
+            |c| is_reserved_in_tag_key(c) || reserved.contains(&c),
+            true,
+            self.max_tag_len,
+        );
+        if key_len == 0 {
+            buf.truncate(start);
+            return false;
+        }
+
+        // If the label value is empty, we treat it as a bare label. This means all we write is something like
+        // `label_name`, instead of a more naive form, like `label_name:`.
+        let remaining = self.max_tag_len.saturating_sub(key_len + 1);
+        if !label.value().is_empty() && remaining > 0 {
+            let value_start = buf.len();
+            buf.push(separator);
+            let value_len = self.write_component(
+                buf,
+                label.value(),
+                |c| is_reserved_in_tag_value(c) || reserved.contains(&c),
+                false,
+                remaining,
+            );
+            if value_len == 0 {
+                buf.truncate(value_start);
+            }
+        }
+
+        true
+    }
+
+    /// Returns `true` if the tag would be written by [`write_tag`][Self::write_tag], rather than dropped as invalid.
+    pub fn is_valid_tag(&self, label: &Label, reserved: &[char]) -> bool {
+        self.write_tag(&mut Vec::new(), label, b':', reserved)
+    }
+
+    /// Writes a field of a service check or event, such as its name or hostname.
+    ///
+    /// Returns the number of characters written.
+    pub fn write_field(&self, buf: &mut Vec<u8>, value: &str) -> usize {
+        self.write_component(buf, value, is_reserved_in_field, false, usize::MAX)
+    }
+
+    /// Writes a sanitized string, up to `max_len` characters.
+    ///
+    /// Returns the number of characters written.
+    fn write_component(
+        &self,
+        buf: &mut Vec<u8>,
+        value: &str,
+        is_reserved: impl Fn(char) -> bool,
+        lowercase: bool,
+        max_len: usize,
+    ) -> usize {
+        let lowercase = lowercase && self.lowercase_tags;
+
+        // Most values need no changes at all, so we check for that first, and write them as-is.
+        let needs_lowercasing = lowercase && value.chars().any(char::is_uppercase);
+        if !needs_lowercasing && value.len() <= max_len && !value.chars().any(&is_reserved) {
+            buf.extend_from_slice(value.as_bytes());
+            return value.chars().count();
+        }
+
+        let mut written = 0;
+        let mut char_buf = [0; 4];
+        for c in value.chars() {
+            let c = if is_reserved(c) {
+                match self.reserved_chars {
+                    ReservedCharacters::Replace => REPLACEMENT_CHAR,
+                    ReservedCharacters::Strip => continue,
+                }
+            } else {
+                c
+            };
+
+            let mut write_char = |c: char| {
+                if written < max_len {
+                    buf.extend_from_slice(c.encode_utf8(&mut char_buf).as_bytes());
+                    written += 1;
+                }
+            };
+            if lowercase {
+                c.to_lowercase().for_each(&mut write_char);
+            } else {
+                write_char(c);
+            }
+
+            if written == max_len {
+                break;
+            }
+        }
+
+        written
+    }
+}
+
+fn is_reserved_in_name(c: char) -> bool {
+    matches!(c, ':' | '|' | '\n' | '\r')
+}
+
+fn is_reserved_in_field(c: char) -> bool {
+    matches!(c, '|' | '\n' | '\r')
+}
+
+fn is_reserved_in_tag_key(c: char) -> bool {
+    c == ':' || is_reserved_in_tag_value(c)
+}
+
+fn is_reserved_in_tag_value(c: char) -> bool {
+    matches!(c, '|' | ',' | '#' | '\n' | '\r')
+}
+
+/// Returns `true` if the name is a valid Datadog metric name.
+///
+/// Metric names must start with a letter, contain only ASCII alphanumerics, underscores, and periods, and be no longer
+/// than 200 characters.
+fn is_valid_metric_name(name: &[u8]) -> bool {
+    name.first().is_some_and(u8::is_ascii_alphabetic)
+        && name.len() <= MAX_METRIC_NAME_LEN
+        && name.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'.')
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::Label;
+
+    use super::Sanitizer;
+    use crate::builder::ReservedCharacters;
+
+    fn tag(sanitizer: &Sanitizer, key: &str, value: &str) -> Option<String> {
+        let mut buf = b"prefix".to_vec();
+        let label = Label::new(key.to_string(), value.to_string());
+        let written = sanitizer.write_tag(&mut buf, &label, b':', &[]);
+        let tag = String::from_utf8(buf.split_off(6)).unwrap();
+        if written {
+            Some(tag)
+        } else {
+            assert!(tag.is_empty());
+            None
+        }
+    }
+
+    fn metric_name(sanitizer: &Sanitizer, prefix: Option<&str>, name: &str) -> Option<String> {
+        let mut buf = Vec::new();
+        sanitizer
+            .write_metric_name(&mut buf, prefix, name, &[])
+            .then(|| String::from_utf8(buf).unwrap())
+    }
+
+    #[test]
+    fn reserved_characters() {
+        let replace = Sanitizer::default();
+        assert_eq!(tag(&replace, "env", "prod").as_deref(), Some("env:prod"));
+        assert_eq!(tag(&replace, "flag", "").as_deref(), Some("flag"));
+        assert_eq!(tag(&replace, "a:b|c", "d:e,f#g\nh").as_deref(), Some("a_b_c:d:e_f_g_h"));
+        assert_eq!(metric_name(&replace, Some("app"), "a:b|c").as_deref(), Some("app.a_b_c"));
+
+        let strip = Sanitizer { reserved_chars: ReservedCharacters::Strip, ..Sanitizer::default() };
+        assert_eq!(tag(&strip, "a:b|c", "d:e,f#g\nh").as_deref(), Some("abc:d:efgh"));
+        assert_eq!(tag(&strip, "flag", "|,").as_deref(), Some("flag"));
+        assert_eq!(tag(&strip, "|,", "value"), None);
+        assert_eq!(metric_name(&strip, None, "a:b|c").as_deref(), Some("abc"));
+        assert_eq!(metric_name(&strip, None, "|"), None);
+    }
+
+    #[test]
+    fn lowercase_and_truncate() {
+        let sanitizer = Sanitizer { lowercase_tags: true, max_tag_len: 8, ..Sanitizer::default() };
+        assert_eq!(tag(&sanitizer, "Env", "PROD").as_deref(), Some("env:PROD"));
+        assert_eq!(tag(&sanitizer, "env", "production").as_deref(), Some("env:prod"));
+        assert_eq!(tag(&sanitizer, "region", "us").as_deref(), Some("region:u"));
+        assert_eq!(tag(&sanitizer, "service", "web").as_deref(), Some("service"));
+        assert_eq!(tag(&sanitizer, "Ünïcödé_key", "").as_deref(), Some("ünïcödé_"));
+    }
+
+    #[test]
+    fn metric_name_validation() {
+        let sanitizer = Sanitizer { validate_metric_names: true, ..Sanitizer::default() };
+        assert_eq!(
+            metric_name(&sanitizer, Some("app"), "requests.total").as_deref(),
+            Some("app.requests.total")
+        );
+        assert_eq!(
+            metric_name(&sanitizer, None, "requests:total").as_deref(),
+            Some("requests_total")
+        );
+        assert_eq!(metric_name(&sanitizer, None, "1requests"), None);
+        assert_eq!(metric_name(&sanitizer, None, "requests-total"), None);
+        assert_eq!(metric_name(&sanitizer, None, &"a".repeat(201)), None);
+        assert_eq!(metric_name(&sanitizer, None, ""), None);
+    }
+}
@@ -0,0 +1,45 @@
+use std::{fmt, sync::Arc};
+
+use crate::storage::AtomicSet;
+
+/// A handle for recording unique values to a DogStatsD set.
+///
+/// Sets count the number of unique values seen during each flush interval, such as the number of unique users making
+/// requests. Values are de-duplicated on the client side, so each unique value is only sent once per flush, regardless
+/// of how many times it was inserted.
+///
+/// As sets have no equivalent in `metrics`, handles must be acquired directly from the exporter with
+/// [`DogStatsDHandle::register_set`][crate::DogStatsDHandle::register_set] or
+/// [`DogStatsDRecorder::register_set`][crate::DogStatsDRecorder::register_set]. Handles are cheap to clone, and all
+/// handles for the same key refer to the same set.
+#[derive(Clone, Default)]
+pub struct Set {
+    inner: Option<Arc<AtomicSet>>,
+}
+
+impl Set {
+    /// Creates a no-op `Set` which does nothing.
+    pub fn noop() -> Self {
+        Self { inner: None }
+    }
+
+    pub(crate) fn from_arc(inner: Arc<AtomicSet>) -> Self {
+        Self { inner: Some(inner) }
+    }
+
+    /// Inserts a value into the set.
+    ///
+    /// Values containing `:`, `|`, or a newline cannot be represented in the DogStatsD protocol, and will be dropped
+    /// when flushed.
+    pub fn insert<V: AsRef<str>>(&self, value: V) {
+        if let Some(set) = &self.inner {
+            set.insert(value.as_ref());
+        }
+    }
+}
+
+impl fmt::Debug for Set {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Set").field("noop", &self.inner.is_none()).finish()
+    }
+}
@@ -1,12 +1,21 @@
-use std::{collections::HashSet, time::SystemTime};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::SystemTime,
+};
 
 use metrics::Key;
 use metrics_util::registry::Registry;
 use tracing::error;
 
 use crate::{
-    builder::AggregationMode, storage::ClientSideAggregatedStorage, telemetry::TelemetryUpdate,
-    writer::PayloadWriter,
+    builder::{AggregationMode, GaugeAggregation},
+    event::{Event, ServiceCheck},
+    matcher::PrefixMatcher,
+    sampling::SampleRates,
+    storage::{AtomicGauge, AtomicSet, ClientSideAggregatedStorage, HistogramOverrides},
+    telemetry::{TelemetrySnapshot, TelemetryUpdate},
+    writer::{PayloadWriter, WriteResult},
 };
 
 /// Exporter state configuration.
@@ -28,14 +37,33 @@ pub struct StateConfiguration {
     /// Whether or not to emit histograms as distributions.
     pub histograms_as_distributions: bool,
 
+    /// Per-metric overrides of the histogram settings.
+    pub histogram_overrides: HistogramOverrides,
+
     /// Global prefix/namespace to use for all metrics
     pub global_prefix: Option<String>,
+
+    /// Client-side sample rates for counters and histograms.
+    pub sample_rates: SampleRates,
+
+    /// Aggregations for gauges, matched by metric name prefix.
+    pub gauge_aggregations: PrefixMatcher<Vec<GaugeAggregation>>,
+}
+
+/// A service check or event waiting to be sent.
+enum Pending {
+    ServiceCheck(ServiceCheck),
+    Event(Event),
 }
 
 /// Exporter state.
 pub(crate) struct State {
     config: StateConfiguration,
     registry: Registry<Key, ClientSideAggregatedStorage>,
+    sets: RwLock<HashMap<Key, Arc<AtomicSet>>>,
+    pending: Mutex<Vec<Pending>>,
+    last_flush: Mutex<FlushState>,
+    telemetry_totals: Mutex<TelemetrySnapshot>,
 }
 
 impl State {
@@ -45,7 +73,15 @@ impl State {
             registry: Registry::new(ClientSideAggregatedStorage::new(
                 config.histogram_sampling,
                 config.histogram_reservoir_size,
+                config.histograms_as_distributions,
+                config.histogram_overrides.clone(),
+                config.sample_rates.clone(),
+                config.gauge_aggregations.clone(),
             )),
+            sets: RwLock::new(HashMap::new()),
+            pending: Mutex::new(Vec::new()),
+            last_flush: Mutex::new(FlushState::default()),
+            telemetry_totals: Mutex::new(TelemetrySnapshot::default()),
             config,
         }
     }
@@ -55,11 +91,44 @@ impl State {
         &self.registry
     }
 
+    /// Gets or creates the set for the given key.
+    pub fn register_set(&self, key: &Key) -> Arc<AtomicSet> {
+        if let Some(set) = self.sets.read().unwrap_or_else(PoisonError::into_inner).get(key) {
+            return Arc::clone(set);
+        }
+
+        let mut sets = self.sets.write().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(sets.entry(key.clone()).or_insert_with(|| Arc::new(AtomicSet::new())))
+    }
+
+    /// Queues a service check to be sent during the next flush.
+    pub fn push_service_check(&self, check: ServiceCheck) {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Pending::ServiceCheck(check));
+    }
+
+    /// Queues an event to be sent during the next flush.
+    pub fn push_event(&self, event: Event) {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).push(Pending::Event(event));
+    }
+
     /// Returns `true` if telemetry is enabled.
     pub fn telemetry_enabled(&self) -> bool {
         self.config.telemetry
     }
 
+    /// Adds a telemetry update to the running totals.
+    pub fn record_telemetry(&self, update: &TelemetryUpdate) {
+        self.telemetry_totals.lock().unwrap_or_else(PoisonError::into_inner).apply_update(update);
+    }
+
+    /// Returns a snapshot of the running telemetry totals.
+    pub fn telemetry_snapshot(&self) -> TelemetrySnapshot {
+        *self.telemetry_totals.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
     fn get_aggregation_timestamp(&self) -> Option<u64> {
         match self.config.agg_mode {
             AggregationMode::Aggressive => {
@@ -69,13 +138,48 @@ impl State {
         }
     }
 
-    /// Flushes all registered metrics to the given payload writer.
-    pub fn flush(
+    /// Writes a gauge, applying any aggregations configured for it.
+    ///
+    /// Returns the write result and the number of points flushed.
+    fn write_gauge(
         &self,
-        flush_state: &mut FlushState,
         writer: &mut PayloadWriter,
-        telemetry: &mut TelemetryUpdate,
-    ) {
+        key: &Key,
+        gauge: &AtomicGauge,
+        prefix: Option<&str>,
+    ) -> (WriteResult, u64) {
+        let timestamp = self.get_aggregation_timestamp();
+
+        let aggregations = gauge.aggregations();
+        if aggregations.is_empty() {
+            let (value, points_flushed) = gauge.flush();
+            return (writer.write_gauge(key, value, timestamp, prefix), points_flushed);
+        }
+
+        let (summary, points_flushed) = gauge.flush_summary();
+        if let [aggregation] = aggregations {
+            let result = writer.write_gauge(key, summary.get(*aggregation), timestamp, prefix);
+            return (result, points_flushed);
+        }
+
+        // Each aggregation is sent as its own gauge, suffixed with the aggregation name.
+        let mut result = WriteResult::new();
+        for aggregation in aggregations {
+            let name = format!("{}.{}", key.name(), aggregation.suffix());
+            let suffixed_key = Key::from_parts(name, key.labels().cloned().collect::<Vec<_>>());
+            let value = summary.get(*aggregation);
+            result.merge(&writer.write_gauge(&suffixed_key, value, timestamp, prefix));
+        }
+
+        (result, points_flushed)
+    }
+
+    /// Flushes all registered metrics to the given payload writer.
+    ///
+    /// Concurrent flushes are serialized, so that each aggregated value is only ever written by a single flush.
+    pub fn flush(&self, writer: &mut PayloadWriter, telemetry: &mut TelemetryUpdate) {
+        let mut flush_state = self.last_flush.lock().unwrap_or_else(PoisonError::into_inner);
+
         // TODO: Delete metrics when they are idle. (This needs support in the handles before we could do this.)
 
         let counters = self.registry.get_counter_handles();
@@ -105,8 +209,13 @@ impl State {
                 self.config.global_prefix.as_deref()
             };
 
-            let result =
-                writer.write_counter(&key, value, self.get_aggregation_timestamp(), prefix);
+            let result = writer.write_counter(
+                &key,
+                value,
+                self.get_aggregation_timestamp(),
+                counter.sample_rate(),
+                prefix,
+            );
             if result.any_failures() {
                 let points_dropped = result.points_dropped();
                 error!(
@@ -126,13 +235,12 @@ impl State {
         telemetry.increment_gauge_contexts(gauges.len());
 
         for (key, gauge) in gauges {
-            let (value, points_flushed) = gauge.flush();
             let prefix = if key.name().starts_with("datadog.dogstatsd.client") {
                 None
             } else {
                 self.config.global_prefix.as_deref()
             };
-            let result = writer.write_gauge(&key, value, self.get_aggregation_timestamp(), prefix);
+            let (result, points_flushed) = self.write_gauge(writer, &key, &gauge, prefix);
             if result.any_failures() {
                 let points_dropped = result.points_dropped();
                 error!(metric_name = key.name(), points_dropped, "Failed to build gauge payload.");
@@ -160,7 +268,7 @@ impl State {
 
             histogram.flush(|maybe_sample_rate, values| {
                 let points_len = values.len();
-                let result = if self.config.histograms_as_distributions {
+                let result = if histogram.is_distribution() {
                     writer.write_distribution(&key, values, maybe_sample_rate, prefix)
                 } else {
                     writer.write_histogram(&key, values, maybe_sample_rate, prefix)
@@ -193,16 +301,72 @@ impl State {
         }
 
         telemetry.increment_histogram_contexts(active_histograms);
+
+        let sets = self
+            .sets
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(key, set)| (key.clone(), Arc::clone(set)))
+            .collect::<Vec<_>>();
+        let mut active_sets = 0;
+
+        for (key, set) in sets {
+            let (values, _) = set.flush();
+            if values.is_empty() {
+                continue;
+            }
+
+            active_sets += 1;
+            let prefix = self.config.global_prefix.as_deref();
+
+            let result = writer.write_set(&key, values.iter().map(String::as_str), prefix);
+            telemetry.increment_set_points(result.payloads_written());
+            if result.any_failures() {
+                let points_dropped = result.points_dropped();
+                error!(metric_name = key.name(), points_dropped, "Failed to build set payload(s).");
+
+                telemetry.track_packet_serializer_failed();
+            }
+        }
+
+        telemetry.increment_set_contexts(active_sets);
+
+        let pending =
+            std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner));
+        for pending in pending {
+            let result = match &pending {
+                Pending::ServiceCheck(check) => {
+                    telemetry.increment_service_checks(1);
+                    writer.write_service_check(check)
+                }
+                Pending::Event(event) => {
+                    telemetry.increment_events(1);
+                    writer.write_event(event)
+                }
+            };
+
+            if result.any_failures() {
+                error!("Failed to build service check/event payload.");
+
+                telemetry.track_packet_serializer_failed();
+            }
+        }
+
+        let invalid_tags = writer.take_invalid_tags();
+        if invalid_tags > 0 {
+            error!(invalid_tags, "Dropped invalid tag(s) while building payloads.");
+
+            telemetry.track_tags_dropped(invalid_tags);
+        }
     }
 }
 
 /// Flush state.
 ///
-/// This type contains state information related to flush operations, and is intended to be held by the forwarder and
-/// used during each flush. This allows the flush state information to be updated without needing to wrap calls to
-/// `State` within a lock.
+/// This type contains state information related to flush operations, which is carried over from one flush to the next.
 #[derive(Default)]
-pub struct FlushState {
+struct FlushState {
     idle_counters: HashSet<Key>,
 }
 
@@ -1,11 +1,12 @@
 use std::{
+    collections::HashSet,
     slice::Iter,
     sync::{
         atomic::{
             AtomicBool, AtomicU64,
             Ordering::{AcqRel, Acquire, Relaxed, Release},
         },
-        Arc,
+        Arc, Mutex, PoisonError,
     },
 };
 
@@ -18,21 +19,42 @@ use metrics_util::{
     },
 };
 
+use crate::{
+    builder::GaugeAggregation,
+    matcher::PrefixMatcher,
+    sampling::{should_sample, SampleRates},
+};
+
 pub(crate) struct AtomicCounter {
     is_absolute: AtomicBool,
     last: AtomicU64,
     current: AtomicU64,
     updates: AtomicU64,
+    sample_rate: Option<f64>,
 }
 
 impl AtomicCounter {
     /// Creates a new `AtomicCounter`.
-    fn new() -> Self {
+    ///
+    /// When a sample rate is given, incremental updates are randomly dropped to match the sample rate.
+    fn new(sample_rate: Option<f64>) -> Self {
         Self {
             is_absolute: AtomicBool::new(false),
             last: AtomicU64::new(0),
             current: AtomicU64::new(0),
             updates: AtomicU64::new(0),
+            sample_rate,
+        }
+    }
+
+    /// Returns the sample rate of the flushed values, if the counter is being sampled.
+    ///
+    /// Absolute updates are never sampled, as the deltas calculated from them would otherwise be skewed.
+    pub fn sample_rate(&self) -> Option<f64> {
+        if self.is_absolute.load(Acquire) {
+            None
+        } else {
+            self.sample_rate
         }
     }
 
@@ -50,6 +72,12 @@ impl AtomicCounter {
 
 impl CounterFn for AtomicCounter {
     fn increment(&self, value: u64) {
+        if let Some(sample_rate) = self.sample_rate {
+            if !should_sample(sample_rate) {
+                return;
+            }
+        }
+
         self.is_absolute.store(false, Release);
         self.current.fetch_add(value, Relaxed);
         self.updates.fetch_add(1, Relaxed);
@@ -69,15 +97,68 @@ impl CounterFn for AtomicCounter {
     }
 }
 
+/// Summary of the values a gauge has taken since the last flush.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GaugeSummary {
+    last: f64,
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+impl GaugeSummary {
+    fn new(value: f64) -> Self {
+        Self { last: value, min: value, max: value, sum: value, count: 1 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.last = value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Returns the value of the given aggregation.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn get(&self, aggregation: GaugeAggregation) -> f64 {
+        match aggregation {
+            GaugeAggregation::Last => self.last,
+            GaugeAggregation::Min => self.min,
+            GaugeAggregation::Max => self.max,
+            GaugeAggregation::Avg => self.sum / self.count as f64,
+            GaugeAggregation::Sum => self.sum,
+        }
+    }
+}
+
 pub(crate) struct AtomicGauge {
     inner: AtomicU64,
     updates: AtomicU64,
+    aggregations: Vec<GaugeAggregation>,
+    summary: Mutex<Option<GaugeSummary>>,
 }
 
 impl AtomicGauge {
     /// Creates a new `AtomicGauge`.
-    fn new() -> Self {
-        Self { inner: AtomicU64::new(0.0f64.to_bits()), updates: AtomicU64::new(0) }
+    ///
+    /// When aggregations are given, every value the gauge takes is tracked until the next flush, rather than only the
+    /// last one.
+    fn new(aggregations: Vec<GaugeAggregation>) -> Self {
+        Self {
+            inner: AtomicU64::new(0.0f64.to_bits()),
+            updates: AtomicU64::new(0),
+            aggregations,
+            summary: Mutex::new(None),
+        }
+    }
+
+    /// Returns the aggregations configured for this gauge.
+    ///
+    /// If empty, only the last value is tracked, and the gauge should be flushed with [`flush`][Self::flush].
+    pub fn aggregations(&self) -> &[GaugeAggregation] {
+        &self.aggregations
     }
 
     /// Flushes the current gauge value and the number of updates since the last flush.
@@ -87,63 +168,116 @@ impl AtomicGauge {
 
         (current, updates)
     }
+
+    /// Flushes the summary of the gauge values and the number of updates since the last flush.
+    ///
+    /// If the gauge hasn't been updated since the last flush, the summary only contains its current value.
+    pub fn flush_summary(&self) -> (GaugeSummary, u64) {
+        let summary = self.summary.lock().unwrap_or_else(PoisonError::into_inner).take();
+        let (current, updates) = self.flush();
+
+        (summary.unwrap_or_else(|| GaugeSummary::new(current)), updates)
+    }
+
+    fn observe(&self, value: f64) {
+        if self.aggregations.is_empty() {
+            return;
+        }
+
+        let mut summary = self.summary.lock().unwrap_or_else(PoisonError::into_inner);
+        match summary.as_mut() {
+            Some(summary) => summary.observe(value),
+            None => *summary = Some(GaugeSummary::new(value)),
+        }
+    }
 }
 
 impl GaugeFn for AtomicGauge {
     fn increment(&self, value: f64) {
-        self.inner
+        let previous = self
+            .inner
             .fetch_update(AcqRel, Relaxed, |current| {
                 let new = f64::from_bits(current) + value;
                 Some(f64::to_bits(new))
             })
             .expect("should never fail to update gauge");
         self.updates.fetch_add(1, Relaxed);
+        self.observe(f64::from_bits(previous) + value);
     }
 
     fn decrement(&self, value: f64) {
-        self.inner
+        let previous = self
+            .inner
             .fetch_update(AcqRel, Relaxed, |current| {
                 let new = f64::from_bits(current) - value;
                 Some(f64::to_bits(new))
             })
             .expect("should never fail to update gauge");
         self.updates.fetch_add(1, Relaxed);
+        self.observe(f64::from_bits(previous) - value);
     }
 
     fn set(&self, value: f64) {
         self.inner.store(value.to_bits(), Release);
         self.updates.fetch_add(1, Relaxed);
+        self.observe(value);
     }
 }
 
-pub(crate) enum AtomicHistogram {
+enum HistogramValues {
     Raw(AtomicBucket<f64>),
     Sampled(AtomicSamplingReservoir),
 }
 
+pub(crate) struct AtomicHistogram {
+    values: HistogramValues,
+    sample_rate: Option<f64>,
+    is_distribution: bool,
+}
+
 impl AtomicHistogram {
     /// Creates a new `AtomicHistogram` based on the given sampling configuration.
-    fn new(sampling: bool, reservoir_size: usize) -> Self {
-        if sampling {
-            AtomicHistogram::Sampled(AtomicSamplingReservoir::new(reservoir_size))
+    ///
+    /// When a sample rate is given, values are randomly dropped to match the sample rate before being recorded.
+    fn new(
+        sampling: bool,
+        reservoir_size: usize,
+        sample_rate: Option<f64>,
+        is_distribution: bool,
+    ) -> Self {
+        let values = if sampling {
+            HistogramValues::Sampled(AtomicSamplingReservoir::new(reservoir_size))
         } else {
-            AtomicHistogram::Raw(AtomicBucket::new())
-        }
+            HistogramValues::Raw(AtomicBucket::new())
+        };
+
+        Self { values, sample_rate, is_distribution }
+    }
+
+    /// Returns `true` if the histogram should be sent as a distribution.
+    pub fn is_distribution(&self) -> bool {
+        self.is_distribution
     }
 
     /// Returns `true` if the histogram is empty.
     pub fn is_empty(&self) -> bool {
-        match self {
-            AtomicHistogram::Raw(bucket) => bucket.is_empty(),
-            AtomicHistogram::Sampled(reservoir) => reservoir.is_empty(),
+        match &self.values {
+            HistogramValues::Raw(bucket) => bucket.is_empty(),
+            HistogramValues::Sampled(reservoir) => reservoir.is_empty(),
         }
     }
 
     /// Records a new value in the histogram.
     pub fn record(&self, value: f64) {
-        match self {
-            AtomicHistogram::Raw(bucket) => bucket.push(value),
-            AtomicHistogram::Sampled(reservoir) => reservoir.push(value),
+        if let Some(sample_rate) = self.sample_rate {
+            if !should_sample(sample_rate) {
+                return;
+            }
+        }
+
+        match &self.values {
+            HistogramValues::Raw(bucket) => bucket.push(value),
+            HistogramValues::Sampled(reservoir) => reservoir.push(value),
         }
     }
 
@@ -152,8 +286,9 @@ impl AtomicHistogram {
     ///
     /// If the sample rate is `None`, the histogram has not been sampled at all and the iterator will contain all of the
     /// values since the last flush. Otherwise, the sample rate will be a value between 0.0 and 1.0, indicating the
-    /// extent of sampling which has occurred since the last flush. The values may not _necessarily_ have been sampled,
-    /// in which case the sample rate will be a nominal 1.0 value.
+    /// extent of sampling which has occurred since the last flush, combining both the client-side sample rate and any
+    /// reservoir sampling. The values may not _necessarily_ have been sampled, in which case the sample rate will be a
+    /// nominal 1.0 value.
     ///
     /// Depending on the underlying histogram implementation, the closure may be called multiple times. Callers are
     /// responsible for using the sample rate and reported length of the iterator ([`Values<'a>`] implements
@@ -162,12 +297,14 @@ impl AtomicHistogram {
     where
         F: FnMut(Option<f64>, Values<'_>),
     {
-        match self {
-            AtomicHistogram::Raw(bucket) => bucket.clear_with(|values| {
-                f(None, Values::Raw(values.iter()));
+        let client_sample_rate = self.sample_rate;
+        match &self.values {
+            HistogramValues::Raw(bucket) => bucket.clear_with(|values| {
+                f(client_sample_rate, Values::Raw(values.iter()));
             }),
-            AtomicHistogram::Sampled(reservoir) => reservoir.consume(|values| {
-                f(Some(values.sample_rate()), Values::Sampled(values));
+            HistogramValues::Sampled(reservoir) => reservoir.consume(|values| {
+                let sample_rate = values.sample_rate() * client_sample_rate.unwrap_or(1.0);
+                f(Some(sample_rate), Values::Sampled(values));
             }),
         }
     }
@@ -204,6 +341,53 @@ impl ExactSizeIterator for Values<'_> {
     }
 }
 
+pub(crate) struct AtomicSet {
+    values: Mutex<HashSet<String>>,
+    updates: AtomicU64,
+}
+
+impl AtomicSet {
+    /// Creates a new `AtomicSet`.
+    pub fn new() -> Self {
+        Self { values: Mutex::new(HashSet::new()), updates: AtomicU64::new(0) }
+    }
+
+    /// Inserts a value into the set.
+    ///
+    /// Values are de-duplicated until the next flush, so only the first insert of a given value allocates.
+    pub fn insert(&self, value: &str) {
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        if !values.contains(value) {
+            values.insert(value.to_string());
+        }
+        drop(values);
+
+        self.updates.fetch_add(1, Relaxed);
+    }
+
+    /// Flushes the set, returning the unique values, and the number of updates, since the last flush.
+    pub fn flush(&self) -> (HashSet<String>, u64) {
+        let values =
+            std::mem::take(&mut *self.values.lock().unwrap_or_else(PoisonError::into_inner));
+        let updates = self.updates.swap(0, AcqRel);
+
+        (values, updates)
+    }
+}
+
+/// Per-metric overrides of the histogram settings, matched by metric name prefix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HistogramOverrides {
+    /// Whether or not to sample histograms.
+    pub sampling: PrefixMatcher<bool>,
+
+    /// Reservoir size when histogram sampling is enabled.
+    pub reservoir_size: PrefixMatcher<usize>,
+
+    /// Whether or not to emit histograms as distributions.
+    pub as_distributions: PrefixMatcher<bool>,
+}
+
 /// Client-side aggregated metrics storage.
 ///
 /// This storage implementation is designed to be used for aggregating metric values on the client side before sending
@@ -213,8 +397,13 @@ impl ExactSizeIterator for Values<'_> {
 /// # Behavior
 ///
 /// - Counters are aggregated by summing the increments since the last flush.
-/// - Gauges simply maintain their standard "last write wins" behavior and emit the latest value when flushed.
+/// - Gauges simply maintain their standard "last write wins" behavior and emit the latest value when flushed, unless
+///   aggregations are configured for them, in which case a summary of all values since the last flush is kept.
 /// - Histograms have their individual values stored as there ia no suitable way to aggregate them.
+/// - Counters and histograms with a client-side sample rate randomly drop updates to match the sample rate, and report
+///   the sample rate when flushed so that the server can scale them back up.
+/// - Sets, which are not part of `Storage` as they have no equivalent in `metrics`, store each unique value seen since
+///   the last flush.
 ///
 /// # Absolute versus incremental updates to counters
 ///
@@ -231,12 +420,30 @@ impl ExactSizeIterator for Values<'_> {
 pub(crate) struct ClientSideAggregatedStorage {
     histogram_sampling: bool,
     histogram_reservoir_size: usize,
+    histograms_as_distributions: bool,
+    histogram_overrides: HistogramOverrides,
+    sample_rates: SampleRates,
+    gauge_aggregations: PrefixMatcher<Vec<GaugeAggregation>>,
 }
 
 impl ClientSideAggregatedStorage {
     /// Creates a new `ClientSideAggregatedStorage`.
-    pub fn new(histogram_sampling: bool, histogram_reservoir_size: usize) -> Self {
-        Self { histogram_sampling, histogram_reservoir_size }
+    pub fn new(
+        histogram_sampling: bool,
+        histogram_reservoir_size: usize,
+        histograms_as_distributions: bool,
+        histogram_overrides: HistogramOverrides,
+        sample_rates: SampleRates,
+        gauge_aggregations: PrefixMatcher<Vec<GaugeAggregation>>,
+    ) -> Self {
+        Self {
+            histogram_sampling,
+            histogram_reservoir_size,
+            histograms_as_distributions,
+            histogram_overrides,
+            sample_rates,
+            gauge_aggregations,
+        }
     }
 }
 
@@ -245,28 +452,47 @@ impl Storage<Key> for ClientSideAggregatedStorage {
     type Gauge = Arc<AtomicGauge>;
     type Histogram = Arc<AtomicHistogram>;
 
-    fn counter(&self, _: &Key) -> Self::Counter {
-        Arc::new(AtomicCounter::new())
+    fn counter(&self, key: &Key) -> Self::Counter {
+        Arc::new(AtomicCounter::new(self.sample_rates.get(key.name())))
     }
 
-    fn gauge(&self, _: &Key) -> Self::Gauge {
-        Arc::new(AtomicGauge::new())
+    fn gauge(&self, key: &Key) -> Self::Gauge {
+        let aggregations = self.gauge_aggregations.get(key.name()).cloned().unwrap_or_default();
+        Arc::new(AtomicGauge::new(aggregations))
     }
 
-    fn histogram(&self, _: &Key) -> Self::Histogram {
-        Arc::new(AtomicHistogram::new(self.histogram_sampling, self.histogram_reservoir_size))
+    fn histogram(&self, key: &Key) -> Self::Histogram {
+        let name = key.name();
+        let overrides = &self.histogram_overrides;
+        Arc::new(AtomicHistogram::new(
+            overrides.sampling.get(name).copied().unwrap_or(self.histogram_sampling),
+            overrides.reservoir_size.get(name).copied().unwrap_or(self.histogram_reservoir_size),
+            self.sample_rates.get(name),
+            overrides
+                .as_distributions
+                .get(name)
+                .copied()
+                .unwrap_or(self.histograms_as_distributions),
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use metrics::{CounterFn as _, GaugeFn as _};
+    use std::collections::HashSet;
 
-    use super::{AtomicCounter, AtomicGauge};
+    use metrics::{CounterFn as _, GaugeFn as _, Key};
+    use metrics_util::registry::Storage as _;
+
+    use super::{
+        AtomicCounter, AtomicGauge, AtomicHistogram, AtomicSet, ClientSideAggregatedStorage,
+        HistogramOverrides, HistogramValues,
+    };
+    use crate::{builder::GaugeAggregation, matcher::PrefixMatcher, sampling::SampleRates};
 
     #[test]
     fn atomic_counter_increment() {
-        let counter = AtomicCounter::new();
+        let counter = AtomicCounter::new(None);
         assert_eq!(counter.flush(), (0, 0));
 
         counter.increment(42);
@@ -287,7 +513,7 @@ mod tests {
         let third_value_delta = 13;
         let third_value = second_value + third_value_delta;
 
-        let counter = AtomicCounter::new();
+        let counter = AtomicCounter::new(None);
         assert_eq!(counter.flush(), (0, 0));
 
         counter.absolute(first_value);
@@ -306,7 +532,7 @@ mod tests {
         let second_value_delta = 66;
         let second_value = first_value + second_value_delta;
 
-        let counter = AtomicCounter::new();
+        let counter = AtomicCounter::new(None);
         assert_eq!(counter.flush(), (0, 0));
 
         counter.absolute(first_value);
@@ -316,7 +542,7 @@ mod tests {
 
     #[test]
     fn atomic_counter_incremental_to_absolute_reset() {
-        let counter = AtomicCounter::new();
+        let counter = AtomicCounter::new(None);
         assert_eq!(counter.flush(), (0, 0));
 
         counter.increment(27);
@@ -335,7 +561,7 @@ mod tests {
 
     #[test]
     fn atomic_gauge_increment() {
-        let gauge = AtomicGauge::new();
+        let gauge = AtomicGauge::new(Vec::new());
         assert_eq!(gauge.flush(), (0.0, 0));
 
         gauge.increment(42.0);
@@ -347,7 +573,7 @@ mod tests {
 
     #[test]
     fn atomic_gauge_decrement() {
-        let gauge = AtomicGauge::new();
+        let gauge = AtomicGauge::new(Vec::new());
         assert_eq!(gauge.flush(), (0.0, 0));
 
         gauge.decrement(42.0);
@@ -359,7 +585,7 @@ mod tests {
 
     #[test]
     fn atomic_gauge_set() {
-        let gauge = AtomicGauge::new();
+        let gauge = AtomicGauge::new(Vec::new());
         assert_eq!(gauge.flush(), (0.0, 0));
 
         gauge.set(42.0);
@@ -368,4 +594,115 @@ mod tests {
         gauge.set(-13.0);
         assert_eq!(gauge.flush(), (-13.0, 1));
     }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn atomic_gauge_aggregations() {
+        use GaugeAggregation::{Avg, Last, Max, Min, Sum};
+
+        let gauge = AtomicGauge::new(vec![Max]);
+
+        gauge.set(4.0);
+        gauge.set(10.0);
+        gauge.decrement(8.0);
+        gauge.increment(3.0);
+
+        let (summary, updates) = gauge.flush_summary();
+        assert_eq!(updates, 4);
+        assert_eq!(
+            [Last, Min, Max, Avg, Sum].map(|agg| summary.get(agg)),
+            [5.0, 2.0, 10.0, 5.25, 21.0]
+        );
+
+        // Without any updates, all aggregations use the current value.
+        let (summary, updates) = gauge.flush_summary();
+        assert_eq!(updates, 0);
+        assert_eq!([Last, Min, Max, Avg, Sum].map(|agg| summary.get(agg)), [5.0; 5]);
+    }
+
+    #[test]
+    fn atomic_set_insert() {
+        let set = AtomicSet::new();
+        assert_eq!(set.flush(), (HashSet::new(), 0));
+
+        set.insert("alice");
+        set.insert("bob");
+        set.insert("alice");
+        let expected = ["alice", "bob"].iter().map(ToString::to_string).collect::<HashSet<_>>();
+        assert_eq!(set.flush(), (expected, 3));
+
+        assert_eq!(set.flush(), (HashSet::new(), 0));
+    }
+
+    #[test]
+    fn atomic_counter_sampled() {
+        let counter = AtomicCounter::new(Some(0.5));
+        assert_eq!(counter.sample_rate(), Some(0.5));
+
+        for _ in 0..1000 {
+            counter.increment(1);
+        }
+        let (value, updates) = counter.flush();
+        assert_eq!(value, updates);
+        assert!((350..650).contains(&value), "kept {value} of 1000 increments");
+
+        // Absolute updates are never sampled.
+        counter.absolute(42);
+        assert_eq!(counter.sample_rate(), None);
+        assert_eq!(counter.flush(), (0, 1));
+    }
+
+    #[test]
+    fn atomic_histogram_sampled() {
+        let histogram = AtomicHistogram::new(false, 0, Some(0.5), true);
+        for _ in 0..1000 {
+            histogram.record(1.0);
+        }
+
+        let mut kept = 0;
+        histogram.flush(|sample_rate, values| {
+            assert_eq!(sample_rate, Some(0.5));
+            kept += values.len();
+        });
+        assert!((350..650).contains(&kept), "kept {kept} of 1000 values");
+
+        // Client-side sampling happens before values reach the reservoir, so the sample rates are combined.
+        let histogram = AtomicHistogram::new(true, 1000, Some(0.5), true);
+        for _ in 0..1000 {
+            histogram.record(1.0);
+        }
+
+        let mut flushed = Vec::new();
+        histogram.flush(|sample_rate, _| flushed.push(sample_rate));
+        assert_eq!(flushed, [Some(0.5)]);
+    }
+
+    #[test]
+    fn histogram_overrides() {
+        let mut overrides = HistogramOverrides::default();
+        overrides.sampling.insert("http.".to_string(), true);
+        overrides.reservoir_size.insert("http.".to_string(), 64);
+        overrides.as_distributions.insert("http.latency".to_string(), true);
+
+        let storage = ClientSideAggregatedStorage::new(
+            false,
+            1024,
+            false,
+            overrides,
+            SampleRates::default(),
+            PrefixMatcher::default(),
+        );
+
+        let histogram = storage.histogram(&Key::from_name("http.latency"));
+        assert!(histogram.is_distribution());
+        assert!(matches!(&histogram.values, HistogramValues::Sampled(_)));
+
+        let histogram = storage.histogram(&Key::from_name("http.response_size"));
+        assert!(!histogram.is_distribution());
+        assert!(matches!(&histogram.values, HistogramValues::Sampled(_)));
+
+        let histogram = storage.histogram(&Key::from_name("db.latency"));
+        assert!(!histogram.is_distribution());
+        assert!(matches!(&histogram.values, HistogramValues::Raw(_)));
+    }
 }
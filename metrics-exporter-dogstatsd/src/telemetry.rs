@@ -9,17 +9,26 @@ pub struct Telemetry {
     counter_points: Counter,
     gauge_points: Counter,
     histogram_points: Counter,
+    set_points: Counter,
+    events: Counter,
+    service_checks: Counter,
     packets_sent: Counter,
     packets_dropped: Counter,
     packets_dropped_writer: Counter,
     packets_dropped_serializer: Counter,
+    packets_dropped_queue: Counter,
+    packets_buffered: Counter,
     bytes_dropped: Counter,
     bytes_sent: Counter,
     bytes_dropped_writer: Counter,
+    bytes_dropped_queue: Counter,
+    bytes_buffered: Counter,
+    tags_dropped: Counter,
     agg_contexts: Counter,
     agg_contexts_counter: Counter,
     agg_contexts_gauge: Counter,
     agg_contexts_histogram: Counter,
+    agg_contexts_set: Counter,
 }
 
 impl Telemetry {
@@ -32,6 +41,7 @@ impl Telemetry {
             telemetry_tags!("client_transport" => transport, "metrics_type" => "gauge");
         let histogram_labels =
             telemetry_tags!("client_transport" => transport, "metrics_type" => "histogram");
+        let set_labels = telemetry_tags!("client_transport" => transport, "metrics_type" => "set");
 
         Self {
             metric_points: counter!("datadog.dogstatsd.client.metrics", base_labels.iter()),
@@ -44,6 +54,9 @@ impl Telemetry {
                 "datadog.dogstatsd.client.metrics_by_type",
                 histogram_labels.iter()
             ),
+            set_points: counter!("datadog.dogstatsd.client.metrics_by_type", set_labels.iter()),
+            events: counter!("datadog.dogstatsd.client.events", base_labels.iter()),
+            service_checks: counter!("datadog.dogstatsd.client.service_checks", base_labels.iter()),
             packets_sent: counter!("datadog.dogstatsd.client.packets_sent", base_labels.iter()),
             packets_dropped: counter!(
                 "datadog.dogstatsd.client.packets_dropped",
@@ -57,12 +70,26 @@ impl Telemetry {
                 "datadog.dogstatsd.client.packets_dropped_serializer",
                 base_labels.iter()
             ),
+            packets_dropped_queue: counter!(
+                "datadog.dogstatsd.client.packets_dropped_queue",
+                base_labels.iter()
+            ),
+            packets_buffered: counter!(
+                "datadog.dogstatsd.client.packets_buffered",
+                base_labels.iter()
+            ),
             bytes_dropped: counter!("datadog.dogstatsd.client.bytes_dropped", base_labels.iter()),
             bytes_sent: counter!("datadog.dogstatsd.client.bytes_sent", base_labels.iter()),
             bytes_dropped_writer: counter!(
                 "datadog.dogstatsd.client.bytes_dropped_writer",
                 base_labels.iter()
             ),
+            bytes_dropped_queue: counter!(
+                "datadog.dogstatsd.client.bytes_dropped_queue",
+                base_labels.iter()
+            ),
+            bytes_buffered: counter!("datadog.dogstatsd.client.bytes_buffered", base_labels.iter()),
+            tags_dropped: counter!("datadog.dogstatsd.client.tags_dropped", base_labels.iter()),
             agg_contexts: counter!(
                 "datadog.dogstatsd.client.aggregated_context",
                 base_labels.iter()
@@ -79,30 +106,197 @@ impl Telemetry {
                 "datadog.dogstatsd.client.aggregated_context_by_type",
                 histogram_labels.iter()
             ),
+            agg_contexts_set: counter!(
+                "datadog.dogstatsd.client.aggregated_context_by_type",
+                set_labels.iter()
+            ),
         }
     }
 
     /// Applies the given telemetry update, updating the internal metrics.
     pub fn apply_update(&mut self, update: &TelemetryUpdate) {
-        let metric_points = update.counter_points + update.gauge_points + update.histogram_points;
-        let agg_contexts =
-            update.counter_contexts + update.gauge_contexts + update.histogram_contexts;
+        let metric_points = update.counter_points
+            + update.gauge_points
+            + update.histogram_points
+            + update.set_points;
+        let agg_contexts = update.counter_contexts
+            + update.gauge_contexts
+            + update.histogram_contexts
+            + update.set_contexts;
 
         self.metric_points.increment(metric_points);
         self.counter_points.increment(update.counter_points);
         self.gauge_points.increment(update.gauge_points);
         self.histogram_points.increment(update.histogram_points);
+        self.set_points.increment(update.set_points);
+        self.events.increment(update.events);
+        self.service_checks.increment(update.service_checks);
         self.packets_sent.increment(update.packets_sent);
         self.packets_dropped.increment(update.packets_dropped);
         self.packets_dropped_writer.increment(update.packets_dropped_writer);
         self.packets_dropped_serializer.increment(update.packets_dropped_serializer);
+        self.packets_dropped_queue.increment(update.packets_dropped_queue);
+        self.packets_buffered.increment(update.packets_buffered);
         self.bytes_dropped.increment(update.bytes_dropped);
         self.bytes_sent.increment(update.bytes_sent);
         self.bytes_dropped_writer.increment(update.bytes_dropped_writer);
+        self.bytes_dropped_queue.increment(update.bytes_dropped_queue);
+        self.bytes_buffered.increment(update.bytes_buffered);
+        self.tags_dropped.increment(update.tags_dropped);
         self.agg_contexts.increment(agg_contexts);
         self.agg_contexts_counter.increment(update.counter_contexts);
         self.agg_contexts_gauge.increment(update.gauge_contexts);
         self.agg_contexts_histogram.increment(update.histogram_contexts);
+        self.agg_contexts_set.increment(update.set_contexts);
+    }
+}
+
+/// A snapshot of the exporter's telemetry.
+///
+/// All values are cumulative totals since the exporter was built. Unlike the `datadog.dogstatsd.client.*` metrics, which
+/// are only emitted when telemetry is enabled, these totals are always tracked, and can be read at any time through
+/// [`DogStatsDHandle::telemetry`][crate::DogStatsDHandle::telemetry].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TelemetrySnapshot {
+    metric_points: u64,
+    counter_points: u64,
+    gauge_points: u64,
+    histogram_points: u64,
+    set_points: u64,
+    events: u64,
+    service_checks: u64,
+    packets_sent: u64,
+    packets_dropped: u64,
+    packets_dropped_writer: u64,
+    packets_dropped_serializer: u64,
+    packets_dropped_queue: u64,
+    packets_buffered: u64,
+    bytes_sent: u64,
+    bytes_dropped: u64,
+    bytes_dropped_writer: u64,
+    bytes_dropped_queue: u64,
+    bytes_buffered: u64,
+    tags_dropped: u64,
+}
+
+impl TelemetrySnapshot {
+    /// Adds the given telemetry update to the totals.
+    pub(crate) fn apply_update(&mut self, update: &TelemetryUpdate) {
+        self.counter_points += update.counter_points;
+        self.gauge_points += update.gauge_points;
+        self.histogram_points += update.histogram_points;
+        self.set_points += update.set_points;
+        self.metric_points =
+            self.counter_points + self.gauge_points + self.histogram_points + self.set_points;
+        self.events += update.events;
+        self.service_checks += update.service_checks;
+        self.packets_sent += update.packets_sent;
+        self.packets_dropped += update.packets_dropped;
+        self.packets_dropped_writer += update.packets_dropped_writer;
+        self.packets_dropped_serializer += update.packets_dropped_serializer;
+        self.packets_dropped_queue += update.packets_dropped_queue;
+        self.packets_buffered += update.packets_buffered;
+        self.bytes_sent += update.bytes_sent;
+        self.bytes_dropped += update.bytes_dropped;
+        self.bytes_dropped_writer += update.bytes_dropped_writer;
+        self.bytes_dropped_queue += update.bytes_dropped_queue;
+        self.bytes_buffered += update.bytes_buffered;
+        self.tags_dropped += update.tags_dropped;
+    }
+
+    /// Returns the number of metric points flushed, across all metric types.
+    pub fn metric_points(&self) -> u64 {
+        self.metric_points
+    }
+
+    /// Returns the number of counter points flushed.
+    pub fn counter_points(&self) -> u64 {
+        self.counter_points
+    }
+
+    /// Returns the number of gauge points flushed.
+    pub fn gauge_points(&self) -> u64 {
+        self.gauge_points
+    }
+
+    /// Returns the number of histogram points flushed, including distributions.
+    pub fn histogram_points(&self) -> u64 {
+        self.histogram_points
+    }
+
+    /// Returns the number of set points flushed.
+    pub fn set_points(&self) -> u64 {
+        self.set_points
+    }
+
+    /// Returns the number of events flushed.
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    /// Returns the number of service checks flushed.
+    pub fn service_checks(&self) -> u64 {
+        self.service_checks
+    }
+
+    /// Returns the number of packets sent.
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+
+    /// Returns the number of packets dropped, for any reason.
+    pub fn packets_dropped(&self) -> u64 {
+        self.packets_dropped
+    }
+
+    /// Returns the number of packets dropped because they could not be sent.
+    pub fn packets_dropped_writer(&self) -> u64 {
+        self.packets_dropped_writer
+    }
+
+    /// Returns the number of packets dropped because they could not be serialized.
+    pub fn packets_dropped_serializer(&self) -> u64 {
+        self.packets_dropped_serializer
+    }
+
+    /// Returns the number of packets dropped because they could not be held in the retry buffer.
+    pub fn packets_dropped_queue(&self) -> u64 {
+        self.packets_dropped_queue
+    }
+
+    /// Returns the number of packets buffered to be retried later.
+    pub fn packets_buffered(&self) -> u64 {
+        self.packets_buffered
+    }
+
+    /// Returns the number of bytes sent.
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of bytes dropped, for any reason.
+    pub fn bytes_dropped(&self) -> u64 {
+        self.bytes_dropped
+    }
+
+    /// Returns the number of bytes dropped because they could not be sent.
+    pub fn bytes_dropped_writer(&self) -> u64 {
+        self.bytes_dropped_writer
+    }
+
+    /// Returns the number of bytes dropped because they could not be held in the retry buffer.
+    pub fn bytes_dropped_queue(&self) -> u64 {
+        self.bytes_dropped_queue
+    }
+
+    /// Returns the number of bytes buffered to be retried later.
+    pub fn bytes_buffered(&self) -> u64 {
+        self.bytes_buffered
+    }
+
+    /// Returns the number of tags dropped as invalid from otherwise valid metrics, service checks, and events.
+    pub fn tags_dropped(&self) -> u64 {
+        self.tags_dropped
     }
 }
 
@@ -112,16 +306,25 @@ pub struct TelemetryUpdate {
     counter_contexts: u64,
     gauge_contexts: u64,
     histogram_contexts: u64,
+    set_contexts: u64,
     counter_points: u64,
     gauge_points: u64,
     histogram_points: u64,
+    set_points: u64,
+    events: u64,
+    service_checks: u64,
     packets_sent: u64,
     packets_dropped: u64,
     packets_dropped_writer: u64,
     packets_dropped_serializer: u64,
+    packets_dropped_queue: u64,
+    packets_buffered: u64,
     bytes_sent: u64,
     bytes_dropped: u64,
     bytes_dropped_writer: u64,
+    bytes_dropped_queue: u64,
+    bytes_buffered: u64,
+    tags_dropped: u64,
 }
 
 impl TelemetryUpdate {
@@ -130,21 +333,35 @@ impl TelemetryUpdate {
         self.counter_contexts = 0;
         self.gauge_contexts = 0;
         self.histogram_contexts = 0;
+        self.set_contexts = 0;
         self.counter_points = 0;
         self.gauge_points = 0;
         self.histogram_points = 0;
+        self.set_points = 0;
+        self.events = 0;
+        self.service_checks = 0;
         self.packets_sent = 0;
         self.packets_dropped = 0;
         self.packets_dropped_writer = 0;
         self.packets_dropped_serializer = 0;
+        self.packets_dropped_queue = 0;
+        self.packets_buffered = 0;
         self.bytes_sent = 0;
         self.bytes_dropped = 0;
         self.bytes_dropped_writer = 0;
+        self.bytes_dropped_queue = 0;
+        self.bytes_buffered = 0;
+        self.tags_dropped = 0;
     }
 
     /// Returns `true` if any updates have been recorded.
     pub fn had_updates(&self) -> bool {
-        self.counter_points > 0 || self.gauge_points > 0 || self.histogram_points > 0
+        self.counter_points > 0
+            || self.gauge_points > 0
+            || self.histogram_points > 0
+            || self.set_points > 0
+            || self.events > 0
+            || self.service_checks > 0
     }
 
     /// Increments the number of counter contexts collected.
@@ -162,6 +379,11 @@ impl TelemetryUpdate {
         self.histogram_contexts += value as u64;
     }
 
+    /// Increments the number of set contexts collected.
+    pub fn increment_set_contexts(&mut self, value: usize) {
+        self.set_contexts += value as u64;
+    }
+
     /// Increments the number of counter points collected.
     pub fn increment_counter_points(&mut self, value: u64) {
         self.counter_points += value;
@@ -177,6 +399,21 @@ impl TelemetryUpdate {
         self.histogram_points += value;
     }
 
+    /// Increments the number of set points collected.
+    pub fn increment_set_points(&mut self, value: u64) {
+        self.set_points += value;
+    }
+
+    /// Increments the number of events collected.
+    pub fn increment_events(&mut self, value: u64) {
+        self.events += value;
+    }
+
+    /// Increments the number of service checks collected.
+    pub fn increment_service_checks(&mut self, value: u64) {
+        self.service_checks += value;
+    }
+
     /// Tracks a successful packet send.
     pub fn track_packet_send_succeeded(&mut self, bytes_len: usize) {
         self.packets_sent += 1;
@@ -191,11 +428,32 @@ impl TelemetryUpdate {
         self.bytes_dropped_writer += bytes_len as u64;
     }
 
+    /// Tracks a packet being buffered to be retried later.
+    pub fn track_packet_buffered(&mut self, bytes_len: usize) {
+        self.packets_buffered += 1;
+        self.bytes_buffered += bytes_len as u64;
+    }
+
+    /// Tracks a buffered packet being evicted, or dropped as it could not be buffered.
+    pub fn track_packet_evicted(&mut self, bytes_len: usize) {
+        self.packets_dropped += 1;
+        self.packets_dropped_queue += 1;
+        self.bytes_dropped += bytes_len as u64;
+        self.bytes_dropped_queue += bytes_len as u64;
+    }
+
     /// Tracks a failed packet serialization.
     pub fn track_packet_serializer_failed(&mut self) {
         self.packets_dropped += 1;
         self.packets_dropped_serializer += 1;
     }
+
+    /// Tracks tags which were dropped as invalid during serialization.
+    ///
+    /// These are not counted as dropped packets, as the metrics themselves are still sent.
+    pub fn track_tags_dropped(&mut self, count: u64) {
+        self.tags_dropped += count;
+    }
 }
 
 macro_rules! _telemetry_tags {
@@ -2,6 +2,12 @@ use std::vec::Drain;
 
 use metrics::{Key, Label};
 
+use crate::{
+    builder::WireDialect,
+    event::{Event, ServiceCheck},
+    sanitize::Sanitizer,
+};
+
 const SMALLEST_VALID_PAYLOAD: &[u8] = b"a:0|c\n";
 
 #[derive(Clone, Copy)]
@@ -10,6 +16,7 @@ enum MetricType {
     Gauge,
     Histogram,
     Distribution,
+    Set,
 }
 
 impl MetricType {
@@ -19,6 +26,7 @@ impl MetricType {
             MetricType::Gauge => b"|g",
             MetricType::Histogram => b"|h",
             MetricType::Distribution => b"|d",
+            MetricType::Set => b"|s",
         }
     }
 }
@@ -61,10 +69,16 @@ impl WriteResult {
         Self { payloads_written: 0, points_dropped }
     }
 
-    const fn new() -> Self {
+    pub const fn new() -> Self {
         Self { payloads_written: 0, points_dropped: 0 }
     }
 
+    /// Merges the result of another write into this one.
+    pub fn merge(&mut self, other: &WriteResult) {
+        self.payloads_written += other.payloads_written;
+        self.points_dropped += other.points_dropped;
+    }
+
     fn increment_payloads_written(&mut self) {
         self.payloads_written += 1;
     }
@@ -108,6 +122,10 @@ pub(super) struct PayloadWriter {
     trailer_buf: Vec<u8>,
     with_length_prefix: bool,
     global_tags: Vec<Label>,
+    container_id: Option<String>,
+    dialect: WireDialect,
+    sanitizer: Sanitizer,
+    invalid_tags: u64,
 }
 
 impl PayloadWriter {
@@ -137,6 +155,10 @@ impl PayloadWriter {
             trailer_buf: Vec::new(),
             with_length_prefix,
             global_tags: Vec::new(),
+            container_id: None,
+            dialect: WireDialect::DogStatsD,
+            sanitizer: Sanitizer::default(),
+            invalid_tags: 0,
         };
 
         writer.prepare_for_write();
@@ -149,6 +171,29 @@ impl PayloadWriter {
         self
     }
 
+    /// Sets the container ID to send with all payloads, for origin detection.
+    pub fn with_container_id(mut self, container_id: Option<&str>) -> Self {
+        self.container_id = container_id.map(ToString::to_string);
+        self
+    }
+
+    /// Sets the sanitization applied to metric names and tags.
+    pub fn with_sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = sanitizer;
+        self
+    }
+
+    /// Returns the number of tags dropped as invalid since the last call, and resets the count.
+    pub fn take_invalid_tags(&mut self) -> u64 {
+        std::mem::take(&mut self.invalid_tags)
+    }
+
+    /// Sets the wire dialect used to serialize metrics.
+    pub fn with_dialect(mut self, dialect: WireDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
     fn last_offset(&self) -> usize {
         self.offsets.last().copied().unwrap_or(0)
     }
@@ -251,15 +296,52 @@ impl PayloadWriter {
         self.uncommitted_len() + len > self.max_payload_len
     }
 
-    fn write_metric_header(&mut self, prefix: Option<&str>, key: &Key) {
+    /// Writes the metric header, which is the metric name and, for some dialects, the tags.
+    ///
+    /// Returns `false` if the metric name is not valid, in which case the metric must not be written.
+    fn write_metric_header(&mut self, prefix: Option<&str>, key: &Key) -> bool {
         self.header_buf.clear();
 
-        if let Some(prefix) = prefix {
-            self.header_buf.extend_from_slice(prefix.as_bytes());
-            self.header_buf.push(b'.');
+        let reserved_in_name = self.dialect.reserved_in_names();
+        if !self.sanitizer.write_metric_name(
+            &mut self.header_buf,
+            prefix,
+            key.name(),
+            reserved_in_name,
+        ) {
+            return false;
         }
 
-        self.header_buf.extend_from_slice(key.name().as_bytes());
+        // Dialects other than DogStatsD carry tags as part of the metric name.
+        let buf = &mut self.header_buf;
+        let sanitizer = &self.sanitizer;
+        let tags = key.labels().chain(self.global_tags.iter());
+        let reserved = self.dialect.reserved_in_tags();
+        let invalid_tags = match self.dialect {
+            WireDialect::DogStatsD => 0,
+            WireDialect::Etsy => {
+                let mut invalid_tags = 0;
+                for tag in tags {
+                    let start = buf.len();
+                    buf.push(b'.');
+                    if !sanitizer.write_tag(buf, tag, b'.', reserved) {
+                        buf.truncate(start);
+                        invalid_tags += 1;
+                    }
+                }
+                invalid_tags
+            }
+            WireDialect::Graphite => {
+                write_name_tags(buf, sanitizer, tags, reserved, b";", b';', b"")
+            }
+            WireDialect::Influx => write_name_tags(buf, sanitizer, tags, reserved, b",", b',', b""),
+            WireDialect::SignalFx => {
+                write_name_tags(buf, sanitizer, tags, reserved, b"[", b',', b"]")
+            }
+        };
+        self.invalid_tags += invalid_tags;
+
+        true
     }
 
     fn write_metric_trailer(
@@ -282,23 +364,20 @@ impl PayloadWriter {
             self.trailer_buf.extend_from_slice(sample_rate_str.as_bytes());
         }
 
-        // Write any tags that are present on the key first, and then additionally write any global tags.
-        let tags = key.labels();
-        let mut wrote_tag = false;
-        for tag in tags.chain(self.global_tags.iter()) {
-            // If we haven't written a tag yet, write out the tags prefix first.
-            //
-            // Otherwise, write a tag separator.
-            if wrote_tag {
-                self.trailer_buf.push(b',');
-            } else {
-                self.trailer_buf.extend_from_slice(b"|#");
-                wrote_tag = true;
-            }
-
-            write_tag(&mut self.trailer_buf, tag);
+        // Tags, container IDs, and timestamps are DogStatsD extensions, so we only write them when using that dialect.
+        if self.dialect != WireDialect::DogStatsD {
+            self.trailer_buf.push(b'\n');
+            return;
         }
 
+        // Write any tags that are present on the key first, and then additionally write any global tags.
+        self.invalid_tags += write_tags(
+            &mut self.trailer_buf,
+            &self.sanitizer,
+            key.labels().chain(self.global_tags.iter()),
+        );
+        write_optional_field(&mut self.trailer_buf, b"|c:", self.container_id.as_deref());
+
         if let Some(timestamp) = maybe_timestamp {
             let mut int_writer = itoa::Buffer::new();
             let ts_str = int_writer.format(timestamp);
@@ -317,15 +396,23 @@ impl PayloadWriter {
         metric_value: MetricValue,
         metric_type: MetricType,
         maybe_timestamp: Option<u64>,
+        maybe_sample_rate: Option<f64>,
         prefix: Option<&str>,
     ) -> WriteResult {
         // Write our metric header and trailer.
-        self.write_metric_header(prefix, key);
-        self.write_metric_trailer(key, metric_type, maybe_timestamp, None);
+        if !self.write_metric_header(prefix, key) {
+            return WriteResult::failure(1);
+        }
+        self.write_metric_trailer(key, metric_type, maybe_timestamp, maybe_sample_rate);
 
         let mut formatter = MetricValueFormatter::new();
         let metric_value_str = formatter.format(metric_value);
 
+        self.try_commit_single_value(metric_value_str)
+    }
+
+    /// Writes a single value between the current metric header and trailer, and commits the metric.
+    fn try_commit_single_value(&mut self, metric_value_str: &str) -> WriteResult {
         // Check if the full metric length exceeds the maximum payload length.
         //
         // If it does, we return early.
@@ -357,7 +444,9 @@ impl PayloadWriter {
         I: Iterator<Item = MetricValue> + ExactSizeIterator,
     {
         // Write our metric header and trailer.
-        self.write_metric_header(prefix, key);
+        if !self.write_metric_header(prefix, key) {
+            return WriteResult::failure(metric_values.len() as u64);
+        }
         self.write_metric_trailer(key, metric_type, None, maybe_sample_rate);
 
         // Check if the full metric length exceeds the maximum payload length. Since we're dealing with multiple values,
@@ -371,6 +460,21 @@ impl PayloadWriter {
         let mut result = WriteResult::new();
         let mut formatter = MetricValueFormatter::new();
 
+        // Packing multiple values into a single metric is a DogStatsD extension, so for other dialects, we write each
+        // value as its own metric.
+        if self.dialect != WireDialect::DogStatsD {
+            for metric_value in metric_values {
+                let metric_value_str = formatter.format(metric_value);
+                if self.try_commit_single_value(metric_value_str).any_failures() {
+                    result.increment_points_dropped();
+                } else {
+                    result.increment_payloads_written();
+                }
+            }
+
+            return result;
+        }
+
         // Iterate over all of the values, trying to write each of them.
         //
         // We keep track of the overall size of the payload as we go, and if writing the current value would cause us to
@@ -424,11 +528,14 @@ impl PayloadWriter {
     }
 
     /// Writes a counter payload.
+    ///
+    /// If the counter was sampled on the client side, the sample rate must be given so the server can scale the value.
     pub fn write_counter(
         &mut self,
         key: &Key,
         value: u64,
         timestamp: Option<u64>,
+        maybe_sample_rate: Option<f64>,
         prefix: Option<&str>,
     ) -> WriteResult {
         self.try_write_single(
@@ -436,6 +543,7 @@ impl PayloadWriter {
             MetricValue::Integer(value),
             MetricType::Counter,
             timestamp,
+            maybe_sample_rate,
             prefix,
         )
     }
@@ -453,6 +561,7 @@ impl PayloadWriter {
             MetricValue::FloatingPoint(value),
             MetricType::Gauge,
             timestamp,
+            None,
             prefix,
         )
     }
@@ -501,6 +610,133 @@ impl PayloadWriter {
         )
     }
 
+    /// Writes a set payload.
+    ///
+    /// Each value is written as its own metric, as sets do not support multiple values per metric. Values containing
+    /// characters that cannot be represented in the DogStatsD protocol -- `:`, `|`, or a newline -- are dropped.
+    pub fn write_set<'v, I>(&mut self, key: &Key, values: I, prefix: Option<&str>) -> WriteResult
+    where
+        I: IntoIterator<Item = &'v str>,
+    {
+        // Write our metric header and trailer, which are shared by every value.
+        if !self.write_metric_header(prefix, key) {
+            return WriteResult::failure(values.into_iter().count() as u64);
+        }
+        self.write_metric_trailer(key, MetricType::Set, None, None);
+
+        let mut result = WriteResult::new();
+        for value in values {
+            if value.is_empty() || value.contains([':', '|', '\n']) {
+                result.increment_points_dropped();
+                continue;
+            }
+
+            let value_result = self.try_commit_single_value(value);
+            if value_result.any_failures() {
+                result.increment_points_dropped();
+            } else {
+                result.increment_payloads_written();
+            }
+        }
+
+        result
+    }
+
+    /// Writes a service check payload.
+    ///
+    /// Service checks can only be written when using the DogStatsD dialect. Reserved characters in the name and hostname
+    /// are sanitized, and the service check is dropped if its name is empty after sanitization.
+    pub fn write_service_check(&mut self, check: &ServiceCheck) -> WriteResult {
+        if self.dialect != WireDialect::DogStatsD {
+            return WriteResult::failure(1);
+        }
+
+        self.header_buf.clear();
+        self.values_buf.clear();
+        self.trailer_buf.clear();
+
+        let buf = &mut self.values_buf;
+        buf.extend_from_slice(b"_sc|");
+        if self.sanitizer.write_field(buf, &check.name) == 0 {
+            buf.clear();
+            return WriteResult::failure(1);
+        }
+        buf.push(b'|');
+        buf.extend_from_slice(check.status.as_bytes());
+        write_optional_timestamp(buf, check.timestamp);
+        write_optional_sanitized_field(buf, &self.sanitizer, b"|h:", check.hostname.as_deref());
+        self.invalid_tags +=
+            write_tags(buf, &self.sanitizer, check.labels.iter().chain(self.global_tags.iter()));
+        write_optional_field(buf, b"|c:", self.container_id.as_deref());
+
+        // The message must always come last, and needs to be escaped so that it isn't confused with another field.
+        if let Some(message) = &check.message {
+            buf.extend_from_slice(b"|m:");
+            buf.extend_from_slice(escape_text(message).replace("m:", "m\\:").as_bytes());
+        }
+
+        self.trailer_buf.push(b'\n');
+        self.commit_whole()
+    }
+
+    /// Writes an event payload.
+    ///
+    /// Events can only be written when using the DogStatsD dialect. Reserved characters in the hostname, aggregation key,
+    /// and source type name are sanitized.
+    pub fn write_event(&mut self, event: &Event) -> WriteResult {
+        if self.dialect != WireDialect::DogStatsD {
+            return WriteResult::failure(1);
+        }
+
+        self.header_buf.clear();
+        self.values_buf.clear();
+        self.trailer_buf.clear();
+
+        let title = escape_text(&event.title);
+        let text = escape_text(&event.text);
+
+        let mut int_writer = itoa::Buffer::new();
+        let buf = &mut self.values_buf;
+        buf.extend_from_slice(b"_e{");
+        buf.extend_from_slice(int_writer.format(title.len()).as_bytes());
+        buf.push(b',');
+        buf.extend_from_slice(int_writer.format(text.len()).as_bytes());
+        buf.extend_from_slice(b"}:");
+        buf.extend_from_slice(title.as_bytes());
+        buf.push(b'|');
+        buf.extend_from_slice(text.as_bytes());
+        write_optional_timestamp(buf, event.timestamp);
+        let sanitizer = &self.sanitizer;
+        write_optional_sanitized_field(buf, sanitizer, b"|h:", event.hostname.as_deref());
+        write_optional_sanitized_field(buf, sanitizer, b"|k:", event.aggregation_key.as_deref());
+        if let Some(priority) = event.priority {
+            buf.extend_from_slice(b"|p:");
+            buf.extend_from_slice(priority.as_bytes());
+        }
+        write_optional_sanitized_field(buf, sanitizer, b"|s:", event.source_type_name.as_deref());
+        if let Some(alert_type) = event.alert_type {
+            buf.extend_from_slice(b"|t:");
+            buf.extend_from_slice(alert_type.as_bytes());
+        }
+        self.invalid_tags +=
+            write_tags(buf, &self.sanitizer, event.labels.iter().chain(self.global_tags.iter()));
+        write_optional_field(buf, b"|c:", self.container_id.as_deref());
+
+        self.trailer_buf.push(b'\n');
+        self.commit_whole()
+    }
+
+    /// Commits a payload written entirely into the values buffer, such as a service check or event.
+    fn commit_whole(&mut self) -> WriteResult {
+        let result = if self.commit() { WriteResult::success(1) } else { WriteResult::failure(1) };
+
+        // Unlike metrics, nothing here is reusable for a subsequent write.
+        self.values_buf.clear();
+        self.trailer_buf.clear();
+
+        result
+    }
+
     /// Returns a consuming iterator over all payloads written by this writer.
     ///
     /// The iterator will yield payloads in the order they were written, and the payloads will be cleared from the
@@ -564,23 +800,131 @@ impl<'a> Payloads<'a> {
     }
 }
 
-fn write_tag(buf: &mut Vec<u8>, label: &Label) {
-    // If the label value is empty, we treat it as a bare label. This means all we write is something like
-    // `label_name`, instead of a more naive form, like `label_name:`.
-    buf.extend_from_slice(label.key().as_bytes());
-    if label.value().is_empty() {
-        return;
+/// Writes tags in the DogStatsD form of `|#key:value,key:value`.
+///
+/// Returns the number of tags that were dropped as invalid.
+fn write_tags<'a, I>(buf: &mut Vec<u8>, sanitizer: &Sanitizer, tags: I) -> u64
+where
+    I: Iterator<Item = &'a Label>,
+{
+    let mut wrote_tag = false;
+    let mut invalid_tags = 0;
+    for tag in tags {
+        // If we haven't written a tag yet, write out the tags prefix first.
+        //
+        // Otherwise, write a tag separator.
+        let start = buf.len();
+        if wrote_tag {
+            buf.push(b',');
+        } else {
+            buf.extend_from_slice(b"|#");
+        }
+
+        if sanitizer.write_tag(buf, tag, b':', &[]) {
+            wrote_tag = true;
+        } else {
+            buf.truncate(start);
+            invalid_tags += 1;
+        }
+    }
+
+    invalid_tags
+}
+
+/// Writes tags as part of the metric name, in the form of `<open>key=value<separator>key=value<close>`.
+///
+/// Tags without a value are skipped, as they cannot be represented in these dialects. Characters in `reserved` are
+/// sanitized in both tag keys and values, as they would otherwise be mistaken for the delimiters.
+///
+/// Returns the number of tags that were dropped as invalid.
+fn write_name_tags<'a, I>(
+    buf: &mut Vec<u8>,
+    sanitizer: &Sanitizer,
+    tags: I,
+    reserved: &[char],
+    open: &[u8],
+    separator: u8,
+    close: &[u8],
+) -> u64
+where
+    I: Iterator<Item = &'a Label>,
+{
+    let mut wrote_tag = false;
+    let mut invalid_tags = 0;
+    for tag in tags.filter(|tag| !tag.value().is_empty()) {
+        let start = buf.len();
+        if wrote_tag {
+            buf.push(separator);
+        } else {
+            buf.extend_from_slice(open);
+        }
+
+        if sanitizer.write_tag(buf, tag, b'=', reserved) {
+            wrote_tag = true;
+        } else {
+            buf.truncate(start);
+            invalid_tags += 1;
+        }
+    }
+
+    if wrote_tag {
+        buf.extend_from_slice(close);
+    }
+
+    invalid_tags
+}
+
+fn write_optional_timestamp(buf: &mut Vec<u8>, maybe_timestamp: Option<u64>) {
+    if let Some(timestamp) = maybe_timestamp {
+        let mut int_writer = itoa::Buffer::new();
+        buf.extend_from_slice(b"|d:");
+        buf.extend_from_slice(int_writer.format(timestamp).as_bytes());
+    }
+}
+
+fn write_optional_field(buf: &mut Vec<u8>, field_prefix: &[u8], maybe_value: Option<&str>) {
+    if let Some(value) = maybe_value {
+        buf.extend_from_slice(field_prefix);
+        buf.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// Writes an optional field of a service check or event, sanitizing its value.
+///
+/// The field is omitted entirely if its value is empty after sanitization.
+fn write_optional_sanitized_field(
+    buf: &mut Vec<u8>,
+    sanitizer: &Sanitizer,
+    field_prefix: &[u8],
+    maybe_value: Option<&str>,
+) {
+    if let Some(value) = maybe_value {
+        let start = buf.len();
+        buf.extend_from_slice(field_prefix);
+        if sanitizer.write_field(buf, value) == 0 {
+            buf.truncate(start);
+        }
     }
+}
 
-    buf.push(b':');
-    buf.extend_from_slice(label.value().as_bytes());
+/// Escapes newlines in free-form text, as the protocol is newline delimited.
+fn escape_text(text: &str) -> std::borrow::Cow<'_, str> {
+    if text.contains('\n') {
+        text.replace('\n', "\\n").into()
+    } else {
+        text.into()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use metrics::{Key, Label};
+    use metrics_dogstatsd_receiver::{parse_line, Message};
     use proptest::{collection::vec as arb_vec, prelude::*, prop_oneof, proptest};
 
+    use crate::builder::{ReservedCharacters, WireDialect};
+    use crate::event::{Event, EventAlertType, EventPriority, ServiceCheck, ServiceCheckStatus};
+    use crate::sanitize::Sanitizer;
     use crate::writer::SMALLEST_VALID_PAYLOAD;
     const SMALLEST_VALID_PAYLOAD_LEN: usize = SMALLEST_VALID_PAYLOAD.len();
 
@@ -694,7 +1038,7 @@ mod tests {
 
         for (key, value, ts, prefix, global_labels, expected) in cases {
             let mut writer = PayloadWriter::new(8192, false).with_global_labels(global_labels);
-            let result = writer.write_counter(&key, value, ts, prefix);
+            let result = writer.write_counter(&key, value, ts, None, prefix);
             assert_eq!(result.payloads_written(), 1);
 
             let actual = string_from_writer(&mut writer);
@@ -885,6 +1229,242 @@ mod tests {
         }
     }
 
+    #[test]
+    fn set() {
+        let key = Key::from_parts("test_set", &[("foo", "bar")]);
+        let mut writer =
+            PayloadWriter::new(8192, false).with_global_labels(&[Label::new("gfoo", "bar")][..]);
+        let result = writer.write_set(&key, ["alice", "bob:1", "", "carol"], Some("server1"));
+        assert_eq!(result.payloads_written(), 2);
+        assert_eq!(result.points_dropped(), 2);
+
+        let actual = string_from_writer(&mut writer);
+        assert_eq!(
+            actual,
+            "server1.test_set:alice|s|#foo:bar,gfoo:bar\nserver1.test_set:carol|s|#foo:bar,gfoo:bar\n"
+        );
+    }
+
+    #[test]
+    fn service_check() {
+        let cases = [
+            (ServiceCheck::new("app.ok", ServiceCheckStatus::Ok), "_sc|app.ok|0|#gfoo:bar\n"),
+            (
+                ServiceCheck::new("app.db", ServiceCheckStatus::Critical)
+                    .with_timestamp(1_700_000_000)
+                    .with_hostname("host1")
+                    .with_labels(vec![Label::new("db", "main")])
+                    .with_message("connection refused\nm:retrying"),
+                "_sc|app.db|2|d:1700000000|h:host1|#db:main,gfoo:bar|m:connection refused\\nm\\:retrying\n",
+            ),
+        ];
+
+        for (check, expected) in cases {
+            let mut writer =
+                PayloadWriter::new(8192, false).with_global_labels(&[Label::new("gfoo", "bar")]);
+            let result = writer.write_service_check(&check);
+            assert_eq!(result.payloads_written(), 1);
+
+            let actual = string_from_writer(&mut writer);
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn event() {
+        let cases = [
+            (Event::new("Deploy", "v1.2.3"), "_e{6,6}:Deploy|v1.2.3\n"),
+            (
+                Event::new("Deploy", "line one\nline two")
+                    .with_timestamp(1_700_000_000)
+                    .with_hostname("host1")
+                    .with_aggregation_key("deploys")
+                    .with_priority(EventPriority::Low)
+                    .with_source_type_name("ci")
+                    .with_alert_type(EventAlertType::Success)
+                    .with_labels(vec![Label::new("env", "prod")]),
+                "_e{6,18}:Deploy|line one\\nline two|d:1700000000|h:host1|k:deploys|p:low|s:ci|t:success|#env:prod\n",
+            ),
+        ];
+
+        for (event, expected) in cases {
+            let mut writer = PayloadWriter::new(8192, false);
+            let result = writer.write_event(&event);
+            assert_eq!(result.payloads_written(), 1);
+
+            let actual = string_from_writer(&mut writer);
+            assert_eq!(actual, expected);
+        }
+
+        let mut writer = PayloadWriter::new(16, false);
+        let result = writer.write_event(&Event::new("Deploy", "far too long to fit"));
+        assert_eq!(result.points_dropped(), 1);
+        assert_eq!(string_from_writer(&mut writer), "");
+    }
+
+    #[test]
+    fn service_check_and_event_sanitization() {
+        let mut writer = PayloadWriter::new(8192, false);
+        let check = ServiceCheck::new("app|db\n", ServiceCheckStatus::Warning)
+            .with_hostname("host|1\nx")
+            .with_message("m");
+        let result = writer.write_service_check(&check);
+        assert_eq!(result.payloads_written(), 1);
+
+        let event = Event::new("Deploy", "v1")
+            .with_hostname("host|1")
+            .with_aggregation_key("deploys|#x:y")
+            .with_source_type_name("|");
+        let result = writer.write_event(&event);
+        assert_eq!(result.payloads_written(), 1);
+        assert_eq!(
+            string_from_writer(&mut writer),
+            "_sc|app_db_|1|h:host_1_x|m:m\n\
+             _e{6,2}:Deploy|v1|h:host_1|k:deploys_#x:y|s:_\n"
+        );
+
+        // Service checks need a name, so one which is empty after sanitization is dropped.
+        let mut writer = PayloadWriter::new(8192, false).with_sanitizer(Sanitizer {
+            reserved_chars: ReservedCharacters::Strip,
+            ..Sanitizer::default()
+        });
+        let check = ServiceCheck::new("|", ServiceCheckStatus::Ok).with_hostname("|");
+        let result = writer.write_service_check(&check);
+        assert_eq!(result.points_dropped(), 1);
+        let result = writer.write_event(&Event::new("Deploy", "v1").with_hostname("|\n"));
+        assert_eq!(result.payloads_written(), 1);
+        assert_eq!(string_from_writer(&mut writer), "_e{6,2}:Deploy|v1\n");
+    }
+
+    #[test]
+    fn sample_rate() {
+        let key = Key::from_parts("test_counter", &[("foo", "bar")]);
+        let mut writer = PayloadWriter::new(8192, false);
+        writer.write_counter(&key, 7, Some(1_700_000_000), Some(0.1), None);
+        writer.write_histogram(&key, [1.0, 2.0], Some(0.25), None);
+
+        let actual = string_from_writer(&mut writer);
+        assert_eq!(
+            actual,
+            "test_counter:7|c|@0.1|#foo:bar|T1700000000\ntest_counter:1.0:2.0|h|@0.25|#foo:bar\n"
+        );
+    }
+
+    #[test]
+    fn dialects() {
+        let key = Key::from_parts("test_histogram", &[("foo", "bar"), ("bare", "")]);
+        let cases = [
+            (
+                WireDialect::DogStatsD,
+                "server1.test_histogram:1.0:2.0|h|@0.5|#foo:bar,bare,gfoo:gbar|c:abc123\n",
+            ),
+            (
+                WireDialect::Etsy,
+                "server1.test_histogram.foo.bar.bare.gfoo.gbar:1.0|h|@0.5\n\
+                 server1.test_histogram.foo.bar.bare.gfoo.gbar:2.0|h|@0.5\n",
+            ),
+            (
+                WireDialect::Graphite,
+                "server1.test_histogram;foo=bar;gfoo=gbar:1.0|h|@0.5\n\
+                 server1.test_histogram;foo=bar;gfoo=gbar:2.0|h|@0.5\n",
+            ),
+            (
+                WireDialect::Influx,
+                "server1.test_histogram,foo=bar,gfoo=gbar:1.0|h|@0.5\n\
+                 server1.test_histogram,foo=bar,gfoo=gbar:2.0|h|@0.5\n",
+            ),
+            (
+                WireDialect::SignalFx,
+                "server1.test_histogram[foo=bar,gfoo=gbar]:1.0|h|@0.5\n\
+                 server1.test_histogram[foo=bar,gfoo=gbar]:2.0|h|@0.5\n",
+            ),
+        ];
+
+        for (dialect, expected) in cases {
+            let mut writer = PayloadWriter::new(8192, false)
+                .with_global_labels(&[Label::new("gfoo", "gbar")])
+                .with_container_id(Some("abc123"))
+                .with_dialect(dialect);
+            writer.write_histogram(&key, [1.0, 2.0], Some(0.5), Some("server1"));
+
+            let actual = string_from_writer(&mut writer);
+            assert_eq!(actual, expected, "dialect {dialect:?}");
+        }
+
+        // Timestamps, service checks, and events are only supported by DogStatsD.
+        let mut writer = PayloadWriter::new(8192, false).with_dialect(WireDialect::SignalFx);
+        writer.write_counter(&Key::from_name("test_counter"), 1, Some(1_700_000_000), None, None);
+        let result = writer.write_event(&Event::new("Deploy", "v1"));
+        assert_eq!(result.points_dropped(), 1);
+        let result =
+            writer.write_service_check(&ServiceCheck::new("app.ok", ServiceCheckStatus::Ok));
+        assert_eq!(result.points_dropped(), 1);
+        assert_eq!(string_from_writer(&mut writer), "test_counter:1|c\n");
+    }
+
+    #[test]
+    fn dialect_reserved_characters() {
+        let key = Key::from_parts("req;a,b[c", &[("k.e", "v:1=2;3,4[5]6.7")]);
+        let cases = [
+            (WireDialect::DogStatsD, "req;a,b[c:1|c|#k.e:v:1=2;3_4[5]6.7\n"),
+            (WireDialect::Etsy, "req;a,b[c.k_e.v_1=2;3_4[5]6_7:1|c\n"),
+            (WireDialect::Graphite, "req_a,b[c;k.e=v_1_2_3_4[5]6.7:1|c\n"),
+            (WireDialect::Influx, "req;a_b[c,k.e=v_1_2;3_4[5]6.7:1|c\n"),
+            (WireDialect::SignalFx, "req;a,b_c[k.e=v_1_2;3_4_5_6.7]:1|c\n"),
+        ];
+
+        for (dialect, expected) in cases {
+            let mut writer = PayloadWriter::new(8192, false).with_dialect(dialect);
+            writer.write_counter(&key, 1, None, None, None);
+
+            let actual = string_from_writer(&mut writer);
+            assert_eq!(actual, expected, "dialect {dialect:?}");
+        }
+    }
+
+    #[test]
+    fn container_id() {
+        let key = Key::from_parts("test_counter", &[("foo", "bar")]);
+        let mut writer = PayloadWriter::new(8192, false).with_container_id(Some("abc123"));
+        writer.write_counter(&key, 1, Some(1_700_000_000), None, None);
+        writer.write_distribution(&key, [1.0, 2.0], None, None);
+        writer.write_service_check(&ServiceCheck::new("app.ok", ServiceCheckStatus::Ok));
+        writer.write_event(&Event::new("Deploy", "v1"));
+
+        let actual = string_from_writer(&mut writer);
+        assert_eq!(
+            actual,
+            "test_counter:1|c|#foo:bar|c:abc123|T1700000000\n\
+             test_counter:1.0:2.0|d|#foo:bar|c:abc123\n\
+             _sc|app.ok|0|c:abc123\n\
+             _e{6,2}:Deploy|v1|c:abc123\n"
+        );
+    }
+
+    #[test]
+    fn sanitization() {
+        let key = Key::from_parts("requests|total", &[("env|x", "prod,eu"), ("|", "empty")]);
+        let mut writer = PayloadWriter::new(8192, false)
+            .with_sanitizer(Sanitizer {
+                reserved_chars: ReservedCharacters::Strip,
+                ..Sanitizer::default()
+            })
+            .with_global_labels(&[Label::new("Service", "web")]);
+        let result = writer.write_counter(&key, 1, None, None, None);
+        assert_eq!(result.payloads_written(), 1);
+        assert_eq!(writer.take_invalid_tags(), 1);
+        assert_eq!(writer.take_invalid_tags(), 0);
+        assert_eq!(string_from_writer(&mut writer), "requeststotal:1|c|#envx:prodeu,Service:web\n");
+
+        let mut writer = PayloadWriter::new(8192, false)
+            .with_sanitizer(Sanitizer { validate_metric_names: true, ..Sanitizer::default() });
+        let result = writer.write_histogram(&Key::from_name("9lives"), [1.0, 2.0], None, None);
+        assert_eq!(result.points_dropped(), 2);
+        let result = writer.write_set(&Key::from_name("users-seen"), ["a", "b"], Some("app"));
+        assert_eq!(result.points_dropped(), 2);
+        assert_eq!(string_from_writer(&mut writer), "");
+    }
+
     #[test]
     fn length_prefix() {
         let prefixed = |buf: &str| {
@@ -943,7 +1523,7 @@ mod tests {
                     InputMetric::Counter(key, value, ts) => {
                         total_input_points += 1;
 
-                        let result = writer.write_counter(&key, value, ts, None);
+                        let result = writer.write_counter(&key, value, ts, None, None);
                         payloads_written += result.payloads_written();
                         points_dropped += result.points_dropped();
                     },
@@ -976,19 +1556,14 @@ mod tests {
                 let payload_lines = std::str::from_utf8(payload).unwrap().lines();
 
                 // For each payload line, we increment the number of payloads emitted and we also extract the number of
-                // points contained in the metric payload.
+                // points contained in the metric payload, which also checks that every line is a valid metric.
                 for payload_line in payload_lines {
                     payloads_emitted += 1;
 
-                    // We don't care about the actual values in the payload, just the number of them.
-                    //
-                    // Split the name/points by taking everything in front of the first pipe character, and then split
-                    // by colon, and remove the first element which is the metric name.
-                    let num_points = payload_line.split('|')
-                        .next().unwrap()
-                        .split(':')
-                        .skip(1)
-                        .count();
+                    let Message::Metric(metric) = parse_line(payload_line).unwrap() else {
+                        panic!("expected a metric: {payload_line}");
+                    };
+                    let num_points = metric.values.as_numbers().unwrap().len();
                     assert!(num_points > 0);
 
                     points_emitted += num_points as u64;
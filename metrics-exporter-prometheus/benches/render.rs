@@ -0,0 +1,42 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use metrics::{Key, Label, Recorder};
+use metrics_exporter_prometheus::PrometheusBuilder;
+
+const FAMILIES: usize = 100;
+const SERIES_PER_FAMILY: usize = 2_000;
+
+static METADATA: metrics::Metadata =
+    metrics::Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
+
+fn render_benchmark(c: &mut Criterion) {
+    let recorder =
+        PrometheusBuilder::new().add_global_label("region", "us-east-1").build_recorder();
+
+    for family in 0..FAMILIES {
+        for series in 0..SERIES_PER_FAMILY {
+            let labels = vec![
+                Label::new("endpoint", format!("/api/v1/resource/{series}")),
+                Label::new("status", "200"),
+            ];
+            let key = Key::from_parts(format!("service.requests.{family}"), labels);
+            recorder.register_counter(&key, &METADATA).increment(series as u64);
+        }
+    }
+
+    let handle = recorder.handle();
+    let mut output = Vec::new();
+
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Elements((FAMILIES * SERIES_PER_FAMILY) as u64));
+    group.sample_size(10);
+    group.bench_function("counters", |b| {
+        b.iter(|| {
+            output.clear();
+            handle.render_to_write(&mut output).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render_benchmark);
+criterion_main!(benches);
@@ -1,8 +1,6 @@
-use std::collections::HashMap;
+use crate::PrometheusRecorder;
 
-use crate::{distribution::Distribution, PrometheusRecorder};
-
-use crate::formatting::sanitize_metric_name;
+use crate::formatting::{sanitize_metric_name, NameEscapingScheme};
 use indexmap::IndexMap;
 use metrics::SetRecorderError;
 use thiserror::Error;
@@ -41,6 +39,83 @@ impl Matcher {
     }
 }
 
+/// Selects which metric families are rendered.
+///
+/// An empty filter selects every metric family.  Otherwise, a metric family is selected if its name is equal to one of
+/// the configured names, or starts with one of the configured prefixes.  Names are compared against metric family
+/// names as they are rendered, after escaping, but before any unit or `_total` suffix is added.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MetricFilter {
+    names: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl MetricFilter {
+    /// Creates an empty [`MetricFilter`], which selects every metric family.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects metric families with the given name.
+    #[must_use]
+    pub fn with_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Selects metric families whose name starts with the given prefix.
+    #[must_use]
+    pub fn with_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// Returns `true` if the filter selects every metric family.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.prefixes.is_empty()
+    }
+
+    /// Checks if the metric family with the given name is selected by this filter.
+    pub fn matches(&self, name: &str) -> bool {
+        self.is_empty()
+            || self.names.iter().any(|n| n == name)
+            || self.prefixes.iter().any(|prefix| name.starts_with(prefix.as_str()))
+    }
+}
+
+/// Options for rendering a payload from a [`PrometheusHandle`][crate::PrometheusHandle].
+///
+/// By default, metric and label names are escaped with [`NameEscapingScheme::Underscores`], and every metric family
+/// is rendered.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RenderOptions {
+    pub(crate) escaping: NameEscapingScheme,
+    pub(crate) filter: MetricFilter,
+}
+
+impl RenderOptions {
+    /// Creates a [`RenderOptions`] with the default escaping scheme, which renders every metric family.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the scheme used to escape metric and label names.
+    #[must_use]
+    pub fn with_escaping(mut self, escaping: NameEscapingScheme) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// Sets the filter selecting which metric families are rendered.
+    ///
+    /// Metric families which are not selected are skipped entirely, rather than being rendered and then discarded.
+    #[must_use]
+    pub fn with_filter(mut self, filter: MetricFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
 /// Errors that could occur while building or installing a Prometheus recorder/exporter.
 #[derive(Debug, Error)]
 pub enum BuildError {
@@ -78,6 +153,13 @@ pub enum BuildError {
     /// Bucket duration cannot be zero.
     #[error("bucket durations cannot be set to zero")]
     ZeroBucketDuration,
+
+    /// The given configuration is not valid.
+    #[error("invalid configuration: {reason}")]
+    InvalidConfiguration {
+        /// Why the configuration is not valid.
+        reason: String,
+    },
 }
 
 /// Represents a set of labels as structured key-value pairs
@@ -115,10 +197,3 @@ impl LabelSet {
         })
     }
 }
-
-#[derive(Debug)]
-pub struct Snapshot {
-    pub counters: HashMap<String, HashMap<LabelSet, u64>>,
-    pub gauges: HashMap<String, HashMap<LabelSet, f64>>,
-    pub distributions: HashMap<String, IndexMap<LabelSet, Distribution>>,
-}
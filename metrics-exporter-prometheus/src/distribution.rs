@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::num::NonZeroU32;
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
@@ -41,6 +42,17 @@ pub enum Distribution {
 }
 
 impl Distribution {
+    /// Returns the distribution type, matching the values returned by
+    /// [`DistributionBuilder::get_distribution_type`].
+    pub(crate) fn distribution_type(&self) -> &'static str {
+        match self {
+            Distribution::Histogram(_) => "histogram",
+            Distribution::Summary(..) => "summary",
+            Distribution::NativeHistogram(hist) if hist.has_classic_buckets() => "histogram",
+            Distribution::NativeHistogram(_) => "native_histogram",
+        }
+    }
+
     /// Creates a histogram distribution.
     ///
     /// # Panics
@@ -79,14 +91,101 @@ impl Distribution {
                 }
             }
             Distribution::NativeHistogram(hist) => {
-                for (sample, _ts) in samples {
-                    hist.observe(*sample);
+                hist.observe_many(samples.iter().map(|(sample, _ts)| sample));
+            }
+        }
+    }
+}
+
+/// The values of one or more distributions, rendered as a single series.
+///
+/// Keys whose names and labels escape to the same series are rendered as one, so their distributions are merged rather
+/// than rendered as duplicate series.  Such keys share a sanitized name, and so always have the same kind of
+/// distribution, with the same configuration.
+#[derive(Debug)]
+pub(crate) enum MergedDistribution<'a> {
+    /// Cumulative bucket counts, excluding the `+Inf` bucket, along with the sum and count of all samples.
+    Histogram { buckets: Vec<(f64, u64)>, sum: f64, count: u64 },
+    /// A snapshot of the current window, along with the sum and count of all samples.
+    Summary { snapshot: Summary, quantiles: &'a [Quantile], sum: f64, count: u64 },
+    /// A native histogram, along with its classic buckets, if enabled.
+    NativeHistogram { histogram: Cow<'a, NativeHistogram>, classic: Option<Vec<(f64, u64)>> },
+}
+
+impl<'a> MergedDistribution<'a> {
+    /// Merges the given distributions, returning `None` if there are none.
+    ///
+    /// A single distribution is only borrowed, so merging is only paid for when keys collide.
+    pub(crate) fn merge(distributions: impl IntoIterator<Item = &'a Distribution>) -> Option<Self> {
+        let mut distributions = distributions.into_iter();
+        let mut merged = Self::from(distributions.next()?);
+        for distribution in distributions {
+            merged.merge_one(distribution);
+        }
+        Some(merged)
+    }
+
+    fn merge_one(&mut self, distribution: &'a Distribution) {
+        match (self, distribution) {
+            (Self::Histogram { buckets, sum, count }, Distribution::Histogram(histogram)) => {
+                add_buckets(buckets, histogram.buckets());
+                *sum += histogram.sum();
+                *count += histogram.count();
+            }
+            (
+                Self::Summary { snapshot, sum, count, .. },
+                Distribution::Summary(summary, _, summary_sum),
+            ) => {
+                snapshot
+                    .merge(&summary.snapshot(Instant::now()))
+                    .expect("merge can only fail if summary config inconsistent");
+                *sum += summary_sum;
+                *count += summary.count() as u64;
+            }
+            (
+                Self::NativeHistogram { histogram, classic },
+                Distribution::NativeHistogram(native),
+            ) => {
+                histogram.to_mut().merge(native);
+                if let (Some(classic), Some(other)) = (classic, native.classic_buckets()) {
+                    add_buckets(classic, other);
                 }
             }
+            // Colliding keys always have the same kind of distribution, so there is nothing sensible to merge here.
+            _ => {}
+        }
+    }
+}
+
+impl<'a> From<&'a Distribution> for MergedDistribution<'a> {
+    fn from(distribution: &'a Distribution) -> Self {
+        match distribution {
+            Distribution::Histogram(histogram) => Self::Histogram {
+                buckets: histogram.buckets(),
+                sum: histogram.sum(),
+                count: histogram.count(),
+            },
+            Distribution::Summary(summary, quantiles, sum) => Self::Summary {
+                snapshot: summary.snapshot(Instant::now()),
+                quantiles,
+                sum: *sum,
+                count: summary.count() as u64,
+            },
+            Distribution::NativeHistogram(histogram) => Self::NativeHistogram {
+                histogram: Cow::Borrowed(histogram),
+                classic: histogram.classic_buckets(),
+            },
         }
     }
 }
 
+/// Adds the counts of `other` to the matching buckets in `buckets`.
+fn add_buckets(buckets: &mut [(f64, u64)], other: Vec<(f64, u64)>) {
+    for ((_, count), (_, other_count)) in buckets.iter_mut().zip(other) {
+        *count += other_count;
+    }
+}
+
 /// Builds distributions for metric names based on a set of configured overrides.
 #[derive(Debug)]
 pub struct DistributionBuilder {
@@ -96,6 +195,8 @@ pub struct DistributionBuilder {
     bucket_count: Option<NonZeroU32>,
     bucket_overrides: Option<Vec<(Matcher, Vec<f64>)>>,
     native_histogram_overrides: Option<Vec<(Matcher, NativeHistogramConfig)>>,
+    native_histogram_classic_fallback: bool,
+    native_histogram_classic_bounds: Vec<f64>,
 }
 
 impl DistributionBuilder {
@@ -123,31 +224,58 @@ impl DistributionBuilder {
                 matchers.sort_by(|a, b| a.0.cmp(&b.0));
                 matchers
             }),
+            native_histogram_classic_fallback: false,
+            native_histogram_classic_bounds: Vec::new(),
         }
     }
 
-    /// Returns a distribution for the given metric key.
-    pub fn get_distribution(&self, name: &str) -> Distribution {
-        // Check for native histogram overrides first (highest priority)
-        if let Some(ref overrides) = self.native_histogram_overrides {
-            for (matcher, config) in overrides {
+    /// Sets whether native histograms also expose classic buckets.
+    ///
+    /// When enabled, native histograms also track classic buckets using the configured histogram buckets for the
+    /// metric, if any, or otherwise the given `default_bounds`.  This allows native histograms to be exposed in the
+    /// text format, and to be exposed with both bucket layouts in the protobuf format.
+    #[must_use]
+    pub fn with_native_histogram_classic_fallback(
+        mut self,
+        enabled: bool,
+        default_bounds: Vec<f64>,
+    ) -> Self {
+        self.native_histogram_classic_fallback = enabled;
+        self.native_histogram_classic_bounds = default_bounds;
+        self
+    }
+
+    fn get_buckets(&self, name: &str) -> Option<&[f64]> {
+        if let Some(ref overrides) = self.bucket_overrides {
+            for (matcher, buckets) in overrides {
                 if matcher.matches(name) {
-                    return Distribution::new_native_histogram(config.clone());
+                    return Some(buckets);
                 }
             }
         }
 
-        // Check for histogram bucket overrides
-        if let Some(ref overrides) = self.bucket_overrides {
-            for (matcher, buckets) in overrides {
+        self.buckets.as_deref()
+    }
+
+    /// Returns a distribution for the given metric key.
+    pub fn get_distribution(&self, name: &str) -> Distribution {
+        // Check for native histogram overrides first (highest priority)
+        if let Some(ref overrides) = self.native_histogram_overrides {
+            for (matcher, config) in overrides {
                 if matcher.matches(name) {
-                    return Distribution::new_histogram(buckets);
+                    let mut hist = NativeHistogram::new(config.clone());
+                    if self.native_histogram_classic_fallback {
+                        let bounds =
+                            self.get_buckets(name).unwrap_or(&self.native_histogram_classic_bounds);
+                        hist = hist.with_classic_buckets(bounds);
+                    }
+                    return Distribution::NativeHistogram(hist);
                 }
             }
         }
 
-        // Check for global histogram buckets
-        if let Some(ref buckets) = self.buckets {
+        // Check for histogram bucket overrides, then global histogram buckets
+        if let Some(buckets) = self.get_buckets(name) {
             return Distribution::new_histogram(buckets);
         }
 
@@ -164,6 +292,10 @@ impl DistributionBuilder {
         if let Some(ref overrides) = self.native_histogram_overrides {
             for (matcher, _) in overrides {
                 if matcher.matches(name) {
+                    // Native histograms with classic buckets are exposed as regular histograms in the text format.
+                    if self.native_histogram_classic_fallback {
+                        return "histogram";
+                    }
                     return "native_histogram";
                 }
             }
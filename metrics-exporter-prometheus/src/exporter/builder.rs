@@ -22,15 +22,19 @@ use quanta::Clock;
 
 use metrics_util::{
     parse_quantiles,
-    registry::{GenerationalStorage, Recency, Registry},
+    registry::{GenerationalStorage, IdleTimeout, Recency, Registry},
     MetricKindMask, Quantile,
 };
 
 use crate::common::Matcher;
 use crate::distribution::DistributionBuilder;
-use crate::native_histogram::NativeHistogramConfig;
-use crate::recorder::{new_description_handles, Inner, PrometheusRecorder};
+use crate::formatting::sanitize_metric_name;
+use crate::native_histogram::{
+    power_of_two_bounds, NativeHistogramConfig, DEFAULT_CLASSIC_BUCKET_RANGE,
+};
+use crate::recorder::{new_description_handles, Inner, PrometheusRecorder, RenderCache};
 use crate::registry::AtomicStorage;
+use crate::telemetry::{self, TELEMETRY_PREFIX};
 use crate::{common::BuildError, PrometheusHandle};
 
 use super::ExporterConfig;
@@ -43,6 +47,7 @@ use super::ExporterFuture;
 
 /// Builder for creating and installing a Prometheus recorder/exporter.
 #[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct PrometheusBuilder {
     #[cfg_attr(
         not(any(
@@ -61,13 +66,17 @@ pub struct PrometheusBuilder {
     buckets: Option<Vec<f64>>,
     bucket_overrides: Option<HashMap<Matcher, Vec<f64>>>,
     native_histogram_overrides: Option<HashMap<Matcher, NativeHistogramConfig>>,
+    native_histogram_classic_fallback: bool,
+    native_histogram_classic_bounds: Vec<f64>,
     idle_timeout: Option<Duration>,
+    idle_timeout_overrides: Option<HashMap<Matcher, IdleTimeout>>,
     upkeep_timeout: Duration,
     recency_mask: MetricKindMask,
     global_labels: Option<IndexMap<String, String>>,
     enable_recommended_naming: bool,
     /// TODO Remove this field in next version and merge with `enable_recommended_naming`
     enable_unit_suffix: bool,
+    telemetry: bool,
 }
 
 impl PrometheusBuilder {
@@ -97,12 +106,19 @@ impl PrometheusBuilder {
             buckets: None,
             bucket_overrides: None,
             native_histogram_overrides: None,
+            native_histogram_classic_fallback: false,
+            native_histogram_classic_bounds: power_of_two_bounds(
+                DEFAULT_CLASSIC_BUCKET_RANGE.0,
+                DEFAULT_CLASSIC_BUCKET_RANGE.1,
+            ),
             idle_timeout: None,
+            idle_timeout_overrides: None,
             upkeep_timeout,
             recency_mask: MetricKindMask::NONE,
             global_labels: None,
             enable_recommended_naming: false,
             enable_unit_suffix: false,
+            telemetry: false,
         }
     }
 
@@ -336,6 +352,19 @@ impl PrometheusBuilder {
         self
     }
 
+    /// Sets whether or not to enable telemetry for the exporter.
+    ///
+    /// When enabled, the exporter reports metrics about itself, such as the number and duration of renders, the number
+    /// of series by kind, idle evictions, push gateway results, and rejected connections.  These metrics are prefixed
+    /// with `metrics_exporter_`, are rendered alongside all other metrics, and are never subject to idle timeouts.
+    ///
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn with_telemetry(mut self, enabled: bool) -> Self {
+        self.telemetry = enabled;
+        self
+    }
+
     /// Sets the bucket for a specific pattern.
     ///
     /// The match pattern can be a full match (equality), prefix match, or suffix match.  The matchers are applied in
@@ -374,7 +403,8 @@ impl PrometheusBuilder {
     /// metric, the prefix match would win.
     ///
     /// Native histograms use exponential buckets and take precedence over regular histograms and summaries.
-    /// They are only supported in the protobuf format.
+    /// They are only supported in the protobuf format, unless classic buckets are enabled with
+    /// [`set_native_histogram_classic_fallback`][Self::set_native_histogram_classic_fallback].
     #[must_use]
     pub fn set_native_histogram_for_metric(
         mut self,
@@ -386,6 +416,57 @@ impl PrometheusBuilder {
         self
     }
 
+    /// Sets whether native histograms also expose classic buckets.
+    ///
+    /// When enabled, native histograms are exposed with both classic and native buckets in the protobuf format, and
+    /// with classic buckets in the text format, instead of being omitted from it.  This mirrors the behavior of the
+    /// official Go client when both bucket layouts are configured, and is useful while migrating scrapers to native
+    /// histograms.
+    ///
+    /// The classic buckets come from the buckets configured for the metric via
+    /// [`set_buckets_for_metric`][Self::set_buckets_for_metric] or [`set_buckets`][Self::set_buckets], if any.
+    /// Otherwise, every power of two within the range set by
+    /// [`set_native_histogram_classic_range`][Self::set_native_histogram_classic_range] is used as a bucket bound.
+    /// Either way, the bucket bounds of a metric are fixed, so they do not change between scrapes as observations
+    /// populate new native buckets.
+    ///
+    /// Defaults to `false`.
+    #[must_use]
+    pub fn set_native_histogram_classic_fallback(mut self, enabled: bool) -> Self {
+        self.native_histogram_classic_fallback = enabled;
+        self
+    }
+
+    /// Sets the range of the classic buckets exposed for native histograms without configured buckets.
+    ///
+    /// Only used when classic buckets are enabled with
+    /// [`set_native_histogram_classic_fallback`][Self::set_native_histogram_classic_fallback].  Every power of two
+    /// between `min` and `max`, inclusive, becomes a classic bucket bound.  Observations above the largest bound are
+    /// only counted in the `+Inf` bucket.
+    ///
+    /// Defaults to `2^-10` (~0.001) through `2^10` (1024).
+    ///
+    /// ## Errors
+    ///
+    /// If the range does not contain any power of two, or `min` is not positive, an error variant will be returned.
+    pub fn set_native_histogram_classic_range(
+        mut self,
+        min: f64,
+        max: f64,
+    ) -> Result<Self, BuildError> {
+        let bounds = power_of_two_bounds(min, max);
+        if bounds.is_empty() {
+            return Err(BuildError::InvalidConfiguration {
+                reason: format!(
+                    "native histogram classic range [{min}, {max}] must be positive, finite, and contain a power of two"
+                ),
+            });
+        }
+
+        self.native_histogram_classic_bounds = bounds;
+        Ok(self)
+    }
+
     /// Sets the idle timeout for metrics.
     ///
     /// If a metric hasn't been updated within this timeout, it will be removed from the registry and in turn removed
@@ -406,6 +487,36 @@ impl PrometheusBuilder {
         self
     }
 
+    /// Sets the idle timeout for a specific pattern.
+    ///
+    /// The match pattern can be a full match (equality), prefix match, or suffix match.  The matchers are applied in
+    /// that order if two or more matchers would apply to a single metric.  That is to say, if a full match and a prefix
+    /// match applied to a metric, the full match would win, and if a prefix match and a suffix match applied to a
+    /// metric, the prefix match would win.
+    ///
+    /// Matching metrics use the given timeout instead of the global timeout set by
+    /// [`idle_timeout`][Self::idle_timeout], regardless of their kind or the global metric kind mask.
+    #[must_use]
+    pub fn set_idle_timeout_for_metric(mut self, matcher: Matcher, timeout: Duration) -> Self {
+        let overrides = self.idle_timeout_overrides.get_or_insert_with(HashMap::new);
+        overrides.insert(matcher.sanitized(), IdleTimeout::After(timeout));
+        self
+    }
+
+    /// Disables the idle timeout for a specific pattern.
+    ///
+    /// The match pattern can be a full match (equality), prefix match, or suffix match, and follows the same precedence
+    /// rules as [`set_idle_timeout_for_metric`][Self::set_idle_timeout_for_metric].
+    ///
+    /// Matching metrics will never be removed for being idle, even if they would otherwise be covered by the global
+    /// timeout set by [`idle_timeout`][Self::idle_timeout].
+    #[must_use]
+    pub fn disable_idle_timeout_for_metric(mut self, matcher: Matcher) -> Self {
+        let overrides = self.idle_timeout_overrides.get_or_insert_with(HashMap::new);
+        overrides.insert(matcher.sanitized(), IdleTimeout::Never);
+        self
+    }
+
     /// Sets the upkeep interval.
     ///
     /// The upkeep task handles periodic maintenance operations, such as draining histogram data, to ensure that all
@@ -607,12 +718,36 @@ impl PrometheusBuilder {
     }
 
     pub(crate) fn build_with_clock(self, clock: Clock) -> PrometheusRecorder {
-        let (descriptions_wr, descriptions_rd) = new_description_handles();
+        let (mut descriptions_wr, descriptions_rd) = new_description_handles();
+        if self.telemetry {
+            telemetry::describe(&mut descriptions_wr);
+        }
+
+        let mut recency = Recency::new(clock, self.recency_mask, self.idle_timeout);
+        if self.idle_timeout_overrides.is_some() || self.telemetry {
+            let mut matchers =
+                self.idle_timeout_overrides.unwrap_or_default().into_iter().collect::<Vec<_>>();
+            matchers.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let telemetry = self.telemetry;
+            recency = recency.with_idle_timeout_resolver(move |key: &metrics::Key| {
+                // Telemetry is only updated when rendering or pushing, so it should never be considered idle.
+                if telemetry && key.name().starts_with(TELEMETRY_PREFIX) {
+                    return Some(IdleTimeout::Never);
+                }
+
+                let name = sanitize_metric_name(key.name());
+                matchers
+                    .iter()
+                    .find(|(matcher, _)| matcher.matches(&name))
+                    .map(|(_, timeout)| *timeout)
+            });
+        }
 
         let inner = Inner {
             registry: Registry::new(GenerationalStorage::new(AtomicStorage)),
-            recency: Recency::new(clock, self.recency_mask, self.idle_timeout),
-            distributions: RwLock::new(HashMap::new()),
+            recency,
+            distributions: RwLock::new(HashMap::default()),
             distribution_builder: DistributionBuilder::new(
                 self.quantiles,
                 self.bucket_duration,
@@ -620,12 +755,18 @@ impl PrometheusBuilder {
                 self.bucket_count,
                 self.bucket_overrides,
                 self.native_histogram_overrides,
+            )
+            .with_native_histogram_classic_fallback(
+                self.native_histogram_classic_fallback,
+                self.native_histogram_classic_bounds,
             ),
             descriptions_rd: Mutex::new(descriptions_rd),
             descriptions_wr: Mutex::new(descriptions_wr),
+            render_cache: Mutex::new(RenderCache::default()),
             global_labels: self.global_labels.unwrap_or_default(),
             enable_unit_suffix: self.enable_recommended_naming || self.enable_unit_suffix,
             counter_suffix: self.enable_recommended_naming.then_some("total"),
+            telemetry: self.telemetry,
         };
 
         PrometheusRecorder::from(inner)
@@ -648,7 +789,10 @@ mod tests {
     use metrics::{Key, KeyName, Label, Recorder, Unit};
     use metrics_util::MetricKindMask;
 
-    use super::{Matcher, PrometheusBuilder};
+    use super::{BuildError, Matcher, PrometheusBuilder};
+    use crate::formatting::NameEscapingScheme;
+    use crate::parse::{self, MetricType, MetricValue};
+    use crate::{MetricFilter, NativeHistogramConfig, RenderOptions};
 
     static METADATA: metrics::Metadata =
         metrics::Metadata::new(module_path!(), metrics::Level::INFO, Some(module_path!()));
@@ -884,6 +1028,79 @@ mod tests {
         assert!(rendered.contains(default_data));
     }
 
+    #[test]
+    fn test_native_histogram_classic_fallback() {
+        let recorder = PrometheusBuilder::new()
+            .set_native_histogram_for_metric(
+                Matcher::Full("native_only".to_owned()),
+                NativeHistogramConfig::new(2.0, 160, 1e-9).unwrap(),
+            )
+            .build_recorder();
+
+        let key = Key::from_name("native_only");
+        let histo1 = recorder.register_histogram(&key, &METADATA);
+        histo1.record(1.5);
+
+        // Without classic buckets, native histograms are not exposed in the text format.
+        let handle = recorder.handle();
+        assert_eq!(handle.render(), "");
+
+        let recorder = PrometheusBuilder::new()
+            .set_native_histogram_for_metric(
+                Matcher::Prefix("native_".to_owned()),
+                NativeHistogramConfig::new(2.0, 160, 1e-9).unwrap(),
+            )
+            .set_buckets_for_metric(Matcher::Full("native_fixed".to_owned()), &[1.0, 2.0])
+            .unwrap()
+            .set_native_histogram_classic_fallback(true)
+            .set_native_histogram_classic_range(1.0, 8.0)
+            .unwrap()
+            .build_recorder();
+
+        let key = Key::from_name("native_fixed");
+        let histo1 = recorder.register_histogram(&key, &METADATA);
+        histo1.record(1.5);
+        histo1.record(3.0);
+
+        let key = Key::from_name("native_derived");
+        let histo2 = recorder.register_histogram(&key, &METADATA);
+        histo2.record(1.5);
+        histo2.record(3.0);
+
+        let handle = recorder.handle();
+        let rendered = handle.render();
+
+        let fixed_data = concat!(
+            "# TYPE native_fixed histogram\n",
+            "native_fixed_bucket{le=\"1\"} 0\n",
+            "native_fixed_bucket{le=\"2\"} 1\n",
+            "native_fixed_bucket{le=\"+Inf\"} 2\n",
+            "native_fixed_sum 4.5\n",
+            "native_fixed_count 2\n",
+            "\n",
+        );
+        // Without configured buckets, every power of two in the classic range is a bucket, populated or not.
+        let derived_data = concat!(
+            "# TYPE native_derived histogram\n",
+            "native_derived_bucket{le=\"1\"} 0\n",
+            "native_derived_bucket{le=\"2\"} 1\n",
+            "native_derived_bucket{le=\"4\"} 2\n",
+            "native_derived_bucket{le=\"8\"} 2\n",
+            "native_derived_bucket{le=\"+Inf\"} 2\n",
+            "native_derived_sum 4.5\n",
+            "native_derived_count 2\n",
+            "\n",
+        );
+
+        assert!(rendered.contains(fixed_data), "unexpected output: {}", rendered);
+        assert!(rendered.contains(derived_data), "unexpected output: {}", rendered);
+
+        assert!(matches!(
+            PrometheusBuilder::new().set_native_histogram_classic_range(3.0, 3.5),
+            Err(BuildError::InvalidConfiguration { .. })
+        ));
+    }
+
     #[test]
     fn test_idle_timeout_all() {
         let (clock, mock) = Clock::mock();
@@ -1150,6 +1367,473 @@ mod tests {
         assert_eq!(rendered, "");
     }
 
+    #[test]
+    fn test_render_with_escaping() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+
+        let key = Key::from_parts("http.server.requests", vec![Label::new("service.name", "api")]);
+        let counter1 = recorder.register_counter(&key, &METADATA);
+        counter1.increment(42);
+        recorder.describe_counter(
+            KeyName::from("http.server.requests"),
+            None,
+            "Number of requests.".into(),
+        );
+
+        let handle = recorder.handle();
+
+        let rendered = handle.render();
+        let expected = concat!(
+            "# HELP http_server_requests Number of requests.\n",
+            "# TYPE http_server_requests counter\n",
+            "http_server_requests{service_name=\"api\"} 42\n\n",
+        );
+        assert_eq!(rendered, expected);
+
+        let rendered = handle.render_with_options(
+            &RenderOptions::new().with_escaping(NameEscapingScheme::AllowUtf8),
+        );
+        let expected = concat!(
+            "# HELP \"http.server.requests\" Number of requests.\n",
+            "# TYPE \"http.server.requests\" counter\n",
+            "{\"http.server.requests\",\"service.name\"=\"api\"} 42\n\n",
+        );
+        assert_eq!(rendered, expected);
+
+        let rendered = handle
+            .render_with_options(&RenderOptions::new().with_escaping(NameEscapingScheme::Dots));
+        let expected = concat!(
+            "# HELP http_dot_server_dot_requests Number of requests.\n",
+            "# TYPE http_dot_server_dot_requests counter\n",
+            "http_dot_server_dot_requests{service_dot_name=\"api\"} 42\n\n",
+        );
+        assert_eq!(rendered, expected);
+
+        let rendered = handle
+            .render_with_options(&RenderOptions::new().with_escaping(NameEscapingScheme::Values));
+        let expected = concat!(
+            "# HELP U__http_2e_server_2e_requests Number of requests.\n",
+            "# TYPE U__http_2e_server_2e_requests counter\n",
+            "U__http_2e_server_2e_requests{U__service_2e_name=\"api\"} 42\n\n",
+        );
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Full("latency".to_owned()), &[0.5, 1.0])
+            .unwrap()
+            .add_global_label("region", "us-east")
+            .build_recorder();
+
+        let key = Key::from_parts("requests", vec![Label::new("path", "/\"quoted\"")]);
+        recorder.register_counter(&key, &METADATA).increment(7);
+        recorder.describe_counter(
+            KeyName::from("requests"),
+            None,
+            "Requests.\nAll of them.".into(),
+        );
+        recorder.register_gauge(&Key::from_name("temperature"), &METADATA).set(-2.5);
+        let latency = recorder.register_histogram(&Key::from_name("latency"), &METADATA);
+        latency.record(0.25);
+        latency.record(0.75);
+        latency.record(5.0);
+        recorder.register_histogram(&Key::from_name("sizes"), &METADATA).record(12.0);
+
+        let handle = recorder.handle();
+        let mut families = parse::text::parse(&handle.render()).unwrap();
+        families.sort_by(|a, b| a.name.cmp(&b.name));
+        let names = families.iter().map(|family| family.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["latency", "requests", "sizes", "temperature"]);
+
+        let latency = &families[0];
+        assert_eq!(latency.metric_type, MetricType::Histogram);
+        let metric = latency.get(&[("region", "us-east")]).unwrap();
+        let MetricValue::Histogram(histogram) = &metric.value else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(histogram.buckets, vec![(0.5, 1), (1.0, 2), (f64::INFINITY, 3)]);
+        assert_eq!((histogram.count, histogram.sum), (3, 6.0));
+
+        let requests = &families[1];
+        assert_eq!(requests.help.as_deref(), Some("Requests.\nAll of them."));
+        let metric = requests.get(&[("region", "us-east"), ("path", "/\"quoted\"")]).unwrap();
+        assert_eq!(metric.value, MetricValue::Counter(7.0));
+
+        let sizes = &families[2];
+        assert_eq!(sizes.metric_type, MetricType::Summary);
+        let MetricValue::Summary(summary) = &sizes.metrics[0].value else {
+            panic!("expected a summary");
+        };
+        assert_eq!((summary.count, summary.sum), (1, 12.0));
+        assert_eq!(summary.quantiles.len(), 7);
+
+        assert_eq!(families[3].metrics[0].value.as_f64(), Some(-2.5));
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_render_protobuf_round_trip() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        recorder.register_counter(&Key::from_name("requests"), &METADATA).increment(7);
+        recorder.register_gauge(&Key::from_name("temperature"), &METADATA).set(-2.5);
+
+        let handle = recorder.handle();
+        let mut from_protobuf = parse::protobuf::parse(&handle.render_protobuf()).unwrap();
+        let mut from_text = parse::text::parse(&handle.render()).unwrap();
+        from_protobuf.sort_by(|a, b| a.name.cmp(&b.name));
+        from_text.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(from_protobuf, from_text);
+    }
+
+    #[test]
+    fn test_render_with_filter() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+
+        let counter1 = recorder.register_counter(&Key::from_name("db_queries"), &METADATA);
+        counter1.increment(3);
+        let gauge1 = recorder.register_gauge(&Key::from_name("db_connections"), &METADATA);
+        gauge1.set(4.0);
+        let histogram1 = recorder.register_histogram(&Key::from_name("http_latency"), &METADATA);
+        histogram1.record(0.5);
+        let counter2 = recorder.register_counter(&Key::from_name("http_requests"), &METADATA);
+        counter2.increment(1);
+
+        let handle = recorder.handle();
+
+        let filter = MetricFilter::new().with_prefix("db_");
+        let rendered = handle.render_with_options(&RenderOptions::new().with_filter(filter));
+        assert!(rendered.contains("db_queries 3\n"));
+        assert!(rendered.contains("db_connections 4\n"));
+        assert!(!rendered.contains("http_"));
+
+        let filter = MetricFilter::new().with_name("http_latency").with_name("db_queries");
+        let rendered = handle.render_with_options(&RenderOptions::new().with_filter(filter));
+        assert!(rendered.contains("db_queries 3\n"));
+        assert!(rendered.contains("http_latency_count 1\n"));
+        assert!(!rendered.contains("db_connections"));
+        assert!(!rendered.contains("http_requests"));
+
+        // Filtering doesn't affect subsequent unfiltered renders.
+        let rendered = handle.render();
+        assert!(rendered.contains("db_queries 3\n"));
+        assert!(rendered.contains("db_connections 4\n"));
+        assert!(rendered.contains("http_latency_count 1\n"));
+        assert!(rendered.contains("http_requests 1\n"));
+    }
+
+    #[test]
+    fn test_render_groups_colliding_names() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+
+        let counter1 = recorder.register_counter(&Key::from_name("requests.total"), &METADATA);
+        counter1.increment(1);
+        let key = Key::from_parts("requests_total", vec![Label::new("method", "GET")]);
+        let counter2 = recorder.register_counter(&key, &METADATA);
+        counter2.increment(2);
+
+        let handle = recorder.handle();
+
+        // Render twice, so that the second render is served from cached names and labels.
+        for _ in 0..2 {
+            let rendered = handle.render();
+            assert_eq!(rendered.matches("# TYPE requests_total counter\n").count(), 1);
+            assert!(rendered.contains("requests_total 1\n"));
+            assert!(rendered.contains("requests_total{method=\"GET\"} 2\n"));
+        }
+
+        let rendered = handle.render_with_options(
+            &RenderOptions::new().with_escaping(NameEscapingScheme::AllowUtf8),
+        );
+        assert!(rendered.contains("# TYPE \"requests.total\" counter\n"));
+        assert!(rendered.contains("# TYPE requests_total counter\n"));
+    }
+
+    #[test]
+    fn test_render_merges_colliding_series() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Prefix("size".to_owned()), &[1.0, 5.0])
+            .unwrap()
+            .build_recorder();
+
+        // Each pair of keys escapes to the same family and labels.
+        let key = Key::from_parts("requests.total", vec![Label::new("http.method", "GET")]);
+        recorder.register_counter(&key, &METADATA).increment(1);
+        let key = Key::from_parts("requests_total", vec![Label::new("http_method", "GET")]);
+        recorder.register_counter(&key, &METADATA).increment(2);
+        recorder.register_histogram(&Key::from_name("lat.x"), &METADATA).record(1.0);
+        recorder.register_histogram(&Key::from_name("lat_x"), &METADATA).record(2.0);
+        recorder.register_histogram(&Key::from_name("size.x"), &METADATA).record(1.0);
+        recorder.register_histogram(&Key::from_name("size_x"), &METADATA).record(3.0);
+
+        let handle = recorder.handle();
+
+        // Render twice, so that the second render is served from cached names and labels.
+        for _ in 0..2 {
+            let rendered = handle.render();
+
+            // Only one of the counter values can be rendered.
+            let samples = rendered
+                .lines()
+                .filter(|line| line.starts_with("requests_total{http_method=\"GET\"} "))
+                .collect::<Vec<_>>();
+            assert_eq!(samples.len(), 1, "unexpected output: {rendered}");
+
+            // Distributions are merged.
+            assert_eq!(rendered.matches("lat_x_count").count(), 1, "unexpected output: {rendered}");
+            assert!(rendered.contains("lat_x_sum 3\nlat_x_count 2\n"));
+            assert!(rendered.contains(concat!(
+                "size_x_bucket{le=\"1\"} 1\n",
+                "size_x_bucket{le=\"5\"} 2\n",
+                "size_x_bucket{le=\"+Inf\"} 2\n",
+                "size_x_sum 4\n",
+                "size_x_count 2\n",
+            )));
+        }
+
+        // Without escaping, the keys are distinct families.
+        let rendered = handle.render_with_options(
+            &RenderOptions::new().with_escaping(NameEscapingScheme::AllowUtf8),
+        );
+        assert!(rendered.contains("{\"lat.x_sum\"} 1\n"), "unexpected output: {}", rendered);
+        assert!(rendered.contains("lat_x_sum 2\n"), "unexpected output: {}", rendered);
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_render_protobuf_merges_colliding_series() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Prefix("size".to_owned()), &[1.0, 5.0])
+            .unwrap()
+            .set_native_histogram_for_metric(
+                Matcher::Prefix("native".to_owned()),
+                NativeHistogramConfig::new(2.0, 160, 1e-9).unwrap(),
+            )
+            .build_recorder();
+
+        recorder.register_counter(&Key::from_name("requests.total"), &METADATA).increment(1);
+        recorder.register_counter(&Key::from_name("requests_total"), &METADATA).increment(1);
+        recorder.register_histogram(&Key::from_name("lat.x"), &METADATA).record(1.0);
+        recorder.register_histogram(&Key::from_name("lat_x"), &METADATA).record(2.0);
+        recorder.register_histogram(&Key::from_name("size.x"), &METADATA).record(1.0);
+        recorder.register_histogram(&Key::from_name("size_x"), &METADATA).record(3.0);
+        recorder.register_histogram(&Key::from_name("native.x"), &METADATA).record(1.5);
+        recorder.register_histogram(&Key::from_name("native_x"), &METADATA).record(3.0);
+
+        let handle = recorder.handle();
+        let mut from_protobuf = parse::protobuf::parse(&handle.render_protobuf()).unwrap();
+        from_protobuf.sort_by(|a, b| a.name.cmp(&b.name));
+        assert!(from_protobuf.iter().all(|family| family.metrics.len() == 1));
+
+        let native = from_protobuf.iter().find(|family| family.name == "native_x").unwrap();
+        let MetricValue::Histogram(histogram) = &native.metrics[0].value else {
+            panic!("expected a histogram");
+        };
+        assert_eq!((histogram.count, histogram.sum), (2, 4.5));
+        let native_buckets = histogram.native.as_ref().unwrap();
+        assert_eq!(native_buckets.positive.iter().map(|(_, count)| count).sum::<u64>(), 2);
+
+        // Aside from native histograms, which are not exposed in the text format, both formats agree.
+        from_protobuf.retain(|family| family.name != "native_x");
+        let mut from_text = parse::text::parse(&handle.render()).unwrap();
+        from_text.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(from_protobuf, from_text);
+    }
+
+    #[test]
+    fn test_idle_timeout_per_metric() {
+        let (clock, mock) = Clock::mock();
+
+        let recorder = PrometheusBuilder::new()
+            .idle_timeout(MetricKindMask::ALL, Some(Duration::from_secs(10)))
+            .set_idle_timeout_for_metric(
+                Matcher::Prefix("connection_".to_owned()),
+                Duration::from_secs(60),
+            )
+            .disable_idle_timeout_for_metric(Matcher::Suffix("_orders".to_owned()))
+            .build_with_clock(clock);
+
+        let key = Key::from_name("processed_orders");
+        let counter1 = recorder.register_counter(&key, &METADATA);
+        counter1.increment(42);
+
+        let key = Key::from_name("connection_bytes");
+        let gauge1 = recorder.register_gauge(&key, &METADATA);
+        gauge1.set(12.0);
+
+        let key = Key::from_name("basic_gauge");
+        let gauge2 = recorder.register_gauge(&key, &METADATA);
+        gauge2.set(-3.14);
+
+        let handle = recorder.handle();
+        let rendered = handle.render();
+        assert!(rendered.contains("processed_orders 42\n"));
+        assert!(rendered.contains("connection_bytes 12\n"));
+        assert!(rendered.contains("basic_gauge -3.14\n"));
+
+        // Past the global idle timeout, only the metric without an override should be removed.
+        mock.increment(Duration::from_secs(11));
+        let rendered = handle.render();
+        assert!(rendered.contains("processed_orders 42\n"));
+        assert!(rendered.contains("connection_bytes 12\n"));
+        assert!(!rendered.contains("basic_gauge"));
+
+        // Past the per-metric idle timeout, only the metric that never expires should remain.
+        mock.increment(Duration::from_secs(50));
+        let rendered = handle.render();
+        assert_eq!(rendered, "# TYPE processed_orders counter\nprocessed_orders 42\n\n");
+    }
+
+    #[test]
+    fn test_telemetry() {
+        let (clock, mock) = Clock::mock();
+
+        let recorder = PrometheusBuilder::new()
+            .idle_timeout(MetricKindMask::ALL, Some(Duration::from_secs(10)))
+            .with_telemetry(true)
+            .build_with_clock(clock);
+
+        let key = Key::from_name("basic_counter");
+        let counter1 = recorder.register_counter(&key, &METADATA);
+        counter1.increment(42);
+
+        let key = Key::from_name("basic_gauge");
+        let gauge1 = recorder.register_gauge(&key, &METADATA);
+        gauge1.set(-3.14);
+
+        // Telemetry describes the previous render, so nothing has been recorded yet.
+        let handle = recorder.handle();
+        let rendered = handle.render();
+        assert!(!rendered.contains("metrics_exporter_"));
+
+        let rendered = handle.render();
+        assert!(rendered.contains(
+            "# HELP metrics_exporter_renders_total Number of times metrics have been rendered, whether scraped, pushed, or rendered directly.\n"
+        ));
+        assert!(rendered.contains("metrics_exporter_renders_total 1\n"));
+        assert!(rendered.contains("metrics_exporter_series{kind=\"counter\"} 1\n"));
+        assert!(rendered.contains("metrics_exporter_series{kind=\"gauge\"} 1\n"));
+        assert!(rendered.contains("metrics_exporter_series{kind=\"histogram\"} 0\n"));
+        assert!(rendered.contains("metrics_exporter_render_duration_seconds_count 1\n"));
+        assert!(!rendered.contains("metrics_exporter_idle_evictions_total"));
+
+        // Only the gauge is updated, so the counter is evicted, while telemetry never expires.
+        mock.increment(Duration::from_secs(11));
+        gauge1.set(1.0);
+        handle.render();
+        let rendered = handle.render();
+        assert!(!rendered.contains("basic_counter"));
+        assert!(rendered.contains("basic_gauge 1\n"));
+        assert!(rendered.contains("metrics_exporter_renders_total 3\n"));
+        assert!(rendered.contains("metrics_exporter_idle_evictions_total{kind=\"counter\"} 1\n"));
+    }
+
+    #[test]
+    fn test_telemetry_ignores_filtered_series_counts() {
+        let recorder = PrometheusBuilder::new().with_telemetry(true).build_recorder();
+
+        let key = Key::from_name("basic_counter");
+        let counter1 = recorder.register_counter(&key, &METADATA);
+        counter1.increment(42);
+
+        let key = Key::from_name("basic_gauge");
+        let gauge1 = recorder.register_gauge(&key, &METADATA);
+        gauge1.set(-3.14);
+
+        let handle = recorder.handle();
+        handle.render();
+
+        // A filtered render only sees some of the series, so it must not overwrite the series counts.
+        let filter = MetricFilter::new().with_name("basic_gauge");
+        let rendered = handle.render_with_options(&RenderOptions::new().with_filter(filter));
+        assert!(!rendered.contains("basic_counter"));
+
+        let rendered = handle.render();
+        assert!(rendered.contains("metrics_exporter_renders_total 2\n"));
+        assert!(rendered.contains("metrics_exporter_series{kind=\"counter\"} 1\n"));
+        assert!(rendered.contains("metrics_exporter_series{kind=\"gauge\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_orders_families_by_name() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        recorder.register_counter(&Key::from_name("b_counter"), &METADATA).increment(1);
+        recorder
+            .register_counter(&Key::from_parts("a_counter", &[("x", "2")]), &METADATA)
+            .increment(2);
+        recorder
+            .register_counter(&Key::from_parts("a_counter", &[("x", "1")]), &METADATA)
+            .increment(3);
+
+        // Families are ordered by name, and series within a family by when they were first seen.
+        let rendered = recorder.handle().render();
+        let expected = concat!(
+            "# TYPE a_counter counter\n",
+            "a_counter{x=\"2\"} 2\n",
+            "a_counter{x=\"1\"} 3\n\n",
+            "# TYPE b_counter counter\n",
+            "b_counter 1\n\n",
+        );
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_idle_timeout_evicts_from_every_escaping_scheme() {
+        let (clock, mock) = Clock::mock();
+        let recorder = PrometheusBuilder::new()
+            .idle_timeout(MetricKindMask::ALL, Some(Duration::from_secs(10)))
+            .build_with_clock(clock);
+
+        let counter = recorder.register_counter(&Key::from_name("basic.counter"), &METADATA);
+        counter.increment(1);
+        let gauge = recorder.register_gauge(&Key::from_name("basic.gauge"), &METADATA);
+        gauge.set(1.0);
+
+        let handle = recorder.handle();
+        let utf8 = RenderOptions::new().with_escaping(NameEscapingScheme::AllowUtf8);
+        handle.render_with_options(&utf8);
+        handle.render();
+        assert_eq!(handle.cached_series(NameEscapingScheme::AllowUtf8), (2, 2));
+
+        // Only the gauge is updated, so the counter is evicted by the next render, in every scheme.
+        mock.increment(Duration::from_secs(11));
+        gauge.set(2.0);
+        let rendered = handle.render();
+        assert!(!rendered.contains("basic_counter"));
+        assert_eq!(handle.cached_series(NameEscapingScheme::Underscores), (1, 1));
+        assert_eq!(handle.cached_series(NameEscapingScheme::AllowUtf8), (1, 1));
+    }
+
+    #[test]
+    fn test_idle_timeout_per_metric_without_global_timeout() {
+        let (clock, mock) = Clock::mock();
+
+        let recorder = PrometheusBuilder::new()
+            .set_idle_timeout_for_metric(
+                Matcher::Full("connection_bytes".to_owned()),
+                Duration::from_secs(10),
+            )
+            .build_with_clock(clock);
+
+        let key = Key::from_name("connection_bytes");
+        let gauge1 = recorder.register_gauge(&key, &METADATA);
+        gauge1.set(12.0);
+
+        let key = Key::from_name("basic_gauge");
+        let gauge2 = recorder.register_gauge(&key, &METADATA);
+        gauge2.set(-3.14);
+
+        let handle = recorder.handle();
+        let rendered = handle.render();
+        assert!(rendered.contains("connection_bytes 12\n"));
+        assert!(rendered.contains("basic_gauge -3.14\n"));
+
+        mock.increment(Duration::from_secs(11));
+        let rendered = handle.render();
+        assert_eq!(rendered, "# TYPE basic_gauge gauge\nbasic_gauge -3.14\n\n");
+    }
+
     #[test]
     pub fn test_global_labels() {
         let recorder = PrometheusBuilder::new()
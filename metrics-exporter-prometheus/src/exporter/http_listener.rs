@@ -1,11 +1,9 @@
 use std::net::SocketAddr;
 
 use http_body_util::Full;
-#[cfg(feature = "protobuf")]
-use hyper::header::ACCEPT;
 use hyper::{
     body::{Bytes, Incoming},
-    header::{HeaderValue, CONTENT_TYPE},
+    header::{HeaderValue, ACCEPT, CONTENT_TYPE},
     server::conn::http1::Builder as HyperHttpBuilder,
     service::service_fn,
     Request, Response, StatusCode,
@@ -19,7 +17,10 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::net::{UnixListener, UnixStream};
 use tracing::warn;
 
-use crate::{common::BuildError, ExporterFuture, PrometheusHandle};
+use crate::{
+    common::BuildError, formatting::NameEscapingScheme, ExporterFuture, MetricFilter,
+    PrometheusHandle, RenderOptions,
+};
 
 struct HttpListeningExporter {
     handle: PrometheusHandle,
@@ -86,7 +87,7 @@ impl HttpListeningExporter {
             // No allowed addresses specified, so everything is allowed
             return true;
         };
-        stream.peer_addr().map_or_else(
+        let is_allowed = stream.peer_addr().map_or_else(
             |e| {
                 warn!(error = ?e, "Error obtaining remote address.");
                 false
@@ -95,7 +96,14 @@ impl HttpListeningExporter {
                 let remote_ip = peer_addr.ip();
                 addrs.iter().any(|addr| addr.contains(&remote_ip))
             },
-        )
+        );
+
+        if !is_allowed {
+            if let Some(telemetry) = self.handle.telemetry() {
+                telemetry.record_rejected_connection();
+            }
+        }
+        is_allowed
     }
 
     #[cfg(feature = "uds-listener")]
@@ -145,46 +153,135 @@ impl HttpListeningExporter {
         }
 
         // Check content negotiation for metrics endpoint
-        let response_format = Self::negotiate_content_type(&req);
+        let (response_format, escaping) = Self::negotiate_content_type(&req);
+        let options = RenderOptions::new()
+            .with_escaping(escaping)
+            .with_filter(parse_filter(req.uri().query().unwrap_or("")));
         let (body, content_type) = match response_format {
             #[cfg(feature = "protobuf")]
             ResponseFormat::Protobuf => {
-                let data =
-                    tokio::task::spawn_blocking(move || handle.render_protobuf()).await.unwrap();
+                let data = tokio::task::spawn_blocking(move || {
+                    handle.render_protobuf_with_options(&options)
+                })
+                .await
+                .unwrap();
                 (data.into(), crate::protobuf::PROTOBUF_CONTENT_TYPE)
             }
             ResponseFormat::Text => {
-                let data = tokio::task::spawn_blocking(move || handle.render()).await.unwrap();
+                let data =
+                    tokio::task::spawn_blocking(move || handle.render_with_options(&options))
+                        .await
+                        .unwrap();
                 (data.into(), "text/plain")
             }
         };
 
+        // Only advertise the escaping scheme when the scraper asked for something other than the legacy behavior.
+        // Quoted UTF-8 names are part of text format 1.0.0, so the text response announces that version as well.
+        let content_type = match (response_format, escaping) {
+            (_, NameEscapingScheme::Underscores) => HeaderValue::from_static(content_type),
+            (ResponseFormat::Text, escaping) => HeaderValue::from_str(&format!(
+                "{content_type}; version=1.0.0; charset=utf-8; escaping={}",
+                escaping.as_param()
+            ))
+            .unwrap(),
+            #[cfg(feature = "protobuf")]
+            (ResponseFormat::Protobuf, escaping) => {
+                HeaderValue::from_str(&format!("{content_type}; escaping={}", escaping.as_param()))
+                    .unwrap()
+            }
+        };
+
         let mut response = Response::new(body);
-        response.headers_mut().append(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        response.headers_mut().append(CONTENT_TYPE, content_type);
         Ok(response)
     }
 
-    fn negotiate_content_type(req: &Request<Incoming>) -> ResponseFormat {
+    fn negotiate_content_type(req: &Request<Incoming>) -> (ResponseFormat, NameEscapingScheme) {
+        let accept_header =
+            req.headers().get(ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or("");
+
         #[cfg(feature = "protobuf")]
-        {
-            let accept_header =
-                req.headers().get(ACCEPT).and_then(|value| value.to_str().ok()).unwrap_or("");
-
-            for mime_type in mime::MimeIter::new(accept_header).flatten() {
-                if mime_type.type_() == "application"
-                    && (mime_type.subtype() == "vnd.google.protobuf"
-                        || mime_type.subtype() == "x-protobuf")
-                {
-                    return ResponseFormat::Protobuf;
-                }
+        for mime_type in mime::MimeIter::new(accept_header).flatten() {
+            if mime_type.type_() == "application"
+                && (mime_type.subtype() == "vnd.google.protobuf"
+                    || mime_type.subtype() == "x-protobuf")
+            {
+                let escaping = mime_type
+                    .get_param("escaping")
+                    .and_then(|value| NameEscapingScheme::from_param(value.as_str()))
+                    .unwrap_or_default();
+                return (ResponseFormat::Protobuf, escaping);
             }
         }
 
-        #[cfg(not(feature = "protobuf"))]
-        let _ = req;
+        (ResponseFormat::Text, negotiate_escaping(accept_header))
+    }
+}
+
+/// Finds the first supported `escaping` parameter among the `text/plain` media ranges of an `Accept` header.
+///
+/// Only `text/plain` is served as text, so parameters on other media ranges, such as OpenMetrics, are ignored.  Falls
+/// back to [`NameEscapingScheme::Underscores`] when no `text/plain` media range carries a supported scheme.
+fn negotiate_escaping(accept_header: &str) -> NameEscapingScheme {
+    accept_header
+        .split(',')
+        .filter_map(|media_range| {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next()?.trim();
+            media_type.eq_ignore_ascii_case("text/plain").then_some(parts)
+        })
+        .flatten()
+        .filter_map(|param| param.trim().strip_prefix("escaping="))
+        .find_map(|value| NameEscapingScheme::from_param(value.trim_matches('"')))
+        .unwrap_or_default()
+}
 
-        ResponseFormat::Text
+/// Builds a [`MetricFilter`] from the `name[]` and `prefix` parameters of a query string.
+///
+/// Both parameters can be given multiple times, and `name` is accepted as an alias of `name[]`.  Other parameters are
+/// ignored.
+fn parse_filter(query: &str) -> MetricFilter {
+    let mut filter = MetricFilter::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        if value.is_empty() {
+            continue;
+        }
+
+        match percent_decode(key).as_str() {
+            "name[]" | "name" => filter = filter.with_name(value),
+            "prefix" => filter = filter.with_prefix(value),
+            _ => {}
+        }
+    }
+    filter
+}
+
+/// Decodes a percent-encoded query string component, treating `+` as a space.
+///
+/// Invalid escape sequences are passed through as-is, and invalid UTF-8 is replaced.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len()
+                && bytes[i + 1].is_ascii_hexdigit()
+                && bytes[i + 2].is_ascii_hexdigit() =>
+            {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
     }
+    String::from_utf8_lossy(&decoded).into_owned()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -244,3 +341,55 @@ pub(crate) fn new_http_uds_listener(
 
     Ok(Box::pin(async move { exporter.serve().await.map_err(super::ExporterError::HttpListener) }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate_escaping, parse_filter};
+    use crate::{formatting::NameEscapingScheme, MetricFilter};
+
+    #[test]
+    fn test_negotiate_escaping() {
+        let cases = &[
+            ("", NameEscapingScheme::Underscores),
+            ("text/plain", NameEscapingScheme::Underscores),
+            ("text/plain;version=1.0.0;escaping=allow-utf-8", NameEscapingScheme::AllowUtf8),
+            ("text/plain; escaping=dots", NameEscapingScheme::Dots),
+            ("text/plain;escaping=\"values\";q=0.5", NameEscapingScheme::Values),
+            ("text/plain;escaping=unknown,text/plain;escaping=dots", NameEscapingScheme::Dots),
+            ("text/plain;escaping=unknown,*/*;escaping=dots", NameEscapingScheme::Underscores),
+            ("TEXT/PLAIN;escaping=values", NameEscapingScheme::Values),
+            (
+                "application/openmetrics-text;version=1.0.0;escaping=allow-utf-8;q=0.6,text/plain;version=0.0.4;q=0.3",
+                NameEscapingScheme::Underscores,
+            ),
+            (
+                "application/openmetrics-text;escaping=dots;q=0.6,text/plain;version=1.0.0;escaping=allow-utf-8",
+                NameEscapingScheme::AllowUtf8,
+            ),
+        ];
+
+        for (accept, expected) in cases {
+            assert_eq!(negotiate_escaping(accept), *expected, "accept header: {accept}");
+        }
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let cases = [
+            ("", MetricFilter::new()),
+            ("format=text", MetricFilter::new()),
+            ("name[]=foo&name[]=bar", MetricFilter::new().with_name("foo").with_name("bar")),
+            ("name%5B%5D=foo&name=bar", MetricFilter::new().with_name("foo").with_name("bar")),
+            ("prefix=db_&prefix=", MetricFilter::new().with_prefix("db_")),
+            (
+                "prefix=http%2Eserver&name[]=a+b",
+                MetricFilter::new().with_name("a b").with_prefix("http.server"),
+            ),
+            ("name[]=100%&prefix=%zz", MetricFilter::new().with_name("100%").with_prefix("%zz")),
+        ];
+
+        for (query, expected) in cases {
+            assert_eq!(parse_filter(query), expected, "query: {query}");
+        }
+    }
+}
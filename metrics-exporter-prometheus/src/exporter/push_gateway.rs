@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use http_body_util::{BodyExt, Collected, Full};
 use hyper::body::Bytes;
@@ -44,8 +44,8 @@ pub(super) fn new_push_gateway(
                 builder = builder.header("authorization", auth.clone());
             }
 
-            let handle = handle.clone();
-            let output = tokio::task::spawn_blocking(move || handle.render()).await.unwrap();
+            let render_handle = handle.clone();
+            let output = tokio::task::spawn_blocking(move || render_handle.render()).await.unwrap();
             let result =
                 builder.method(http_method.clone()).uri(endpoint.clone()).body(Full::from(output));
             let req = match result {
@@ -56,9 +56,12 @@ pub(super) fn new_push_gateway(
                 }
             };
 
-            match client.request(req).await {
+            let start = Instant::now();
+            let success = match client.request(req).await {
                 Ok(response) => {
-                    if !response.status().is_success() {
+                    if response.status().is_success() {
+                        true
+                    } else {
                         let status = response.status();
                         let status = status.canonical_reason().unwrap_or_else(|| status.as_str());
                         let body = response
@@ -74,9 +77,17 @@ pub(super) fn new_push_gateway(
                             status,
                             %body,
                         );
+                        false
                     }
                 }
-                Err(e) => error!("error sending request to push gateway: {:?}", e),
+                Err(e) => {
+                    error!("error sending request to push gateway: {:?}", e);
+                    false
+                }
+            };
+
+            if let Some(telemetry) = handle.telemetry() {
+                telemetry.record_push(success, start.elapsed());
             }
         }
     })
@@ -6,6 +6,54 @@ use metrics::Unit;
 
 use crate::common::LabelSet;
 
+/// Name escaping scheme used when rendering metric and label names.
+///
+/// Prometheus 3 supports UTF-8 metric and label names, which scrapers opt into by adding an `escaping` parameter to
+/// the `Accept` header of their scrape requests.  Each scheme controls how names that are not valid under the legacy
+/// Prometheus [data model] are transformed before being rendered.
+///
+/// [data model]: https://prometheus.io/docs/concepts/data_model/#metric-names-and-labels
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum NameEscapingScheme {
+    /// Replaces each invalid character with an underscore.
+    ///
+    /// This is the legacy behavior, and is used when a scraper does not request a specific scheme.
+    #[default]
+    Underscores,
+    /// Replaces dots with `_dot_`, underscores with `__`, and any other invalid character with `__`.
+    Dots,
+    /// Prefixes invalid names with `U__`, and replaces each invalid character with its Unicode code point in
+    /// hexadecimal, surrounded by underscores.  Underscores are replaced with `__`.
+    Values,
+    /// Leaves names unchanged, quoting them in the text format when they are not valid legacy names.
+    AllowUtf8,
+}
+
+impl NameEscapingScheme {
+    /// Parses the value of an `escaping` media type parameter.
+    ///
+    /// Returns `None` if the value does not correspond to a known escaping scheme.
+    pub fn from_param(value: &str) -> Option<Self> {
+        match value {
+            "underscores" => Some(Self::Underscores),
+            "dots" => Some(Self::Dots),
+            "values" => Some(Self::Values),
+            "allow-utf-8" => Some(Self::AllowUtf8),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the `escaping` media type parameter for this scheme.
+    pub fn as_param(self) -> &'static str {
+        match self {
+            Self::Underscores => "underscores",
+            Self::Dots => "dots",
+            Self::Values => "values",
+            Self::AllowUtf8 => "allow-utf-8",
+        }
+    }
+}
+
 /// Writes a help (description) line in the Prometheus [exposition format].
 ///
 /// [exposition format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-format-details
@@ -15,9 +63,25 @@ pub fn write_help_line(
     unit: Option<Unit>,
     suffix: Option<&'static str>,
     desc: &str,
+) {
+    write_help_line_with_scheme(buffer, name, unit, suffix, desc, NameEscapingScheme::Underscores);
+}
+
+/// Writes a help (description) line in the Prometheus [exposition format], using the given escaping scheme.
+///
+/// `name` is expected to already be escaped with [`escape_metric_name`] using the same scheme.
+///
+/// [exposition format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-format-details
+pub fn write_help_line_with_scheme(
+    buffer: &mut String,
+    name: &str,
+    unit: Option<Unit>,
+    suffix: Option<&'static str>,
+    desc: &str,
+    scheme: NameEscapingScheme,
 ) {
     buffer.push_str("# HELP ");
-    add_metric_name(buffer, name, unit, suffix);
+    add_metric_name(buffer, name, unit, suffix, needs_quoting(name, scheme));
     buffer.push(' ');
     let desc = sanitize_description(desc);
     buffer.push_str(&desc);
@@ -33,9 +97,32 @@ pub fn write_type_line(
     unit: Option<Unit>,
     suffix: Option<&'static str>,
     metric_type: &str,
+) {
+    write_type_line_with_scheme(
+        buffer,
+        name,
+        unit,
+        suffix,
+        metric_type,
+        NameEscapingScheme::Underscores,
+    );
+}
+
+/// Writes a metric type line in the Prometheus [exposition format], using the given escaping scheme.
+///
+/// `name` is expected to already be escaped with [`escape_metric_name`] using the same scheme.
+///
+/// [exposition format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-format-details
+pub fn write_type_line_with_scheme(
+    buffer: &mut String,
+    name: &str,
+    unit: Option<Unit>,
+    suffix: Option<&'static str>,
+    metric_type: &str,
+    scheme: NameEscapingScheme,
 ) {
     buffer.push_str("# TYPE ");
-    add_metric_name(buffer, name, unit, suffix);
+    add_metric_name(buffer, name, unit, suffix, needs_quoting(name, scheme));
     buffer.push(' ');
     buffer.push_str(metric_type);
     buffer.push('\n');
@@ -61,25 +148,109 @@ pub fn write_metric_line<T, T2>(
     T: std::fmt::Display,
     T2: std::fmt::Display,
 {
-    buffer.reserve(name.len() + 128);
+    write_metric_line_with_scheme(
+        buffer,
+        name,
+        suffix,
+        labels,
+        additional_label,
+        value,
+        unit,
+        NameEscapingScheme::Underscores,
+    );
+}
 
-    add_metric_name(buffer, name, unit, suffix);
+/// Writes a metric in the Prometheus [exposition format], using the given escaping scheme.
+///
+/// `name` is expected to already be escaped with [`escape_metric_name`] using the same scheme, while label keys are
+/// escaped as they are written.  When using [`NameEscapingScheme::AllowUtf8`], names which are not valid legacy names
+/// are quoted, with the metric name moving inside the braces.
+///
+/// [exposition format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-format-details
+#[allow(clippy::too_many_arguments)]
+pub fn write_metric_line_with_scheme<T, T2>(
+    buffer: &mut String,
+    name: &str,
+    suffix: Option<&'static str>,
+    labels: &LabelSet,
+    additional_label: Option<(&'static str, T)>,
+    value: T2,
+    unit: Option<Unit>,
+    scheme: NameEscapingScheme,
+) where
+    T: std::fmt::Display,
+    T2: std::fmt::Display,
+{
+    let mut formatted = String::new();
+    write_labels(&mut formatted, labels, scheme);
+    write_metric_line_with_formatted_labels(
+        buffer,
+        name,
+        suffix,
+        &formatted,
+        additional_label,
+        value,
+        unit,
+        scheme,
+    );
+}
 
-    if !labels.is_empty() || additional_label.is_some() {
+/// Writes the given labels as a comma-separated list of `key="value"` pairs, without any surrounding braces.
+///
+/// Label keys are escaped using the given scheme, and label values are sanitized.
+pub(crate) fn write_labels(buffer: &mut String, labels: &LabelSet, scheme: NameEscapingScheme) {
+    for (i, (key, value)) in labels.labels.iter().enumerate() {
+        if i > 0 {
+            buffer.push(',');
+        }
+        add_label_key(buffer, key, scheme);
+        buffer.push_str("=\"");
+        buffer.push_str(&sanitize_label_value(value));
+        buffer.push('"');
+    }
+}
+
+/// Writes a metric in the Prometheus [exposition format], using labels that were already formatted by
+/// [`write_labels`] with the same scheme.
+///
+/// [exposition format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-format-details
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_metric_line_with_formatted_labels<T, T2>(
+    buffer: &mut String,
+    name: &str,
+    suffix: Option<&'static str>,
+    labels: &str,
+    additional_label: Option<(&'static str, T)>,
+    value: T2,
+    unit: Option<Unit>,
+    scheme: NameEscapingScheme,
+) where
+    T: std::fmt::Display,
+    T2: std::fmt::Display,
+{
+    buffer.reserve(name.len() + labels.len() + 32);
+
+    let quoted = needs_quoting(name, scheme);
+    if quoted {
         buffer.push('{');
+        add_metric_name(buffer, name, unit, suffix, true);
+    } else {
+        add_metric_name(buffer, name, unit, suffix, false);
+        if !labels.is_empty() || additional_label.is_some() {
+            buffer.push('{');
+        }
+    }
 
-        let mut first = true;
-        for label in labels.to_strings() {
-            if first {
-                first = false;
-            } else {
+    if quoted || !labels.is_empty() || additional_label.is_some() {
+        if !labels.is_empty() {
+            if quoted {
                 buffer.push(',');
             }
-            buffer.push_str(&label);
+            buffer.push_str(labels);
         }
 
         if let Some((name, value)) = additional_label {
-            if !first {
+            if quoted || !labels.is_empty() {
                 buffer.push(',');
             }
             let _ = write!(buffer, "{name}=\"{value}\"");
@@ -96,14 +267,51 @@ fn add_metric_name(
     name: &str,
     unit: Option<Unit>,
     suffix: Option<&'static str>,
+    quoted: bool,
 ) {
-    buffer.push_str(name);
+    if quoted {
+        buffer.push('"');
+        push_quoted_name(buffer, name);
+    } else {
+        buffer.push_str(name);
+    }
     if let Some(unit) = unit {
         add_unit_if_missing(buffer, unit);
     }
     if let Some(suffix) = suffix {
         add_suffix_if_missing(buffer, suffix);
     }
+    if quoted {
+        buffer.push('"');
+    }
+}
+
+fn add_label_key(buffer: &mut String, key: &str, scheme: NameEscapingScheme) {
+    if scheme != NameEscapingScheme::AllowUtf8 {
+        buffer.push_str(&escape_label_key(key, scheme));
+    } else if is_valid_legacy_label_key(key) {
+        buffer.push_str(key);
+    } else {
+        buffer.push('"');
+        push_quoted_name(buffer, key);
+        buffer.push('"');
+    }
+}
+
+fn needs_quoting(name: &str, scheme: NameEscapingScheme) -> bool {
+    scheme == NameEscapingScheme::AllowUtf8 && !is_valid_legacy_metric_name(name)
+}
+
+/// Writes a name that will be surrounded by double quotes, escaping backslashes, double quotes, and line feeds.
+fn push_quoted_name(buffer: &mut String, name: &str) {
+    for c in name.chars() {
+        match c {
+            '\\' => buffer.push_str("\\\\"),
+            '"' => buffer.push_str("\\\""),
+            '\n' => buffer.push_str("\\n"),
+            c => buffer.push(c),
+        }
+    }
 }
 
 /// Adds a suffix to the metric name if it is not already in the name.
@@ -186,6 +394,105 @@ pub fn sanitize_label_key(key: &str) -> String {
         .collect()
 }
 
+/// Escapes a metric name using the given escaping scheme.
+///
+/// [`NameEscapingScheme::Underscores`] is equivalent to [`sanitize_metric_name`], while
+/// [`NameEscapingScheme::AllowUtf8`] leaves the name unchanged.
+pub fn escape_metric_name(name: &str, scheme: NameEscapingScheme) -> String {
+    match scheme {
+        NameEscapingScheme::Underscores => sanitize_metric_name(name),
+        NameEscapingScheme::AllowUtf8 => name.to_string(),
+        scheme => escape_name(
+            name,
+            scheme,
+            is_valid_legacy_metric_name,
+            valid_metric_name_start_character,
+            valid_metric_name_character,
+        ),
+    }
+}
+
+/// Escapes a label key using the given escaping scheme.
+///
+/// [`NameEscapingScheme::Underscores`] is equivalent to [`sanitize_label_key`], while
+/// [`NameEscapingScheme::AllowUtf8`] leaves the key unchanged.
+pub fn escape_label_key(key: &str, scheme: NameEscapingScheme) -> String {
+    match scheme {
+        NameEscapingScheme::Underscores => sanitize_label_key(key),
+        NameEscapingScheme::AllowUtf8 => key.to_string(),
+        scheme => escape_name(
+            key,
+            scheme,
+            is_valid_legacy_label_key,
+            valid_label_key_start_character,
+            valid_label_key_character,
+        ),
+    }
+}
+
+fn escape_name(
+    name: &str,
+    scheme: NameEscapingScheme,
+    is_valid: fn(&str) -> bool,
+    valid_start_character: fn(char) -> bool,
+    valid_character: fn(char) -> bool,
+) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    match scheme {
+        NameEscapingScheme::Dots => {
+            // Underscores are always doubled, even for otherwise valid names, so that dots can be recovered.
+            for (i, c) in name.chars().enumerate() {
+                match c {
+                    '_' => escaped.push_str("__"),
+                    '.' => escaped.push_str("_dot_"),
+                    c if i == 0 && valid_start_character(c) || i != 0 && valid_character(c) => {
+                        escaped.push(c);
+                    }
+                    _ => escaped.push_str("__"),
+                }
+            }
+        }
+        NameEscapingScheme::Values => {
+            if is_valid(name) {
+                return name.to_string();
+            }
+
+            escaped.push_str("U__");
+            for (i, c) in name.chars().enumerate() {
+                match c {
+                    '_' => escaped.push_str("__"),
+                    c if i == 0 && valid_start_character(c) || i != 0 && valid_character(c) => {
+                        escaped.push(c);
+                    }
+                    c => {
+                        let _ = write!(escaped, "_{:x}_", u32::from(c));
+                    }
+                }
+            }
+        }
+        NameEscapingScheme::Underscores | NameEscapingScheme::AllowUtf8 => unreachable!(),
+    }
+    escaped
+}
+
+/// Returns `true` if the given metric name is valid under the legacy Prometheus [data model].
+///
+/// [data model]: https://prometheus.io/docs/concepts/data_model/#metric-names-and-labels
+pub fn is_valid_legacy_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(valid_metric_name_start_character)
+        && chars.all(valid_metric_name_character)
+}
+
+/// Returns `true` if the given label key is valid under the legacy Prometheus [data model].
+///
+/// [data model]: https://prometheus.io/docs/concepts/data_model/#metric-names-and-labels
+pub fn is_valid_legacy_label_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(valid_label_key_start_character)
+        && chars.all(valid_label_key_character)
+}
+
 /// Sanitizes a label value to be valid under the Prometheus [data model].
 ///
 /// [data model]: https://prometheus.io/docs/concepts/data_model/#metric-names-and-labels
@@ -279,11 +586,15 @@ fn valid_label_key_character(c: char) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use crate::common::LabelSet;
     use crate::formatting::{
-        sanitize_description, sanitize_label_key, sanitize_label_value, sanitize_metric_name,
-        valid_label_key_character, valid_label_key_start_character, valid_metric_name_character,
-        valid_metric_name_start_character,
+        escape_label_key, escape_metric_name, sanitize_description, sanitize_label_key,
+        sanitize_label_value, sanitize_metric_name, valid_label_key_character,
+        valid_label_key_start_character, valid_metric_name_character,
+        valid_metric_name_start_character, write_metric_line_with_scheme,
+        write_type_line_with_scheme, NameEscapingScheme,
     };
+    use indexmap::IndexMap;
     use proptest::prelude::*;
 
     #[test]
@@ -359,6 +670,115 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_escape_metric_name_known_cases() {
+        let cases = &[
+            ("foo_bar", NameEscapingScheme::Underscores, "foo_bar"),
+            ("http.server.duration", NameEscapingScheme::Underscores, "http_server_duration"),
+            ("foo_bar", NameEscapingScheme::Dots, "foo__bar"),
+            ("http.server.duration", NameEscapingScheme::Dots, "http_dot_server_dot_duration"),
+            ("foo-bar", NameEscapingScheme::Dots, "foo__bar"),
+            ("foo_bar", NameEscapingScheme::Values, "foo_bar"),
+            ("http.server", NameEscapingScheme::Values, "U__http_2e_server"),
+            ("foo_bar.baz", NameEscapingScheme::Values, "U__foo__bar_2e_baz"),
+            ("1foo", NameEscapingScheme::Values, "U___31_foo"),
+            ("\u{1f600}", NameEscapingScheme::Values, "U___1f600_"),
+            ("http.server.duration", NameEscapingScheme::AllowUtf8, "http.server.duration"),
+        ];
+
+        for (input, scheme, expected) in cases {
+            let result = escape_metric_name(input, *scheme);
+            assert_eq!(expected, &result, "input: {input}, scheme: {scheme:?}");
+        }
+    }
+
+    #[test]
+    fn test_escape_label_key_known_cases() {
+        let cases = &[
+            ("service.name", NameEscapingScheme::Underscores, "service_name"),
+            ("foo:bar", NameEscapingScheme::Underscores, "foo_bar"),
+            ("service.name", NameEscapingScheme::Dots, "service_dot_name"),
+            ("foo:bar", NameEscapingScheme::Values, "U__foo_3a_bar"),
+            ("service.name", NameEscapingScheme::AllowUtf8, "service.name"),
+        ];
+
+        for (input, scheme, expected) in cases {
+            let result = escape_label_key(input, *scheme);
+            assert_eq!(expected, &result, "input: {input}, scheme: {scheme:?}");
+        }
+    }
+
+    #[test]
+    fn test_write_quoted_names() {
+        let key = metrics::Key::from_parts(
+            "",
+            vec![metrics::Label::new("service.name", "api"), metrics::Label::new("method", "GET")],
+        );
+        let labels = LabelSet::from_key_and_global(&key, &IndexMap::new());
+        let no_labels =
+            LabelSet::from_key_and_global(&metrics::Key::from_name(""), &IndexMap::new());
+        let scheme = NameEscapingScheme::AllowUtf8;
+
+        let mut buffer = String::new();
+        write_type_line_with_scheme(
+            &mut buffer,
+            "http.server.duration",
+            None,
+            None,
+            "histogram",
+            scheme,
+        );
+        write_metric_line_with_scheme(
+            &mut buffer,
+            "http.server.duration",
+            Some("bucket"),
+            &labels,
+            Some(("le", 0.5)),
+            3,
+            None,
+            scheme,
+        );
+        write_metric_line_with_scheme::<&str, u64>(
+            &mut buffer,
+            "http.server.duration",
+            Some("count"),
+            &no_labels,
+            None,
+            3,
+            None,
+            scheme,
+        );
+        write_metric_line_with_scheme::<&str, u64>(
+            &mut buffer,
+            "http_requests",
+            None,
+            &labels,
+            None,
+            7,
+            None,
+            scheme,
+        );
+        write_metric_line_with_scheme::<&str, u64>(
+            &mut buffer,
+            "quote\"name",
+            None,
+            &no_labels,
+            None,
+            1,
+            None,
+            scheme,
+        );
+
+        let expected = concat!(
+            "# TYPE \"http.server.duration\" histogram\n",
+            "{\"http.server.duration_bucket\",\"service.name\"=\"api\",method=\"GET\",le=\"0.5\"} 3\n",
+            "{\"http.server.duration_count\"} 3\n",
+            "http_requests{\"service.name\"=\"api\",method=\"GET\"} 7\n",
+            "{\"quote\\\"name\"} 1\n",
+        );
+        assert_eq!(buffer, expected);
+    }
+
     proptest! {
         #[test]
         fn test_sanitize_metric_name(input in "[\n\"\\\\]?.*[\n\"\\\\]?") {
@@ -10,11 +10,14 @@
 //! - scrape endpoint support
 //! - push gateway support
 //! - IP-based allowlist for scrape endpoint
+//! - selecting metric families on the scrape endpoint via `name[]` and `prefix` query parameters
 //! - ability to push histograms as either aggregated summaries or aggregated histograms, with configurable
 //!   quantiles/buckets
 //! - ability to control bucket configuration on a per-metric basis
 //! - configurable global labels (applied to all metrics, overridden by metric's own labels if present)
 //! - protobuf format support with automatic content negotiation
+//! - optional telemetry about the exporter itself
+//! - parsers for the exposition formats, for testing and federation (see [`parse`])
 //!
 //! ## Behavior
 //!
@@ -34,6 +37,10 @@
 //! name or label, but there is no way to report to the user that a metric name or label key is invalid only when using
 //! the Prometheus exporter, so we must cope with these situations by replacing invalid characters at runtime.
 //!
+//! Scrapers that support UTF-8 names (Prometheus 3 and later) can opt out of this behavior by sending an `escaping`
+//! parameter in the `Accept` header of their scrape requests, such as `escaping=allow-utf-8`, in which case names are
+//! quoted rather than modified.  See [`NameEscapingScheme`][formatting::NameEscapingScheme] for the supported schemes.
+//!
 //! ## Usage
 //!
 //! Using the exporter is straightforward:
@@ -119,7 +126,7 @@
 #![deny(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_cfg), deny(rustdoc::broken_intra_doc_links))]
 mod common;
-pub use self::common::{BuildError, LabelSet, Matcher};
+pub use self::common::{BuildError, LabelSet, Matcher, MetricFilter, RenderOptions};
 
 mod distribution;
 pub use distribution::{Distribution, DistributionBuilder};
@@ -145,10 +152,13 @@ pub use self::exporter::builder::PrometheusBuilder;
 pub use self::exporter::ExporterFuture;
 
 pub mod formatting;
+pub mod parse;
 #[cfg(feature = "protobuf")]
 pub mod protobuf;
 mod recorder;
 
 mod registry;
 
+mod telemetry;
+
 pub use self::recorder::{PrometheusHandle, PrometheusRecorder};
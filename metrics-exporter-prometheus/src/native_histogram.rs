@@ -6,6 +6,8 @@
 use std::collections::btree_map::Entry;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 
+use metrics_util::storage::Histogram;
+
 /// IEEE 754 frexp implementation matching Go's math.Frexp behavior.
 /// Returns (mantissa, exponent) such that f = mantissa × 2^exponent,
 /// where mantissa is in the range [0.5, 1) for finite non-zero f.
@@ -608,6 +610,24 @@ pub(crate) fn calculate_schema_from_bucket_factor(bucket_factor: f64) -> i32 {
     schema.clamp(MIN_SCHEMA, MAX_SCHEMA)
 }
 
+/// Range of the power-of-two classic buckets exposed for native histograms, from `2^-10` to `2^10`.
+pub(crate) const DEFAULT_CLASSIC_BUCKET_RANGE: (f64, f64) = (0.000_976_562_5, 1024.0);
+
+/// Returns the power-of-two bucket bounds within `[min, max]`, in ascending order.
+///
+/// Used as the classic buckets of native histograms that have no bucket bounds configured.  Power-of-two bounds are
+/// shared by every native schema of zero or greater, and the set only depends on the range, so the classic buckets of a
+/// metric never change between scrapes.
+pub(crate) fn power_of_two_bounds(min: f64, max: f64) -> Vec<f64> {
+    if !(min > 0.0 && min <= max && max.is_finite()) {
+        return Vec::new();
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let (first, last) = (min.log2().ceil() as i32, max.log2().floor() as i32);
+    (first..=last).map(|exp| 2f64.powi(exp)).collect()
+}
+
 /// Configuration for native histograms.
 #[derive(Debug, Clone)]
 pub struct NativeHistogramConfig {
@@ -691,6 +711,8 @@ pub struct NativeHistogram {
     schema: AtomicI32,
     /// Number of buckets currently used (for limiting)
     bucket_count: AtomicU64,
+    /// Classic buckets exposed alongside the native buckets, if enabled
+    classic: Option<Histogram>,
 }
 
 impl NativeHistogram {
@@ -707,6 +729,29 @@ impl NativeHistogram {
             positive_buckets: std::sync::RwLock::new(std::collections::BTreeMap::new()),
             negative_buckets: std::sync::RwLock::new(std::collections::BTreeMap::new()),
             bucket_count: AtomicU64::new(0),
+            classic: None,
+        }
+    }
+
+    /// Enables classic buckets alongside the native buckets.
+    ///
+    /// Observations are also tracked in a classic histogram with the given bucket bounds.  Classic buckets stay disabled
+    /// if `buckets` is empty.
+    pub(crate) fn with_classic_buckets(mut self, buckets: &[f64]) -> Self {
+        self.classic = Histogram::new(buckets);
+        self
+    }
+
+    /// Records a set of observations.
+    pub(crate) fn observe_many<'a, S>(&mut self, values: S)
+    where
+        S: IntoIterator<Item = &'a f64>,
+    {
+        for value in values {
+            self.observe(*value);
+            if let Some(histogram) = &mut self.classic {
+                histogram.record(*value);
+            }
         }
     }
 
@@ -907,14 +952,48 @@ impl NativeHistogram {
         // maintaining count, sum, and zero_count while reducing resolution.
     }
 
+    /// Merges the observations of `other` into this histogram.
+    ///
+    /// Both histograms are brought to the coarser of their two schemas first.  Classic buckets are not merged.
+    pub(crate) fn merge(&mut self, other: &NativeHistogram) {
+        let reduced;
+        let other = if other.schema.load(Ordering::Relaxed) > self.schema.load(Ordering::Relaxed) {
+            reduced = other.clone();
+            while reduced.schema.load(Ordering::Relaxed) > self.schema.load(Ordering::Relaxed) {
+                reduced.reduce_bucket_resolution();
+            }
+            &reduced
+        } else {
+            while self.schema.load(Ordering::Relaxed) > other.schema.load(Ordering::Relaxed) {
+                self.reduce_bucket_resolution();
+            }
+            other
+        };
+
+        *self.count.get_mut() += other.count();
+        *self.sum.get_mut() = (self.sum() + other.sum()).to_bits();
+        *self.zero_count.get_mut() += other.zero_count.load(Ordering::Relaxed);
+
+        let positive_buckets = self.positive_buckets.get_mut().unwrap();
+        for (index, count) in other.positive_buckets.read().unwrap().iter() {
+            *positive_buckets.entry(*index).or_insert(0) += count;
+        }
+        let negative_buckets = self.negative_buckets.get_mut().unwrap();
+        for (index, count) in other.negative_buckets.read().unwrap().iter() {
+            *negative_buckets.entry(*index).or_insert(0) += count;
+        }
+
+        let bucket_count = positive_buckets.len() + negative_buckets.len();
+        *self.bucket_count.get_mut() = bucket_count as u64;
+        self.limit_buckets();
+    }
+
     /// Returns the total count of observations.
-    #[cfg(any(feature = "protobuf", test))]
     pub(crate) fn count(&self) -> u64 {
         self.count.load(Ordering::Relaxed)
     }
 
     /// Returns the sum of all observations.
-    #[cfg(any(feature = "protobuf", test))]
     pub(crate) fn sum(&self) -> f64 {
         f64::from_bits(self.sum.load(Ordering::Relaxed))
     }
@@ -949,6 +1028,18 @@ impl NativeHistogram {
         self.schema.load(Ordering::Relaxed)
     }
 
+    /// Returns `true` if classic buckets are exposed alongside the native buckets.
+    pub(crate) fn has_classic_buckets(&self) -> bool {
+        self.classic.is_some()
+    }
+
+    /// Returns the classic buckets as cumulative `(upper bound, count)` pairs, excluding the `+Inf` bucket.
+    ///
+    /// Returns `None` if classic buckets are not enabled for this histogram.
+    pub(crate) fn classic_buckets(&self) -> Option<Vec<(f64, u64)>> {
+        self.classic.as_ref().map(Histogram::buckets)
+    }
+
     /// Returns the total number of buckets currently in use.
     fn bucket_count(&self) -> u64 {
         self.bucket_count.load(Ordering::Relaxed)
@@ -966,6 +1057,7 @@ impl Clone for NativeHistogram {
             negative_buckets: std::sync::RwLock::new(self.negative_buckets.read().unwrap().clone()),
             schema: AtomicI32::new(self.schema.load(Ordering::Relaxed)),
             bucket_count: AtomicU64::new(self.bucket_count()),
+            classic: self.classic.clone(),
         }
     }
 }
@@ -1126,6 +1218,88 @@ mod tests {
         assert!(!pos_buckets.is_empty());
     }
 
+    #[test]
+    fn test_power_of_two_bounds() {
+        assert_eq!(power_of_two_bounds(1.0, 8.0), vec![1.0, 2.0, 4.0, 8.0]);
+        assert_eq!(power_of_two_bounds(0.3, 5.0), vec![0.5, 1.0, 2.0, 4.0]);
+        assert_eq!(power_of_two_bounds(4.0, 4.0), vec![4.0]);
+        assert!(power_of_two_bounds(3.0, 3.5).is_empty());
+        assert!(power_of_two_bounds(0.0, 8.0).is_empty());
+        assert!(power_of_two_bounds(8.0, 1.0).is_empty());
+        assert!(power_of_two_bounds(1.0, f64::INFINITY).is_empty());
+    }
+
+    #[test]
+    fn test_classic_buckets_are_stable() {
+        let config = NativeHistogramConfig::new(2.0, 160, 1e-9).unwrap();
+        let mut hist =
+            NativeHistogram::new(config).with_classic_buckets(&power_of_two_bounds(1.0, 8.0));
+        assert!(hist.has_classic_buckets());
+
+        // The classic bounds do not depend on which native buckets are populated.
+        hist.observe_many(&[1.5]);
+        assert_eq!(hist.classic_buckets().unwrap(), vec![(1.0, 0), (2.0, 1), (4.0, 1), (8.0, 1)]);
+
+        hist.observe_many(&[-3.0, 0.0, 100.0]);
+        assert_eq!(hist.classic_buckets().unwrap(), vec![(1.0, 2), (2.0, 3), (4.0, 3), (8.0, 3)]);
+        assert_eq!(hist.count(), 4);
+    }
+
+    #[test]
+    fn test_merge() {
+        let config = NativeHistogramConfig::new(1.1, 160, 1e-9).unwrap();
+        let mut hist = NativeHistogram::new(config.clone());
+        hist.observe_many(&[1.0, 2.0, -4.0]);
+
+        // Fewer buckets force `other` to a coarser schema, which the merged histogram must match.
+        let coarse_config = NativeHistogramConfig::new(1.1, 2, 1e-9).unwrap();
+        let other = NativeHistogram::new(coarse_config);
+        for value in [0.0, 1.0, 3.0, 100.0, 1000.0] {
+            other.observe(value);
+        }
+        assert!(other.schema() < hist.schema());
+
+        hist.merge(&other);
+
+        assert_eq!(hist.schema(), other.schema());
+        assert_eq!(hist.count(), 8);
+        assert!((hist.sum() - 1103.0).abs() < f64::EPSILON);
+        assert_eq!(hist.zero_count(), 1);
+        assert_eq!(hist.positive_buckets().values().sum::<u64>(), 6);
+        assert_eq!(hist.negative_buckets().values().sum::<u64>(), 1);
+
+        // Merging a finer histogram into a coarser one never increases the coarser schema.
+        let mut coarse = other.clone();
+        let fine = NativeHistogram::new(config);
+        fine.observe(1.0);
+        coarse.merge(&fine);
+        assert!(coarse.schema() <= other.schema());
+        assert_eq!(coarse.count(), 6);
+    }
+
+    #[test]
+    fn test_fixed_classic_buckets() {
+        let config = NativeHistogramConfig::new(1.1, 160, 1e-9).unwrap();
+        let mut hist = NativeHistogram::new(config).with_classic_buckets(&[1.0, 5.0]);
+
+        hist.observe_many(&[0.5, 2.0, 3.0, 10.0]);
+
+        let buckets = hist.classic_buckets().unwrap();
+        assert_eq!(buckets, vec![(1.0, 1), (5.0, 3)]);
+        assert!(!hist.positive_buckets().is_empty());
+    }
+
+    #[test]
+    fn test_no_classic_buckets() {
+        let config = NativeHistogramConfig::new(1.1, 160, 1e-9).unwrap();
+        let mut hist = NativeHistogram::new(config);
+
+        hist.observe_many(&[0.5, 2.0]);
+
+        assert!(!hist.has_classic_buckets());
+        assert!(hist.classic_buckets().is_none());
+    }
+
     #[test]
     fn test_invalid_config() {
         // Invalid bucket_factor
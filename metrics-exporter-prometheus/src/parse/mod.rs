@@ -0,0 +1,203 @@
+//! Parsers for the Prometheus exposition formats.
+//!
+//! These turn rendered output back into typed metric families, which is useful for asserting on the output of the
+//! exporter in tests, or for re-ingesting metrics scraped from another endpoint, such as when federating.
+//!
+//! - [`text`] parses the Prometheus text format (version 0.0.4) and the OpenMetrics text format.
+//! - [`protobuf`] parses the length-delimited Prometheus protobuf format.  (requires the `protobuf` feature)
+
+#[cfg(feature = "protobuf")]
+#[cfg_attr(docsrs, doc(cfg(feature = "protobuf")))]
+pub mod protobuf;
+pub mod text;
+
+use thiserror::Error;
+
+/// Errors that could occur while parsing an exposition format.
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    /// A line of the text format could not be parsed.
+    #[error("invalid line {line}: {reason}")]
+    InvalidLine {
+        /// The line number, starting at 1.
+        line: usize,
+        /// Why the line is invalid.
+        reason: String,
+    },
+
+    /// The OpenMetrics text format input did not end with a `# EOF` line.
+    #[error("missing `# EOF` line at the end of OpenMetrics input")]
+    MissingEof,
+
+    /// The protobuf input could not be decoded.
+    #[error("invalid protobuf payload: {0}")]
+    InvalidProtobuf(String),
+}
+
+/// The type of a metric family.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetricType {
+    /// A monotonically increasing counter.
+    Counter,
+    /// A value that can go up and down.
+    Gauge,
+    /// A histogram with cumulative buckets.
+    Histogram,
+    /// A histogram whose buckets can go up and down.  (OpenMetrics only)
+    GaugeHistogram,
+    /// A summary with precomputed quantiles.
+    Summary,
+    /// Textual information about a target.  (OpenMetrics only)
+    Info,
+    /// A set of boolean states.  (OpenMetrics only)
+    StateSet,
+    /// A metric of unknown type.
+    Untyped,
+}
+
+impl MetricType {
+    /// Parses a metric type as written in a `TYPE` line.
+    ///
+    /// `unknown`, as used by OpenMetrics, is parsed as [`MetricType::Untyped`].
+    pub fn from_type_name(name: &str) -> Option<Self> {
+        match name {
+            "counter" => Some(Self::Counter),
+            "gauge" => Some(Self::Gauge),
+            "histogram" => Some(Self::Histogram),
+            "gaugehistogram" => Some(Self::GaugeHistogram),
+            "summary" => Some(Self::Summary),
+            "info" => Some(Self::Info),
+            "stateset" => Some(Self::StateSet),
+            "untyped" | "unknown" => Some(Self::Untyped),
+            _ => None,
+        }
+    }
+}
+
+/// A group of metrics sharing a name, type, and description.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricFamily {
+    /// The name of the family, without any type-specific suffixes such as `_bucket`.
+    pub name: String,
+    /// The description of the family, if any.
+    pub help: Option<String>,
+    /// The unit of the family, if any.  (OpenMetrics and protobuf only)
+    pub unit: Option<String>,
+    /// The type of the family.
+    pub metric_type: MetricType,
+    /// The metrics in the family, one per distinct set of labels, in order of appearance.
+    pub metrics: Vec<Metric>,
+}
+
+impl MetricFamily {
+    /// Creates an empty [`MetricFamily`].
+    pub fn new<N: Into<String>>(name: N, metric_type: MetricType) -> Self {
+        Self { name: name.into(), help: None, unit: None, metric_type, metrics: Vec::new() }
+    }
+
+    /// Gets the metric with exactly the given labels, in any order.
+    pub fn get(&self, labels: &[(&str, &str)]) -> Option<&Metric> {
+        self.metrics.iter().find(|metric| {
+            metric.labels.len() == labels.len()
+                && labels.iter().all(|(key, value)| metric.label(key) == Some(*value))
+        })
+    }
+}
+
+/// A single metric within a family, identified by its labels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// The labels of the metric, in order of appearance.
+    ///
+    /// This excludes the `le` and `quantile` labels of histogram buckets and summary quantiles.
+    pub labels: Vec<(String, String)>,
+    /// The value of the metric.
+    pub value: MetricValue,
+    /// The timestamp of the metric in milliseconds since the Unix epoch, if any.
+    pub timestamp_ms: Option<i64>,
+}
+
+impl Metric {
+    /// Gets the value of the label with the given key.
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+/// The value of a metric.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricValue {
+    /// The value of a counter.
+    Counter(f64),
+    /// The value of a gauge, or of an info or stateset metric.
+    Gauge(f64),
+    /// The value of an untyped metric.
+    Untyped(f64),
+    /// The value of a summary.
+    Summary(SummaryValue),
+    /// The value of a histogram or gauge histogram.
+    Histogram(HistogramValue),
+}
+
+impl MetricValue {
+    /// Gets the value of a counter, gauge, or untyped metric.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Counter(value) | Self::Gauge(value) | Self::Untyped(value) => Some(*value),
+            Self::Summary(_) | Self::Histogram(_) => None,
+        }
+    }
+
+    fn empty(metric_type: MetricType) -> Self {
+        match metric_type {
+            MetricType::Counter => Self::Counter(0.0),
+            MetricType::Gauge | MetricType::Info | MetricType::StateSet => Self::Gauge(0.0),
+            MetricType::Untyped => Self::Untyped(0.0),
+            MetricType::Summary => Self::Summary(SummaryValue::default()),
+            MetricType::Histogram | MetricType::GaugeHistogram => {
+                Self::Histogram(HistogramValue::default())
+            }
+        }
+    }
+}
+
+/// The value of a summary.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SummaryValue {
+    /// The number of observations.
+    pub count: u64,
+    /// The sum of all observations.
+    pub sum: f64,
+    /// The quantiles of the summary, as `(quantile, value)` pairs.
+    pub quantiles: Vec<(f64, f64)>,
+}
+
+/// The value of a histogram.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistogramValue {
+    /// The number of observations.
+    pub count: u64,
+    /// The sum of all observations.
+    pub sum: f64,
+    /// The classic buckets of the histogram, as `(upper bound, cumulative count)` pairs.
+    ///
+    /// This includes the `+Inf` bucket if it was present in the input.
+    pub buckets: Vec<(f64, u64)>,
+    /// The native buckets of the histogram, if any.  (protobuf only)
+    pub native: Option<NativeBuckets>,
+}
+
+/// The buckets of a native histogram.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NativeBuckets {
+    /// The resolution of the buckets.
+    pub schema: i32,
+    /// The width of the zero bucket.
+    pub zero_threshold: f64,
+    /// The number of observations in the zero bucket.
+    pub zero_count: u64,
+    /// Populated buckets for positive observations, as `(bucket index, count)` pairs.
+    pub positive: Vec<(i32, u64)>,
+    /// Populated buckets for negative observations, as `(bucket index, count)` pairs.
+    pub negative: Vec<(i32, u64)>,
+}
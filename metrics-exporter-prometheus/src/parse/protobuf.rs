@@ -0,0 +1,215 @@
+//! Parser for the Prometheus protobuf format.
+
+use prost::Message;
+
+use super::{
+    HistogramValue, Metric, MetricFamily, MetricType, MetricValue, NativeBuckets, ParseError,
+    SummaryValue,
+};
+use crate::protobuf::pb;
+
+/// Parses the Prometheus protobuf format, where each `MetricFamily` message is prefixed with a varint length header.
+///
+/// # Errors
+///
+/// If any message cannot be decoded, an error is returned.
+pub fn parse(mut input: &[u8]) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut families = Vec::new();
+    while !input.is_empty() {
+        let family = pb::MetricFamily::decode_length_delimited(&mut input)
+            .map_err(|e| ParseError::InvalidProtobuf(e.to_string()))?;
+        families.push(convert_family(family));
+    }
+    Ok(families)
+}
+
+fn convert_family(family: pb::MetricFamily) -> MetricFamily {
+    let metric_type = match family.r#type() {
+        pb::MetricType::Counter => MetricType::Counter,
+        pb::MetricType::Gauge => MetricType::Gauge,
+        pb::MetricType::Summary => MetricType::Summary,
+        pb::MetricType::Untyped => MetricType::Untyped,
+        pb::MetricType::Histogram => MetricType::Histogram,
+        pb::MetricType::GaugeHistogram => MetricType::GaugeHistogram,
+    };
+
+    let metrics = family
+        .metric
+        .into_iter()
+        .map(|metric| Metric {
+            labels: metric
+                .label
+                .iter()
+                .map(|label| (label.name().to_string(), label.value().to_string()))
+                .collect(),
+            value: convert_value(metric_type, &metric),
+            timestamp_ms: metric.timestamp_ms,
+        })
+        .collect();
+
+    MetricFamily {
+        name: family.name.unwrap_or_default(),
+        help: family.help,
+        unit: family.unit.filter(|unit| !unit.is_empty()),
+        metric_type,
+        metrics,
+    }
+}
+
+fn convert_value(metric_type: MetricType, metric: &pb::Metric) -> MetricValue {
+    match metric_type {
+        MetricType::Counter => {
+            MetricValue::Counter(metric.counter.as_ref().map_or(0.0, pb::Counter::value))
+        }
+        MetricType::Gauge | MetricType::Info | MetricType::StateSet => {
+            MetricValue::Gauge(metric.gauge.as_ref().map_or(0.0, pb::Gauge::value))
+        }
+        MetricType::Untyped => {
+            MetricValue::Untyped(metric.untyped.as_ref().map_or(0.0, pb::Untyped::value))
+        }
+        MetricType::Summary => {
+            let summary = metric.summary.clone().unwrap_or_default();
+            MetricValue::Summary(SummaryValue {
+                count: summary.sample_count(),
+                sum: summary.sample_sum(),
+                quantiles: summary.quantile.iter().map(|q| (q.quantile(), q.value())).collect(),
+            })
+        }
+        MetricType::Histogram | MetricType::GaugeHistogram => {
+            let histogram = metric.histogram.clone().unwrap_or_default();
+            MetricValue::Histogram(convert_histogram(&histogram))
+        }
+    }
+}
+
+fn convert_histogram(histogram: &pb::Histogram) -> HistogramValue {
+    let count = float_or(histogram.sample_count_float(), histogram.sample_count());
+    let buckets = histogram
+        .bucket
+        .iter()
+        .map(|bucket| {
+            let count = float_or(bucket.cumulative_count_float(), bucket.cumulative_count());
+            (bucket.upper_bound(), count)
+        })
+        .collect();
+
+    let native = histogram.schema.map(|schema| NativeBuckets {
+        schema,
+        zero_threshold: histogram.zero_threshold(),
+        zero_count: float_or(histogram.zero_count_float(), histogram.zero_count()),
+        positive: native_buckets(
+            &histogram.positive_span,
+            &histogram.positive_delta,
+            &histogram.positive_count,
+        ),
+        negative: native_buckets(
+            &histogram.negative_span,
+            &histogram.negative_delta,
+            &histogram.negative_count,
+        ),
+    });
+
+    HistogramValue { count, sum: histogram.sample_sum(), buckets, native }
+}
+
+/// Expands spans of native buckets into `(bucket index, count)` pairs.
+///
+/// Counts are either given as deltas from the previous bucket, or as absolute counts for float histograms.
+#[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+fn native_buckets(spans: &[pb::BucketSpan], deltas: &[i64], counts: &[f64]) -> Vec<(i32, u64)> {
+    let mut buckets = Vec::new();
+    let mut index = 0;
+    let mut count = 0i64;
+    let mut position = 0;
+    for span in spans {
+        index += span.offset();
+        for _ in 0..span.length() {
+            let bucket_count = if let Some(delta) = deltas.get(position) {
+                count += delta;
+                count as u64
+            } else {
+                float_or(counts.get(position).copied().unwrap_or_default(), 0)
+            };
+            buckets.push((index, bucket_count));
+            index += 1;
+            position += 1;
+        }
+    }
+    buckets
+}
+
+/// Uses the float variant of a count if it's set, as it takes precedence over the integer variant.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn float_or(float: f64, integer: u64) -> u64 {
+    if float > 0.0 {
+        float as u64
+    } else {
+        integer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::parse::{MetricType, MetricValue, NativeBuckets, ParseError};
+    use crate::protobuf::pb;
+    use prost::Message;
+
+    #[test]
+    fn test_parse_native_histogram() {
+        let family = pb::MetricFamily {
+            name: Some("latency".to_string()),
+            help: Some("Request latency.".to_string()),
+            r#type: Some(pb::MetricType::Histogram as i32),
+            metric: vec![pb::Metric {
+                label: vec![pb::LabelPair {
+                    name: Some("path".to_string()),
+                    value: Some("/".to_string()),
+                }],
+                histogram: Some(pb::Histogram {
+                    sample_count: Some(4),
+                    sample_sum: Some(10.0),
+                    schema: Some(3),
+                    zero_threshold: Some(1e-128),
+                    zero_count: Some(1),
+                    positive_span: vec![
+                        pb::BucketSpan { offset: Some(-1), length: Some(2) },
+                        pb::BucketSpan { offset: Some(3), length: Some(1) },
+                    ],
+                    positive_delta: vec![1, 0, -1],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            unit: None,
+        };
+
+        let mut input = Vec::new();
+        family.encode_length_delimited(&mut input).unwrap();
+        family.encode_length_delimited(&mut input).unwrap();
+
+        let families = parse(&input).unwrap();
+        assert_eq!(families.len(), 2);
+        assert_eq!(families[0].name, "latency");
+        assert_eq!(families[0].help.as_deref(), Some("Request latency."));
+        assert_eq!(families[0].metric_type, MetricType::Histogram);
+        assert_eq!(families[0].metrics[0].label("path"), Some("/"));
+
+        let MetricValue::Histogram(histogram) = &families[0].metrics[0].value else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(histogram.count, 4);
+        assert_eq!(
+            histogram.native,
+            Some(NativeBuckets {
+                schema: 3,
+                zero_threshold: 1e-128,
+                zero_count: 1,
+                positive: vec![(-1, 1), (0, 1), (4, 0)],
+                negative: vec![],
+            })
+        );
+
+        assert!(matches!(parse(&input[..input.len() - 1]), Err(ParseError::InvalidProtobuf(_))));
+    }
+}
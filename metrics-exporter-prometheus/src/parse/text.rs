@@ -0,0 +1,563 @@
+//! Parser for the Prometheus and OpenMetrics text formats.
+
+use std::collections::HashMap;
+
+use super::{Metric, MetricFamily, MetricType, MetricValue, ParseError};
+
+/// Parses the Prometheus [text format], version 0.0.4.
+///
+/// Metric and label names may be quoted, as rendered with
+/// [`NameEscapingScheme::AllowUtf8`][crate::formatting::NameEscapingScheme::AllowUtf8].  Samples without a preceding
+/// `TYPE` line are parsed as untyped families.
+///
+/// # Errors
+///
+/// If any line cannot be parsed, an error is returned.
+///
+/// [text format]: https://github.com/prometheus/docs/blob/main/content/docs/instrumenting/exposition_formats.md#text-based-format
+pub fn parse(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    Parser::new(false).parse(input)
+}
+
+/// Parses the [OpenMetrics] text format.
+///
+/// Exemplars and `_created` samples are ignored.
+///
+/// # Errors
+///
+/// If any line cannot be parsed, or the input does not end with a `# EOF` line, an error is returned.
+///
+/// [OpenMetrics]: https://github.com/prometheus/OpenMetrics/blob/main/specification/OpenMetrics.md
+pub fn parse_openmetrics(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    Parser::new(true).parse(input)
+}
+
+struct Parser {
+    openmetrics: bool,
+    families: Vec<MetricFamily>,
+    /// Index of metrics within the current family, by label set.
+    metrics: HashMap<Vec<(String, String)>, usize>,
+}
+
+impl Parser {
+    fn new(openmetrics: bool) -> Self {
+        Self { openmetrics, families: Vec::new(), metrics: HashMap::new() }
+    }
+
+    fn parse(mut self, input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+        let mut saw_eof = false;
+        for (i, line) in input.lines().enumerate() {
+            let invalid = |reason: String| ParseError::InvalidLine { line: i + 1, reason };
+
+            let line = line.trim();
+            if saw_eof && !line.is_empty() {
+                return Err(invalid("unexpected content after `# EOF`".to_string()));
+            }
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = if let Some(comment) = line.strip_prefix('#') {
+                if comment.trim() == "EOF" {
+                    saw_eof = true;
+                    Ok(())
+                } else {
+                    self.parse_comment(comment)
+                }
+            } else {
+                self.parse_sample(line)
+            };
+            result.map_err(invalid)?;
+        }
+
+        if self.openmetrics && !saw_eof {
+            return Err(ParseError::MissingEof);
+        }
+
+        Ok(self.families)
+    }
+
+    fn parse_comment(&mut self, comment: &str) -> Result<(), String> {
+        let mut cursor = Cursor::new(comment);
+        cursor.skip_whitespace();
+        let keyword = cursor.take_token();
+        if !matches!(keyword, "HELP" | "TYPE" | "UNIT") {
+            // Any other comment is ignored.
+            return Ok(());
+        }
+
+        cursor.skip_whitespace();
+        let name = cursor.parse_name()?;
+        cursor.skip_whitespace();
+        let rest = cursor.rest();
+
+        let family = self.family_for_metadata(&name);
+        match keyword {
+            "HELP" => family.help = Some(unescape(rest)),
+            "UNIT" => family.unit = Some(rest.to_string()),
+            _ => {
+                let metric_type = MetricType::from_type_name(rest)
+                    .ok_or_else(|| format!("unknown metric type `{rest}`"))?;
+                if !family.metrics.is_empty() {
+                    return Err(format!("`TYPE` line for `{name}` after its samples"));
+                }
+                family.metric_type = metric_type;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the family that metadata for `name` applies to, starting a new family if it isn't the current one.
+    fn family_for_metadata(&mut self, name: &str) -> &mut MetricFamily {
+        if self.families.last().map_or(true, |family| family.name != name) {
+            self.start_family(MetricFamily::new(name, MetricType::Untyped));
+        }
+
+        // UNWRAP: We just ensured there's a current family.
+        self.families.last_mut().unwrap()
+    }
+
+    fn start_family(&mut self, family: MetricFamily) {
+        self.families.push(family);
+        self.metrics.clear();
+    }
+
+    fn parse_sample(&mut self, line: &str) -> Result<(), String> {
+        let mut cursor = Cursor::new(line);
+        let mut name = if cursor.peek() == Some('{') { None } else { Some(cursor.parse_name()?) };
+        let mut labels = Vec::new();
+        if cursor.eat('{') {
+            cursor.parse_labels(&mut name, &mut labels)?;
+        }
+        let name = name.ok_or_else(|| "missing metric name".to_string())?;
+
+        if !cursor.skip_whitespace() {
+            return Err("expected whitespace before value".to_string());
+        }
+        let value = parse_float(cursor.take_token())?;
+
+        cursor.skip_whitespace();
+        let timestamp_ms = match cursor.peek() {
+            None | Some('#') => None,
+            Some(_) => Some(self.parse_timestamp(cursor.take_token())?),
+        };
+
+        // Anything left over must be an exemplar, which we don't keep.
+        cursor.skip_whitespace();
+        let is_exemplar = self.openmetrics && cursor.peek() == Some('#');
+        if !cursor.rest().is_empty() && !is_exemplar {
+            return Err(format!("unexpected trailing content `{}`", cursor.rest()));
+        }
+
+        self.add_sample(&name, labels, value, timestamp_ms)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn parse_timestamp(&self, token: &str) -> Result<i64, String> {
+        if self.openmetrics {
+            // OpenMetrics timestamps are in seconds, and may be fractional.
+            parse_float(token).map(|seconds| (seconds * 1000.0).round() as i64)
+        } else {
+            token.parse().map_err(|_| format!("invalid timestamp `{token}`"))
+        }
+    }
+
+    fn add_sample(
+        &mut self,
+        name: &str,
+        mut labels: Vec<(String, String)>,
+        value: f64,
+        timestamp_ms: Option<i64>,
+    ) -> Result<(), String> {
+        let suffix_len = match self.families.last() {
+            Some(family) => name
+                .strip_prefix(family.name.as_str())
+                .filter(|suffix| is_valid_suffix(family.metric_type, suffix))
+                .map(str::len),
+            None => None,
+        };
+        let suffix_len = suffix_len.unwrap_or_else(|| {
+            self.start_family(MetricFamily::new(name, MetricType::Untyped));
+            0
+        });
+        let suffix = &name[name.len() - suffix_len..];
+
+        // UNWRAP: There's always a current family at this point.
+        let family = self.families.last_mut().unwrap();
+        let metric_type = family.metric_type;
+
+        // Buckets and quantiles are part of a single metric, so their distinguishing label is split out.
+        let split_label = match (metric_type, suffix) {
+            (MetricType::Histogram | MetricType::GaugeHistogram, "_bucket") => Some("le"),
+            (MetricType::Summary, "") => Some("quantile"),
+            _ => None,
+        };
+        let split_value = match split_label {
+            Some(split_label) => {
+                let position = labels
+                    .iter()
+                    .position(|(key, _)| key == split_label)
+                    .ok_or_else(|| format!("missing `{split_label}` label"))?;
+                Some(parse_float(&labels.remove(position).1)?)
+            }
+            None => None,
+        };
+
+        let index = if let Some(index) = self.metrics.get(&labels) {
+            *index
+        } else {
+            family.metrics.push(Metric {
+                labels: labels.clone(),
+                value: MetricValue::empty(metric_type),
+                timestamp_ms: None,
+            });
+            self.metrics.insert(labels, family.metrics.len() - 1);
+            family.metrics.len() - 1
+        };
+        let metric = &mut family.metrics[index];
+        if timestamp_ms.is_some() {
+            metric.timestamp_ms = timestamp_ms;
+        }
+
+        match (&mut metric.value, suffix) {
+            (_, "_created") => {}
+            (MetricValue::Counter(v) | MetricValue::Gauge(v) | MetricValue::Untyped(v), _) => {
+                *v = value;
+            }
+            (MetricValue::Summary(summary), "") => {
+                // UNWRAP: Summary samples without a suffix always have a quantile.
+                summary.quantiles.push((split_value.unwrap(), value));
+            }
+            (MetricValue::Summary(summary), "_sum") => summary.sum = value,
+            (MetricValue::Summary(summary), _) => summary.count = to_count(value),
+            (MetricValue::Histogram(histogram), "_bucket") => {
+                // UNWRAP: Bucket samples always have an upper bound.
+                histogram.buckets.push((split_value.unwrap(), to_count(value)));
+            }
+            (MetricValue::Histogram(histogram), "_sum" | "_gsum") => histogram.sum = value,
+            (MetricValue::Histogram(histogram), _) => histogram.count = to_count(value),
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks if a sample with the given suffix belongs to a family of the given type.
+fn is_valid_suffix(metric_type: MetricType, suffix: &str) -> bool {
+    match metric_type {
+        MetricType::Counter => matches!(suffix, "" | "_total" | "_created"),
+        MetricType::Gauge | MetricType::StateSet | MetricType::Untyped => suffix.is_empty(),
+        MetricType::Info => matches!(suffix, "" | "_info"),
+        MetricType::Summary => matches!(suffix, "" | "_sum" | "_count" | "_created"),
+        MetricType::Histogram => matches!(suffix, "_bucket" | "_sum" | "_count" | "_created"),
+        MetricType::GaugeHistogram => matches!(suffix, "_bucket" | "_gsum" | "_gcount"),
+    }
+}
+
+fn parse_float(token: &str) -> Result<f64, String> {
+    token.parse().map_err(|_| format!("invalid value `{token}`"))
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_count(value: f64) -> u64 {
+    value as u64
+}
+
+/// Unescapes backslashes, double quotes, and line feeds.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c @ ('\\' | '"')) => unescaped.push(c),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+struct Cursor<'a> {
+    input: &'a str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if let Some(rest) = self.input.strip_prefix(c) {
+            self.input = rest;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        self.input
+    }
+
+    /// Skips whitespace, returning `true` if any was skipped.
+    fn skip_whitespace(&mut self) -> bool {
+        let trimmed = self.input.trim_start();
+        let skipped = trimmed.len() != self.input.len();
+        self.input = trimmed;
+        skipped
+    }
+
+    /// Takes everything up to the next whitespace.
+    fn take_token(&mut self) -> &'a str {
+        let end = self.input.find(char::is_whitespace).unwrap_or(self.input.len());
+        let (token, rest) = self.input.split_at(end);
+        self.input = rest;
+        token
+    }
+
+    /// Parses a legacy name, or a quoted name.
+    fn parse_name(&mut self) -> Result<String, String> {
+        if self.peek() == Some('"') {
+            return self.parse_quoted();
+        }
+
+        let end = self
+            .input
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(self.input.len());
+        if end == 0 {
+            return Err(format!("expected name at `{}`", self.input));
+        }
+
+        let (name, rest) = self.input.split_at(end);
+        self.input = rest;
+        Ok(name.to_string())
+    }
+
+    fn parse_quoted(&mut self) -> Result<String, String> {
+        if !self.eat('"') {
+            return Err(format!("expected `\"` at `{}`", self.input));
+        }
+
+        let mut escaped = false;
+        for (i, c) in self.input.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => {
+                    let value = unescape(&self.input[..i]);
+                    self.input = &self.input[i + 1..];
+                    return Ok(value);
+                }
+                _ => escaped = false,
+            }
+        }
+
+        Err("unterminated quoted string".to_string())
+    }
+
+    /// Parses labels up to and including the closing brace.
+    ///
+    /// If `name` is `None`, the first item may be a quoted metric name rather than a label.
+    fn parse_labels(
+        &mut self,
+        name: &mut Option<String>,
+        labels: &mut Vec<(String, String)>,
+    ) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(());
+            }
+
+            let key = self.parse_name()?;
+            self.skip_whitespace();
+            if name.is_none() && labels.is_empty() && matches!(self.peek(), Some(',' | '}')) {
+                *name = Some(key);
+            } else {
+                if !self.eat('=') {
+                    return Err(format!("expected `=` after label `{key}`"));
+                }
+                self.skip_whitespace();
+                let value = self.parse_quoted()?;
+                labels.push((key, value));
+            }
+
+            self.skip_whitespace();
+            if !self.eat(',') && self.peek() != Some('}') {
+                return Err(format!("expected `,` or `}}` at `{}`", self.input));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, parse_openmetrics};
+    use crate::parse::{
+        HistogramValue, Metric, MetricFamily, MetricType, MetricValue, ParseError, SummaryValue,
+    };
+
+    #[test]
+    fn test_parse_counters_and_gauges() {
+        let input = concat!(
+            "# HELP requests_total Number of requests.\\nPer path.\n",
+            "# TYPE requests_total counter\n",
+            "requests_total{path=\"/\",method=\"GET\"} 42\n",
+            "requests_total{path=\"/a\\\"b\\\\c\"} 1 1700000000000\n",
+            "\n",
+            "# TYPE temperature gauge\n",
+            "temperature -3.5\n",
+            "# A plain comment.\n",
+            "untyped_value +Inf\n",
+        );
+
+        let families = parse(input).unwrap();
+        assert_eq!(families.len(), 3);
+
+        let requests = &families[0];
+        assert_eq!(requests.name, "requests_total");
+        assert_eq!(requests.help.as_deref(), Some("Number of requests.\nPer path."));
+        assert_eq!(requests.metric_type, MetricType::Counter);
+        let metric = requests.get(&[("method", "GET"), ("path", "/")]).unwrap();
+        assert_eq!(metric.value, MetricValue::Counter(42.0));
+        let metric = requests.get(&[("path", "/a\"b\\c")]).unwrap();
+        assert_eq!(metric.timestamp_ms, Some(1_700_000_000_000));
+
+        let temperature = &families[1];
+        assert_eq!(temperature.metric_type, MetricType::Gauge);
+        assert_eq!(temperature.metrics[0].value, MetricValue::Gauge(-3.5));
+
+        let untyped = &families[2];
+        assert_eq!(untyped.metric_type, MetricType::Untyped);
+        assert_eq!(untyped.metrics[0].value, MetricValue::Untyped(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_parse_histograms_and_summaries() {
+        let input = concat!(
+            "# TYPE latency histogram\n",
+            "latency_bucket{path=\"/\",le=\"0.5\"} 1\n",
+            "latency_bucket{path=\"/\",le=\"+Inf\"} 3\n",
+            "latency_sum{path=\"/\"} 4.5\n",
+            "latency_count{path=\"/\"} 3\n",
+            "# TYPE sizes summary\n",
+            "sizes{quantile=\"0.5\"} 10\n",
+            "sizes{quantile=\"0.99\"} 20\n",
+            "sizes_sum 30\n",
+            "sizes_count 2\n",
+        );
+
+        let families = parse(input).unwrap();
+        assert_eq!(
+            families[0].metrics,
+            vec![Metric {
+                labels: vec![("path".to_string(), "/".to_string())],
+                value: MetricValue::Histogram(HistogramValue {
+                    count: 3,
+                    sum: 4.5,
+                    buckets: vec![(0.5, 1), (f64::INFINITY, 3)],
+                    native: None,
+                }),
+                timestamp_ms: None,
+            }]
+        );
+        assert_eq!(
+            families[1].metrics[0].value,
+            MetricValue::Summary(SummaryValue {
+                count: 2,
+                sum: 30.0,
+                quantiles: vec![(0.5, 10.0), (0.99, 20.0)],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_quoted_names() {
+        let input = concat!(
+            "# HELP \"http.requests\" Requests.\n",
+            "# TYPE \"http.requests\" counter\n",
+            "{\"http.requests\",\"service.name\"=\"api\"} 1\n",
+        );
+
+        let families = parse(input).unwrap();
+        assert_eq!(families[0].name, "http.requests");
+        assert_eq!(families[0].metric_type, MetricType::Counter);
+        assert_eq!(families[0].metrics[0].label("service.name"), Some("api"));
+    }
+
+    #[test]
+    fn test_parse_openmetrics() {
+        let input = concat!(
+            "# TYPE requests counter\n",
+            "# UNIT requests requests\n",
+            "requests_total{path=\"/\"} 3 1700000000.5 # {trace_id=\"abc\"} 1\n",
+            "requests_created{path=\"/\"} 1600000000\n",
+            "# TYPE queue gaugehistogram\n",
+            "queue_bucket{le=\"+Inf\"} 2\n",
+            "queue_gsum 5\n",
+            "queue_gcount 2\n",
+            "# EOF\n",
+        );
+
+        let families = parse_openmetrics(input).unwrap();
+        let mut requests = MetricFamily::new("requests", MetricType::Counter);
+        requests.unit = Some("requests".to_string());
+        requests.metrics.push(Metric {
+            labels: vec![("path".to_string(), "/".to_string())],
+            value: MetricValue::Counter(3.0),
+            timestamp_ms: Some(1_700_000_000_500),
+        });
+        assert_eq!(families[0], requests);
+        assert_eq!(
+            families[1].metrics[0].value,
+            MetricValue::Histogram(HistogramValue {
+                count: 2,
+                sum: 5.0,
+                buckets: vec![(f64::INFINITY, 2)],
+                native: None,
+            })
+        );
+
+        assert_eq!(parse_openmetrics("foo 1\n"), Err(ParseError::MissingEof));
+        assert!(parse_openmetrics("# EOF\nfoo 1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            "foo",
+            "foo{bar=\"baz\" 1",
+            "foo{bar} 1",
+            "foo{bar=baz} 1",
+            "foo one",
+            "foo 1 2 3",
+            "# TYPE foo nonsense",
+            "# TYPE foo summary\nfoo 1",
+            "# TYPE foo histogram\nfoo_bucket 1",
+            "foo 1\n# TYPE foo gauge",
+        ];
+
+        for input in cases {
+            assert!(
+                matches!(parse(input), Err(ParseError::InvalidLine { .. })),
+                "input: {}",
+                input
+            );
+        }
+    }
+}
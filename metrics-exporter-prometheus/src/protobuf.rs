@@ -3,13 +3,12 @@
 use prost::Message;
 use std::io::Write;
 
-use crate::common::{LabelSet, Snapshot};
-use crate::distribution::Distribution;
-use crate::formatting::sanitize_metric_name;
-use crate::recorder::DescriptionReadHandle;
+use crate::common::LabelSet;
+use crate::distribution::MergedDistribution;
+use crate::formatting::{escape_label_key, NameEscapingScheme};
 
 // Include the generated protobuf code
-mod pb {
+pub(crate) mod pb {
     #![allow(missing_docs, clippy::trivially_copy_pass_by_ref, clippy::doc_markdown)]
     include!(concat!(env!("OUT_DIR"), "/io.prometheus.client.rs"));
 }
@@ -18,273 +17,189 @@ mod pb {
 pub(crate) const PROTOBUF_CONTENT_TYPE: &str =
     "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
 
-/// Renders a snapshot of metrics into protobuf format using length-delimited encoding.
-///
-/// This function takes a snapshot of metrics and converts them into the Prometheus
-/// protobuf wire format, where each `MetricFamily` message is prefixed with a varint
-/// length header.
-#[allow(clippy::too_many_lines)]
-pub(crate) fn render_protobuf(
-    snapshot: Snapshot,
-    descriptions_rd: &DescriptionReadHandle,
-    counter_suffix: Option<&'static str>,
-) -> Vec<u8> {
-    let mut output = Vec::new();
-    render_protobuf_to_write(&mut output, snapshot, descriptions_rd, counter_suffix)
-        .expect("writing to an in-memory buffer should not fail");
-    output
+/// Creates a metric family from the given metrics.
+pub(crate) fn metric_family(
+    name: String,
+    help: Option<String>,
+    metric_type: pb::MetricType,
+    metrics: Vec<pb::Metric>,
+) -> pb::MetricFamily {
+    pb::MetricFamily {
+        name: Some(name),
+        help: help.filter(|help| !help.is_empty()),
+        r#type: Some(metric_type as i32),
+        metric: metrics,
+        unit: None,
+    }
 }
 
-/// Renders a snapshot of metrics into protobuf format using length-delimited encoding.
+/// Writes a metric family using length-delimited encoding.
 ///
-/// This function takes a snapshot of metrics and converts them into the Prometheus
-/// protobuf wire format, where each `MetricFamily` message is prefixed with a varint
-/// length header.
-#[allow(clippy::too_many_lines)]
-pub(crate) fn render_protobuf_to_write<W: Write>(
+/// Each `MetricFamily` message is prefixed with a varint length header, as expected by Prometheus.  `buffer` is used
+/// as scratch space for encoding, so that it can be reused across families.
+pub(crate) fn write_metric_family<W: Write>(
     writer: &mut W,
-    snapshot: Snapshot,
-    descriptions_rd: &DescriptionReadHandle,
-    counter_suffix: Option<&'static str>,
+    buffer: &mut Vec<u8>,
+    metric_family: &pb::MetricFamily,
 ) -> std::io::Result<()> {
-    let mut buffer = Vec::new();
-
-    // Process counters
-    for (name, by_labels) in snapshot.counters {
-        let sanitized_name = sanitize_metric_name(&name);
-        let help = descriptions_rd
-            .get_one(name.as_str())
-            .map(|entry| {
-                let (desc, _) = &*entry;
-                desc.to_string()
-            })
-            .unwrap_or_default();
-
-        let mut metrics = Vec::new();
-        for (labels, value) in by_labels {
-            let label_pairs = label_set_to_protobuf(labels);
-
-            metrics.push(pb::Metric {
-                label: label_pairs,
-                counter: Some(pb::Counter {
-                    #[allow(clippy::cast_precision_loss)]
-                    value: Some(value as f64),
+    buffer.clear();
+    metric_family.encode_length_delimited(buffer).unwrap();
+    writer.write_all(buffer)
+}
+
+/// Creates a counter metric.
+pub(crate) fn counter_metric(label: Vec<pb::LabelPair>, value: u64) -> pb::Metric {
+    pb::Metric {
+        label,
+        counter: Some(pb::Counter {
+            #[allow(clippy::cast_precision_loss)]
+            value: Some(value as f64),
+
+            ..Default::default()
+        }),
+
+        ..Default::default()
+    }
+}
+
+/// Creates a gauge metric.
+pub(crate) fn gauge_metric(label: Vec<pb::LabelPair>, value: f64) -> pb::Metric {
+    pb::Metric { label, gauge: Some(pb::Gauge { value: Some(value) }), ..Default::default() }
+}
+
+/// Creates a histogram or summary metric, returning it along with its type.
+pub(crate) fn distribution_metric(
+    label: Vec<pb::LabelPair>,
+    distribution: MergedDistribution<'_>,
+) -> (pb::MetricType, pb::Metric) {
+    match distribution {
+        MergedDistribution::Summary { snapshot, quantiles, sum, count } => {
+            let quantile_values: Vec<pb::Quantile> = quantiles
+                .iter()
+                .map(|q| pb::Quantile {
+                    quantile: Some(q.value()),
+                    value: Some(snapshot.quantile(q.value()).unwrap_or(0.0)),
+                })
+                .collect();
+
+            let metric = pb::Metric {
+                label,
+                summary: Some(pb::Summary {
+                    sample_count: Some(count),
+                    sample_sum: Some(sum),
+                    quantile: quantile_values,
+
+                    created_timestamp: None,
+                }),
+
+                ..Default::default()
+            };
+            (pb::MetricType::Summary, metric)
+        }
+        MergedDistribution::Histogram { buckets, sum, count } => {
+            let metric = pb::Metric {
+                label,
+                histogram: Some(pb::Histogram {
+                    sample_count: Some(count),
+                    sample_sum: Some(sum),
+                    bucket: classic_buckets(buckets, count),
 
                     ..Default::default()
                 }),
 
                 ..Default::default()
-            });
+            };
+            (pb::MetricType::Histogram, metric)
         }
+        MergedDistribution::NativeHistogram { histogram: native_hist, classic } => {
+            // Convert our native histogram into Prometheus native histogram format
+            let positive_buckets = native_hist.positive_buckets();
+            let negative_buckets = native_hist.negative_buckets();
 
-        let metric_family = pb::MetricFamily {
-            name: Some(add_suffix_to_name(&sanitized_name, counter_suffix)),
-            help: if help.is_empty() { None } else { Some(help) },
-            r#type: Some(pb::MetricType::Counter as i32),
-            metric: metrics,
-            unit: None,
-        };
+            // Get the current schema being used by the histogram
+            let schema = native_hist.schema();
 
-        buffer.clear();
-        metric_family.encode_length_delimited(&mut buffer).unwrap();
-        writer.write_all(&buffer)?;
-    }
+            // Convert positive buckets to spans and deltas (matches Go makeBuckets function)
+            let (positive_spans, positive_deltas) = make_buckets(&positive_buckets);
+            let (negative_spans, negative_deltas) = make_buckets(&negative_buckets);
 
-    // Process gauges
-    for (name, by_labels) in snapshot.gauges {
-        let sanitized_name = sanitize_metric_name(&name);
-        let help = descriptions_rd
-            .get_one(name.as_str())
-            .map(|entry| {
-                let (desc, _) = &*entry;
-                desc.to_string()
-            })
-            .unwrap_or_default();
-
-        let mut metrics = Vec::new();
-        for (labels, value) in by_labels {
-            let label_pairs = label_set_to_protobuf(labels);
-
-            metrics.push(pb::Metric {
-                label: label_pairs,
-                gauge: Some(pb::Gauge { value: Some(value) }),
+            // Match Go Write() method output exactly
+            let mut histogram = pb::Histogram {
+                sample_count: Some(native_hist.count()),
+                sample_sum: Some(native_hist.sum()),
 
-                ..Default::default()
-            });
-        }
+                // Native histogram fields from Go implementation
+                zero_threshold: Some(native_hist.config().zero_threshold()),
+                schema: Some(schema),
+                zero_count: Some(native_hist.zero_count()),
 
-        let metric_family = pb::MetricFamily {
-            name: Some(sanitized_name),
-            help: if help.is_empty() { None } else { Some(help) },
-            r#type: Some(pb::MetricType::Gauge as i32),
-            metric: metrics,
-            unit: None,
-        };
+                positive_span: positive_spans,
+                positive_delta: positive_deltas,
 
-        buffer.clear();
-        metric_family.encode_length_delimited(&mut buffer).unwrap();
-        writer.write_all(&buffer)?;
-    }
+                negative_span: negative_spans,
+                negative_delta: negative_deltas,
 
-    // Process distributions (histograms and summaries)
-    for (name, by_labels) in snapshot.distributions {
-        let sanitized_name = sanitize_metric_name(&name);
-        let help = descriptions_rd
-            .get_one(name.as_str())
-            .map(|entry| {
-                let (desc, _) = &*entry;
-                desc.to_string()
-            })
-            .unwrap_or_default();
-
-        let mut metrics = Vec::new();
-        let mut metric_type = None;
-        for (labels, distribution) in by_labels {
-            let label_pairs = label_set_to_protobuf(labels);
-
-            let metric = match distribution {
-                Distribution::Summary(summary, quantiles, sum) => {
-                    use quanta::Instant;
-                    metric_type = Some(pb::MetricType::Summary);
-                    let snapshot = summary.snapshot(Instant::now());
-                    let quantile_values: Vec<pb::Quantile> = quantiles
-                        .iter()
-                        .map(|q| pb::Quantile {
-                            quantile: Some(q.value()),
-                            value: Some(snapshot.quantile(q.value()).unwrap_or(0.0)),
-                        })
-                        .collect();
-
-                    pb::Metric {
-                        label: label_pairs,
-                        summary: Some(pb::Summary {
-                            sample_count: Some(summary.count() as u64),
-                            sample_sum: Some(sum),
-                            quantile: quantile_values,
-
-                            created_timestamp: None,
-                        }),
-
-                        ..Default::default()
-                    }
-                }
-                Distribution::Histogram(histogram) => {
-                    metric_type = Some(pb::MetricType::Histogram);
-                    let mut buckets = Vec::new();
-                    for (le, count) in histogram.buckets() {
-                        buckets.push(pb::Bucket {
-                            cumulative_count: Some(count),
-                            upper_bound: Some(le),
-
-                            ..Default::default()
-                        });
-                    }
-                    // Add +Inf bucket
-                    buckets.push(pb::Bucket {
-                        cumulative_count: Some(histogram.count()),
-                        upper_bound: Some(f64::INFINITY),
-
-                        ..Default::default()
-                    });
-
-                    pb::Metric {
-                        label: label_pairs,
-                        histogram: Some(pb::Histogram {
-                            sample_count: Some(histogram.count()),
-                            sample_sum: Some(histogram.sum()),
-                            bucket: buckets,
-
-                            ..Default::default()
-                        }),
-
-                        ..Default::default()
-                    }
-                }
-                Distribution::NativeHistogram(native_hist) => {
-                    metric_type = Some(pb::MetricType::Histogram);
-                    // Convert our native histogram into Prometheus native histogram format
-                    let positive_buckets = native_hist.positive_buckets();
-                    let negative_buckets = native_hist.negative_buckets();
-
-                    // Get the current schema being used by the histogram
-                    let schema = native_hist.schema();
-
-                    // Convert positive buckets to spans and deltas (matches Go makeBuckets function)
-                    let (positive_spans, positive_deltas) = make_buckets(&positive_buckets);
-                    let (negative_spans, negative_deltas) = make_buckets(&negative_buckets);
-
-                    // Match Go Write() method output exactly
-                    let mut histogram = pb::Histogram {
-                        sample_count: Some(native_hist.count()),
-                        sample_sum: Some(native_hist.sum()),
-
-                        // Native histogram fields from Go implementation
-                        zero_threshold: Some(native_hist.config().zero_threshold()),
-                        schema: Some(schema),
-                        zero_count: Some(native_hist.zero_count()),
-
-                        positive_span: positive_spans,
-                        positive_delta: positive_deltas,
-
-                        negative_span: negative_spans,
-                        negative_delta: negative_deltas,
-
-                        ..Default::default()
-                    };
-
-                    // Add a no-op span if histogram is empty (matches Go implementation)
-                    if histogram.zero_threshold == Some(0.0)
-                        && histogram.zero_count == Some(0)
-                        && histogram.positive_span.is_empty()
-                        && histogram.negative_span.is_empty()
-                    {
-                        histogram.positive_span =
-                            vec![pb::BucketSpan { offset: Some(0), length: Some(0) }];
-                    }
-
-                    pb::Metric {
-                        label: label_pairs,
-                        histogram: Some(histogram),
-                        ..Default::default()
-                    }
-                }
+                ..Default::default()
             };
 
-            metrics.push(metric);
-        }
-
-        let Some(metric_type) = metric_type else {
-            // Skip empty metric families
-            continue;
-        };
+            // Expose classic buckets alongside the native buckets, if enabled.
+            if let Some(classic) = classic {
+                histogram.bucket = classic_buckets(classic, native_hist.count());
+            }
 
-        let metric_family = pb::MetricFamily {
-            name: Some(sanitized_name),
-            help: if help.is_empty() { None } else { Some(help) },
-            r#type: Some(metric_type as i32),
-            metric: metrics,
-            unit: None,
-        };
+            // Add a no-op span if histogram is empty (matches Go implementation)
+            if histogram.zero_threshold == Some(0.0)
+                && histogram.zero_count == Some(0)
+                && histogram.positive_span.is_empty()
+                && histogram.negative_span.is_empty()
+            {
+                histogram.positive_span = vec![pb::BucketSpan { offset: Some(0), length: Some(0) }];
+            }
 
-        buffer.clear();
-        metric_family.encode_length_delimited(&mut buffer).unwrap();
-        writer.write_all(&buffer)?;
+            let metric = pb::Metric { label, histogram: Some(histogram), ..Default::default() };
+            (pb::MetricType::Histogram, metric)
+        }
     }
+}
 
-    Ok(())
+/// Converts cumulative `(upper bound, count)` pairs into classic buckets, adding the `+Inf` bucket.
+fn classic_buckets(buckets: Vec<(f64, u64)>, count: u64) -> Vec<pb::Bucket> {
+    buckets
+        .into_iter()
+        .map(|(le, count)| pb::Bucket {
+            cumulative_count: Some(count),
+            upper_bound: Some(le),
+
+            ..Default::default()
+        })
+        .chain(std::iter::once(pb::Bucket {
+            cumulative_count: Some(count),
+            upper_bound: Some(f64::INFINITY),
+
+            ..Default::default()
+        }))
+        .collect()
 }
 
-fn label_set_to_protobuf(labels: LabelSet) -> Vec<pb::LabelPair> {
+pub(crate) fn label_set_to_protobuf(
+    labels: LabelSet,
+    escaping: NameEscapingScheme,
+) -> Vec<pb::LabelPair> {
     let mut label_pairs = Vec::new();
 
     for (key, value) in labels.labels {
+        let key = if escaping == NameEscapingScheme::AllowUtf8 {
+            key
+        } else {
+            escape_label_key(&key, escaping)
+        };
         label_pairs.push(pb::LabelPair { name: Some(key), value: Some(value) });
     }
 
     label_pairs
 }
 
-fn add_suffix_to_name(name: &str, suffix: Option<&'static str>) -> String {
+pub(crate) fn add_suffix_to_name(name: &str, suffix: Option<&'static str>) -> String {
     match suffix {
         Some(suffix) if !name.ends_with(suffix) => format!("{name}_{suffix}"),
         _ => name.to_string(),
@@ -345,30 +260,31 @@ fn make_buckets(buckets: &std::collections::BTreeMap<i32, u64>) -> (Vec<pb::Buck
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::common::Snapshot;
-    use crate::recorder::new_description_handles;
     use indexmap::IndexMap;
-    use metrics::SharedString;
     use prost::Message;
-    use std::collections::HashMap;
+
+    fn render(metric_family: &pb::MetricFamily) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_metric_family(&mut output, &mut Vec::new(), metric_family).unwrap();
+        output
+    }
 
     #[test]
     fn test_render_protobuf_counters() {
-        let mut counters = HashMap::new();
-        let mut counter_labels = HashMap::new();
         let labels = LabelSet::from_key_and_global(
             &metrics::Key::from_parts("", vec![metrics::Label::new("method", "GET")]),
             &IndexMap::new(),
         );
-        counter_labels.insert(labels, 42u64);
-        counters.insert("http_requests".to_string(), counter_labels);
-
-        let snapshot = Snapshot { counters, gauges: HashMap::new(), distributions: HashMap::new() };
-
-        let (mut descriptions_wr, descriptions_rd) = new_description_handles();
-        descriptions_wr.publish();
+        let metric =
+            counter_metric(label_set_to_protobuf(labels, NameEscapingScheme::Underscores), 42);
+        let metric_family = super::metric_family(
+            add_suffix_to_name("http_requests", Some("total")),
+            None,
+            pb::MetricType::Counter,
+            vec![metric],
+        );
 
-        let protobuf_data = render_protobuf(snapshot, &descriptions_rd, Some("total"));
+        let protobuf_data = render(&metric_family);
 
         assert!(!protobuf_data.is_empty(), "Protobuf data should not be empty");
 
@@ -387,25 +303,20 @@ mod tests {
 
     #[test]
     fn test_render_protobuf_gauges() {
-        let mut gauges = HashMap::new();
-        let mut gauge_labels = HashMap::new();
         let labels = LabelSet::from_key_and_global(
             &metrics::Key::from_parts("", vec![metrics::Label::new("instance", "localhost")]),
             &IndexMap::new(),
         );
-        gauge_labels.insert(labels, 0.75f64);
-        gauges.insert("cpu_usage".to_string(), gauge_labels);
-
-        let snapshot = Snapshot { counters: HashMap::new(), gauges, distributions: HashMap::new() };
-
-        let (mut descriptions_wr, descriptions_rd) = new_description_handles();
-        descriptions_wr.update(
+        let metric =
+            gauge_metric(label_set_to_protobuf(labels, NameEscapingScheme::Underscores), 0.75);
+        let metric_family = super::metric_family(
             "cpu_usage".to_string(),
-            (SharedString::const_str("CPU usage percentage"), None),
+            Some("CPU usage percentage".to_string()),
+            pb::MetricType::Gauge,
+            vec![metric],
         );
-        descriptions_wr.publish();
 
-        let protobuf_data = render_protobuf(snapshot, &descriptions_rd, None);
+        let protobuf_data = render(&metric_family);
 
         assert!(!protobuf_data.is_empty(), "Protobuf data should not be empty");
 
@@ -420,6 +331,54 @@ mod tests {
         assert!(metric.gauge.is_some());
         let gauge_value = metric.gauge.as_ref().unwrap().value.unwrap();
         assert!((gauge_value - 0.75).abs() < f64::EPSILON);
+
+        // Empty descriptions are omitted entirely.
+        let metric_family = super::metric_family(
+            "cpu_usage".to_string(),
+            Some(String::new()),
+            pb::MetricType::Gauge,
+            vec![],
+        );
+        assert!(metric_family.help.is_none());
+    }
+
+    #[test]
+    fn test_render_protobuf_utf8_names() {
+        let labels = LabelSet::from_key_and_global(
+            &metrics::Key::from_parts("", vec![metrics::Label::new("service.name", "api")]),
+            &IndexMap::new(),
+        );
+
+        let metric = gauge_metric(
+            label_set_to_protobuf(labels.clone(), NameEscapingScheme::AllowUtf8),
+            0.75,
+        );
+        let metric_family = super::metric_family(
+            "process.cpu.usage".to_string(),
+            None,
+            pb::MetricType::Gauge,
+            vec![metric],
+        );
+
+        let protobuf_data = render(&metric_family);
+        let metric_family = pb::MetricFamily::decode_length_delimited(&protobuf_data[..]).unwrap();
+
+        assert_eq!(metric_family.name.as_ref().unwrap(), "process.cpu.usage");
+        assert_eq!(metric_family.metric[0].label[0].name.as_ref().unwrap(), "service.name");
+
+        let metric = gauge_metric(label_set_to_protobuf(labels, NameEscapingScheme::Dots), 0.75);
+        let metric_family = super::metric_family(
+            "process_dot_cpu_dot_usage".to_string(),
+            None,
+            pb::MetricType::Gauge,
+            vec![metric],
+        );
+
+        let protobuf_data = render(&metric_family);
+        let metric_family = pb::MetricFamily::decode_length_delimited(&protobuf_data[..]).unwrap();
+
+        assert_eq!(metric_family.name.as_ref().unwrap(), "process_dot_cpu_dot_usage");
+        assert_eq!(metric_family.metric[0].label[0].name.as_ref().unwrap(), "service_dot_name");
     }
 
     #[test]
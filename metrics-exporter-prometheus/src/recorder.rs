@@ -1,22 +1,27 @@
 use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
 use std::io;
 #[cfg(feature = "protobuf")]
 use std::io::Write;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+#[cfg(feature = "protobuf")]
+use std::sync::OnceLock;
 use std::sync::{Mutex, PoisonError, RwLock};
 
 use indexmap::IndexMap;
 use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
-use metrics_util::registry::{Recency, Registry};
-use quanta::Instant;
+use metrics_util::registry::{Generation, Recency, Registry};
+use metrics_util::KeyHasher;
 
-use crate::common::{LabelSet, Snapshot};
-use crate::distribution::{Distribution, DistributionBuilder};
+use crate::common::{LabelSet, MetricFilter, RenderOptions};
+use crate::distribution::{Distribution, DistributionBuilder, MergedDistribution};
 use crate::formatting::{
-    sanitize_metric_name, write_help_line, write_metric_line, write_type_line,
+    escape_metric_name, sanitize_metric_name, write_help_line_with_scheme, write_labels,
+    write_metric_line_with_formatted_labels, write_type_line_with_scheme, NameEscapingScheme,
 };
 use crate::registry::GenerationalAtomicStorage;
+use crate::telemetry::{CountingWriter, RenderStats, Telemetry};
 
 pub(crate) type DescriptionReadHandle =
     evmap::handles::ReadHandle<String, (SharedString, Option<Unit>)>;
@@ -32,7 +37,7 @@ pub(crate) type DescriptionWriteHandle =
 /// and `Eq`:
 /// - `SharedString` is a `Cow<'static, str>` and hashes/compares by string contents.
 /// - `Unit` is a fixed enum and hashes/compares by its discriminant.
-/// - The key is a sanitized metric name (`String`), which is also deterministic.
+/// - The key is a raw metric name (`String`), which is also deterministic.
 pub(crate) fn new_description_handles() -> (DescriptionWriteHandle, DescriptionReadHandle) {
     // SAFETY: description key/value hashing and equality are deterministic.
     let (mut write_handle, read_handle) = unsafe { evmap::new_assert_stable() };
@@ -40,275 +45,691 @@ pub(crate) fn new_description_handles() -> (DescriptionWriteHandle, DescriptionR
     (write_handle, read_handle)
 }
 
+type KeyHashMap<V> = HashMap<Key, V, BuildHasherDefault<KeyHasher>>;
+
+/// A metric family, as it appears in rendered output.
+#[derive(Debug)]
+struct Family {
+    /// The escaped metric name.
+    name: String,
+    /// The raw metric name of the first series seen for this family, used to look up its description.
+    raw_name: String,
+}
+
+/// A series, as it appears in rendered output.
+///
+/// Keys whose names and labels escape to the same family and labels share a single series, and are rendered as one.
+#[derive(Debug)]
+struct Series {
+    family: Arc<Family>,
+    /// The order in which this series was added to its family.
+    index: u64,
+    /// Global and series labels, formatted with [`write_labels`].
+    labels: Arc<str>,
+    /// Global and series labels, escaped for the protobuf format on first use.
+    #[cfg(feature = "protobuf")]
+    label_pairs: OnceLock<Vec<crate::protobuf::pb::LabelPair>>,
+}
+
+/// A family along with its series, keyed by their formatted labels.
+#[derive(Debug)]
+struct CachedFamily {
+    family: Arc<Family>,
+    series: HashMap<Arc<str>, Arc<Series>>,
+    next_index: u64,
+}
+
+/// A key in the registry, along with the series it is rendered as.
+#[derive(Debug)]
+struct CachedSeries {
+    key: Key,
+    series: Arc<Series>,
+    /// The render pass during which this key was last seen in the registry.
+    last_seen: AtomicU64,
+}
+
+/// Escaped names and formatted labels for a single escaping scheme.
+#[derive(Debug, Default)]
+struct SeriesCache {
+    pass: u64,
+    families: HashMap<String, CachedFamily>,
+    counters: KeyHashMap<Arc<CachedSeries>>,
+    gauges: KeyHashMap<Arc<CachedSeries>>,
+    histograms: KeyHashMap<Arc<CachedSeries>>,
+}
+
+impl SeriesCache {
+    /// Evicts the given keys, dropping any series and families left without keys.
+    fn evict(&mut self, counters: &[Key], gauges: &[Key], histograms: &[Key]) {
+        let mut evicted = false;
+        for (cache, keys) in [
+            (&mut self.counters, counters),
+            (&mut self.gauges, gauges),
+            (&mut self.histograms, histograms),
+        ] {
+            for key in keys {
+                evicted |= cache.remove(key).is_some();
+            }
+        }
+
+        if evicted {
+            self.prune_families();
+        }
+    }
+
+    /// Drops any series and families that no longer have keys.
+    fn prune_families(&mut self) {
+        self.families.retain(|_, family| {
+            family.series.retain(|_, series| Arc::strong_count(series) > 1);
+            !family.series.is_empty()
+        });
+    }
+}
+
+/// A key collected during rendering, along with its generation and raw value.
+type CollectedSeries = (Arc<CachedSeries>, Generation, u64);
+
+/// State carried between calls to render metrics.
+///
+/// Formatting names and labels dominates the cost of rendering, and neither changes for a given key, so they're
+/// computed once per key and reused across renders.  Entries are evicted when their key is removed from the registry.
+#[derive(Debug, Default)]
+pub(crate) struct RenderCache {
+    schemes: HashMap<NameEscapingScheme, SeriesCache>,
+    counters: Vec<CollectedSeries>,
+    gauges: Vec<CollectedSeries>,
+    histograms: Vec<CollectedSeries>,
+}
+
+/// The keys selected for a render, sorted such that keys of the same family, and then of the same series, are
+/// adjacent.
+struct Collected<'a> {
+    counters: &'a [CollectedSeries],
+    gauges: &'a [CollectedSeries],
+    histograms: &'a [CollectedSeries],
+    distributions: &'a KeyHashMap<Distribution>,
+    descriptions_rd: &'a DescriptionReadHandle,
+}
+
+/// Output format of a render.
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Text,
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
 #[derive(Debug)]
 pub(crate) struct Inner {
     pub registry: Registry<Key, GenerationalAtomicStorage>,
     pub recency: Recency<Key>,
-    pub distributions: RwLock<HashMap<String, IndexMap<LabelSet, Distribution>>>,
+    pub distributions: RwLock<KeyHashMap<Distribution>>,
     pub distribution_builder: DistributionBuilder,
     pub(crate) descriptions_rd: Mutex<DescriptionReadHandle>,
     pub(crate) descriptions_wr: Mutex<DescriptionWriteHandle>,
+    pub(crate) render_cache: Mutex<RenderCache>,
     pub global_labels: IndexMap<String, String>,
     pub enable_unit_suffix: bool,
     pub counter_suffix: Option<&'static str>,
+    pub telemetry: bool,
 }
 
 impl Inner {
-    fn get_recent_metrics(&self) -> Snapshot {
-        let mut counters = HashMap::new();
-        let counter_handles = self.registry.get_counter_handles();
-        for (key, counter) in counter_handles {
-            let gen = counter.get_generation();
-            if !self.recency.should_store_counter(&key, gen, &self.registry) {
-                continue;
+    /// Drains histogram samples into distribution.
+    fn drain_histograms_to_distributions(&self) {
+        let mut wg = self.distributions.write().unwrap_or_else(PoisonError::into_inner);
+        self.registry.visit_histograms(|key, histogram| {
+            if !wg.contains_key(key) {
+                let name = sanitize_metric_name(key.name());
+                let distribution = self.distribution_builder.get_distribution(name.as_str());
+                wg.insert(key.clone(), distribution);
             }
 
-            let name = sanitize_metric_name(key.name());
-            let labels = LabelSet::from_key_and_global(&key, &self.global_labels);
-            let value = counter.get_inner().load(Ordering::Acquire);
-            let entry =
-                counters.entry(name).or_insert_with(HashMap::new).entry(labels).or_insert(0);
-            *entry = value;
-        }
+            // UNWRAP: We just inserted the distribution if it was missing.
+            let entry = wg.get_mut(key).unwrap();
+            histogram.get_inner().clear_with(|samples| entry.record_samples(samples));
+        });
+    }
 
-        let mut gauges = HashMap::new();
-        let gauge_handles = self.registry.get_gauge_handles();
-        for (key, gauge) in gauge_handles {
-            let gen = gauge.get_generation();
-            if !self.recency.should_store_gauge(&key, gen, &self.registry) {
-                continue;
-            }
+    /// Collects the keys selected by `filter` from the registry, and passes them to `render`.
+    ///
+    /// Keys which are no longer recent enough are evicted along the way, and the per-kind counts are recorded in
+    /// `stats`.  Series are only counted when `filter` selects every metric family.
+    fn collect<T>(
+        &self,
+        escaping: NameEscapingScheme,
+        filter: &MetricFilter,
+        stats: &mut RenderStats,
+        render: impl FnOnce(&Collected<'_>) -> T,
+    ) -> T {
+        stats.filtered = !filter.is_empty();
+        self.commit_outstanding_description_writes();
+        self.drain_histograms_to_distributions();
+        let descriptions_rd = self.read_handle();
+
+        let mut render_cache = self.render_cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let RenderCache { schemes, counters, gauges, histograms } = &mut *render_cache;
+        let cache = schemes.entry(escaping).or_default();
+        cache.pass += 1;
+        let pass = cache.pass;
+        let (mut expired_counters, mut expired_gauges, mut expired_histograms) =
+            (Vec::new(), Vec::new(), Vec::new());
 
-            let name = sanitize_metric_name(key.name());
-            let labels = LabelSet::from_key_and_global(&key, &self.global_labels);
-            let value = f64::from_bits(gauge.get_inner().load(Ordering::Acquire));
-            let entry =
-                gauges.entry(name).or_insert_with(HashMap::new).entry(labels).or_insert(0.0);
-            *entry = value;
+        self.collect_series(
+            &mut cache.counters,
+            &mut cache.families,
+            pass,
+            escaping,
+            filter,
+            counters,
+            &mut expired_counters,
+            |collect| {
+                self.registry.visit_counters(|key, counter| {
+                    collect(
+                        key,
+                        counter.get_generation(),
+                        counter.get_inner().load(Ordering::Acquire),
+                    );
+                });
+            },
+            |key, gen| self.recency.should_store_counter(key, gen, &self.registry),
+        );
+        if !stats.filtered {
+            stats.counters = Groups::by_series(counters).count() as u64;
         }
+        stats.evicted_counters = expired_counters.len() as u64;
 
-        // Update distributions
-        self.drain_histograms_to_distributions();
-        // Remove expired histograms
-        let histogram_handles = self.registry.get_histogram_handles();
-        for (key, histogram) in histogram_handles {
-            let gen = histogram.get_generation();
-            if !self.recency.should_store_histogram(&key, gen, &self.registry) {
-                // Since we store aggregated distributions directly, when we're told that a metric
-                // is not recent enough and should be/was deleted from the registry, we also need to
-                // delete it on our side as well.
-                let name = sanitize_metric_name(key.name());
-                let labels = LabelSet::from_key_and_global(&key, &self.global_labels);
-                let mut wg = self.distributions.write().unwrap_or_else(PoisonError::into_inner);
-                let delete_by_name = if let Some(by_name) = wg.get_mut(&name) {
-                    by_name.swap_remove(&labels);
-                    by_name.is_empty()
-                } else {
-                    false
-                };
+        self.collect_series(
+            &mut cache.gauges,
+            &mut cache.families,
+            pass,
+            escaping,
+            filter,
+            gauges,
+            &mut expired_gauges,
+            |collect| {
+                self.registry.visit_gauges(|key, gauge| {
+                    collect(key, gauge.get_generation(), gauge.get_inner().load(Ordering::Acquire));
+                });
+            },
+            |key, gen| self.recency.should_store_gauge(key, gen, &self.registry),
+        );
+        if !stats.filtered {
+            stats.gauges = Groups::by_series(gauges).count() as u64;
+        }
+        stats.evicted_gauges = expired_gauges.len() as u64;
+
+        self.collect_series(
+            &mut cache.histograms,
+            &mut cache.families,
+            pass,
+            escaping,
+            filter,
+            histograms,
+            &mut expired_histograms,
+            |collect| {
+                self.registry.visit_histograms(|key, histogram| {
+                    collect(key, histogram.get_generation(), 0);
+                });
+            },
+            |key, gen| self.recency.should_store_histogram(key, gen, &self.registry),
+        );
+        if !stats.filtered {
+            stats.histograms = Groups::by_series(histograms).count() as u64;
+        }
+        stats.evicted_histograms = expired_histograms.len() as u64;
 
-                // If there's no more variants in the per-metric-name distribution map, then delete
-                // it entirely, otherwise we end up with weird empty output during render.
-                if delete_by_name {
-                    wg.remove(&name);
-                }
+        // Since we store aggregated distributions directly, when we're told that a metric is not
+        // recent enough and should be/was deleted from the registry, we also need to delete it on
+        // our side as well.
+        if !expired_histograms.is_empty() {
+            let mut wg = self.distributions.write().unwrap_or_else(PoisonError::into_inner);
+            for key in &expired_histograms {
+                wg.remove(key);
             }
         }
 
-        let distributions =
-            self.distributions.read().unwrap_or_else(PoisonError::into_inner).clone();
+        let rg = self.distributions.read().unwrap_or_else(PoisonError::into_inner);
+        let result = render(&Collected {
+            counters,
+            gauges,
+            histograms,
+            distributions: &rg,
+            descriptions_rd: &descriptions_rd,
+        });
+        drop(rg);
+
+        // Drop any series and families that no longer have keys.
+        counters.clear();
+        gauges.clear();
+        histograms.clear();
+        cache.prune_families();
 
-        Snapshot { counters, gauges, distributions }
+        // Evicted keys are gone from the registry, so they're dropped from the caches of every other scheme as well,
+        // rather than lingering until that scheme is next rendered, if ever.
+        for (_, other) in schemes.iter_mut().filter(|(scheme, _)| **scheme != escaping) {
+            other.evict(&expired_counters, &expired_gauges, &expired_histograms);
+        }
+
+        result
     }
 
-    /// Drains histogram samples into distribution.
-    fn drain_histograms_to_distributions(&self) {
-        let histogram_handles = self.registry.get_histogram_handles();
-        for (key, histogram) in histogram_handles {
-            let name = sanitize_metric_name(key.name());
-            let labels = LabelSet::from_key_and_global(&key, &self.global_labels);
+    /// Collects the keys visited by `visit` that are selected by `filter` into `scratch`, creating cache entries for
+    /// any new keys.
+    ///
+    /// Keys that `should_store` rejects are evicted from the cache and returned via `expired`, and cache entries for
+    /// keys which are no longer in the registry at all are evicted as well.
+    #[allow(clippy::too_many_arguments)]
+    fn collect_series(
+        &self,
+        cache: &mut KeyHashMap<Arc<CachedSeries>>,
+        families: &mut HashMap<String, CachedFamily>,
+        pass: u64,
+        escaping: NameEscapingScheme,
+        filter: &MetricFilter,
+        scratch: &mut Vec<CollectedSeries>,
+        expired: &mut Vec<Key>,
+        visit: impl FnOnce(&mut dyn FnMut(&Key, Generation, u64)),
+        should_store: impl Fn(&Key, Generation) -> bool,
+    ) {
+        scratch.clear();
+        let mut visited = 0;
+        visit(&mut |key, gen, value| {
+            let cached = if let Some(cached) = cache.get(key) {
+                cached.clone()
+            } else {
+                let cached = Arc::new(self.new_cached_series(key, families, escaping));
+                cache.insert(key.clone(), cached.clone());
+                cached
+            };
+            cached.last_seen.store(pass, Ordering::Relaxed);
+            visited += 1;
 
-            let mut wg = self.distributions.write().unwrap_or_else(PoisonError::into_inner);
-            let entry = wg
-                .entry(name.clone())
-                .or_default()
-                .entry(labels)
-                .or_insert_with(|| self.distribution_builder.get_distribution(name.as_str()));
+            if filter.matches(&cached.series.family.name) {
+                scratch.push((cached, gen, value));
+            }
+        });
 
-            histogram.get_inner().clear_with(|samples| entry.record_samples(samples));
+        // Anything we didn't just visit has been removed from the registry since the last render.
+        if cache.len() != visited {
+            cache.retain(|_, cached| cached.last_seen.load(Ordering::Relaxed) == pass);
         }
+
+        scratch.retain(|(cached, gen, _)| {
+            let keep = should_store(&cached.key, *gen);
+            if !keep {
+                cache.remove(&cached.key);
+                expired.push(cached.key.clone());
+            }
+            keep
+        });
+
+        // Group keys by family, and then by series, so that each family is rendered contiguously, with its series in
+        // the order they were added, and keys of the same series can be rendered as one.
+        scratch.sort_unstable_by(|(a, _, _), (b, _, _)| {
+            let (a, b) = (&a.series, &b.series);
+            a.family.name.cmp(&b.family.name).then(a.index.cmp(&b.index))
+        });
     }
 
-    fn render_to_write(&self, output: &mut impl io::Write) -> io::Result<()> {
-        let Snapshot { mut counters, mut distributions, mut gauges } = self.get_recent_metrics();
+    fn new_cached_series(
+        &self,
+        key: &Key,
+        families: &mut HashMap<String, CachedFamily>,
+        escaping: NameEscapingScheme,
+    ) -> CachedSeries {
+        let name = escape_metric_name(key.name(), escaping);
+        let family = families.entry(name).or_insert_with_key(|name| CachedFamily {
+            family: Arc::new(Family { name: name.clone(), raw_name: key.name().to_string() }),
+            series: HashMap::new(),
+            next_index: 0,
+        });
 
-        let mut intermediate = String::new();
-        self.commit_outstanding_description_writes();
-        let descriptions = self.read_handle();
-
-        for (name, mut by_labels) in counters.drain() {
-            let unit = descriptions.get_one(name.as_str()).and_then(|entry| {
-                let (desc, unit) = &*entry;
-                let unit = unit.filter(|_| self.enable_unit_suffix);
-                write_help_line(&mut intermediate, name.as_str(), unit, self.counter_suffix, desc);
-                unit
+        let mut labels = String::new();
+        write_labels(
+            &mut labels,
+            &LabelSet::from_key_and_global(key, &self.global_labels),
+            escaping,
+        );
+
+        let series = if let Some(series) = family.series.get(labels.as_str()) {
+            series.clone()
+        } else {
+            let labels = Arc::<str>::from(labels);
+            let series = Arc::new(Series {
+                family: family.family.clone(),
+                index: family.next_index,
+                labels: labels.clone(),
+                #[cfg(feature = "protobuf")]
+                label_pairs: OnceLock::new(),
             });
+            family.series.insert(labels, series.clone());
+            family.next_index += 1;
+            series
+        };
+
+        CachedSeries { key: key.clone(), series, last_seen: AtomicU64::new(0) }
+    }
 
-            write_type_line(&mut intermediate, name.as_str(), unit, self.counter_suffix, "counter");
+    /// Writes the `HELP` and `TYPE` lines for a family, returning the unit to use for its metric lines.
+    fn write_family_header(
+        &self,
+        buffer: &mut String,
+        descriptions_rd: &DescriptionReadHandle,
+        family: &Family,
+        suffix: Option<&'static str>,
+        metric_type: &str,
+        escaping: NameEscapingScheme,
+    ) -> Option<Unit> {
+        let unit = descriptions_rd.get_one(family.raw_name.as_str()).and_then(|entry| {
+            let (desc, unit) = &*entry;
+            let unit = unit.filter(|_| self.enable_unit_suffix);
+            write_help_line_with_scheme(buffer, &family.name, unit, suffix, desc, escaping);
+            unit
+        });
+
+        write_type_line_with_scheme(buffer, &family.name, unit, suffix, metric_type, escaping);
+        unit
+    }
+
+    fn render_to_write(
+        &self,
+        output: &mut impl io::Write,
+        format: Format,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        let start = std::time::Instant::now();
+        let mut stats = RenderStats::default();
+        let mut output = CountingWriter::new(output);
+        let escaping = options.escaping;
+        self.collect(escaping, &options.filter, &mut stats, |collected| match format {
+            Format::Text => self.write_text(&mut output, collected, escaping),
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => self.write_protobuf(&mut output, collected, escaping),
+        })?;
+
+        if let Some(telemetry) = self.telemetry() {
+            telemetry.record_render(start.elapsed(), output.count(), &stats);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn write_text(
+        &self,
+        output: &mut impl io::Write,
+        collected: &Collected<'_>,
+        escaping: NameEscapingScheme,
+    ) -> io::Result<()> {
+        let Collected { counters, gauges, histograms, distributions, descriptions_rd } = *collected;
+        let mut intermediate = String::new();
 
-            // A chunk is emitted here, just in case there are a large number of sets below.
-            output.write_all(intermediate.as_bytes())?;
-            intermediate.clear();
+        for family_group in Groups::by_family(counters) {
+            let family = &family_group[0].0.series.family;
+            let unit = self.write_family_header(
+                &mut intermediate,
+                descriptions_rd,
+                family,
+                self.counter_suffix,
+                "counter",
+                escaping,
+            );
 
-            for (labels, value) in by_labels.drain() {
-                write_metric_line::<&str, u64>(
+            for series_group in Groups::by_series(family_group) {
+                // Only one value can be rendered per series, so the last key wins.
+                let (cached, _, value) = &series_group[series_group.len() - 1];
+                write_metric_line_with_formatted_labels::<&str, u64>(
                     &mut intermediate,
-                    &name,
+                    &family.name,
                     self.counter_suffix,
-                    &labels,
+                    &cached.series.labels,
                     None,
-                    value,
+                    *value,
                     unit,
+                    escaping,
                 );
-                // Each set gets its own write invocation.
-                output.write_all(intermediate.as_bytes())?;
-                intermediate.clear();
+                flush_if_full(output, &mut intermediate)?;
             }
-            output.write_all(b"\n")?;
+            intermediate.push('\n');
         }
 
-        for (name, mut by_labels) in gauges.drain() {
-            let unit = descriptions.get_one(name.as_str()).and_then(|entry| {
-                let (desc, unit) = &*entry;
-                let unit = unit.filter(|_| self.enable_unit_suffix);
-                write_help_line(&mut intermediate, name.as_str(), unit, None, desc);
-                unit
-            });
-
-            write_type_line(&mut intermediate, name.as_str(), unit, None, "gauge");
+        for family_group in Groups::by_family(gauges) {
+            let family = &family_group[0].0.series.family;
+            let unit = self.write_family_header(
+                &mut intermediate,
+                descriptions_rd,
+                family,
+                None,
+                "gauge",
+                escaping,
+            );
 
-            // A chunk is emitted here, just in case there are a large number of sets below.
-            output.write_all(intermediate.as_bytes())?;
-            intermediate.clear();
-
-            for (labels, value) in by_labels.drain() {
-                write_metric_line::<&str, f64>(
+            for series_group in Groups::by_series(family_group) {
+                // Only one value can be rendered per series, so the last key wins.
+                let (cached, _, value) = &series_group[series_group.len() - 1];
+                write_metric_line_with_formatted_labels::<&str, f64>(
                     &mut intermediate,
-                    &name,
+                    &family.name,
                     None,
-                    &labels,
+                    &cached.series.labels,
                     None,
-                    value,
+                    f64::from_bits(*value),
                     unit,
+                    escaping,
                 );
-                // Each set gets its own write invocation.
-                output.write_all(intermediate.as_bytes())?;
-                intermediate.clear();
+                flush_if_full(output, &mut intermediate)?;
             }
-            output.write_all(b"\n")?;
+            intermediate.push('\n');
         }
 
-        for (name, mut by_labels) in distributions.drain() {
-            let distribution_type = self.distribution_builder.get_distribution_type(name.as_str());
+        for family_group in Groups::by_family(histograms) {
+            let family = &family_group[0].0.series.family;
+            let distribution_type = family_group
+                .iter()
+                .find_map(|(cached, _, _)| distributions.get(&cached.key))
+                .map_or("summary", Distribution::distribution_type);
 
-            // Skip native histograms in text format - they're only supported in protobuf format
+            // Skip native histograms without classic buckets in text format - they're only
+            // supported in protobuf format
             if distribution_type == "native_histogram" {
                 continue;
             }
 
-            let unit = descriptions.get_one(name.as_str()).and_then(|entry| {
-                let (desc, unit) = &*entry;
-                let unit = unit.filter(|_| self.enable_unit_suffix);
-                write_help_line(&mut intermediate, name.as_str(), unit, None, desc);
-                unit
-            });
-
-            write_type_line(&mut intermediate, name.as_str(), unit, None, distribution_type);
+            let unit = self.write_family_header(
+                &mut intermediate,
+                descriptions_rd,
+                family,
+                None,
+                distribution_type,
+                escaping,
+            );
 
-            // A chunk is emitted here, just in case there are a large number of sets below.
-            output.write_all(intermediate.as_bytes())?;
-            intermediate.clear();
+            for series_group in Groups::by_series(family_group) {
+                let merged = MergedDistribution::merge(
+                    series_group.iter().filter_map(|(cached, _, _)| distributions.get(&cached.key)),
+                );
+                let Some(distribution) = merged else {
+                    continue;
+                };
+                let labels = &*series_group[0].0.series.labels;
 
-            for (labels, distribution) in by_labels.drain(..) {
                 let (sum, count) = match distribution {
-                    Distribution::Summary(summary, quantiles, sum) => {
-                        let snapshot = summary.snapshot(Instant::now());
-                        for quantile in quantiles.iter() {
+                    MergedDistribution::Summary { snapshot, quantiles, sum, count } => {
+                        for quantile in quantiles {
                             let value = snapshot.quantile(quantile.value()).unwrap_or(0.0);
-                            write_metric_line(
+                            write_metric_line_with_formatted_labels(
                                 &mut intermediate,
-                                &name,
+                                &family.name,
                                 None,
-                                &labels,
+                                labels,
                                 Some(("quantile", quantile.value())),
                                 value,
                                 unit,
+                                escaping,
                             );
                         }
 
-                        (sum, summary.count() as u64)
+                        (sum, count)
                     }
-                    Distribution::Histogram(histogram) => {
-                        for (le, count) in histogram.buckets() {
-                            write_metric_line(
-                                &mut intermediate,
-                                &name,
-                                Some("bucket"),
-                                &labels,
-                                Some(("le", le)),
-                                count,
-                                unit,
-                            );
-                        }
-                        write_metric_line(
+                    MergedDistribution::Histogram { buckets, sum, count } => {
+                        write_histogram_buckets(
+                            &mut intermediate,
+                            &family.name,
+                            labels,
+                            buckets,
+                            count,
+                            unit,
+                            escaping,
+                        );
+
+                        (sum, count)
+                    }
+                    MergedDistribution::NativeHistogram { histogram, classic } => {
+                        // Native buckets are not supported in the text format, so we can only
+                        // write the classic buckets, if they're enabled.
+                        let Some(buckets) = classic else {
+                            continue;
+                        };
+                        write_histogram_buckets(
                             &mut intermediate,
-                            &name,
-                            Some("bucket"),
-                            &labels,
-                            Some(("le", "+Inf")),
+                            &family.name,
+                            labels,
+                            buckets,
                             histogram.count(),
                             unit,
+                            escaping,
                         );
 
                         (histogram.sum(), histogram.count())
                     }
-                    Distribution::NativeHistogram(_) => {
-                        // Native histograms are not supported in text format
-                        // This branch should not be reached due to the continue above
-                        continue;
-                    }
                 };
 
-                write_metric_line::<&str, f64>(
+                write_metric_line_with_formatted_labels::<&str, f64>(
                     &mut intermediate,
-                    &name,
+                    &family.name,
                     Some("sum"),
-                    &labels,
+                    labels,
                     None,
                     sum,
                     unit,
+                    escaping,
                 );
-                write_metric_line::<&str, u64>(
+                write_metric_line_with_formatted_labels::<&str, u64>(
                     &mut intermediate,
-                    &name,
+                    &family.name,
                     Some("count"),
-                    &labels,
+                    labels,
                     None,
                     count,
                     unit,
+                    escaping,
+                );
+                flush_if_full(output, &mut intermediate)?;
+            }
+
+            intermediate.push('\n');
+        }
+
+        output.write_all(intermediate.as_bytes())
+    }
+
+    #[cfg(feature = "protobuf")]
+    fn write_protobuf(
+        &self,
+        output: &mut impl io::Write,
+        collected: &Collected<'_>,
+        escaping: NameEscapingScheme,
+    ) -> io::Result<()> {
+        use crate::protobuf::{self, pb};
+
+        let Collected { counters, gauges, histograms, distributions, descriptions_rd } = *collected;
+        let mut buffer = Vec::new();
+
+        let help = |family: &Family| {
+            descriptions_rd.get_one(family.raw_name.as_str()).map(|entry| entry.0.to_string())
+        };
+        let label_pairs = |cached: &CachedSeries| {
+            cached
+                .series
+                .label_pairs
+                .get_or_init(|| {
+                    let labels = LabelSet::from_key_and_global(&cached.key, &self.global_labels);
+                    protobuf::label_set_to_protobuf(labels, escaping)
+                })
+                .clone()
+        };
+
+        for family_group in Groups::by_family(counters) {
+            let family = &family_group[0].0.series.family;
+            let metrics = Groups::by_series(family_group)
+                .map(|series_group| {
+                    // Only one value can be rendered per series, so the last key wins.
+                    let (cached, _, value) = &series_group[series_group.len() - 1];
+                    protobuf::counter_metric(label_pairs(cached), *value)
+                })
+                .collect();
+
+            let name = protobuf::add_suffix_to_name(&family.name, self.counter_suffix);
+            let metric_family =
+                protobuf::metric_family(name, help(family), pb::MetricType::Counter, metrics);
+            protobuf::write_metric_family(output, &mut buffer, &metric_family)?;
+        }
+
+        for family_group in Groups::by_family(gauges) {
+            let family = &family_group[0].0.series.family;
+            let metrics = Groups::by_series(family_group)
+                .map(|series_group| {
+                    // Only one value can be rendered per series, so the last key wins.
+                    let (cached, _, value) = &series_group[series_group.len() - 1];
+                    protobuf::gauge_metric(label_pairs(cached), f64::from_bits(*value))
+                })
+                .collect();
+
+            let metric_family = protobuf::metric_family(
+                family.name.clone(),
+                help(family),
+                pb::MetricType::Gauge,
+                metrics,
+            );
+            protobuf::write_metric_family(output, &mut buffer, &metric_family)?;
+        }
+
+        for family_group in Groups::by_family(histograms) {
+            let family = &family_group[0].0.series.family;
+            let mut metric_type = None;
+            let mut metrics = Vec::new();
+            for series_group in Groups::by_series(family_group) {
+                let merged = MergedDistribution::merge(
+                    series_group.iter().filter_map(|(cached, _, _)| distributions.get(&cached.key)),
                 );
+                let Some(distribution) = merged else {
+                    continue;
+                };
 
-                // Each set gets its own write invocation.
-                output.write_all(intermediate.as_bytes())?;
-                intermediate.clear();
+                let (series_type, metric) =
+                    protobuf::distribution_metric(label_pairs(&series_group[0].0), distribution);
+                metric_type = Some(series_type);
+                metrics.push(metric);
             }
 
-            output.write_all(b"\n")?;
+            let Some(metric_type) = metric_type else {
+                // Skip empty metric families
+                continue;
+            };
+
+            let metric_family =
+                protobuf::metric_family(family.name.clone(), help(family), metric_type, metrics);
+            protobuf::write_metric_family(output, &mut buffer, &metric_family)?;
         }
 
         Ok(())
     }
 
+    /// Gets the exporter's telemetry, if enabled.
+    pub(crate) fn telemetry(&self) -> Option<Telemetry<'_>> {
+        self.telemetry.then(|| Telemetry::new(&self.registry))
+    }
+
     fn run_upkeep(&self) {
         self.drain_histograms_to_distributions();
         self.commit_outstanding_description_writes();
@@ -325,6 +746,83 @@ impl Inner {
     }
 }
 
+/// Splits collected keys, sorted by family and series, into runs that share a family or series.
+struct Groups<'a> {
+    rest: &'a [CollectedSeries],
+    same: fn(&CollectedSeries, &CollectedSeries) -> bool,
+}
+
+impl<'a> Groups<'a> {
+    /// Splits collected keys into one slice per family.
+    fn by_family(collected: &'a [CollectedSeries]) -> Self {
+        Self { rest: collected, same: |a, b| Arc::ptr_eq(&a.0.series.family, &b.0.series.family) }
+    }
+
+    /// Splits collected keys into one slice per series.
+    fn by_series(collected: &'a [CollectedSeries]) -> Self {
+        Self { rest: collected, same: |a, b| Arc::ptr_eq(&a.0.series, &b.0.series) }
+    }
+}
+
+impl<'a> Iterator for Groups<'a> {
+    type Item = &'a [CollectedSeries];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.rest.first()?;
+        let len =
+            self.rest.iter().position(|item| !(self.same)(first, item)).unwrap_or(self.rest.len());
+        let (group, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Some(group)
+    }
+}
+
+/// Size at which buffered output is written out during rendering.
+const RENDER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes out the buffer if it has grown past [`RENDER_CHUNK_SIZE`].
+fn flush_if_full(output: &mut impl io::Write, buffer: &mut String) -> io::Result<()> {
+    if buffer.len() >= RENDER_CHUNK_SIZE {
+        output.write_all(buffer.as_bytes())?;
+        buffer.clear();
+    }
+    Ok(())
+}
+
+/// Writes the cumulative buckets of a histogram, followed by the `+Inf` bucket.
+fn write_histogram_buckets(
+    buffer: &mut String,
+    name: &str,
+    labels: &str,
+    buckets: Vec<(f64, u64)>,
+    count: u64,
+    unit: Option<Unit>,
+    escaping: NameEscapingScheme,
+) {
+    for (le, bucket_count) in buckets {
+        write_metric_line_with_formatted_labels(
+            buffer,
+            name,
+            Some("bucket"),
+            labels,
+            Some(("le", le)),
+            bucket_count,
+            unit,
+            escaping,
+        );
+    }
+    write_metric_line_with_formatted_labels(
+        buffer,
+        name,
+        Some("bucket"),
+        labels,
+        Some(("le", "+Inf")),
+        count,
+        unit,
+        escaping,
+    );
+}
+
 /// A Prometheus recorder.
 ///
 /// Most users will not need to interact directly with the recorder, and can simply deal with the
@@ -347,16 +845,15 @@ impl PrometheusRecorder {
         description: SharedString,
         unit: Option<Unit>,
     ) {
-        let sanitized = sanitize_metric_name(key_name.as_str());
         let mut descriptions =
             self.inner.descriptions_wr.lock().unwrap_or_else(PoisonError::into_inner);
-        let already_present = descriptions.contains_key(&sanitized);
+        let already_present = descriptions.contains_key(key_name.as_str());
 
         if already_present {
             return;
         }
 
-        descriptions.update(sanitized, (description, unit));
+        descriptions.update(key_name.as_str().to_string(), (description, unit));
     }
 }
 
@@ -411,9 +908,17 @@ impl PrometheusHandle {
     /// the Prometheus exposition format.
     #[allow(clippy::missing_panics_doc)]
     pub fn render(&self) -> String {
+        self.render_with_options(&RenderOptions::default())
+    }
+
+    /// Takes a snapshot of the metrics held by the recorder and generates a payload conforming to
+    /// the Prometheus exposition format, escaping names and selecting metric families as configured
+    /// by `options`.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn render_with_options(&self, options: &RenderOptions) -> String {
         let mut buf = Vec::new();
         // UNWRAP: writing to a Vec<u8> does not fail.
-        self.inner.render_to_write(&mut buf).unwrap();
+        self.inner.render_to_write(&mut buf, Format::Text, options).unwrap();
         // UNWRAP: Prometheus exposition format is always UTF-8.
         String::from_utf8(buf).unwrap()
     }
@@ -426,18 +931,41 @@ impl PrometheusHandle {
     ///
     /// Writing to the provided output fails.
     pub fn render_to_write(&self, output: &mut impl io::Write) -> io::Result<()> {
-        self.inner.render_to_write(output)
+        self.render_to_write_with_options(output, &RenderOptions::default())
+    }
+
+    /// Takes a snapshot of the metrics held by the recorder and generates a payload conforming to
+    /// the Prometheus exposition format incrementally, escaping names and selecting metric families
+    /// as configured by `options`.
+    ///
+    /// # Errors
+    ///
+    /// Writing to the provided output fails.
+    pub fn render_to_write_with_options(
+        &self,
+        output: &mut impl io::Write,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        self.inner.render_to_write(output, Format::Text, options)
     }
 
     /// Takes a snapshot of the metrics held by the recorder and generates a payload conforming to
     /// the Prometheus protobuf format.
     #[cfg(feature = "protobuf")]
     pub fn render_protobuf(&self) -> Vec<u8> {
-        let snapshot = self.inner.get_recent_metrics();
-        self.inner.commit_outstanding_description_writes();
-        let descriptions = self.inner.read_handle();
+        self.render_protobuf_with_options(&RenderOptions::default())
+    }
 
-        crate::protobuf::render_protobuf(snapshot, &descriptions, self.inner.counter_suffix)
+    /// Takes a snapshot of the metrics held by the recorder and generates a payload conforming to
+    /// the Prometheus protobuf format, escaping names and selecting metric families as configured
+    /// by `options`.
+    #[cfg(feature = "protobuf")]
+    #[allow(clippy::missing_panics_doc)]
+    pub fn render_protobuf_with_options(&self, options: &RenderOptions) -> Vec<u8> {
+        let mut output = Vec::new();
+        // UNWRAP: writing to a Vec<u8> does not fail.
+        self.inner.render_to_write(&mut output, Format::Protobuf, options).unwrap();
+        output
     }
 
     /// Takes a snapshot of the metrics held by the recorder and writes a payload conforming to
@@ -448,16 +976,33 @@ impl PrometheusHandle {
     /// Writing to the provided output fails.
     #[cfg(feature = "protobuf")]
     pub fn render_protobuf_to_write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        let snapshot = self.inner.get_recent_metrics();
-        self.inner.commit_outstanding_description_writes();
-        let descriptions = self.inner.read_handle();
+        self.render_protobuf_to_write_with_options(writer, &RenderOptions::default())
+    }
 
-        crate::protobuf::render_protobuf_to_write(
-            writer,
-            snapshot,
-            &descriptions,
-            self.inner.counter_suffix,
-        )
+    /// Takes a snapshot of the metrics held by the recorder and writes a payload conforming to
+    /// the Prometheus protobuf format into the provided writer, escaping names and selecting metric
+    /// families as configured by `options`.
+    ///
+    /// # Errors
+    ///
+    /// Writing to the provided output fails.
+    #[cfg(feature = "protobuf")]
+    pub fn render_protobuf_to_write_with_options<W: Write>(
+        &self,
+        writer: &mut W,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        self.inner.render_to_write(writer, Format::Protobuf, options)
+    }
+
+    /// Gets the exporter's telemetry, if enabled.
+    #[cfg(any(
+        feature = "http-listener",
+        feature = "push-gateway",
+        feature = "push-gateway-no-tls-provider"
+    ))]
+    pub(crate) fn telemetry(&self) -> Option<Telemetry<'_>> {
+        self.inner.telemetry()
     }
 
     /// Performs upkeeping operations to ensure metrics held by recorder are up-to-date and do not
@@ -465,4 +1010,16 @@ impl PrometheusHandle {
     pub fn run_upkeep(&self) {
         self.inner.run_upkeep();
     }
+
+    /// Returns the number of keys and families cached for the given escaping scheme.
+    #[cfg(test)]
+    pub(crate) fn cached_series(&self, escaping: NameEscapingScheme) -> (usize, usize) {
+        let render_cache = self.inner.render_cache.lock().unwrap_or_else(PoisonError::into_inner);
+        render_cache.schemes.get(&escaping).map_or((0, 0), |cache| {
+            (
+                cache.counters.len() + cache.gauges.len() + cache.histograms.len(),
+                cache.families.len(),
+            )
+        })
+    }
 }
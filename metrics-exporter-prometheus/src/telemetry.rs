@@ -0,0 +1,183 @@
+use std::io;
+use std::time::Duration;
+
+use metrics::{Counter, Gauge, Histogram, Key, Label, Unit};
+use metrics_util::registry::Registry;
+use metrics_util::MetricKind;
+
+use crate::recorder::DescriptionWriteHandle;
+use crate::registry::GenerationalAtomicStorage;
+
+/// Prefix shared by all telemetry metrics.
+pub(crate) const TELEMETRY_PREFIX: &str = "metrics_exporter_";
+
+static COUNTER_LABELS: [Label; 1] = [Label::from_static_parts("kind", "counter")];
+static GAUGE_LABELS: [Label; 1] = [Label::from_static_parts("kind", "gauge")];
+static HISTOGRAM_LABELS: [Label; 1] = [Label::from_static_parts("kind", "histogram")];
+#[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+static SUCCESS_LABELS: [Label; 1] = [Label::from_static_parts("result", "success")];
+#[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+static FAILURE_LABELS: [Label; 1] = [Label::from_static_parts("result", "failure")];
+
+const RENDERS: &str = "metrics_exporter_renders_total";
+const RENDER_DURATION: &str = "metrics_exporter_render_duration_seconds";
+const RENDERED_BYTES: &str = "metrics_exporter_rendered_bytes_total";
+const SERIES: &str = "metrics_exporter_series";
+const IDLE_EVICTIONS: &str = "metrics_exporter_idle_evictions_total";
+const PUSHES: &str = "metrics_exporter_push_gateway_pushes_total";
+const PUSH_DURATION: &str = "metrics_exporter_push_gateway_push_duration_seconds";
+const REJECTED_CONNECTIONS: &str = "metrics_exporter_rejected_connections_total";
+
+static RENDERS_KEY: Key = Key::from_static_name(RENDERS);
+static RENDER_DURATION_KEY: Key = Key::from_static_name(RENDER_DURATION);
+static RENDERED_BYTES_KEY: Key = Key::from_static_name(RENDERED_BYTES);
+static COUNTER_SERIES_KEY: Key = Key::from_static_parts(SERIES, &COUNTER_LABELS);
+static GAUGE_SERIES_KEY: Key = Key::from_static_parts(SERIES, &GAUGE_LABELS);
+static HISTOGRAM_SERIES_KEY: Key = Key::from_static_parts(SERIES, &HISTOGRAM_LABELS);
+static COUNTER_EVICTIONS_KEY: Key = Key::from_static_parts(IDLE_EVICTIONS, &COUNTER_LABELS);
+static GAUGE_EVICTIONS_KEY: Key = Key::from_static_parts(IDLE_EVICTIONS, &GAUGE_LABELS);
+static HISTOGRAM_EVICTIONS_KEY: Key = Key::from_static_parts(IDLE_EVICTIONS, &HISTOGRAM_LABELS);
+#[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+static PUSH_SUCCESS_KEY: Key = Key::from_static_parts(PUSHES, &SUCCESS_LABELS);
+#[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+static PUSH_FAILURE_KEY: Key = Key::from_static_parts(PUSHES, &FAILURE_LABELS);
+#[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+static PUSH_DURATION_KEY: Key = Key::from_static_name(PUSH_DURATION);
+#[cfg(feature = "http-listener")]
+static REJECTED_CONNECTIONS_KEY: Key = Key::from_static_name(REJECTED_CONNECTIONS);
+
+/// Registers descriptions for all telemetry metrics.
+pub(crate) fn describe(descriptions: &mut DescriptionWriteHandle) {
+    let descriptions_and_units: [(&str, &'static str, Option<Unit>); 8] = [
+        (RENDERS, "Number of times metrics have been rendered, whether scraped, pushed, or rendered directly.", None),
+        (RENDER_DURATION, "Time spent rendering metrics.", Some(Unit::Seconds)),
+        (RENDERED_BYTES, "Number of bytes of rendered metrics.", Some(Unit::Bytes)),
+        (SERIES, "Number of series rendered during the last unfiltered render, by kind.", None),
+        (IDLE_EVICTIONS, "Number of series removed after being idle, by kind.", None),
+        (PUSHES, "Number of pushes to the push gateway, by result.", None),
+        (PUSH_DURATION, "Time spent pushing to the push gateway.", Some(Unit::Seconds)),
+        (REJECTED_CONNECTIONS, "Number of connections rejected by the allowed address list.", None),
+    ];
+
+    for (name, description, unit) in descriptions_and_units {
+        descriptions.update(name.to_string(), (description.into(), unit));
+    }
+}
+
+/// Per-kind counts gathered while rendering.
+///
+/// Series counts are only meaningful for unfiltered renders, as a filtered render only sees a subset of the series.
+#[derive(Debug, Default)]
+pub(crate) struct RenderStats {
+    pub filtered: bool,
+    pub counters: u64,
+    pub gauges: u64,
+    pub histograms: u64,
+    pub evicted_counters: u64,
+    pub evicted_gauges: u64,
+    pub evicted_histograms: u64,
+}
+
+/// Exporter telemetry.
+///
+/// When enabled, the exporter records information about its own behavior as regular metrics prefixed with
+/// `metrics_exporter_`, which are rendered alongside all other metrics.
+///
+/// Metrics are recorded directly into the exporter's registry rather than through the global recorder, as the exporter
+/// may not be installed globally.
+#[derive(Clone, Copy)]
+pub(crate) struct Telemetry<'a> {
+    registry: &'a Registry<Key, GenerationalAtomicStorage>,
+}
+
+impl<'a> Telemetry<'a> {
+    /// Creates a `Telemetry` that records into the given registry.
+    pub fn new(registry: &'a Registry<Key, GenerationalAtomicStorage>) -> Self {
+        Self { registry }
+    }
+
+    /// Records a completed render of `bytes` bytes, along with the per-kind counts gathered while rendering.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn record_render(self, duration: Duration, bytes: u64, stats: &RenderStats) {
+        self.counter(&RENDERS_KEY).increment(1);
+        self.histogram(&RENDER_DURATION_KEY).record(duration);
+        self.counter(&RENDERED_BYTES_KEY).increment(bytes);
+
+        if !stats.filtered {
+            self.gauge(&COUNTER_SERIES_KEY).set(stats.counters as f64);
+            self.gauge(&GAUGE_SERIES_KEY).set(stats.gauges as f64);
+            self.gauge(&HISTOGRAM_SERIES_KEY).set(stats.histograms as f64);
+        }
+
+        self.record_evictions(MetricKind::Counter, stats.evicted_counters);
+        self.record_evictions(MetricKind::Gauge, stats.evicted_gauges);
+        self.record_evictions(MetricKind::Histogram, stats.evicted_histograms);
+    }
+
+    /// Records a push to the push gateway.
+    #[cfg(any(feature = "push-gateway", feature = "push-gateway-no-tls-provider"))]
+    pub fn record_push(self, success: bool, duration: Duration) {
+        let key = if success { &PUSH_SUCCESS_KEY } else { &PUSH_FAILURE_KEY };
+        self.counter(key).increment(1);
+        self.histogram(&PUSH_DURATION_KEY).record(duration);
+    }
+
+    /// Records a connection rejected by the allowed address list.
+    #[cfg(feature = "http-listener")]
+    pub fn record_rejected_connection(self) {
+        self.counter(&REJECTED_CONNECTIONS_KEY).increment(1);
+    }
+
+    fn record_evictions(self, kind: MetricKind, evicted: u64) {
+        if evicted == 0 {
+            return;
+        }
+
+        let key = match kind {
+            MetricKind::Counter => &COUNTER_EVICTIONS_KEY,
+            MetricKind::Gauge => &GAUGE_EVICTIONS_KEY,
+            MetricKind::Histogram => &HISTOGRAM_EVICTIONS_KEY,
+        };
+        self.counter(key).increment(evicted);
+    }
+
+    fn counter(self, key: &Key) -> Counter {
+        self.registry.get_or_create_counter(key, |c| c.clone().into())
+    }
+
+    fn gauge(self, key: &Key) -> Gauge {
+        self.registry.get_or_create_gauge(key, |g| g.clone().into())
+    }
+
+    fn histogram(self, key: &Key) -> Histogram {
+        self.registry.get_or_create_histogram(key, |h| h.clone().into())
+    }
+}
+
+/// A writer that counts the bytes written through it.
+pub(crate) struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    count: u64,
+}
+
+impl<'a, W: io::Write> CountingWriter<'a, W> {
+    pub fn new(inner: &'a mut W) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<W: io::Write> io::Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,9 +1,10 @@
-use std::io::Read;
+use std::io::{Read, Write};
 use std::net::TcpStream;
 
 use bytes::{BufMut, BytesMut};
 use prost::Message;
 
+#[allow(dead_code)]
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/event.proto.rs"));
 }
@@ -12,6 +13,24 @@ fn main() {
     let mut stream =
         TcpStream::connect("127.0.0.1:5000").expect("failed to connect to TCP endpoint");
 
+    // Any arguments are treated as name globs to subscribe to, such as `tcp_server_*`.
+    let filters = std::env::args()
+        .skip(1)
+        .map(|glob| proto::Filter {
+            name: Some(proto::filter::Name::NameGlob(glob)),
+            labels: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    if !filters.is_empty() {
+        let subscribe = proto::Subscribe { filters };
+        let msg = proto::ClientMessage {
+            message: Some(proto::client_message::Message::Subscribe(subscribe)),
+        };
+        stream
+            .write_all(&msg.encode_length_delimited_to_vec())
+            .expect("failed to send subscription");
+    }
+
     let mut buf = BytesMut::new();
     let mut rbuf = [0u8; 1024];
 
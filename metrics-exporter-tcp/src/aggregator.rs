@@ -0,0 +1,274 @@
+//! Interval aggregation, where operations are aggregated in-process and periodically sent to
+//! clients in summarized form.
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use metrics::Key;
+use metrics_util::registry::{AtomicStorage, Registry};
+
+use crate::sketch::Sketch;
+use crate::subscription::Subscription;
+use crate::MetricOperation;
+
+/// Aggregates operations over an interval.
+///
+/// Counters and gauges are updated in place by the recorder, and histogram samples are buffered,
+/// all in the registry.  At the end of each interval, the aggregator turns them into operations:
+/// the change in each counter since the last interval, the value of each gauge that has changed
+/// since the last interval, and a sketch of the samples recorded to each histogram.
+#[derive(Debug)]
+pub(crate) struct Aggregator {
+    interval: Duration,
+    next_flush: Instant,
+    // Values as of the last flush, which is what clients have been sent so far.
+    counters: HashMap<Key, u64>,
+    gauges: HashMap<Key, u64>,
+}
+
+impl Aggregator {
+    pub fn new(interval: Duration) -> Aggregator {
+        Aggregator {
+            interval,
+            next_flush: Instant::now() + interval,
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+        }
+    }
+
+    /// Returns how long until the next flush is due.
+    pub fn time_until_flush(&self) -> Duration {
+        self.next_flush.saturating_duration_since(Instant::now())
+    }
+
+    /// Flushes the aggregated operations if the interval has elapsed.
+    ///
+    /// Returns `None` if the interval has not elapsed yet.
+    pub fn flush_if_due(
+        &mut self,
+        registry: &Registry<Key, AtomicStorage>,
+    ) -> Option<Vec<(Key, MetricOperation)>> {
+        let now = Instant::now();
+        if now < self.next_flush {
+            return None;
+        }
+
+        // If we've fallen behind by more than an interval, there's no point in trying to catch up.
+        self.next_flush += self.interval;
+        if self.next_flush <= now {
+            self.next_flush = now + self.interval;
+        }
+
+        Some(self.flush(registry))
+    }
+
+    fn flush(&mut self, registry: &Registry<Key, AtomicStorage>) -> Vec<(Key, MetricOperation)> {
+        let mut ops = Vec::new();
+
+        registry.visit_counters(|key, counter| {
+            let value = counter.load(Ordering::Acquire);
+            let last = self.counters.get(key).copied().unwrap_or(0);
+            if value == last {
+                return;
+            }
+
+            // Counters only go backwards if they're reset, which can't be expressed as a delta.
+            let op = if value > last {
+                MetricOperation::IncrementCounter(value - last)
+            } else {
+                MetricOperation::SetCounter(value)
+            };
+            ops.push((key.clone(), op));
+            self.counters.insert(key.clone(), value);
+        });
+
+        registry.visit_gauges(|key, gauge| {
+            let value = gauge.load(Ordering::Acquire);
+            if self.gauges.get(key) == Some(&value) {
+                return;
+            }
+
+            ops.push((key.clone(), MetricOperation::SetGauge(f64::from_bits(value))));
+            self.gauges.insert(key.clone(), value);
+        });
+
+        registry.visit_histograms(|key, histogram| {
+            let mut sketch = Sketch::new();
+            histogram.clear_with(|samples| samples.iter().for_each(|sample| sketch.add(*sample)));
+            if !sketch.is_empty() {
+                ops.push((key.clone(), MetricOperation::RecordHistogramSketch(sketch)));
+            }
+        });
+
+        ops
+    }
+
+    /// Generates `set_counter`/`set_gauge` operations for the value of every counter and gauge
+    /// matching the given subscription, as of the last flush.
+    ///
+    /// Anything that has changed since the last flush will be sent as part of the next flush, so
+    /// using the current values instead would count those changes twice.
+    pub fn snapshot(&self, subscription: &Subscription) -> Vec<(Key, MetricOperation)> {
+        let counters = self
+            .counters
+            .iter()
+            .filter(|(key, _)| subscription.matches(key))
+            .map(|(key, value)| (key.clone(), MetricOperation::SetCounter(*value)));
+        let gauges =
+            self.gauges.iter().filter(|(key, _)| subscription.matches(key)).map(|(key, value)| {
+                (key.clone(), MetricOperation::SetGauge(f64::from_bits(*value)))
+            });
+
+        counters.chain(gauges).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+    use std::time::{Duration, Instant};
+
+    use metrics::{Counter, Gauge, Histogram, Key};
+    use metrics_util::registry::{AtomicStorage, Registry};
+
+    use super::Aggregator;
+    use crate::MetricOperation;
+
+    fn flush(
+        aggregator: &mut Aggregator,
+        registry: &Registry<Key, AtomicStorage>,
+    ) -> Vec<(String, MetricOperation)> {
+        let ops = aggregator.flush(registry).into_iter();
+        ops.map(|(key, op)| (key.name().to_string(), op)).collect()
+    }
+
+    #[test]
+    fn counter_deltas() {
+        let registry = Registry::atomic();
+        let mut aggregator = Aggregator::new(Duration::from_secs(1));
+        let counter = Counter::from_arc(
+            registry.get_or_create_counter(&Key::from_name("requests"), Arc::clone),
+        );
+
+        counter.increment(3);
+        counter.increment(4);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(
+            matches!(&ops[..], [(name, MetricOperation::IncrementCounter(7))] if name == "requests")
+        );
+
+        counter.increment(5);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(matches!(&ops[..], [(_, MetricOperation::IncrementCounter(5))]));
+
+        // Nothing changed, so nothing is sent.
+        assert!(flush(&mut aggregator, &registry).is_empty());
+    }
+
+    #[test]
+    fn counter_reset() {
+        let registry = Registry::atomic();
+        let mut aggregator = Aggregator::new(Duration::from_secs(1));
+        let value = registry.get_or_create_counter(&Key::from_name("requests"), Arc::clone);
+        let counter = Counter::from_arc(value.clone());
+
+        counter.increment(10);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(matches!(&ops[..], [(_, MetricOperation::IncrementCounter(10))]));
+
+        // Counters never go backwards when set to a lower absolute value.
+        counter.absolute(4);
+        assert!(flush(&mut aggregator, &registry).is_empty());
+
+        // If the counter was reset, however, the change can't be expressed as a delta, so the new
+        // value is sent as-is, and later deltas are relative to it.
+        value.store(4, Ordering::Release);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(matches!(&ops[..], [(_, MetricOperation::SetCounter(4))]));
+
+        counter.increment(2);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(matches!(&ops[..], [(_, MetricOperation::IncrementCounter(2))]));
+    }
+
+    #[test]
+    fn gauges_only_sent_on_change() {
+        let registry = Registry::atomic();
+        let mut aggregator = Aggregator::new(Duration::from_secs(1));
+        let gauge = Gauge::from_arc(
+            registry.get_or_create_gauge(&Key::from_name("connections"), Arc::clone),
+        );
+
+        gauge.set(1.5);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(
+            matches!(&ops[..], [(name, MetricOperation::SetGauge(v))] if name == "connections" && *v == 1.5)
+        );
+
+        // Setting the same value again isn't a change.
+        assert!(flush(&mut aggregator, &registry).is_empty());
+        gauge.set(1.5);
+        assert!(flush(&mut aggregator, &registry).is_empty());
+
+        // Only the latest value is sent, however it was reached.
+        gauge.increment(2.0);
+        gauge.decrement(1.0);
+        let ops = flush(&mut aggregator, &registry);
+        assert!(matches!(&ops[..], [(_, MetricOperation::SetGauge(v))] if *v == 2.5));
+    }
+
+    #[test]
+    fn histogram_sketches() {
+        let registry = Registry::atomic();
+        let mut aggregator = Aggregator::new(Duration::from_secs(1));
+        let histogram = Histogram::from_arc(
+            registry.get_or_create_histogram(&Key::from_name("latency"), Arc::clone),
+        );
+
+        // Histograms with no samples are skipped.
+        assert!(flush(&mut aggregator, &registry).is_empty());
+
+        for sample in [1.0, 2.0, 4.0, 0.0] {
+            histogram.record(sample);
+        }
+        let mut ops = flush(&mut aggregator, &registry);
+        let sketch = match ops.pop() {
+            Some((name, MetricOperation::RecordHistogramSketch(sketch))) if name == "latency" => {
+                sketch.into_proto()
+            }
+            _ => panic!("expected a single histogram sketch"),
+        };
+        assert!(ops.is_empty());
+        assert_eq!(sketch.count, 4);
+        assert_eq!(sketch.sum, 7.0);
+        assert_eq!(sketch.min, 0.0);
+        assert_eq!(sketch.max, 4.0);
+        assert_eq!(sketch.zero_count, 1);
+        assert_eq!(sketch.positive_bins.values().sum::<u64>(), 3);
+        assert!(sketch.negative_bins.is_empty());
+
+        // Samples are drained by each flush.
+        assert!(flush(&mut aggregator, &registry).is_empty());
+    }
+
+    #[test]
+    fn flush_interval() {
+        let registry = Registry::<Key, AtomicStorage>::atomic();
+        let interval = Duration::from_secs(10);
+        let mut aggregator = Aggregator::new(interval);
+        assert!(aggregator.flush_if_due(&registry).is_none());
+
+        // When on schedule, flushes stay aligned to the interval.
+        let due = Instant::now() - Duration::from_secs(1);
+        aggregator.next_flush = due;
+        assert!(aggregator.flush_if_due(&registry).is_some());
+        assert_eq!(aggregator.next_flush, due + interval);
+
+        // After a stall of many intervals, there's a single flush, and the next one is a full
+        // interval away rather than immediately due.
+        aggregator.next_flush = Instant::now() - interval * 30;
+        assert!(aggregator.flush_if_due(&registry).is_some());
+        assert!(aggregator.flush_if_due(&registry).is_none());
+        assert!(aggregator.time_until_flush() > interval - Duration::from_secs(1));
+    }
+}
@@ -18,6 +18,47 @@
 //! potentially up until the point of memory exhaustion.  A buffer limit is advised for this reason,
 //! even if it is many multiples of the default.
 //!
+//! # Connecting
+//! When a client connects, the exporter first sends the metadata (description and unit) of every
+//! described metric, followed by the current value of every registered counter and gauge, as
+//! `set_counter` and `set_gauge` operations.  After that, operations are streamed as they happen.
+//!
+//! Every operation is either reflected in this snapshot or streamed after it, never both, and the
+//! snapshot doesn't count towards the client's buffer limit, so it is never dropped to make room for
+//! live operations.  Histograms have no current value, so only new samples are streamed.  When
+//! aggregating, the snapshot holds the values as of the end of the last interval, and anything since
+//! then is sent at the end of the current one.
+//!
+//! # Subscriptions
+//! By default, clients are sent every event.  A client can instead subscribe to a subset of metrics
+//! by sending a `ClientMessage` containing a `Subscribe` message, using the same length-delimited
+//! encoding as the events it receives.  A subscription holds a list of filters, each matching
+//! metrics by name prefix or glob, and by label matchers, and only events matching at least one of
+//! the filters are sent to the client.  Events are filtered before they are queued for the client,
+//! so unwanted events never count against its buffer limit.
+//!
+//! Each subscription replaces the previous one.  Whatever is still queued for the client is replaced
+//! with the metadata and current values of the newly matching metrics, followed by live operations.
+//!
+//! # Aggregation
+//! By default, every operation is sent to clients as its own event, which can overwhelm clients,
+//! and the buffers feeding them, for frequently-updated metrics.  With
+//! [`TcpBuilder::aggregation_interval`], operations are instead aggregated in-process, and at the
+//! end of each interval, clients are sent a single event per metric: the change in each counter,
+//! the latest value of each gauge that changed, and a sketch of the samples recorded to each
+//! histogram.  Histogram sketches group samples into logarithmically-sized bins, which bounds the
+//! relative error of any quantile estimated from them.
+//!
+//! # Transports
+//! By default, the exporter listens for plain TCP connections.  On Unix platforms, it can instead
+//! listen on a Unix domain socket with [`TcpBuilder::listen_path`], where access can be restricted
+//! through the permissions of the socket file with [`TcpBuilder::unix_socket_mode`].
+//!
+//! With the `tls` feature enabled, clients can be required to connect over TLS, using a
+//! [`rustls`](https://docs.rs/rustls) server configuration passed to [`TcpBuilder::tls_config`].
+//! This works with either transport, and client certificates can be required by configuring a
+//! client certificate verifier.  The feature uses the `aws-lc-rs` crypto provider.
+//!
 //! # Encoding
 //! Metrics are encoded using Protocol Buffers.  The protocol file can be found in the repository at
 //! `proto/event.proto`.
@@ -46,29 +87,29 @@
 //! [metrics]: https://docs.rs/metrics
 #![deny(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_cfg), deny(rustdoc::broken_intra_doc_links))]
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, PoisonError, RwLock,
 };
 use std::thread;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{
     collections::{BTreeMap, HashMap, VecDeque},
     sync::atomic::AtomicUsize,
 };
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
 use metrics::{
-    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
-    SetRecorderError, SharedString, Unit,
-};
-use mio::{
-    net::{TcpListener, TcpStream},
-    Events, Interest, Poll, Token, Waker,
+    atomics::AtomicU64, Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName,
+    Metadata, Recorder, SetRecorderError, SharedString, Unit,
 };
+use metrics_util::registry::{AtomicStorage, Registry};
+use mio::{Events, Interest, Poll, Token, Waker};
 use prost::{EncodeError, Message};
 use tracing::{error, trace, trace_span};
 
@@ -77,10 +118,26 @@ const LISTENER: Token = Token(1);
 const START_TOKEN: Token = Token(2);
 const CLIENT_INTEREST: Interest = Interest::READABLE.add(Interest::WRITABLE);
 
+// Largest message we'll accept from a client.  Client messages are tiny, so anything larger than
+// this is almost certainly not a client speaking our protocol.
+const MAX_CLIENT_MESSAGE_LEN: usize = 1024 * 1024;
+
 mod proto {
     include!(concat!(env!("OUT_DIR"), "/event.proto.rs"));
 }
 
+mod aggregator;
+use self::aggregator::Aggregator;
+
+mod sketch;
+use self::sketch::Sketch;
+
+mod subscription;
+use self::subscription::Subscription;
+
+mod transport;
+use self::transport::{Connection, Listener};
+
 use self::proto::metadata::MetricType;
 
 enum MetricOperation {
@@ -90,6 +147,7 @@ enum MetricOperation {
     DecrementGauge(f64),
     SetGauge(f64),
     RecordHistogram(f64),
+    RecordHistogramSketch(Sketch),
 }
 
 enum Event {
@@ -137,17 +195,32 @@ impl std::error::Error for Error {
     }
 }
 
-#[derive(Debug)]
 struct State {
     client_count: AtomicUsize,
     should_send: AtomicBool,
     waker: Waker,
     tx: Sender<Event>,
+    // Current value of every counter and gauge, along with the buffered samples of every histogram
+    // when aggregating.
+    registry: Registry<Key, AtomicStorage>,
+    aggregating: bool,
+    // Held for reading while a metric is updated and its operation queued, and for writing while a
+    // snapshot is taken, so that every operation is either reflected in the snapshot or queued
+    // after it.
+    sync: RwLock<()>,
 }
 
 impl State {
-    pub fn new(waker: Waker, tx: Sender<Event>) -> State {
-        State { client_count: AtomicUsize::new(0), should_send: AtomicBool::new(false), waker, tx }
+    pub fn new(waker: Waker, tx: Sender<Event>, aggregating: bool) -> State {
+        State {
+            client_count: AtomicUsize::new(0),
+            should_send: AtomicBool::new(false),
+            waker,
+            tx,
+            registry: Registry::atomic(),
+            aggregating,
+            sync: RwLock::new(()),
+        }
     }
 
     pub fn should_send(&self) -> bool {
@@ -184,44 +257,103 @@ impl State {
         }
     }
 
+    /// Updates the current value of a metric, and queues the corresponding operation.
+    fn update_metric(&self, key: &Key, op: MetricOperation, update: impl FnOnce()) {
+        let _guard = self.sync.read().unwrap_or_else(PoisonError::into_inner);
+        update();
+        self.push_metric(key, op);
+    }
+
     pub fn wake(&self) {
         let _ = self.waker.wake();
     }
+
+    /// Generates `set_counter`/`set_gauge` operations for the current value of every registered
+    /// counter and gauge matching the given subscription.
+    fn snapshot(&self, subscription: &Subscription) -> Vec<(Key, MetricOperation)> {
+        let mut ops = Vec::new();
+        self.registry.visit_counters(|key, value| {
+            if subscription.matches(key) {
+                ops.push((key.clone(), MetricOperation::SetCounter(value.load(Ordering::Acquire))));
+            }
+        });
+        self.registry.visit_gauges(|key, value| {
+            if subscription.matches(key) {
+                let value = f64::from_bits(value.load(Ordering::Acquire));
+                ops.push((key.clone(), MetricOperation::SetGauge(value)));
+            }
+        });
+        ops
+    }
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // manual implementation because `Registry` does not implement `Debug`
+        f.debug_struct("State")
+            .field("client_count", &self.client_count)
+            .field("should_send", &self.should_send)
+            .field("aggregating", &self.aggregating)
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug)]
 struct Handle {
     key: Key,
     state: Arc<State>,
+    // Current value of the metric, which is sent to clients when they first connect. Histograms
+    // have no current value.
+    value: Option<Arc<AtomicU64>>,
 }
 
 impl Handle {
-    fn new(key: Key, state: Arc<State>) -> Handle {
-        Handle { key, state }
+    fn new(key: Key, state: Arc<State>, value: Option<Arc<AtomicU64>>) -> Handle {
+        Handle { key, state, value }
     }
 }
 
 impl CounterFn for Handle {
     fn increment(&self, value: u64) {
-        self.state.push_metric(&self.key, MetricOperation::IncrementCounter(value))
+        self.state.update_metric(&self.key, MetricOperation::IncrementCounter(value), || {
+            if let Some(current) = &self.value {
+                CounterFn::increment(&**current, value);
+            }
+        })
     }
 
     fn absolute(&self, value: u64) {
-        self.state.push_metric(&self.key, MetricOperation::SetCounter(value))
+        self.state.update_metric(&self.key, MetricOperation::SetCounter(value), || {
+            if let Some(current) = &self.value {
+                CounterFn::absolute(&**current, value);
+            }
+        })
     }
 }
 
 impl GaugeFn for Handle {
     fn increment(&self, value: f64) {
-        self.state.push_metric(&self.key, MetricOperation::IncrementGauge(value))
+        self.state.update_metric(&self.key, MetricOperation::IncrementGauge(value), || {
+            if let Some(current) = &self.value {
+                GaugeFn::increment(&**current, value);
+            }
+        })
     }
 
     fn decrement(&self, value: f64) {
-        self.state.push_metric(&self.key, MetricOperation::DecrementGauge(value))
+        self.state.update_metric(&self.key, MetricOperation::DecrementGauge(value), || {
+            if let Some(current) = &self.value {
+                GaugeFn::decrement(&**current, value);
+            }
+        })
     }
 
     fn set(&self, value: f64) {
-        self.state.push_metric(&self.key, MetricOperation::SetGauge(value))
+        self.state.update_metric(&self.key, MetricOperation::SetGauge(value), || {
+            if let Some(current) = &self.value {
+                GaugeFn::set(&**current, value);
+            }
+        })
     }
 }
 
@@ -237,17 +369,37 @@ pub struct TcpRecorder {
     state: Arc<State>,
 }
 
+#[derive(Debug)]
+enum ListenDestination {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
 /// Builder for creating and installing a TCP recorder/exporter.
 #[derive(Debug)]
 pub struct TcpBuilder {
-    listen_addr: SocketAddr,
+    destination: ListenDestination,
+    #[cfg(unix)]
+    unix_socket_mode: Option<u32>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<rustls::ServerConfig>>,
     buffer_size: Option<usize>,
+    aggregation_interval: Option<Duration>,
 }
 
 impl TcpBuilder {
     /// Creates a new `TcpBuilder`.
     pub fn new() -> TcpBuilder {
-        TcpBuilder { listen_addr: ([0, 0, 0, 0], 5000).into(), buffer_size: Some(1024) }
+        TcpBuilder {
+            destination: ListenDestination::Tcp(([0, 0, 0, 0], 5000).into()),
+            #[cfg(unix)]
+            unix_socket_mode: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            buffer_size: Some(1024),
+            aggregation_interval: None,
+        }
     }
 
     /// Sets the listen address.
@@ -260,7 +412,50 @@ impl TcpBuilder {
     where
         A: Into<SocketAddr>,
     {
-        self.listen_addr = addr.into();
+        self.destination = ListenDestination::Tcp(addr.into());
+        self
+    }
+
+    /// Sets the listen path, to accept connections over a Unix domain socket instead of TCP.
+    ///
+    /// An existing socket at the path is removed when the exporter is built, such as one left behind
+    /// by a previous process.  If the path exists but is not a socket, building the exporter fails.
+    ///
+    /// Access to the socket can be restricted with [`TcpBuilder::unix_socket_mode`].
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn listen_path<P>(mut self, path: P) -> TcpBuilder
+    where
+        P: Into<PathBuf>,
+    {
+        self.destination = ListenDestination::Unix(path.into());
+        self
+    }
+
+    /// Sets the file permissions of the Unix domain socket, such as `0o660`.
+    ///
+    /// Only used when listening on a Unix domain socket.  The permissions are applied right after
+    /// the socket is bound, so the directory containing the socket should restrict access as well,
+    /// if clients must never be able to connect before then.
+    ///
+    /// Defaults to `None`, where the permissions are determined by the process umask.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn unix_socket_mode(mut self, mode: Option<u32>) -> TcpBuilder {
+        self.unix_socket_mode = mode;
+        self
+    }
+
+    /// Sets the TLS configuration, to require clients to connect over TLS.
+    ///
+    /// Client certificates can be required by configuring a client certificate verifier, such as
+    /// [`WebPkiClientVerifier`][rustls::server::WebPkiClientVerifier], on the configuration.
+    ///
+    /// Defaults to `None`, where clients connect without TLS.
+    #[cfg(feature = "tls")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tls")))]
+    pub fn tls_config(mut self, config: Option<Arc<rustls::ServerConfig>>) -> TcpBuilder {
+        self.tls_config = config;
         self
     }
 
@@ -282,6 +477,24 @@ impl TcpBuilder {
         self
     }
 
+    /// Sets the aggregation interval.
+    ///
+    /// When set, operations are aggregated in-process rather than sent to clients as they happen.
+    /// At the end of each interval, clients are sent the change in each counter, the latest value
+    /// of each gauge that changed, and a sketch of the samples recorded to each histogram, as a
+    /// single event per metric.
+    ///
+    /// This greatly reduces the number of events sent for frequently-updated metrics, at the cost
+    /// of delaying them by up to the interval, and of histograms being summarized rather than sent
+    /// as individual samples.  As each flush produces up to one event per metric, the buffer size
+    /// should be large enough to hold an event for every metric.
+    ///
+    /// Defaults to `None`, where every operation is sent to clients as it happens.
+    pub fn aggregation_interval(mut self, interval: Option<Duration>) -> TcpBuilder {
+        self.aggregation_interval = interval;
+        self
+    }
+
     /// Installs the recorder and exporter.
     ///
     /// An error will be returned if there's an issue with creating the TCP server or with
@@ -306,13 +519,21 @@ impl TcpBuilder {
         let poll = Poll::new()?;
         let waker = Waker::new(poll.registry(), WAKER)?;
 
-        let mut listener = TcpListener::bind(self.listen_addr)?;
+        let listener = match &self.destination {
+            ListenDestination::Tcp(addr) => Listener::bind_tcp(*addr)?,
+            #[cfg(unix)]
+            ListenDestination::Unix(path) => Listener::bind_unix(path, self.unix_socket_mode)?,
+        };
+        #[cfg(feature = "tls")]
+        let listener = listener.with_tls(self.tls_config);
+        let mut listener = listener;
         poll.registry().register(&mut listener, LISTENER, Interest::READABLE)?;
 
-        let state = Arc::new(State::new(waker, tx));
+        let aggregator = self.aggregation_interval.map(Aggregator::new);
+        let state = Arc::new(State::new(waker, tx, aggregator.is_some()));
         let recorder = TcpRecorder { state: state.clone() };
 
-        thread::spawn(move || run_transport(poll, listener, rx, state, buffer_size));
+        thread::spawn(move || run_transport(poll, listener, rx, state, buffer_size, aggregator));
         Ok(recorder)
     }
 }
@@ -337,25 +558,44 @@ impl Recorder for TcpRecorder {
     }
 
     fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
-        Counter::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone())))
+        let value = self.state.registry.get_or_create_counter(key, Arc::clone);
+        if self.state.aggregating {
+            return Counter::from_arc(value);
+        }
+
+        Counter::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone(), Some(value))))
     }
 
     fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
-        Gauge::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone())))
+        let value = self.state.registry.get_or_create_gauge(key, Arc::clone);
+        if self.state.aggregating {
+            return Gauge::from_arc(value);
+        }
+
+        Gauge::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone(), Some(value))))
     }
 
     fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
-        Histogram::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone())))
+        // Samples are only buffered in the registry when aggregating, as they'd otherwise never be
+        // drained.
+        if self.state.aggregating {
+            return Histogram::from_arc(
+                self.state.registry.get_or_create_histogram(key, Arc::clone),
+            );
+        }
+
+        Histogram::from_arc(Arc::new(Handle::new(key.clone(), self.state.clone(), None)))
     }
 }
 
 #[allow(clippy::mutable_key_type)]
 fn run_transport(
     mut poll: Poll,
-    listener: TcpListener,
+    listener: Listener,
     rx: Receiver<Event>,
     state: Arc<State>,
     buffer_size: Option<usize>,
+    mut aggregator: Option<Aggregator>,
 ) {
     let buffer_limit = buffer_size.unwrap_or(usize::MAX);
     let mut events = Events::with_capacity(1024);
@@ -371,7 +611,8 @@ fn run_transport(
         // Poll until we get something.  All events -- metrics wake-ups and network I/O -- flow
         // through here so we can block without issue.
         let _evspan = trace_span!("event loop");
-        if let Err(e) = poll.poll(&mut events, None) {
+        let timeout = aggregator.as_ref().map(Aggregator::time_until_flush);
+        if let Err(e) = poll.poll(&mut events, timeout) {
             error!(error = %e, "error during poll");
             continue;
         }
@@ -385,113 +626,68 @@ fn run_transport(
         for event in events.iter() {
             match event.token() {
                 WAKER => {
-                    // Read until we hit our buffer limit or there are no more messages.
-                    let _mrxspan = trace_span!("metrics in");
-                    loop {
-                        if buffered_pmsgs.len() >= buffer_limit {
-                            // We didn't drain ourselves here, so schedule a future wake so we
-                            // continue to drain remaining metrics.
-                            state.wake();
-                            break;
-                        }
-
-                        let msg = match rx.try_recv() {
-                            Ok(msg) => msg,
-                            Err(e) if e.is_empty() => {
-                                trace!("metric rx drained");
-                                break;
-                            }
-                            // If our sender is dead, we can't do anything else, so just return.
-                            Err(_) => return,
-                        };
-
-                        match msg {
-                            Event::Metadata(key, metric_type, unit, desc) => {
-                                let entry = metadata
-                                    .entry(key)
-                                    .or_insert_with(|| (metric_type, None, None));
-                                let (_, uentry, dentry) = entry;
-                                *uentry = unit;
-                                *dentry = Some(desc);
-                            }
-                            Event::Metric(key, value) => {
-                                match convert_metric_to_protobuf_encoded(key, value) {
-                                    Ok(pmsg) => buffered_pmsgs.push_back(pmsg),
-                                    Err(e) => error!(error = ?e, "error encoding metric"),
-                                }
-                            }
-                        }
-                    }
-                    drop(_mrxspan);
-
-                    if buffered_pmsgs.is_empty() {
-                        trace!("woken for metrics but no pmsgs buffered");
-                        continue;
-                    }
-
-                    // Now fan out each of these items to each client.
-                    for (token, (conn, wbuf, msgs)) in clients.iter_mut() {
-                        // Before we potentially do any draining, try and drive the connection to
-                        // make sure space is freed up as much as possible.
-                        let done = drive_connection(conn, wbuf, msgs);
-                        if done {
-                            clients_to_remove.push(*token);
-                            state.decrement_clients();
-                            continue;
-                        }
-
-                        // With the encoded metrics, we push them into each client's internal
-                        // list.  We try to write as many of those buffers as possible to the
-                        // client before being told to back off.  If we encounter a partial write
-                        // of a buffer, we store the remaining of that message in a special field
-                        // so that we don't write incomplete metrics to the client.
-                        //
-                        // If there are more messages to hand off to a client than the client's
-                        // internal list has room for, we remove as many as needed to do so.  This
-                        // means we prioritize sending newer metrics if connections are backed up.
-                        let available =
-                            if msgs.len() < buffer_limit { buffer_limit - msgs.len() } else { 0 };
-                        let to_drain = buffered_pmsgs.len().saturating_sub(available);
-                        let _ = msgs.drain(0..to_drain);
-                        msgs.extend(buffered_pmsgs.iter().take(buffer_limit).cloned());
-
-                        let done = drive_connection(conn, wbuf, msgs);
-                        if done {
-                            clients_to_remove.push(*token);
-                            state.decrement_clients();
-                        }
-                    }
-
-                    // We've pushed each metric into each client's internal list, so we can clear
-                    // ourselves and continue on.
-                    buffered_pmsgs.clear();
-
-                    // Remove any clients that were done.
-                    for token in clients_to_remove.drain(..) {
-                        if let Some((conn, _, _)) = clients.get_mut(&token) {
-                            trace!(?conn, ?token, "removing client");
-                            clients.remove(&token);
-                            state.decrement_clients();
-                        }
+                    if !forward_metrics(
+                        &rx,
+                        &state,
+                        &mut metadata,
+                        &mut buffered_pmsgs,
+                        buffer_limit,
+                        &mut clients,
+                        &mut clients_to_remove,
+                    ) {
+                        return;
                     }
                 }
                 LISTENER => {
                     // Accept as many new connections as we can.
                     loop {
                         match listener.accept() {
-                            Ok((mut conn, _)) => {
+                            Ok(mut conn) => {
                                 // Get our client's token and register the connection.
                                 let token = next(&mut next_token);
                                 poll.registry()
                                     .register(&mut conn, token, CLIENT_INTEREST)
                                     .expect("failed to register interest for client connection");
 
-                                state.increment_clients();
+                                // Hold off any updates while we take the snapshot, and forward
+                                // every pending metric to the existing clients first, since their
+                                // values are already reflected in the snapshot we're about to
+                                // send, and the new client would otherwise see them twice.
+                                let sync =
+                                    state.sync.write().unwrap_or_else(PoisonError::into_inner);
+                                if !forward_all_metrics(
+                                    &rx,
+                                    &state,
+                                    &mut metadata,
+                                    &mut buffered_pmsgs,
+                                    buffer_limit,
+                                    &mut clients,
+                                    &mut clients_to_remove,
+                                ) {
+                                    return;
+                                }
 
-                                // Start tracking them, and enqueue all of the metadata.
-                                let metadata = generate_metadata_messages(&metadata);
+                                state.increment_clients();
+                                let subscription = Subscription::default();
+                                let values =
+                                    take_snapshot(&state, aggregator.as_ref(), &subscription);
+                                drop(sync);
+
+                                // Start tracking them, and enqueue all of the metadata, followed by
+                                // the current value of every counter and gauge.
+                                let snapshot =
+                                    generate_initial_messages(&metadata, &subscription, values);
+
+                                let client = Client {
+                                    conn,
+                                    wbuf: None,
+                                    snapshot,
+                                    msgs: VecDeque::new(),
+                                    rbuf: BytesMut::new(),
+                                    subscription,
+                                };
                                 clients
-                                    .insert(token, (conn, None, metadata))
+                                    .insert(token, client)
                                     .ok_or(())
                                     .expect_err("client mapped to existing token!");
                             }
@@ -504,28 +700,311 @@ fn run_transport(
                     }
                 }
                 token => {
-                    if event.is_writable() {
-                        if let Some((conn, wbuf, msgs)) = clients.get_mut(&token) {
-                            let done = drive_connection(conn, wbuf, msgs);
-                            if done {
-                                trace!(?conn, ?token, "removing client");
-                                clients.remove(&token);
-                                state.decrement_clients();
+                    let mut done = false;
+                    if event.is_readable() {
+                        let Some(client) = clients.get_mut(&token) else { continue };
+                        let mut subscription = None;
+                        done = read_client_messages(
+                            &mut client.conn,
+                            &mut client.rbuf,
+                            &mut subscription,
+                        );
+
+                        if let Some(subscription) = subscription.filter(|_| !done) {
+                            // As with new connections, forward any pending metrics first, and
+                            // then replace whatever is still queued for the client with the
+                            // metadata and current values matching the new subscription.
+                            let sync = state.sync.write().unwrap_or_else(PoisonError::into_inner);
+                            if !forward_all_metrics(
+                                &rx,
+                                &state,
+                                &mut metadata,
+                                &mut buffered_pmsgs,
+                                buffer_limit,
+                                &mut clients,
+                                &mut clients_to_remove,
+                            ) {
+                                return;
                             }
+
+                            let values = take_snapshot(&state, aggregator.as_ref(), &subscription);
+                            drop(sync);
+
+                            if let Some(client) = clients.get_mut(&token) {
+                                trace!(conn = ?client.conn, ?token, "client subscribed");
+                                client.snapshot =
+                                    generate_initial_messages(&metadata, &subscription, values);
+                                client.msgs.clear();
+                                client.subscription = subscription;
+                            }
+                        }
+                    }
+
+                    if let Some(client) = clients.get_mut(&token) {
+                        if !done {
+                            done = drive_connection(
+                                &mut client.conn,
+                                &mut client.wbuf,
+                                &mut client.snapshot,
+                                &mut client.msgs,
+                            );
+                        }
+                        if done {
+                            trace!(conn = ?client.conn, ?token, "removing client");
+                            clients.remove(&token);
+                            state.decrement_clients();
                         }
                     }
                 }
             }
         }
+
+        // Send everything aggregated over the last interval, once it has elapsed.
+        if let Some(ops) = aggregator.as_mut().and_then(|a| a.flush_if_due(&state.registry)) {
+            // We still need to flush with no clients, so that the next interval only covers what
+            // happened during it, but there's no reason to encode anything.
+            if !clients.is_empty() {
+                buffered_pmsgs.extend(encode_metric_operations(ops));
+                fan_out_metrics(
+                    &state,
+                    &mut buffered_pmsgs,
+                    buffer_limit,
+                    &mut clients,
+                    &mut clients_to_remove,
+                );
+            }
+        }
     }
 }
 
+#[derive(Debug)]
+struct Client {
+    conn: Connection,
+    // Remainder of a partially-written message, which must be written before anything else.
+    wbuf: Option<Bytes>,
+    // Metadata and current values sent when the client connects or subscribes, ahead of `msgs`.
+    // These don't count towards the buffer limit, so they're never dropped to make room for newer
+    // metrics.
+    snapshot: VecDeque<Bytes>,
+    msgs: VecDeque<Bytes>,
+    // Bytes read from the client that don't yet form a complete message.
+    rbuf: BytesMut,
+    subscription: Subscription,
+}
+
+type ClientMap = HashMap<Token, Client>;
+type MetadataMap = HashMap<KeyName, (MetricType, Option<Unit>, Option<SharedString>)>;
+
+/// Drains pending metrics and fans them out to each connected client.
+///
+/// Returns `false` if the sender has been dropped, and no more metrics can be received.
+#[allow(clippy::mutable_key_type, clippy::too_many_arguments)]
+fn forward_metrics(
+    rx: &Receiver<Event>,
+    state: &State,
+    metadata: &mut MetadataMap,
+    buffered_pmsgs: &mut VecDeque<(Key, Bytes)>,
+    buffer_limit: usize,
+    clients: &mut ClientMap,
+    clients_to_remove: &mut Vec<Token>,
+) -> bool {
+    // Read until we hit our buffer limit or there are no more messages.
+    let _mrxspan = trace_span!("metrics in");
+    loop {
+        if buffered_pmsgs.len() >= buffer_limit {
+            // We didn't drain ourselves here, so schedule a future wake so we
+            // continue to drain remaining metrics.
+            state.wake();
+            break;
+        }
+
+        let msg = match rx.try_recv() {
+            Ok(msg) => msg,
+            Err(e) if e.is_empty() => {
+                trace!("metric rx drained");
+                break;
+            }
+            // If our sender is dead, we can't do anything else, so just return.
+            Err(_) => return false,
+        };
+
+        match msg {
+            Event::Metadata(key, metric_type, unit, desc) => {
+                let entry = metadata.entry(key).or_insert_with(|| (metric_type, None, None));
+                let (_, uentry, dentry) = entry;
+                *uentry = unit;
+                *dentry = Some(desc);
+            }
+            Event::Metric(key, value) => {
+                match convert_metric_to_protobuf_encoded(key.clone(), value) {
+                    Ok(pmsg) => buffered_pmsgs.push_back((key, pmsg)),
+                    Err(e) => error!(error = ?e, "error encoding metric"),
+                }
+            }
+        }
+    }
+    drop(_mrxspan);
+
+    fan_out_metrics(state, buffered_pmsgs, buffer_limit, clients, clients_to_remove);
+    true
+}
+
+/// Drains every pending metric and fans them out to each connected client.
+///
+/// Unlike [`forward_metrics`], this doesn't stop at the buffer limit, so it must only be called
+/// while updates are held off, or it may never finish.
+///
+/// Returns `false` if the sender has been dropped, and no more metrics can be received.
+#[allow(clippy::mutable_key_type, clippy::too_many_arguments)]
+fn forward_all_metrics(
+    rx: &Receiver<Event>,
+    state: &State,
+    metadata: &mut MetadataMap,
+    buffered_pmsgs: &mut VecDeque<(Key, Bytes)>,
+    buffer_limit: usize,
+    clients: &mut ClientMap,
+    clients_to_remove: &mut Vec<Token>,
+) -> bool {
+    loop {
+        if !forward_metrics(
+            rx,
+            state,
+            metadata,
+            buffered_pmsgs,
+            buffer_limit,
+            clients,
+            clients_to_remove,
+        ) {
+            return false;
+        }
+
+        if rx.is_empty() {
+            return true;
+        }
+    }
+}
+
+/// Fans out buffered metrics to each connected client.
+fn fan_out_metrics(
+    state: &State,
+    buffered_pmsgs: &mut VecDeque<(Key, Bytes)>,
+    buffer_limit: usize,
+    clients: &mut ClientMap,
+    clients_to_remove: &mut Vec<Token>,
+) {
+    if buffered_pmsgs.is_empty() {
+        trace!("woken for metrics but no pmsgs buffered");
+        return;
+    }
+
+    // Now fan out each of these items to each client.
+    for (token, client) in clients.iter_mut() {
+        let Client { conn, wbuf, snapshot, msgs, subscription, .. } = client;
+
+        // Before we potentially do any draining, try and drive the connection to
+        // make sure space is freed up as much as possible.
+        let done = drive_connection(conn, wbuf, snapshot, msgs);
+        if done {
+            clients_to_remove.push(*token);
+            state.decrement_clients();
+            continue;
+        }
+
+        // With the encoded metrics, we push them into each client's internal
+        // list.  We try to write as many of those buffers as possible to the
+        // client before being told to back off.  If we encounter a partial write
+        // of a buffer, we store the remaining of that message in a special field
+        // so that we don't write incomplete metrics to the client.
+        //
+        // If there are more messages to hand off to a client than the client's
+        // internal list has room for, we remove as many as needed to do so.  This
+        // means we prioritize sending newer metrics if connections are backed up.
+        //
+        // Only the metrics matching the client's subscription count towards this, since the rest
+        // are never sent to the client at all.
+        let pending = buffered_pmsgs.iter().filter(|(key, _)| subscription.matches(key));
+        let available = if msgs.len() < buffer_limit { buffer_limit - msgs.len() } else { 0 };
+        let to_drain = pending.clone().count().saturating_sub(available).min(msgs.len());
+        let _ = msgs.drain(0..to_drain);
+        msgs.extend(pending.take(buffer_limit).map(|(_, pmsg)| pmsg.clone()));
+
+        let done = drive_connection(conn, wbuf, snapshot, msgs);
+        if done {
+            clients_to_remove.push(*token);
+            state.decrement_clients();
+        }
+    }
+
+    // We've pushed each metric into each client's internal list, so we can clear
+    // ourselves and continue on.
+    buffered_pmsgs.clear();
+
+    // Remove any clients that were done.
+    for token in clients_to_remove.drain(..) {
+        if let Some(Client { conn, .. }) = clients.get_mut(&token) {
+            trace!(?conn, ?token, "removing client");
+            clients.remove(&token);
+            state.decrement_clients();
+        }
+    }
+}
+
+/// Generates `set_counter`/`set_gauge` operations for the current value of every counter and gauge
+/// matching the given subscription.
+///
+/// When not aggregating, updates must be held off while this is called, with every operation
+/// queued before then forwarded to the existing clients.
+fn take_snapshot(
+    state: &State,
+    aggregator: Option<&Aggregator>,
+    subscription: &Subscription,
+) -> Vec<(Key, MetricOperation)> {
+    match aggregator {
+        Some(aggregator) => aggregator.snapshot(subscription),
+        None => state.snapshot(subscription),
+    }
+}
+
+/// Generates the messages sent to a client before any live operations: the metadata of every
+/// described metric, followed by the given snapshot of the current value of every counter and
+/// gauge.
+#[allow(clippy::mutable_key_type)]
+fn generate_initial_messages(
+    metadata: &MetadataMap,
+    subscription: &Subscription,
+    snapshot: Vec<(Key, MetricOperation)>,
+) -> VecDeque<Bytes> {
+    let mut msgs = generate_metadata_messages(metadata, subscription);
+    msgs.extend(encode_metric_operations(snapshot).map(|(_, pmsg)| pmsg));
+    msgs
+}
+
+/// Encodes metric operations, skipping any that fail to encode.
+fn encode_metric_operations(
+    ops: Vec<(Key, MetricOperation)>,
+) -> impl Iterator<Item = (Key, Bytes)> {
+    ops.into_iter().filter_map(|(key, op)| {
+        match convert_metric_to_protobuf_encoded(key.clone(), op) {
+            Ok(pmsg) => Some((key, pmsg)),
+            Err(e) => {
+                error!(error = ?e, "error encoding metric");
+                None
+            }
+        }
+    })
+}
+
 #[allow(clippy::mutable_key_type)]
 fn generate_metadata_messages(
-    metadata: &HashMap<KeyName, (MetricType, Option<Unit>, Option<SharedString>)>,
+    metadata: &MetadataMap,
+    subscription: &Subscription,
 ) -> VecDeque<Bytes> {
     let mut bufs = VecDeque::new();
     for (key_name, (metric_type, unit, desc)) in metadata.iter() {
+        if !subscription.matches_name(key_name.as_str()) {
+            continue;
+        }
+
         let msg =
             convert_metadata_to_protobuf_encoded(key_name, *metric_type, *unit, desc.as_ref())
                 .expect("failed to encode metadata buffer");
@@ -534,10 +1013,11 @@ fn generate_metadata_messages(
     bufs
 }
 
-#[tracing::instrument(skip(wbuf, msgs))]
+#[tracing::instrument(skip(wbuf, snapshot, msgs))]
 fn drive_connection(
-    conn: &mut TcpStream,
+    conn: &mut Connection,
     wbuf: &mut Option<Bytes>,
+    snapshot: &mut VecDeque<Bytes>,
     msgs: &mut VecDeque<Bytes>,
 ) -> bool {
     trace!(?conn, "driving client");
@@ -545,11 +1025,22 @@ fn drive_connection(
         let mut buf = match wbuf.take() {
             // Send the leftover buffer first, if we have one.
             Some(buf) => buf,
-            None => match msgs.pop_front() {
+            // The snapshot always goes out before any live operations.
+            None => match snapshot.pop_front().or_else(|| msgs.pop_front()) {
                 Some(msg) => msg,
                 None => {
                     trace!("client write queue drained");
-                    return false;
+
+                    // Make sure that anything buffered by the connection itself, such as TLS
+                    // records, is written out as well.
+                    return match conn.flush() {
+                        Ok(()) => false,
+                        Err(ref e) if would_block(e) => false,
+                        Err(e) => {
+                            error!(?conn, error = %e, "flush failed");
+                            true
+                        }
+                    };
                 }
             },
         };
@@ -570,8 +1061,15 @@ fn drive_connection(
                 return false;
             }
             Ok(_) => continue,
-            Err(ref e) if would_block(e) => return false,
-            Err(ref e) if interrupted(e) => return drive_connection(conn, wbuf, msgs),
+            // Hold on to the buffer so that we try it again, rather than dropping it.
+            Err(ref e) if would_block(e) => {
+                wbuf.replace(buf);
+                return false;
+            }
+            Err(ref e) if interrupted(e) => {
+                wbuf.replace(buf);
+                return drive_connection(conn, wbuf, snapshot, msgs);
+            }
             Err(e) => {
                 error!(?conn, error = %e, "write failed");
                 return true;
@@ -580,6 +1078,72 @@ fn drive_connection(
     }
 }
 
+/// Reads and decodes any messages sent by the client.
+///
+/// If the client sent a subscription, it is stored in `subscription`.  If more than one was sent,
+/// only the latest is kept.
+///
+/// Returns `true` if the client closed its connection or sent a message that could not be decoded,
+/// in which case the client should be removed.
+fn read_client_messages(
+    conn: &mut Connection,
+    rbuf: &mut BytesMut,
+    subscription: &mut Option<Subscription>,
+) -> bool {
+    let mut chunk = [0; 1024];
+    loop {
+        match conn.read(&mut chunk) {
+            // Zero read = client closed their connection, so remove 'em.
+            Ok(0) => {
+                trace!(?conn, "zero read, closing client");
+                return true;
+            }
+            Ok(n) => rbuf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if would_block(e) => break,
+            Err(ref e) if interrupted(e) => continue,
+            Err(e) => {
+                error!(?conn, error = %e, "read failed");
+                return true;
+            }
+        }
+    }
+
+    loop {
+        let len = match prost::decode_length_delimiter(&rbuf[..]) {
+            Ok(len) => len,
+            // A delimiter is at most 10 bytes, so with fewer than that, we may just not have all of
+            // it yet.
+            Err(_) if rbuf.len() < 10 => return false,
+            Err(e) => {
+                error!(?conn, error = %e, "invalid client message length");
+                return true;
+            }
+        };
+        if len > MAX_CLIENT_MESSAGE_LEN {
+            error!(?conn, len, "client message too large");
+            return true;
+        }
+
+        let needed = prost::length_delimiter_len(len) + len;
+        if rbuf.len() < needed {
+            return false;
+        }
+
+        let frame = rbuf.split_to(needed);
+        match proto::ClientMessage::decode_length_delimited(frame) {
+            Ok(proto::ClientMessage {
+                message: Some(proto::client_message::Message::Subscribe(subscribe)),
+            }) => *subscription = Some(subscribe.into()),
+            // Messages we don't know about, likely from newer clients, are ignored.
+            Ok(_) => {}
+            Err(e) => {
+                error!(?conn, error = %e, "failed to decode client message");
+                return true;
+            }
+        }
+    }
+}
+
 fn convert_metadata_to_protobuf_encoded(
     key_name: &KeyName,
     metric_type: MetricType,
@@ -616,6 +1180,9 @@ fn convert_metric_to_protobuf_encoded(
         MetricOperation::DecrementGauge(v) => proto::metric::Operation::DecrementGauge(v),
         MetricOperation::SetGauge(v) => proto::metric::Operation::SetGauge(v),
         MetricOperation::RecordHistogram(v) => proto::metric::Operation::RecordHistogram(v),
+        MetricOperation::RecordHistogramSketch(sketch) => {
+            proto::metric::Operation::RecordHistogramSketch(sketch.into_proto())
+        }
     };
 
     let now: prost_types::Timestamp = SystemTime::now().into();
@@ -640,3 +1207,179 @@ fn would_block(err: &io::Error) -> bool {
 fn interrupted(err: &io::Error) -> bool {
     err.kind() == io::ErrorKind::Interrupted
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{self, Read};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+    #[cfg(feature = "tls")]
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use bytes::{Buf, BytesMut};
+    use metrics::{Key, Label, Level, Metadata, Recorder, Unit};
+    use prost::Message;
+
+    use super::{proto, TcpBuilder};
+
+    static METADATA: Metadata<'static> =
+        Metadata::new(module_path!(), Level::INFO, Some(module_path!()));
+
+    fn free_address() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).unwrap()
+    }
+
+    /// Returns the name and operation of every metric event.
+    fn operations(events: Vec<proto::Event>) -> Vec<(String, Option<proto::metric::Operation>)> {
+        let metrics = events.into_iter().filter_map(|event| match event.event {
+            Some(proto::event::Event::Metric(metric)) => Some((metric.name, metric.operation)),
+            _ => None,
+        });
+        metrics.collect()
+    }
+
+    /// Reads events from the stream until `done` returns `true`, or the stream ends or times out.
+    fn read_events(
+        stream: &mut impl Read,
+        done: impl Fn(&[proto::Event]) -> bool,
+    ) -> Vec<proto::Event> {
+        let mut events = Vec::new();
+        let mut buf = BytesMut::new();
+        let mut chunk = [0; 64 * 1024];
+        while !done(&events) {
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+
+            while let Ok(len) = prost::decode_length_delimiter(&buf[..]) {
+                let needed = prost::length_delimiter_len(len) + len;
+                if buf.len() < needed {
+                    break;
+                }
+
+                events.push(proto::Event::decode_length_delimited(&buf[..needed]).unwrap());
+                buf.advance(needed);
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn snapshot_is_exempt_from_buffer_limit() {
+        // A snapshot large enough that it can't all be written to the socket before the client
+        // starts reading, so that it's still queued when live operations arrive.
+        const COUNTERS: u64 = 32_000;
+        let padding = "x".repeat(1024);
+
+        let addr = free_address();
+        let recorder = TcpBuilder::new().listen_address(addr).buffer_size(Some(4)).build().unwrap();
+
+        recorder.describe_counter("counter".into(), Some(Unit::Count), "A counter.".into());
+        for i in 0..COUNTERS {
+            let labels =
+                vec![Label::new("index", i.to_string()), Label::new("padding", padding.clone())];
+            let key = Key::from_parts("counter", labels);
+            recorder.register_counter(&key, &METADATA).absolute(i);
+        }
+
+        // Once connected, keep updating a metric without reading, which queues live operations
+        // behind the snapshot.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let live = recorder.register_counter(&Key::from_name("live"), &METADATA);
+        for _ in 0..100 {
+            live.increment(1);
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The metadata and the snapshot come first, ahead of any live operations.
+        let events = read_events(&mut stream, |events| events.len() as u64 > COUNTERS);
+        let mut metadata = Vec::new();
+        let mut values = HashMap::new();
+        for event in events {
+            match event.event {
+                Some(proto::event::Event::Metadata(m)) => metadata.push(m.name),
+                Some(proto::event::Event::Metric(m)) if m.name == "counter" => {
+                    if let Some(proto::metric::Operation::SetCounter(value)) = m.operation {
+                        values.insert(m.labels["index"].clone(), value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(metadata, vec!["counter".to_string()]);
+        assert_eq!(values.len() as u64, COUNTERS);
+        assert!(values.iter().all(|(index, value)| index.parse::<u64>().unwrap() == *value));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("metrics-exporter-tcp-round-trip-{}.sock", std::process::id()));
+        let recorder =
+            TcpBuilder::new().listen_path(&path).unix_socket_mode(Some(0o600)).build().unwrap();
+        recorder.register_counter(&Key::from_name("requests"), &METADATA).increment(3);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let events = read_events(&mut stream, |events| !events.is_empty());
+        assert_eq!(
+            operations(events),
+            vec![("requests".to_string(), Some(proto::metric::Operation::SetCounter(3)))]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_round_trip() {
+        use std::convert::TryFrom as _;
+
+        use rustls::pki_types::pem::PemObject as _;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+
+        // Self-signed certificate for `localhost`, valid until 2126.
+        let cert = include_bytes!("../tests/certs/localhost.crt");
+        let cert = CertificateDer::from_pem_slice(cert).unwrap();
+        let key = PrivateKeyDer::from_pem_slice(include_bytes!("../tests/certs/localhost.key"));
+        let server = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key.unwrap())
+            .unwrap();
+
+        let addr = free_address();
+        let recorder = TcpBuilder::new()
+            .listen_address(addr)
+            .tls_config(Some(Arc::new(server)))
+            .build()
+            .unwrap();
+        recorder.register_counter(&Key::from_name("requests"), &METADATA).increment(3);
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client =
+            rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let conn = rustls::ClientConnection::new(Arc::new(client), server_name).unwrap();
+
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut stream = rustls::StreamOwned::new(conn, stream);
+        let events = read_events(&mut stream, |events| !events.is_empty());
+        assert_eq!(
+            operations(events),
+            vec![("requests".to_string(), Some(proto::metric::Operation::SetCounter(3)))]
+        );
+    }
+}
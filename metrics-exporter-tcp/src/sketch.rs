@@ -0,0 +1,117 @@
+//! Histogram sketches, which summarize the samples recorded to a histogram over an interval.
+use std::collections::BTreeMap;
+
+use crate::proto;
+
+// Relative accuracy of the sketch bins.  At 1%, a sketch covering samples from a nanosecond to an
+// hour needs fewer than 1,500 bins.
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+// Samples smaller than this in magnitude are counted as zero.
+const MIN_VALUE: f64 = 1.0e-9;
+
+/// A sketch of histogram samples, with logarithmically-sized bins.
+///
+/// See `HistogramSketch` in `proto/event.proto` for how samples are binned.
+#[derive(Debug)]
+pub(crate) struct Sketch {
+    ln_gamma: f64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    positive_bins: BTreeMap<i32, u64>,
+    negative_bins: BTreeMap<i32, u64>,
+    zero_count: u64,
+}
+
+impl Sketch {
+    pub fn new() -> Sketch {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        Sketch {
+            ln_gamma: gamma.ln(),
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            positive_bins: BTreeMap::new(),
+            negative_bins: BTreeMap::new(),
+            zero_count: 0,
+        }
+    }
+
+    /// Adds a sample to the sketch.
+    ///
+    /// Samples that aren't finite are ignored.
+    pub fn add(&mut self, value: f64) {
+        if !value.is_finite() {
+            return;
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+
+        if value.abs() < MIN_VALUE {
+            self.zero_count += 1;
+        } else {
+            let bin = (value.abs().ln() / self.ln_gamma).ceil() as i32;
+            let bins = if value > 0.0 { &mut self.positive_bins } else { &mut self.negative_bins };
+            *bins.entry(bin).or_default() += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn into_proto(self) -> proto::HistogramSketch {
+        proto::HistogramSketch {
+            count: self.count,
+            sum: self.sum,
+            min: self.min,
+            max: self.max,
+            relative_accuracy: RELATIVE_ACCURACY,
+            positive_bins: self.positive_bins,
+            negative_bins: self.negative_bins,
+            zero_count: self.zero_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sketch, RELATIVE_ACCURACY};
+
+    #[test]
+    fn bins_within_relative_accuracy() {
+        let mut sketch = Sketch::new();
+        let samples = [-250.0, -0.5, 0.0, 1.0e-12, 0.001, 1.0, 1.5, 1.5, 42.0, 86_400.0, f64::NAN];
+        for sample in samples {
+            sketch.add(sample);
+        }
+        assert!(!sketch.is_empty());
+
+        let sketch = sketch.into_proto();
+        assert_eq!(sketch.count, 10);
+        assert_eq!(sketch.min, -250.0);
+        assert_eq!(sketch.max, 86_400.0);
+        assert_eq!(sketch.zero_count, 2);
+        assert_eq!(sketch.negative_bins.values().sum::<u64>(), 2);
+        assert_eq!(sketch.positive_bins.values().sum::<u64>(), 6);
+        assert_eq!(sketch.positive_bins.len(), 5);
+
+        // Every sample is within the relative accuracy of its bin's representative value.
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        let representative = |bin: i32| 2.0 * gamma.powi(bin) / (gamma + 1.0);
+        for sample in [0.001, 1.0, 1.5, 42.0, 86_400.0] {
+            let closest = sketch
+                .positive_bins
+                .keys()
+                .map(|bin| (representative(*bin) - sample).abs() / sample)
+                .fold(f64::INFINITY, f64::min);
+            assert!(closest <= RELATIVE_ACCURACY, "{} not within accuracy: {}", sample, closest);
+        }
+    }
+}
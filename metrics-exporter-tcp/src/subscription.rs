@@ -0,0 +1,201 @@
+//! Client subscriptions, which limit the events sent to a client.
+use metrics::Key;
+
+use crate::proto;
+
+/// A set of filters, of which at least one must match for an event to be sent to a client.
+///
+/// A subscription without any filters matches every event.
+#[derive(Debug, Default)]
+pub(crate) struct Subscription {
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug)]
+struct Filter {
+    name: NameMatcher,
+    labels: Vec<LabelMatcher>,
+}
+
+#[derive(Debug)]
+enum NameMatcher {
+    Any,
+    Prefix(String),
+    Glob(String),
+}
+
+#[derive(Debug)]
+struct LabelMatcher {
+    key: String,
+    value_glob: Option<String>,
+}
+
+impl Subscription {
+    /// Returns `true` if metadata for the given metric name should be sent.
+    ///
+    /// Metadata is not specific to any set of labels, so only the name matchers are considered.
+    pub fn matches_name(&self, name: &str) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| filter.name.matches(name))
+    }
+
+    /// Returns `true` if events for the given metric should be sent.
+    pub fn matches(&self, key: &Key) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| filter.matches(key))
+    }
+}
+
+impl From<proto::Subscribe> for Subscription {
+    fn from(subscribe: proto::Subscribe) -> Self {
+        let filters = subscribe
+            .filters
+            .into_iter()
+            .map(|filter| Filter {
+                name: match filter.name {
+                    None => NameMatcher::Any,
+                    Some(proto::filter::Name::NamePrefix(prefix)) => NameMatcher::Prefix(prefix),
+                    Some(proto::filter::Name::NameGlob(glob)) => NameMatcher::Glob(glob),
+                },
+                labels: filter
+                    .labels
+                    .into_iter()
+                    .map(|label| LabelMatcher {
+                        key: label.key,
+                        value_glob: Some(label.value_glob).filter(|glob| !glob.is_empty()),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Subscription { filters }
+    }
+}
+
+impl Filter {
+    fn matches(&self, key: &Key) -> bool {
+        self.name.matches(key.name())
+            && self.labels.iter().all(|matcher| {
+                key.labels().any(|label| {
+                    label.key() == matcher.key
+                        && matcher
+                            .value_glob
+                            .as_ref()
+                            .map_or(true, |glob| glob_matches(glob, label.value()))
+                })
+            })
+    }
+}
+
+impl NameMatcher {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NameMatcher::Any => true,
+            NameMatcher::Prefix(prefix) => name.starts_with(prefix.as_str()),
+            NameMatcher::Glob(glob) => glob_matches(glob, name),
+        }
+    }
+}
+
+/// Returns `true` if the value matches the glob pattern.
+///
+/// `*` matches any sequence of characters, including an empty one, and `?` matches any single
+/// character.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    let (mut p, mut v) = (0, 0);
+    // Position of the last `*` seen in the pattern, and the position in the value it was matched
+    // at, so that we can backtrack and have it consume one more character when the rest of the
+    // pattern fails to match.
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_v)) => {
+                    backtrack = Some((star_p, star_v + 1));
+                    p = star_p + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::{Key, Label};
+
+    use super::{glob_matches, Subscription};
+    use crate::proto;
+
+    fn filter(name: Option<proto::filter::Name>, labels: &[(&str, &str)]) -> proto::Filter {
+        proto::Filter {
+            name,
+            labels: labels
+                .iter()
+                .map(|(key, value_glob)| proto::LabelMatcher {
+                    key: key.to_string(),
+                    value_glob: value_glob.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("", ""));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("http_*", "http_requests"));
+        assert!(glob_matches("*_total", "requests_total"));
+        assert!(glob_matches("a*b*c", "aXXbYYbc"));
+        assert!(glob_matches("h?tp", "http") && !glob_matches("h?tp", "htp"));
+        assert!(!glob_matches("http_*", "grpc_requests"));
+        assert!(!glob_matches("a*b", "aXXbc"));
+    }
+
+    #[test]
+    fn subscriptions() {
+        let everything = Subscription::from(proto::Subscribe { filters: vec![] });
+        assert!(everything.matches(&Key::from_name("anything")));
+
+        let subscription = Subscription::from(proto::Subscribe {
+            filters: vec![
+                filter(Some(proto::filter::Name::NamePrefix("http_".to_string())), &[]),
+                filter(
+                    Some(proto::filter::Name::NameGlob("db_*_seconds".to_string())),
+                    &[("table", "user*"), ("primary", "")],
+                ),
+                filter(None, &[("debug", "true")]),
+            ],
+        });
+
+        let key = |name: &'static str, labels: &[(&'static str, &'static str)]| {
+            Key::from_parts(
+                name,
+                labels.iter().map(|(k, v)| Label::new(*k, *v)).collect::<Vec<_>>(),
+            )
+        };
+        assert!(subscription.matches(&key("http_requests", &[("method", "GET")])));
+        assert!(subscription
+            .matches(&key("db_query_seconds", &[("table", "users"), ("primary", "false")])));
+        assert!(!subscription.matches(&key("db_query_seconds", &[("table", "users")])));
+        assert!(!subscription.matches(&key("db_query_seconds", &[("table", "orders")])));
+        assert!(subscription.matches(&key("cache_hits", &[("debug", "true")])));
+        assert!(!subscription.matches(&key("cache_hits", &[])));
+
+        assert!(subscription.matches_name("http_requests"));
+        assert!(subscription.matches_name("db_query_seconds"));
+        assert!(subscription.matches_name("cache_hits"));
+    }
+}
@@ -0,0 +1,397 @@
+//! Listeners and connections for the transports that clients can connect over.
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use mio::event::Source;
+use mio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use mio::net::{UnixListener, UnixStream};
+use mio::{Interest, Registry, Token};
+#[cfg(feature = "tls")]
+use rustls::{ServerConfig, ServerConnection};
+
+/// A listener accepting client connections.
+#[derive(Debug)]
+pub(crate) struct Listener {
+    inner: ListenerKind,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<ServerConfig>>,
+}
+
+#[derive(Debug)]
+enum ListenerKind {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds a TCP listener to the given address.
+    pub fn bind_tcp(addr: SocketAddr) -> io::Result<Listener> {
+        TcpListener::bind(addr).map(|listener| Listener::new(ListenerKind::Tcp(listener)))
+    }
+
+    /// Binds a Unix domain socket listener to the given path.
+    ///
+    /// An existing socket at the path is removed first, such as one left behind by a previous
+    /// process, but any other kind of file is left in place and an error is returned instead.  If
+    /// `mode` is given, the permissions of the socket file are then set to it.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, mode: Option<u32>) -> io::Result<Listener> {
+        use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                let msg = format!("{} already exists and is not a socket", path.display());
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, msg));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(Listener::new(ListenerKind::Unix(listener)))
+    }
+
+    fn new(inner: ListenerKind) -> Listener {
+        Listener {
+            inner,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+        }
+    }
+
+    /// Requires clients to connect over TLS, using the given configuration.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, config: Option<Arc<ServerConfig>>) -> Listener {
+        self.tls_config = config;
+        self
+    }
+
+    /// Accepts a new connection.
+    pub fn accept(&self) -> io::Result<Connection> {
+        let stream = match &self.inner {
+            ListenerKind::Tcp(listener) => listener.accept().map(|(conn, _)| Stream::Tcp(conn))?,
+            #[cfg(unix)]
+            ListenerKind::Unix(listener) => {
+                listener.accept().map(|(conn, _)| Stream::Unix(conn))?
+            }
+        };
+
+        #[cfg(feature = "tls")]
+        if let Some(config) = &self.tls_config {
+            let tls = ServerConnection::new(Arc::clone(config))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            return Ok(Connection::Tls(Box::new(TlsStream { tls, stream })));
+        }
+
+        Ok(Connection::Plain(stream))
+    }
+}
+
+impl Source for Listener {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match &mut self.inner {
+            ListenerKind::Tcp(listener) => listener.register(registry, token, interests),
+            #[cfg(unix)]
+            ListenerKind::Unix(listener) => listener.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match &mut self.inner {
+            ListenerKind::Tcp(listener) => listener.reregister(registry, token, interests),
+            #[cfg(unix)]
+            ListenerKind::Unix(listener) => listener.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match &mut self.inner {
+            ListenerKind::Tcp(listener) => listener.deregister(registry),
+            #[cfg(unix)]
+            ListenerKind::Unix(listener) => listener.deregister(registry),
+        }
+    }
+}
+
+/// A client connection.
+#[derive(Debug)]
+pub(crate) enum Connection {
+    Plain(Stream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+}
+
+impl Connection {
+    fn stream(&mut self) -> &mut Stream {
+        match self {
+            Connection::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls) => &mut tls.stream,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+impl Source for Connection {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream().register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.stream().reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.stream().deregister(registry)
+    }
+}
+
+/// An unencrypted stream.
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.register(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.reregister(registry, token, interests),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.deregister(registry),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.deregister(registry),
+        }
+    }
+}
+
+/// A TLS stream over a non-blocking socket.
+///
+/// Reads and writes behave like those of the underlying socket, returning `WouldBlock` errors
+/// when no data is available, or when the socket can't take any more data, respectively.
+#[cfg(feature = "tls")]
+pub(crate) struct TlsStream {
+    tls: ServerConnection,
+    stream: Stream,
+}
+
+#[cfg(feature = "tls")]
+impl TlsStream {
+    /// Writes out pending TLS records until there are none left, or the socket can't take any more.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.tls.wants_write() {
+            if self.tls.write_tls(&mut self.stream)? == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.tls.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            // We've read all of the plaintext we have, so read more records from the socket.
+            if self.tls.read_tls(&mut self.stream)? == 0 {
+                return Ok(0);
+            }
+            if let Err(e) = self.tls.process_new_packets() {
+                // Try to let the client know why we're closing the connection.
+                let _ = self.write_tls();
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+
+            // Handshake messages may need to be sent in response.  If the socket can't take them
+            // yet, they'll be written when it's next writable.
+            match self.write_tls() {
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Only take on more data once everything already encrypted has been written out, so that
+        // slow clients are subject to the same backpressure as they are without TLS.
+        self.write_tls()?;
+
+        // Plaintext is buffered until the handshake completes, up to a limit.
+        let n = self.tls.writer().write(buf)?;
+        if n == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        match self.write_tls() {
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(n),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_tls()
+    }
+}
+
+#[cfg(feature = "tls")]
+impl std::fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsStream")
+            .field("stream", &self.stream)
+            .field("is_handshaking", &self.tls.is_handshaking())
+            .finish()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io;
+    use std::os::unix::fs::PermissionsExt as _;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    use super::Listener;
+
+    #[test]
+    fn bind_unix() {
+        let path = std::env::temp_dir()
+            .join(format!("metrics-exporter-tcp-bind-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Anything other than a socket is left alone.
+        std::fs::write(&path, "data").unwrap();
+        let err = Listener::bind_unix(&path, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_file(&path).unwrap();
+
+        // A socket left behind by a previous listener is replaced.
+        drop(UnixListener::bind(&path).unwrap());
+        let listener = Listener::bind_unix(&path, Some(0o600)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let _client = UnixStream::connect(&path).unwrap();
+        assert!(listener.accept().is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
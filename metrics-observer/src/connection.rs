@@ -0,0 +1,255 @@
+use std::convert::TryFrom as _;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject as _;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+/// A connection to the exporter.
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Where to connect to the exporter, and how.
+pub struct Target {
+    address: Address,
+    tls: Option<Tls>,
+}
+
+enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+struct Tls {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+/// Options for connecting over TLS.
+#[derive(Default)]
+pub struct TlsOptions {
+    /// Path to the PEM-encoded CA certificates to verify the exporter's certificate against.
+    pub ca: Option<String>,
+    /// Path to the PEM-encoded certificate chain to authenticate to the exporter with.
+    pub cert: Option<String>,
+    /// Path to the PEM-encoded private key for `cert`.
+    pub key: Option<String>,
+    /// Name to verify the exporter's certificate against, instead of the host being connected to.
+    pub server_name: Option<String>,
+}
+
+/// An error that occurred while connecting.
+pub enum ConnectError {
+    /// The target address could not be resolved, which retrying is unlikely to fix.
+    Resolve,
+    /// The connection could not be established.
+    Connect,
+}
+
+impl Target {
+    /// Creates a new target.
+    ///
+    /// Addresses in the form of `unix://<path>` refer to a Unix domain socket, and anything else to
+    /// a TCP host and port.  If `tls` is given, connections are made over TLS, which requires `ca`
+    /// to be set.
+    pub fn new(address: &str, tls: Option<TlsOptions>) -> Result<Target, String> {
+        let address = match address.strip_prefix("unix://") {
+            #[cfg(unix)]
+            Some(path) => Address::Unix(PathBuf::from(path)),
+            #[cfg(not(unix))]
+            Some(_) => return Err("Unix domain sockets are not supported on this platform".into()),
+            None => Address::Tcp(address.to_string()),
+        };
+
+        let tls = match tls {
+            None => None,
+            Some(options) => Some(Tls::new(&address, options)?),
+        };
+
+        Ok(Target { address, tls })
+    }
+
+    /// Connects to the target.
+    ///
+    /// When connecting over TLS, the handshake is completed as part of the first read or write.
+    pub fn connect(&self) -> Result<Box<dyn Stream>, ConnectError> {
+        let stream: Box<dyn Stream> = match &self.address {
+            Address::Tcp(addr) => {
+                let mut addrs = addr.to_socket_addrs().map_err(|_| ConnectError::Resolve)?;
+                // Some of the resolved addresses may be unreachable (e.g. IPv6).
+                // Pick the first one that works.
+                let stream = addrs
+                    .find_map(|addr| TcpStream::connect_timeout(&addr, Duration::from_secs(3)).ok())
+                    .ok_or(ConnectError::Connect)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                Box::new(UnixStream::connect(path).map_err(|_| ConnectError::Connect)?)
+            }
+        };
+
+        match &self.tls {
+            None => Ok(stream),
+            Some(tls) => {
+                let conn = ClientConnection::new(Arc::clone(&tls.config), tls.server_name.clone())
+                    .map_err(|_| ConnectError::Connect)?;
+                Ok(Box::new(StreamOwned::new(conn, stream)))
+            }
+        }
+    }
+}
+
+impl Tls {
+    fn new(address: &Address, options: TlsOptions) -> Result<Tls, String> {
+        let ca = options.ca.ok_or("--tls-ca is required when connecting over TLS")?;
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&ca)? {
+            roots.add(cert).map_err(|e| format!("invalid CA certificate in {}: {}", ca, e))?;
+        }
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match (options.cert, options.key) {
+            (None, None) => builder.with_no_client_auth(),
+            (Some(cert), Some(key)) => {
+                let key = PrivateKeyDer::from_pem_file(&key)
+                    .map_err(|e| format!("failed to load private key from {}: {}", key, e))?;
+                builder
+                    .with_client_auth_cert(load_certs(&cert)?, key)
+                    .map_err(|e| format!("invalid client certificate: {}", e))?
+            }
+            _ => return Err("--tls-cert and --tls-key must be given together".into()),
+        };
+
+        // Default to verifying the certificate against the host we're connecting to.
+        let server_name = match (options.server_name, address) {
+            (Some(name), _) => name,
+            (None, Address::Tcp(addr)) => host(addr).to_string(),
+            #[cfg(unix)]
+            (None, Address::Unix(_)) => {
+                return Err("--tls-server-name is required when connecting to a Unix socket".into())
+            }
+        };
+        let server_name = ServerName::try_from(server_name)
+            .map_err(|e| format!("invalid TLS server name: {}", e))?;
+
+        Ok(Tls { config: Arc::new(config), server_name })
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to load certificates from {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+/// Gets the host portion of a `<host>:<port>` address, without any IPv6 brackets.
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    #[cfg(unix)]
+    use std::os::unix::net::UnixListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use rustls::pki_types::pem::PemObject as _;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+    use super::{host, Target, TlsOptions};
+
+    // Self-signed certificate for `localhost`, valid until 2126.
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/localhost.crt");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs/localhost.key");
+
+    fn tls_options() -> TlsOptions {
+        TlsOptions { ca: Some(CERT.to_string()), ..Default::default() }
+    }
+
+    fn read_all(target: &Target) -> Vec<u8> {
+        let mut stream = target.connect().ok().expect("failed to connect");
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(host("localhost:5000"), "localhost");
+        assert_eq!(host("127.0.0.1:5000"), "127.0.0.1");
+        assert_eq!(host("[::1]:5000"), "::1");
+    }
+
+    #[test]
+    fn tls_options_are_validated() {
+        assert!(Target::new("localhost:5000", Some(TlsOptions::default())).is_err());
+
+        let options = TlsOptions { cert: Some(CERT.to_string()), ..tls_options() };
+        assert!(Target::new("localhost:5000", Some(options)).is_err());
+
+        #[cfg(unix)]
+        assert!(Target::new("unix:///tmp/metrics.sock", Some(tls_options())).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        let path = std::env::temp_dir()
+            .join(format!("metrics-observer-connection-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        let target = Target::new(&format!("unix://{}", path.display()), None).unwrap();
+        assert_eq!(read_all(&target), b"hello");
+
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tls() {
+        let certs = CertificateDer::pem_file_iter(CERT).unwrap().collect::<Result<_, _>>().unwrap();
+        let key = PrivateKeyDer::from_pem_file(KEY).unwrap();
+        let config = ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key);
+        let config = Arc::new(config.unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = StreamOwned::new(ServerConnection::new(config).unwrap(), stream);
+            stream.write_all(b"hello").unwrap();
+            stream.conn.send_close_notify();
+            stream.flush().unwrap();
+        });
+
+        // The certificate is verified against the host being connected to.
+        let target = Target::new(&format!("localhost:{}", port), Some(tls_options())).unwrap();
+        assert_eq!(read_all(&target), b"hello");
+
+        server.join().unwrap();
+    }
+}
@@ -19,6 +19,9 @@ use ratatui::{
     Terminal,
 };
 
+mod connection;
+use self::connection::{Target, TlsOptions};
+
 mod input;
 use self::input::InputEvents;
 
@@ -30,16 +33,66 @@ use self::metrics_inner::{ClientState, MetricData};
 mod selector;
 use self::selector::Selector;
 
+mod sketch;
+
+const USAGE: &str =
+    "usage: metrics-observer [--tls-ca <path>] [--tls-cert <path> --tls-key <path>] \
+                     [--tls-server-name <name>] [address] [filter...]";
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let (target, filters) = match parse_args(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("error: {}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let terminal = init_terminal()?;
-    let result = run(terminal);
+    let result = run(terminal, target, &filters);
     restore_terminal()?;
     result
 }
 
-fn run(mut terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn Error>> {
-    let address = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:5000".to_owned());
-    let client = metrics_inner::Client::new(address);
+/// Parses the command-line arguments into the target to connect to and the filters to apply.
+///
+/// Addresses in the form of `unix://<path>` refer to a Unix domain socket.  Giving any of the
+/// `--tls-*` options connects over TLS.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Target, Vec<String>), String> {
+    let mut tls = None::<TlsOptions>;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let option: fn(&mut TlsOptions) -> &mut Option<String> = match arg.as_str() {
+            "--tls-ca" => |tls: &mut TlsOptions| &mut tls.ca,
+            "--tls-cert" => |tls: &mut TlsOptions| &mut tls.cert,
+            "--tls-key" => |tls: &mut TlsOptions| &mut tls.key,
+            "--tls-server-name" => |tls: &mut TlsOptions| &mut tls.server_name,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        *option(tls.get_or_insert_with(TlsOptions::default)) = Some(value);
+    }
+
+    let mut positional = positional.into_iter();
+    let address = positional.next().unwrap_or_else(|| "127.0.0.1:5000".to_owned());
+    let target = Target::new(&address, tls)?;
+    Ok((target, positional.collect()))
+}
+
+fn run(
+    mut terminal: Terminal<CrosstermBackend<Stdout>>,
+    target: Target,
+    filters: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let client = metrics_inner::Client::new(target, filters);
     let mut selector = Selector::new();
     loop {
         terminal.draw(|f| {
@@ -112,24 +165,18 @@ fn run(mut terminal: Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn E
                     MetricData::Gauge(value) => {
                         format!("current: {}", f64_to_displayable(value, unit))
                     }
-                    MetricData::Histogram(value) => {
-                        let min = value.min();
-                        let max = value.max();
-                        let p50 = value.quantile(0.5).expect("sketch shouldn't exist if no values");
-                        let p99 =
-                            value.quantile(0.99).expect("sketch shouldn't exist if no values");
-                        let p999 =
-                            value.quantile(0.999).expect("sketch shouldn't exist if no values");
-
-                        format!(
-                            "min: {} p50: {} p99: {} p999: {} max: {}",
-                            f64_to_displayable(min, unit),
-                            f64_to_displayable(p50, unit),
-                            f64_to_displayable(p99, unit),
-                            f64_to_displayable(p999, unit),
-                            f64_to_displayable(max, unit),
-                        )
-                    }
+                    MetricData::Histogram(value) => histogram_to_displayable(
+                        value.min(),
+                        value.max(),
+                        |q| value.quantile(q),
+                        unit,
+                    ),
+                    MetricData::HistogramSketch(value) => histogram_to_displayable(
+                        value.min(),
+                        value.max(),
+                        |q| value.quantile(q),
+                        unit,
+                    ),
                 };
 
                 let name_length = display_name.chars().count();
@@ -196,6 +243,24 @@ fn u64_to_displayable(value: u64, unit: Option<Unit>) -> String {
     format!("{}{}", value, label)
 }
 
+fn histogram_to_displayable<F>(min: f64, max: f64, quantile: F, unit: Option<Unit>) -> String
+where
+    F: Fn(f64) -> Option<f64>,
+{
+    let p50 = quantile(0.5).expect("sketch shouldn't exist if no values");
+    let p99 = quantile(0.99).expect("sketch shouldn't exist if no values");
+    let p999 = quantile(0.999).expect("sketch shouldn't exist if no values");
+
+    format!(
+        "min: {} p50: {} p99: {} p999: {} max: {}",
+        f64_to_displayable(min, unit),
+        f64_to_displayable(p50, unit),
+        f64_to_displayable(p99, unit),
+        f64_to_displayable(p999, unit),
+        f64_to_displayable(max, unit),
+    )
+}
+
 fn f64_to_displayable(value: f64, unit: Option<Unit>) -> String {
     let unit = match unit {
         None => return value.to_string(),
@@ -1,6 +1,4 @@
-use std::io::Read;
-use std::net::TcpStream;
-use std::net::ToSocketAddrs;
+use std::io::{self, Read, Write};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
@@ -15,7 +13,10 @@ use prost::Message;
 use metrics::{Key, Label, Unit};
 use metrics_util::{storage::Summary, CompositeKey, MetricKind};
 
-mod proto {
+use crate::connection::{ConnectError, Stream, Target};
+use crate::sketch::Sketch;
+
+pub(crate) mod proto {
     include!(concat!(env!("OUT_DIR"), "/event.proto.rs"));
 }
 
@@ -35,6 +36,7 @@ pub enum MetricData {
     Counter(u64),
     Gauge(f64),
     Histogram(Summary),
+    HistogramSketch(Sketch),
 }
 
 pub struct Client {
@@ -44,16 +46,25 @@ pub struct Client {
 }
 
 impl Client {
-    pub fn new(addr: String) -> Client {
+    /// Creates a new client, connecting to the given target.
+    ///
+    /// If any filters are given, only metrics matching at least one of them are requested from the
+    /// exporter.  See [`parse_filter`] for their syntax.
+    pub fn new(target: Target, filters: &[String]) -> Client {
         let state = Arc::new(Mutex::new(ClientState::Disconnected(None)));
         let metrics = Arc::new(RwLock::new(BTreeMap::new()));
         let metadata = Arc::new(RwLock::new(HashMap::new()));
+        let subscription = (!filters.is_empty()).then(|| proto::ClientMessage {
+            message: Some(proto::client_message::Message::Subscribe(proto::Subscribe {
+                filters: filters.iter().map(|filter| parse_filter(filter)).collect(),
+            })),
+        });
         {
             let state = state.clone();
             let metrics = metrics.clone();
             let metadata = metadata.clone();
             thread::spawn(move || {
-                let mut runner = Runner::new(addr, state, metrics, metadata);
+                let mut runner = Runner::new(target, subscription, state, metrics, metadata);
                 runner.run();
             })
         };
@@ -87,12 +98,13 @@ impl Client {
 enum RunnerState {
     Disconnected,
     ErrorBackoff(&'static str, Duration),
-    Connected(TcpStream),
+    Connected(Box<dyn Stream>),
 }
 
 struct Runner {
     state: RunnerState,
-    addr: String,
+    target: Target,
+    subscription: Option<proto::ClientMessage>,
     client_state: Arc<Mutex<ClientState>>,
     metrics: Arc<RwLock<BTreeMap<CompositeKey, MetricData>>>,
     metadata: Arc<RwLock<HashMap<MetadataKey, MetadataValue>>>,
@@ -100,12 +112,20 @@ struct Runner {
 
 impl Runner {
     pub fn new(
-        addr: String,
+        target: Target,
+        subscription: Option<proto::ClientMessage>,
         state: Arc<Mutex<ClientState>>,
         metrics: Arc<RwLock<BTreeMap<CompositeKey, MetricData>>>,
         metadata: Arc<RwLock<HashMap<MetadataKey, MetadataValue>>>,
     ) -> Runner {
-        Runner { state: RunnerState::Disconnected, addr, client_state: state, metrics, metadata }
+        Runner {
+            state: RunnerState::Disconnected,
+            target,
+            subscription,
+            client_state: state,
+            metrics,
+            metadata,
+        }
     }
 
     pub fn run(&mut self) {
@@ -117,22 +137,16 @@ impl Runner {
                         let mut state = self.client_state.lock().unwrap();
                         *state = ClientState::Disconnected(None);
                     }
-                    // Resolve the target address.
-                    let Ok(mut addrs) = self.addr.to_socket_addrs() else {
-                        let mut state = self.client_state.lock().unwrap();
-                        *state = ClientState::Disconnected(Some(
-                            "failed to resolve specified host".to_string(),
-                        ));
-                        break;
-                    };
-                    // Some of the resolved addresses may be unreachable (e.g. IPv6).
-                    // Pick the first one that works.
-                    let maybe_stream = addrs.find_map(|addr| {
-                        TcpStream::connect_timeout(&addr, Duration::from_secs(3)).ok()
-                    });
-                    match maybe_stream {
-                        Some(stream) => RunnerState::Connected(stream),
-                        None => RunnerState::ErrorBackoff(
+                    match self.target.connect() {
+                        Ok(stream) => RunnerState::Connected(stream),
+                        Err(ConnectError::Resolve) => {
+                            let mut state = self.client_state.lock().unwrap();
+                            *state = ClientState::Disconnected(Some(
+                                "failed to resolve specified host".to_string(),
+                            ));
+                            break;
+                        }
+                        Err(ConnectError::Connect) => RunnerState::ErrorBackoff(
                             "error while connecting",
                             Duration::from_secs(3),
                         ),
@@ -156,6 +170,18 @@ impl Runner {
                         *state = ClientState::Connected;
                     }
 
+                    // Ask for only the metrics we're interested in, if we've been given filters.
+                    if let Some(subscription) = &self.subscription {
+                        if stream.write_all(&subscription.encode_length_delimited_to_vec()).is_err()
+                        {
+                            self.state = RunnerState::ErrorBackoff(
+                                "error while subscribing",
+                                Duration::from_secs(3),
+                            );
+                            continue;
+                        }
+                    }
+
                     let mut buf = BytesMut::new();
                     let mut rbuf = [0u8; 1024];
 
@@ -163,7 +189,10 @@ impl Runner {
                         match stream.read(&mut rbuf[..]) {
                             Ok(0) => break,
                             Ok(n) => buf.put_slice(&rbuf[..n]),
-                            Err(e) => eprintln!("read error: {:?}", e),
+                            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                            // Errors such as a failed TLS handshake won't go away by retrying
+                            // the read, so reconnect instead.
+                            Err(_) => break,
                         };
 
                         loop {
@@ -302,6 +331,23 @@ impl Runner {
                                                 inner.add(value);
                                             }
                                         }
+                                        Operation::RecordHistogramSketch(sketch) => {
+                                            let key =
+                                                CompositeKey::new(MetricKind::Histogram, key_data);
+                                            let mut metrics = self.metrics.write().unwrap();
+                                            match metrics.get_mut(&key) {
+                                                Some(MetricData::HistogramSketch(inner)) => {
+                                                    inner.merge(sketch)
+                                                }
+                                                _ => {
+                                                    let sketch = Sketch::new(sketch);
+                                                    metrics.insert(
+                                                        key,
+                                                        MetricData::HistogramSketch(sketch),
+                                                    );
+                                                }
+                                            }
+                                        }
                                     }
                                 }
                             }
@@ -315,3 +361,24 @@ impl Runner {
         }
     }
 }
+
+/// Parses a filter, in the form of `<name glob>[,<label key>=<label value glob>...]`.
+///
+/// In globs, `*` matches any sequence of characters and `?` matches any single character.  The name
+/// glob can be omitted, such as in `,env=prod`, to match metrics by their labels alone.
+pub fn parse_filter(filter: &str) -> proto::Filter {
+    let mut parts = filter.split(',');
+    let name = parts
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(|name| proto::filter::Name::NameGlob(name.to_string()));
+    let labels = parts
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (key, value_glob) = part.split_once('=').unwrap_or((part, ""));
+            proto::LabelMatcher { key: key.to_string(), value_glob: value_glob.to_string() }
+        })
+        .collect();
+
+    proto::Filter { name, labels }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use crate::metrics_inner::proto::HistogramSketch;
+
+/// Histogram sketches merged together, for histograms that are aggregated by the exporter.
+///
+/// See `HistogramSketch` in `proto/event.proto` for how samples are binned.
+#[derive(Clone)]
+pub struct Sketch {
+    relative_accuracy: f64,
+    count: u64,
+    min: f64,
+    max: f64,
+    positive_bins: BTreeMap<i32, u64>,
+    negative_bins: BTreeMap<i32, u64>,
+    zero_count: u64,
+}
+
+impl Sketch {
+    pub fn new(sketch: HistogramSketch) -> Sketch {
+        Sketch {
+            relative_accuracy: sketch.relative_accuracy,
+            count: sketch.count,
+            min: sketch.min,
+            max: sketch.max,
+            positive_bins: sketch.positive_bins,
+            negative_bins: sketch.negative_bins,
+            zero_count: sketch.zero_count,
+        }
+    }
+
+    /// Merges another sketch into this one.
+    ///
+    /// If the sketches use different bins, this one is replaced entirely.
+    pub fn merge(&mut self, sketch: HistogramSketch) {
+        if sketch.relative_accuracy != self.relative_accuracy {
+            *self = Sketch::new(sketch);
+            return;
+        }
+
+        self.count += sketch.count;
+        self.min = self.min.min(sketch.min);
+        self.max = self.max.max(sketch.max);
+        for (bin, count) in sketch.positive_bins {
+            *self.positive_bins.entry(bin).or_default() += count;
+        }
+        for (bin, count) in sketch.negative_bins {
+            *self.negative_bins.entry(bin).or_default() += count;
+        }
+        self.zero_count += sketch.zero_count;
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Gets the estimated value at the given quantile.
+    ///
+    /// If the sketch is empty, or if the quantile is less than 0.0 or greater than 1.0, then the
+    /// result will be `None`.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if !(0.0..=1.0).contains(&q) || self.count == 0 {
+            return None;
+        }
+
+        let gamma = (1.0 + self.relative_accuracy) / (1.0 - self.relative_accuracy);
+        let value = |bin: i32| 2.0 * gamma.powi(bin) / (gamma + 1.0);
+
+        // Walk the bins from the smallest value to the largest until we reach the rank of the
+        // quantile: negative bins by descending magnitude, then zero, then positive bins.
+        let rank = (q * (self.count - 1) as f64) as u64;
+        let bins = self
+            .negative_bins
+            .iter()
+            .rev()
+            .map(|(bin, count)| (-value(*bin), *count))
+            .chain(std::iter::once((0.0, self.zero_count)))
+            .chain(self.positive_bins.iter().map(|(bin, count)| (value(*bin), *count)));
+
+        let mut seen = 0;
+        for (value, count) in bins {
+            seen += count;
+            if seen > rank {
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
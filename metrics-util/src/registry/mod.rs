@@ -17,7 +17,7 @@ mod recency;
 #[cfg(feature = "recency")]
 #[cfg_attr(docsrs, doc(cfg(feature = "recency")))]
 pub use recency::{
-    Generation, Generational, GenerationalAtomicStorage, GenerationalStorage, Recency,
+    Generation, Generational, GenerationalAtomicStorage, GenerationalStorage, IdleTimeout, Recency,
 };
 
 use crate::common::KeyHasher;
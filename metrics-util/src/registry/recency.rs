@@ -25,7 +25,7 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
-use std::{collections::HashMap, ops::DerefMut};
+use std::{collections::HashMap, fmt, ops::DerefMut};
 
 use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn};
 use quanta::{Clock, Instant};
@@ -203,6 +203,20 @@ impl GenerationalAtomicStorage {
     }
 }
 
+/// Idle timeout override for an individual metric.
+///
+/// Returned by the resolver given to [`Recency::with_idle_timeout_resolver`] to override the global
+/// idle timeout for specific metrics.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdleTimeout {
+    /// The metric never expires, regardless of the global idle timeout.
+    Never,
+    /// The metric expires after it has not been updated for the given duration.
+    After(Duration),
+}
+
+type IdleTimeoutResolver<K> = Box<dyn Fn(&K) -> Option<IdleTimeout> + Send + Sync>;
+
 /// Tracks recency of metric updates by their registry generation and time.
 ///
 /// In many cases, a user may have a long-running process where metrics are stored over time using
@@ -216,12 +230,26 @@ impl GenerationalAtomicStorage {
 ///
 /// [`Recency`] is separate from [`Registry`] specifically to avoid imposing any slowdowns when
 /// tracking recency does not matter, despite their otherwise tight coupling.
-#[derive(Debug)]
 pub struct Recency<K> {
     mask: MetricKindMask,
     #[allow(clippy::type_complexity)]
     inner: Mutex<(Clock, HashMap<K, (Generation, Instant)>)>,
     idle_timeout: Option<Duration>,
+    resolver: Option<IdleTimeoutResolver<K>>,
+}
+
+impl<K> fmt::Debug for Recency<K>
+where
+    K: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recency")
+            .field("mask", &self.mask)
+            .field("inner", &self.inner)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("has_resolver", &self.resolver.is_some())
+            .finish()
+    }
 }
 
 impl<K> Recency<K>
@@ -241,7 +269,23 @@ where
     /// Refer to the documentation for [`MetricKindMask`](crate::MetricKindMask) for more
     /// information on defining a metric kind mask.
     pub fn new(clock: Clock, mask: MetricKindMask, idle_timeout: Option<Duration>) -> Self {
-        Recency { mask, inner: Mutex::new((clock, HashMap::new())), idle_timeout }
+        Recency { mask, inner: Mutex::new((clock, HashMap::new())), idle_timeout, resolver: None }
+    }
+
+    /// Sets a resolver for per-metric idle timeouts.
+    ///
+    /// The resolver is called each time a metric is checked.  If it returns `Some`, the
+    /// returned [`IdleTimeout`] is used for that metric instead of the global idle timeout, and
+    /// regardless of whether or not the metric kind is covered by the mask.  If it returns `None`,
+    /// the global idle timeout and mask apply as usual.  Metrics which end up with no idle timeout
+    /// are not tracked at all.
+    #[must_use]
+    pub fn with_idle_timeout_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&K) -> Option<IdleTimeout> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Box::new(resolver));
+        self
     }
 
     /// Checks if the given counter should be stored, based on its known recency.
@@ -311,36 +355,42 @@ where
         F: Fn(&Registry<K, S>, &K) -> bool,
         S: Storage<K>,
     {
-        if let Some(idle_timeout) = self.idle_timeout {
-            if self.mask.matches(kind) {
-                let mut guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
-                let (clock, entries) = guard.deref_mut();
-
-                let now = clock.now();
-                let deleted = if let Some((last_gen, last_update)) = entries.get_mut(key) {
-                    // If the value is the same as the latest value we have internally, and
-                    // we're over the idle timeout period, then remove it and continue.
-                    if *last_gen == gen {
-                        // If the delete returns false, that means that our generation counter is
-                        // out-of-date, and that the metric has been updated since, so we don't
-                        // actually want to delete it yet.
-                        (now - *last_update) > idle_timeout && delete_op(registry, key)
-                    } else {
-                        // Value has changed, so mark it such.
-                        *last_update = now;
-                        *last_gen = gen;
-                        false
-                    }
-                } else {
-                    entries.insert(key.clone(), (gen, now));
-                    false
-                };
-
-                if deleted {
-                    entries.remove(key);
-                    return false;
-                }
+        let idle_timeout = match self.resolver.as_ref().and_then(|resolver| resolver(key)) {
+            Some(IdleTimeout::Never) => None,
+            Some(IdleTimeout::After(timeout)) => Some(timeout),
+            None => self.idle_timeout.filter(|_| self.mask.matches(kind)),
+        };
+        let idle_timeout = match idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return true,
+        };
+
+        let mut guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let (clock, entries) = guard.deref_mut();
+
+        let now = clock.now();
+        let deleted = if let Some((last_gen, last_update)) = entries.get_mut(key) {
+            // If the value is the same as the latest value we have internally, and
+            // we're over the idle timeout period, then remove it and continue.
+            if *last_gen == gen {
+                // If the delete returns false, that means that our generation counter is
+                // out-of-date, and that the metric has been updated since, so we don't
+                // actually want to delete it yet.
+                (now - *last_update) > idle_timeout && delete_op(registry, key)
+            } else {
+                // Value has changed, so mark it such.
+                *last_update = now;
+                *last_gen = gen;
+                false
             }
+        } else {
+            entries.insert(key.clone(), (gen, now));
+            false
+        };
+
+        if deleted {
+            entries.remove(key);
+            return false;
         }
 
         true